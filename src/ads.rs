@@ -0,0 +1,221 @@
+//! Detecting Periods that contain ads (--skip-ads)
+//
+// Server-side ad insertion services generally insert each ad as a separate Period. We classify a
+// Period as an ad if:
+//
+//   - it falls within an ad break signalled by a SCTE-35 EventStream in another Period (an Event
+//   with a presentationTime and a duration);
+//
+//   - its media segments are served from the host of a known ad insertion service (Google DAI,
+//   FreeWheel, etc.), or from the ad segment paths of AWS MediaTailor, whose endpoints also serve the
+//   programme content;
+//
+//   - its @id looks like that of an ad ("mid-roll-1-ad-1", "preroll", "adbreak-3");
+//
+//   - it is short and its video stream uses a different codec or a different maximum resolution
+//   from the main content, which is typical of ads that are transcoded separately from the
+//   programme.
+//
+// Each decision is logged, so that the user can check the classification with --simulate. An ad
+// break that is signalled within a Period can't be removed without splitting the Period, and is only
+// reported.
+
+
+use std::collections::HashMap;
+use url::Url;
+use tracing::info;
+use dash_mpd::{MPD, Period};
+use crate::manifest::{Manifest, period_start, period_duration, representation_base_url};
+
+
+// Hosts of server-side ad insertion services, matched against the end of the host name.
+const AD_SERVER_HOSTS: [&str; 5] = [
+    "dai.google.com", "doubleclick.net", "fwmrm.net", "freewheel.tv", "yospace.com"];
+
+// Tokens in a Period@id that identify an ad.
+const AD_ID_TOKENS: [&str; 8] = [
+    "ad", "ads", "advert", "advertisement", "adbreak", "preroll", "midroll", "postroll"];
+
+// Periods longer than this are not classified as ads on the basis of an encoding discontinuity
+// alone.
+const MAX_DISCONTINUITY_AD_DURATION: f64 = 180.0;
+
+// Tolerance when comparing Period boundaries with the SCTE-35 ad break signals, in seconds.
+const SPLICE_TOLERANCE: f64 = 0.5;
+
+
+fn is_ad_url(url: &Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    // MediaTailor endpoints are regional, such as xxx.mediatailor.eu-west-1.amazonaws.com, and serve
+    // the programme content of the session (under /v1/segment/) as well as the ads (under
+    // /v1/dashsegment/). Ads may also be served from segments.mediatailor.eu-west-1.amazonaws.com.
+    if host.contains(".mediatailor.") && host.ends_with(".amazonaws.com") {
+        return host.starts_with("segments.mediatailor.") || url.path().starts_with("/v1/dashsegment/");
+    }
+    AD_SERVER_HOSTS.iter()
+        .any(|ad| host == *ad || host.ends_with(&format!(".{ad}")))
+}
+
+fn ad_server_host(manifest: &Manifest, period: &Period) -> Option<String> {
+    for adaptation in &period.adaptations {
+        for rep in &adaptation.representations {
+            let base = representation_base_url(manifest, period, adaptation, rep);
+            if is_ad_url(&base) {
+                return base.host_str().map(String::from);
+            }
+        }
+    }
+    None
+}
+
+fn has_ad_id(period: &Period) -> bool {
+    let Some(id) = &period.id else {
+        return false;
+    };
+    let id = id.to_lowercase();
+    let tokens: Vec<&str> = id.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .collect();
+    // Also check adjacent tokens joined, to catch ids such as "mid-roll-1" or "ad_break".
+    tokens.iter().any(|t| AD_ID_TOKENS.contains(t)) ||
+        tokens.windows(2).any(|w| AD_ID_TOKENS.contains(&format!("{}{}", w[0], w[1]).as_str()))
+}
+
+// The video codec families (such as "avc1" or "hvc1") and the maximum video height used in the
+// Period, or None if the Period contains no video.
+fn video_signature(period: &Period) -> Option<String> {
+    let mut codecs = Vec::new();
+    let mut height = 0;
+    for adaptation in &period.adaptations {
+        let adaptation_is_video = adaptation.contentType.as_deref() == Some("video") ||
+            adaptation.mimeType.as_deref().is_some_and(|m| m.starts_with("video/"));
+        for rep in &adaptation.representations {
+            let is_video = adaptation_is_video ||
+                rep.mimeType.as_deref().is_some_and(|m| m.starts_with("video/"));
+            if !is_video {
+                continue;
+            }
+            if let Some(c) = rep.codecs.as_ref().or(adaptation.codecs.as_ref()) {
+                let family = c.split('.').next().unwrap_or(c).to_string();
+                if !codecs.contains(&family) {
+                    codecs.push(family);
+                }
+            }
+            height = height.max(rep.height.or(adaptation.maxHeight).unwrap_or(0));
+        }
+    }
+    if codecs.is_empty() && height == 0 {
+        return None;
+    }
+    codecs.sort();
+    Some(format!("{} {height}p", codecs.join("+")))
+}
+
+// The splice points signalled by SCTE-35 EventStreams: the index of the Period that carries the
+// signal, the presentation time of the splice point and the duration of the break if specified, in
+// seconds.
+pub fn scte35_events(mpd: &MPD) -> Vec<(usize, f64, Option<f64>)> {
+    let mut events = Vec::new();
+    for (idx, period) in mpd.periods.iter().enumerate() {
+        let pstart = period_start(mpd, idx);
+        for es in &period.event_streams {
+            if !es.schemeIdUri.as_deref().is_some_and(|s| s.starts_with("urn:scte:scte35:")) {
+                continue;
+            }
+            let timescale = es.timescale.unwrap_or(1).max(1) as f64;
+            let pto = es.presentationTimeOffset.unwrap_or(0);
+            for event in &es.event {
+                if let Some(pt) = event.presentationTime {
+                    let start = pstart + pt.saturating_sub(pto) as f64 / timescale;
+                    events.push((idx, start, event.duration.map(|d| d as f64 / timescale)));
+                }
+            }
+        }
+    }
+    events
+}
+
+// The ad breaks signalled by SCTE-35 EventStreams, as intervals of presentation time in seconds,
+// with the index of the Period that carries the signal.
+fn scte35_breaks(mpd: &MPD) -> Vec<(usize, f64, f64)> {
+    scte35_events(mpd).into_iter()
+        .filter_map(|(idx, start, duration)| duration.map(|d| (idx, start, start + d)))
+        .collect()
+}
+
+fn describe(period: &Period, idx: usize) -> String {
+    match &period.id {
+        Some(id) => format!("Period {} (id {id})", idx + 1),
+        None => format!("Period {}", idx + 1),
+    }
+}
+
+// Classify each Period of the manifest as an ad (true) or programme content (false), logging the
+// reason for each decision.
+pub fn detect_ads(manifest: &Manifest) -> Vec<bool> {
+    let mpd = &manifest.mpd;
+    let breaks = scte35_breaks(mpd);
+    let mut reasons: Vec<Option<String>> = Vec::new();
+    for (idx, period) in mpd.periods.iter().enumerate() {
+        let start = period_start(mpd, idx);
+        let end = period_duration(mpd, idx).map(|d| start + d);
+        let in_break = breaks.iter().find(|(carrier, from, to)| {
+            *carrier != idx &&
+                start >= from - SPLICE_TOLERANCE &&
+                start < *to &&
+                end.is_some_and(|end| end <= to + SPLICE_TOLERANCE)
+        });
+        let reason = if let Some((carrier, from, to)) = in_break {
+            Some(format!("within the SCTE-35 ad break from {from:.1}s to {to:.1}s signalled in Period {}",
+                         carrier + 1))
+        } else if let Some(host) = ad_server_host(manifest, period) {
+            Some(format!("media served by ad insertion service {host}"))
+        } else if has_ad_id(period) {
+            Some(String::from("Period id identifies an ad"))
+        } else {
+            None
+        };
+        reasons.push(reason);
+    }
+    // The video encoding of the main content is that used for the greatest duration by the Periods
+    // that we haven't already classified as ads.
+    let signatures: Vec<Option<String>> = mpd.periods.iter().map(video_signature).collect();
+    let mut durations: HashMap<&str, f64> = HashMap::new();
+    for (idx, sig) in signatures.iter().enumerate() {
+        if let (None, Some(sig)) = (&reasons[idx], sig) {
+            *durations.entry(sig.as_str()).or_default() += period_duration(mpd, idx).unwrap_or(0.0);
+        }
+    }
+    let main = durations.into_iter()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(sig, _)| sig.to_string());
+    for (idx, sig) in signatures.iter().enumerate() {
+        if reasons[idx].is_some() {
+            continue;
+        }
+        if let (Some(main), Some(sig)) = (&main, sig) {
+            let short = period_duration(mpd, idx).is_some_and(|d| d < MAX_DISCONTINUITY_AD_DURATION);
+            if sig != main && short {
+                reasons[idx] = Some(format!("video encoding {sig} differs from the main content ({main})"));
+            }
+        }
+    }
+    for (idx, period) in mpd.periods.iter().enumerate() {
+        match &reasons[idx] {
+            Some(reason) => info!("{} is an ad ({reason}); skipping", describe(period, idx)),
+            None => info!("{} is programme content", describe(period, idx)),
+        }
+    }
+    // Ad breaks that don't correspond to a Period boundary.
+    for (carrier, from, to) in &breaks {
+        let pstart = period_start(mpd, *carrier);
+        let pend = period_duration(mpd, *carrier).map(|d| pstart + d);
+        if *from >= pstart && pend.is_some_and(|pend| *to <= pend + SPLICE_TOLERANCE) {
+            info!("SCTE-35 ad break from {from:.1}s to {to:.1}s is inside {} and can't be removed with --skip-ads (see --start-time and --end-time)",
+                  describe(&mpd.periods[*carrier], *carrier));
+        }
+    }
+    reasons.into_iter().map(|r| r.is_some()).collect()
+}
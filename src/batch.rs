@@ -0,0 +1,53 @@
+//! Support for downloading from a list of manifests provided in a batch file
+//
+// The batch file contains one item per line, in the format
+//
+//     MPD-URL [OUTPUT-PATH]
+//
+// Blank lines and lines starting with '#' are ignored. If no output path is specified for an item,
+// the output filename is derived from the manifest URL, as when no --output argument is provided.
+
+
+use std::io::{self, Read};
+use fs_err as fs;
+use anyhow::{anyhow, Result, Context};
+
+
+#[derive(Debug, Clone)]
+pub struct BatchItem {
+    pub url: String,
+    pub output: Option<String>,
+}
+
+fn parse_batch(content: &str) -> Result<Vec<BatchItem>> {
+    let mut items = Vec::new();
+    for (lineno, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (url, output) = match line.split_once(char::is_whitespace) {
+            Some((u, o)) => (u, Some(o.trim().to_string())),
+            None => (line, None),
+        };
+        if url::Url::parse(url).is_err() {
+            return Err(anyhow!("invalid manifest URL {url} on line {} of batch file", lineno + 1));
+        }
+        items.push(BatchItem { url: url.to_string(), output });
+    }
+    Ok(items)
+}
+
+// Read batch items from path, or from stdin if path is "-".
+pub fn read_batch_file(path: &str) -> Result<Vec<BatchItem>> {
+    let content = if path.eq("-") {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf)
+            .context("reading batch file from stdin")?;
+        buf
+    } else {
+        fs::read_to_string(path)
+            .context("reading batch file")?
+    };
+    parse_batch(&content)
+}
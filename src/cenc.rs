@@ -0,0 +1,724 @@
+//! Builtin decryptor for ISO Common Encryption (--decryption-application builtin)
+//
+// The DashDownloader decrypts media streams by running mp4decrypt or shaka-packager. The builtin
+// decryptor handles the two Common Encryption schemes (ISO/IEC 23001-7) that are used for DASH
+// content, without an external application:
+//
+//   - cenc: AES-128 in counter mode. The protected bytes of a sample (the protected part of each of
+//   its subsamples, or the whole sample) form a single keystream, whose counter block starts from
+//   the initialization vector of the sample.
+//
+//   - cbcs: AES-128 in CBC mode with pattern encryption. In each protected range, the first
+//   crypt_byte_block 16-byte blocks out of every crypt_byte_block + skip_byte_block are encrypted
+//   (typically 1 in 10 for video), and any final partial block is in the clear. The CBC chain
+//   restarts from the initialization vector, which is usually a constant IV from the tenc box, at
+//   the start of each subsample.
+//
+// The initialization vector and the subsample layout of each sample are read from the senc box of
+// the track fragment (or the equivalent PIFF uuid box), or else from the sample auxiliary
+// information located by the saiz and saio boxes. Samples are decrypted in place, so that the size
+// and position of every box are unchanged: the encrypted sample entry (encv or enca) takes back the
+// original format recorded in its frma box, and the boxes that only describe the encryption (sinf,
+// pssh, senc, saiz and saio) are turned into free boxes, which players ignore.
+
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use fs_err::{self as fs, File};
+use tracing::info;
+use anyhow::{anyhow, Result, Context};
+use aes::Aes128;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit, generic_array::GenericArray};
+use crate::fmp4::{FourCC, BoxRange, child_boxes, fourcc, read_u32, read_u64, full_box_version,
+                  versioned_u32, read_box_header, read_range};
+use crate::keys::Key;
+
+
+// The extended type of the PIFF SampleEncryptionBox, a uuid box with the same content as senc.
+const PIFF_SENC: [u8; 16] = [0xa2, 0x39, 0x4f, 0x52, 0x5a, 0x9b, 0x4f, 0x14,
+                             0xa2, 0x44, 0x6c, 0x42, 0x7c, 0x64, 0x8d, 0xf4];
+
+// The decryption keys for a KID or for a track id, from --key and --key-file.
+#[derive(Debug, Clone, Default)]
+pub struct DecryptionKeys {
+    by_kid: HashMap<[u8; 16], [u8; 16]>,
+    by_track: HashMap<u32, [u8; 16]>,
+}
+
+pub fn parse_hex16(s: &str) -> Option<[u8; 16]> {
+    if s.len() != 32 || !s.is_ascii() {
+        return None;
+    }
+    let mut out = [0u8; 16];
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[2*i..2*i+2], 16).ok()?;
+    }
+    Some(out)
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl DecryptionKeys {
+    pub fn new(keys: &[Key]) -> DecryptionKeys {
+        let mut out = DecryptionKeys::default();
+        for k in keys {
+            let Some(key) = parse_hex16(&k.key) else {
+                continue;
+            };
+            if let Some(kid) = parse_hex16(&k.kid) {
+                out.by_kid.insert(kid, key);
+            } else if let Ok(track) = k.kid.parse::<u32>() {
+                out.by_track.insert(track, key);
+            }
+        }
+        out
+    }
+
+    fn get(&self, kid: &[u8; 16], track: u32) -> Option<&[u8; 16]> {
+        self.by_kid.get(kid).or_else(|| self.by_track.get(&track))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scheme {
+    Cenc,
+    Cbcs,
+}
+
+// How the samples of an encrypted track are protected, from the tenc box of its sample entry.
+#[derive(Clone)]
+struct Protection {
+    scheme: Scheme,
+    kid: [u8; 16],
+    // The size of the per-sample initialization vectors, or zero when a constant IV is used.
+    iv_size: usize,
+    constant_iv: Option<[u8; 16]>,
+    crypt_blocks: usize,
+    skip_blocks: usize,
+    cipher: Aes128,
+}
+
+struct TrackInfo {
+    protection: Option<Protection>,
+    // The default sample size from the trex box.
+    default_sample_size: u32,
+}
+
+// The initialization vector of a sample and its subsamples, as (clear bytes, protected bytes).
+struct SampleInfo {
+    iv: [u8; 16],
+    subsamples: Vec<(usize, usize)>,
+}
+
+// Sequential reads from a range of a buffer, for the variable-length sample encryption information.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    end: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize, end: usize) -> Self {
+        Reader { data, pos, end: end.min(data.len()) }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.pos + len > self.end {
+            return Err(anyhow!("truncated sample encryption information"));
+        }
+        self.pos += len;
+        Ok(&self.data[self.pos - len..self.pos])
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(read_u32(self.bytes(4)?, 0))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(read_u64(self.bytes(8)?, 0))
+    }
+}
+
+// The boxes contained in parent, after skip bytes of its payload, with offsets in data.
+fn children(data: &[u8], parent: &BoxRange, skip: usize) -> Result<Vec<BoxRange>> {
+    let start = parent.payload + skip;
+    if start > parent.end {
+        return Err(anyhow!("truncated {} box", fourcc(&parent.kind)));
+    }
+    Ok(child_boxes(&data[start..parent.end])?.into_iter()
+       .map(|b| BoxRange { start: b.start + start, payload: b.payload + start, end: b.end + start, ..b })
+       .collect())
+}
+
+fn child(data: &[u8], parent: &BoxRange, kind: &FourCC) -> Result<Option<BoxRange>> {
+    Ok(children(data, parent, 0)?.into_iter().find(|b| &b.kind == kind))
+}
+
+fn set_kind(data: &mut [u8], b: &BoxRange, kind: &FourCC) {
+    data[b.start+4..b.start+8].copy_from_slice(kind);
+}
+
+fn is_piff_senc(data: &[u8], b: &BoxRange) -> bool {
+    &b.kind == b"uuid" && data.get(b.payload..b.payload + 16) == Some(&PIFF_SENC[..])
+}
+
+// An 8-byte initialization vector is the first half of the 16-byte counter block or IV.
+fn full_iv(iv: &[u8]) -> Result<[u8; 16]> {
+    let mut out = [0u8; 16];
+    match iv.len() {
+        8 | 16 => out[..iv.len()].copy_from_slice(iv),
+        n => return Err(anyhow!("invalid initialization vector size {n}")),
+    }
+    Ok(out)
+}
+
+// The original format and the protection of an encrypted sample entry, from its sinf box. Returns no
+// protection if the samples are in the clear by default.
+fn read_sinf(data: &[u8], sinf: &BoxRange, track: u32, keys: &DecryptionKeys) -> Result<(FourCC, Option<Protection>)> {
+    let boxes = children(data, sinf, 0)?;
+    let find = |kind: &FourCC| boxes.iter().find(|b| &b.kind == kind).copied()
+        .ok_or_else(|| anyhow!("sinf box without {} box", fourcc(kind)));
+    let frma = find(b"frma")?;
+    if frma.payload + 4 > frma.end {
+        return Err(anyhow!("truncated frma box"));
+    }
+    let format: FourCC = data[frma.payload..frma.payload+4].try_into().unwrap();
+    let schm = find(b"schm")?;
+    full_box_version(data, &schm, 8)?;
+    let scheme = match &data[schm.payload+4..schm.payload+8] {
+        b"cenc" => Scheme::Cenc,
+        b"cbcs" => Scheme::Cbcs,
+        other => return Err(anyhow!("unsupported protection scheme {} (the builtin decryptor handles cenc and cbcs)",
+                                    String::from_utf8_lossy(other))),
+    };
+    let schi = find(b"schi")?;
+    let tenc = child(data, &schi, b"tenc")?
+        .ok_or_else(|| anyhow!("schi box without tenc box"))?;
+    let version = full_box_version(data, &tenc, 20)?;
+    let at = tenc.payload + 4;
+    let (crypt_blocks, skip_blocks) = if version >= 1 {
+        (usize::from(data[at+1] >> 4), usize::from(data[at+1] & 0x0f))
+    } else {
+        (0, 0)
+    };
+    if data[at+2] == 0 {
+        return Ok((format, None));
+    }
+    let iv_size = usize::from(data[at+3]);
+    let kid: [u8; 16] = data[at+4..at+20].try_into().unwrap();
+    let mut constant_iv = None;
+    if iv_size == 0 {
+        let mut r = Reader::new(data, at + 20, tenc.end);
+        let len = usize::from(r.u8()?);
+        constant_iv = Some(full_iv(r.bytes(len)?)?);
+    } else if iv_size != 8 && iv_size != 16 {
+        return Err(anyhow!("invalid initialization vector size {iv_size}"));
+    }
+    let key = keys.get(&kid, track)
+        .ok_or_else(|| anyhow!("no key for KID {} of track {track}", hex(&kid)))?;
+    let protection = Protection {
+        scheme,
+        kid,
+        iv_size,
+        constant_iv,
+        crypt_blocks,
+        skip_blocks,
+        cipher: Aes128::new(GenericArray::from_slice(key)),
+    };
+    Ok((format, Some(protection)))
+}
+
+// Read the track id and protection of a trak box, and restore the original format of its encrypted
+// sample entries.
+fn decrypt_trak(moov: &mut [u8], trak: &BoxRange, keys: &DecryptionKeys) -> Result<(u32, Option<Protection>)> {
+    let tkhd = child(moov, trak, b"tkhd")?
+        .ok_or_else(|| anyhow!("trak box without tkhd box"))?;
+    let id = versioned_u32(moov, &tkhd, 12, 20)?;
+    let mut stsd = *trak;
+    for kind in [b"mdia", b"minf", b"stbl", b"stsd"] {
+        match child(moov, &stsd, kind)? {
+            Some(b) => stsd = b,
+            None => return Ok((id, None)),
+        }
+    }
+    full_box_version(moov, &stsd, 4)?;
+    let mut protection = None;
+    for entry in children(moov, &stsd, 8)? {
+        // The child boxes of a sample entry follow the fields of a VisualSampleEntry or an
+        // AudioSampleEntry, whose length depends on its version for QuickTime audio.
+        let fields = match &entry.kind {
+            b"encv" => 78,
+            b"enca" => match moov.get(entry.payload+8..entry.payload+10) {
+                Some([0, 1]) => 44,
+                Some([0, 2]) => 64,
+                _ => 28,
+            },
+            _ => continue,
+        };
+        let sinf = children(moov, &entry, fields)?.into_iter()
+            .find(|b| &b.kind == b"sinf")
+            .ok_or_else(|| anyhow!("encrypted sample entry of track {id} without sinf box"))?;
+        let (format, p) = read_sinf(moov, &sinf, id, keys)?;
+        set_kind(moov, &entry, &format);
+        set_kind(moov, &sinf, b"free");
+        if protection.is_none() {
+            protection = p;
+        }
+    }
+    Ok((id, protection))
+}
+
+// Read the protection of the tracks described by a moov box, and rewrite it to describe the
+// decrypted streams.
+fn decrypt_moov(moov: &mut [u8], keys: &DecryptionKeys) -> Result<HashMap<u32, TrackInfo>> {
+    let top = *child_boxes(moov)?.first()
+        .ok_or_else(|| anyhow!("empty moov box"))?;
+    let mut tracks = HashMap::new();
+    let mut sample_sizes = HashMap::new();
+    for b in children(moov, &top, 0)? {
+        match &b.kind {
+            b"trak" => {
+                let (id, protection) = decrypt_trak(moov, &b, keys)?;
+                tracks.insert(id, TrackInfo { protection, default_sample_size: 0 });
+            },
+            b"mvex" => {
+                for trex in children(moov, &b, 0)?.iter().filter(|c| &c.kind == b"trex") {
+                    full_box_version(moov, trex, 20)?;
+                    sample_sizes.insert(read_u32(moov, trex.payload + 4), read_u32(moov, trex.payload + 16));
+                }
+            },
+            b"pssh" => set_kind(moov, &b, b"free"),
+            _ => {},
+        }
+    }
+    for (id, size) in sample_sizes {
+        if let Some(track) = tracks.get_mut(&id) {
+            track.default_sample_size = size;
+        }
+    }
+    Ok(tracks)
+}
+
+fn read_sample_info(r: &mut Reader, iv_size: usize, subsamples: bool, p: &Protection) -> Result<SampleInfo> {
+    let iv = if iv_size == 0 {
+        p.constant_iv.ok_or_else(|| anyhow!("no initialization vector for sample"))?
+    } else {
+        full_iv(r.bytes(iv_size)?)?
+    };
+    let mut info = SampleInfo { iv, subsamples: Vec::new() };
+    if subsamples {
+        for _ in 0..r.u16()? {
+            info.subsamples.push((usize::from(r.u16()?), r.u32()? as usize));
+        }
+    }
+    Ok(info)
+}
+
+// A moof box and the mdat box that follows it, with their positions in the file.
+struct Fragment<'a> {
+    moof: &'a [u8],
+    moof_offset: u64,
+    mdat: &'a [u8],
+    mdat_offset: u64,
+}
+
+// The encryption information of the count samples of a track fragment, whose child boxes are
+// traf_boxes, from its senc box, or else from the sample auxiliary information located by its saiz
+// and saio boxes, whose offsets are relative to base.
+fn sample_infos(
+    fragment: &Fragment,
+    traf_boxes: &[BoxRange],
+    base: u64,
+    p: &Protection,
+    count: usize) -> Result<Vec<SampleInfo>>
+{
+    let moof = fragment.moof;
+    for b in traf_boxes {
+        let piff = is_piff_senc(moof, b);
+        if &b.kind != b"senc" && !piff {
+            continue;
+        }
+        let mut r = Reader::new(moof, if piff { b.payload + 16 } else { b.payload }, b.end);
+        let flags = r.u32()? & 0xff_ffff;
+        let mut iv_size = p.iv_size;
+        if piff && flags & 0x01 != 0 {
+            r.bytes(3)?;
+            iv_size = usize::from(r.u8()?);
+            r.bytes(16)?;
+        }
+        let n = r.u32()? as usize;
+        if n != count {
+            return Err(anyhow!("senc box describes {n} samples, but the track fragment contains {count}"));
+        }
+        return (0..n).map(|_| read_sample_info(&mut r, iv_size, flags & 0x02 != 0, p)).collect();
+    }
+    let find = |kind: &FourCC| traf_boxes.iter().find(|b| &b.kind == kind);
+    let (Some(saiz), Some(saio)) = (find(b"saiz"), find(b"saio")) else {
+        if let Some(iv) = p.constant_iv {
+            // Whole samples encrypted with the constant IV.
+            return Ok((0..count).map(|_| SampleInfo { iv, subsamples: Vec::new() }).collect());
+        }
+        return Err(anyhow!("no sample encryption information (senc box or saiz and saio boxes) in track fragment"));
+    };
+    let mut r = Reader::new(moof, saiz.payload, saiz.end);
+    let flags = r.u32()? & 0xff_ffff;
+    if flags & 0x01 != 0 {
+        r.bytes(8)?;
+    }
+    let default_size = usize::from(r.u8()?);
+    let n = r.u32()? as usize;
+    if n != count {
+        return Err(anyhow!("saiz box describes {n} samples, but the track fragment contains {count}"));
+    }
+    let sizes: Vec<usize> = if default_size == 0 {
+        r.bytes(n)?.iter().map(|s| usize::from(*s)).collect()
+    } else {
+        vec![default_size; n]
+    };
+    let mut r = Reader::new(moof, saio.payload, saio.end);
+    let version_flags = r.u32()?;
+    if version_flags & 0x01 != 0 {
+        r.bytes(8)?;
+    }
+    let entries = r.u32()?;
+    if entries != 1 {
+        return Err(anyhow!("saio box with {entries} offsets is not supported"));
+    }
+    let offset = if version_flags >> 24 == 0 { u64::from(r.u32()?) } else { r.u64()? };
+    let start = base + offset;
+    let len: usize = sizes.iter().sum();
+    // The sample auxiliary information is usually in the moof box, and otherwise in the mdat box.
+    let within = |data: &[u8], offset: u64| start >= offset && start + len as u64 <= offset + data.len() as u64;
+    let mut r = if within(moof, fragment.moof_offset) {
+        let pos = (start - fragment.moof_offset) as usize;
+        Reader::new(moof, pos, pos + len)
+    } else if within(fragment.mdat, fragment.mdat_offset) {
+        let pos = (start - fragment.mdat_offset) as usize;
+        Reader::new(fragment.mdat, pos, pos + len)
+    } else {
+        return Err(anyhow!("sample auxiliary information outside the media fragment"));
+    };
+    sizes.iter()
+        .map(|size| read_sample_info(&mut r, p.iv_size, *size > p.iv_size, p))
+        .collect()
+}
+
+// AES-CTR keystream, with a 64-bit block counter in the second half of the counter block.
+struct Keystream<'a> {
+    cipher: &'a Aes128,
+    counter: [u8; 16],
+    block: [u8; 16],
+    used: usize,
+}
+
+impl<'a> Keystream<'a> {
+    fn new(cipher: &'a Aes128, iv: &[u8; 16]) -> Self {
+        Keystream { cipher, counter: *iv, block: [0; 16], used: 16 }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.used == 16 {
+                let mut block = GenericArray::clone_from_slice(&self.counter);
+                self.cipher.encrypt_block(&mut block);
+                self.block.copy_from_slice(&block);
+                let count = u64::from_be_bytes(self.counter[8..].try_into().unwrap()).wrapping_add(1);
+                self.counter[8..].copy_from_slice(&count.to_be_bytes());
+                self.used = 0;
+            }
+            *byte ^= self.block[self.used];
+            self.used += 1;
+        }
+    }
+}
+
+// Decrypt a protected range with AES-CBC, where only the first crypt blocks of every crypt + skip
+// blocks are encrypted. A 0:0 pattern means that all the blocks are encrypted.
+fn decrypt_cbc_pattern(cipher: &Aes128, iv: &[u8; 16], crypt: usize, skip: usize, data: &mut [u8]) {
+    let (crypt, skip) = if crypt == 0 { (1, 0) } else { (crypt, skip) };
+    let mut chain = *iv;
+    for (i, block) in data.chunks_exact_mut(16).enumerate() {
+        if i % (crypt + skip) >= crypt {
+            continue;
+        }
+        let mut ciphertext = [0u8; 16];
+        ciphertext.copy_from_slice(block);
+        let mut plain = GenericArray::clone_from_slice(block);
+        cipher.decrypt_block(&mut plain);
+        for ((out, p), c) in block.iter_mut().zip(plain.iter()).zip(chain.iter()) {
+            *out = p ^ c;
+        }
+        chain = ciphertext;
+    }
+}
+
+fn decrypt_sample(p: &Protection, info: &SampleInfo, sample: &mut [u8]) -> Result<()> {
+    let mut ranges = Vec::new();
+    if info.subsamples.is_empty() {
+        ranges.push(0..sample.len());
+    } else {
+        let mut pos = 0;
+        for (clear, protected) in &info.subsamples {
+            let start = pos + clear;
+            pos = start + protected;
+            if pos > sample.len() {
+                return Err(anyhow!("subsamples extend beyond the end of the sample"));
+            }
+            ranges.push(start..pos);
+        }
+    }
+    match p.scheme {
+        Scheme::Cenc => {
+            let mut keystream = Keystream::new(&p.cipher, &info.iv);
+            for range in ranges {
+                keystream.apply(&mut sample[range]);
+            }
+        },
+        Scheme::Cbcs => {
+            for range in ranges {
+                decrypt_cbc_pattern(&p.cipher, &info.iv, p.crypt_blocks, p.skip_blocks, &mut sample[range]);
+            }
+        },
+    }
+    Ok(())
+}
+
+// Key rotation, where sample groups assign other KIDs to some samples, is not supported.
+fn check_sample_groups(moof: &[u8], traf_boxes: &[BoxRange], p: &Protection) -> Result<()> {
+    for sgpd in traf_boxes.iter().filter(|b| &b.kind == b"sgpd") {
+        let mut r = Reader::new(moof, sgpd.payload, sgpd.end);
+        let version = r.u32()? >> 24;
+        if r.bytes(4)? != b"seig" {
+            continue;
+        }
+        let default_length = if version >= 1 { r.u32()? } else { 0 };
+        if version >= 2 {
+            r.u32()?;
+        }
+        for _ in 0..r.u32()? {
+            if version >= 1 && default_length == 0 {
+                r.u32()?;
+            }
+            let entry = r.bytes(20)?;
+            if entry[2] == 1 && entry[4..20] != p.kid {
+                return Err(anyhow!("key rotation (samples encrypted with KID {}) is not supported by the builtin decryptor",
+                                   hex(&entry[4..20])));
+            }
+            if entry[2] == 1 && entry[3] == 0 {
+                let len = usize::from(r.u8()?);
+                r.bytes(len)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Decrypt in place the samples of the track fragments of a moof box, which are located in the mdat
+// box that follows it. moof_offset and mdat_offset are the positions of the boxes in the file.
+fn decrypt_fragment(
+    moof: &mut [u8],
+    moof_offset: u64,
+    mdat: &mut [u8],
+    mdat_offset: u64,
+    tracks: &HashMap<u32, TrackInfo>) -> Result<()>
+{
+    let top = *child_boxes(moof)?.first()
+        .ok_or_else(|| anyhow!("empty moof box"))?;
+    // Without an explicit base data offset, the data of a track fragment follows that of the previous
+    // one, starting from the moof box.
+    let mut next_base = moof_offset;
+    for traf in children(moof, &top, 0)?.iter().filter(|b| &b.kind == b"traf") {
+        let boxes = children(moof, traf, 0)?;
+        let tfhd = boxes.iter().find(|b| &b.kind == b"tfhd")
+            .ok_or_else(|| anyhow!("traf box without tfhd box"))?;
+        let mut r = Reader::new(moof, tfhd.payload, tfhd.end);
+        let flags = r.u32()? & 0xff_ffff;
+        let id = r.u32()?;
+        let base = if flags & 0x01 != 0 {
+            r.u64()?
+        } else if flags & 0x2_0000 != 0 {
+            moof_offset
+        } else {
+            next_base
+        };
+        if flags & 0x02 != 0 {
+            r.u32()?;
+        }
+        if flags & 0x08 != 0 {
+            r.u32()?;
+        }
+        let track = tracks.get(&id)
+            .ok_or_else(|| anyhow!("track fragment for unknown track {id}"))?;
+        let default_size = if flags & 0x10 != 0 { r.u32()? } else { track.default_sample_size };
+        // The position in the file and the size of each sample.
+        let mut samples = Vec::new();
+        let mut data_end = base;
+        for trun in boxes.iter().filter(|b| &b.kind == b"trun") {
+            let mut r = Reader::new(moof, trun.payload, trun.end);
+            let flags = r.u32()? & 0xff_ffff;
+            let count = r.u32()?;
+            let mut pos = data_end;
+            if flags & 0x01 != 0 {
+                let offset = i64::from(r.u32()? as i32);
+                pos = base.checked_add_signed(offset)
+                    .ok_or_else(|| anyhow!("invalid data offset in trun box"))?;
+            }
+            if flags & 0x04 != 0 {
+                r.u32()?;
+            }
+            for _ in 0..count {
+                if flags & 0x100 != 0 {
+                    r.u32()?;
+                }
+                let size = if flags & 0x200 != 0 { r.u32()? } else { default_size };
+                if flags & 0x400 != 0 {
+                    r.u32()?;
+                }
+                if flags & 0x800 != 0 {
+                    r.u32()?;
+                }
+                samples.push((pos, u64::from(size)));
+                pos += u64::from(size);
+            }
+            data_end = pos;
+        }
+        next_base = data_end;
+        let Some(protection) = &track.protection else {
+            continue;
+        };
+        check_sample_groups(moof, &boxes, protection)?;
+        let fragment = Fragment { moof, moof_offset, mdat, mdat_offset };
+        let infos = sample_infos(&fragment, &boxes, base, protection, samples.len())?;
+        for ((pos, size), info) in samples.iter().zip(infos) {
+            let start = pos.checked_sub(mdat_offset)
+                .filter(|start| start + size <= mdat.len() as u64)
+                .ok_or_else(|| anyhow!("sample data outside the mdat box that follows the moof box"))?;
+            let start = start as usize;
+            decrypt_sample(protection, &info, &mut mdat[start..start + *size as usize])?;
+        }
+        for b in &boxes {
+            if matches!(&b.kind, b"senc" | b"saiz" | b"saio") || is_piff_senc(moof, b) {
+                set_kind(moof, b, b"free");
+            }
+        }
+    }
+    for pssh in children(moof, &top, 0)?.iter().filter(|b| &b.kind == b"pssh") {
+        set_kind(moof, pssh, b"free");
+    }
+    Ok(())
+}
+
+// Copy the boxes of the input file to out, decrypting its media fragments. Returns whether any of its
+// tracks is encrypted.
+fn decrypt_boxes(input: &mut File, len: u64, out: &mut impl Write, keys: &DecryptionKeys) -> Result<bool> {
+    let mut tracks: Option<HashMap<u32, TrackInfo>> = None;
+    let mut encrypted = false;
+    let mut fragments = 0;
+    let mut offset = 0;
+    while let Some((kind, _, size)) = read_box_header(input, offset, len)? {
+        match &kind {
+            // When Periods have been concatenated, each one has its own initialization segment.
+            b"moov" => {
+                let mut moov = read_range(input, offset, size)?;
+                let moov_tracks = decrypt_moov(&mut moov, keys)?;
+                encrypted |= moov_tracks.values().any(|t| t.protection.is_some());
+                tracks = Some(moov_tracks);
+                out.write_all(&moov)?;
+            },
+            b"moof" => {
+                let tracks = tracks.as_ref()
+                    .ok_or_else(|| anyhow!("media fragment before the moov box"))?;
+                let mut moof = read_range(input, offset, size)?;
+                let mdat_offset = offset + size;
+                let mut mdat = match read_box_header(input, mdat_offset, len)? {
+                    Some((kind, _, mdat_size)) if &kind == b"mdat" => read_range(input, mdat_offset, mdat_size)?,
+                    _ => Vec::new(),
+                };
+                decrypt_fragment(&mut moof, offset, &mut mdat, mdat_offset, tracks)
+                    .with_context(|| format!("media fragment at offset {offset}"))?;
+                out.write_all(&moof)?;
+                out.write_all(&mdat)?;
+                fragments += 1;
+                offset = mdat_offset + mdat.len() as u64;
+                continue;
+            },
+            _ => {
+                input.seek(SeekFrom::Start(offset))?;
+                let copied = std::io::copy(&mut Read::by_ref(input).take(size), out)?;
+                if copied != size {
+                    return Err(anyhow!("truncated {} box at offset {offset}", fourcc(&kind)));
+                }
+            },
+        }
+        offset += size;
+    }
+    if encrypted && fragments == 0 {
+        return Err(anyhow!("the builtin decryptor only handles fragmented MP4 streams"));
+    }
+    Ok(encrypted)
+}
+
+// Decrypt the fragmented MP4 file at path in place. Returns false, leaving the file unchanged, if none
+// of its tracks is encrypted.
+pub fn decrypt_file(path: &Path, keys: &DecryptionKeys) -> Result<bool> {
+    let mut input = File::open(path)?;
+    let len = input.metadata()?.len();
+    let tmp = path.with_extension("decrypted.mp4");
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    let outcome = decrypt_boxes(&mut input, len, &mut out, keys)
+        .and_then(|encrypted| {
+            out.flush()?;
+            Ok(encrypted)
+        });
+    drop(out);
+    match outcome {
+        Ok(true) => {
+            fs::rename(&tmp, path)?;
+            info!("Decrypted {} with the builtin decryptor", path.display());
+            Ok(true)
+        },
+        Ok(false) => {
+            fs::remove_file(&tmp)?;
+            Ok(false)
+        },
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e.context(format!("decrypting {}", path.display())))
+        },
+    }
+}
+
+// Combine the decrypted streams into the output file with ffmpeg, for containers other than MP4 or
+// when the builtin muxer is not preferred.
+pub fn mux_streams(ffmpeg: &str, inputs: &[PathBuf], output: &Path) -> Result<()> {
+    let mut cmd = std::process::Command::new(ffmpeg);
+    cmd.args(["-hide_banner", "-nostats", "-loglevel", "error", "-y"]);
+    for input in inputs {
+        cmd.arg("-i").arg(input);
+    }
+    for n in 0..inputs.len() {
+        cmd.args(["-map", &n.to_string()]);
+    }
+    let out = cmd.args(["-c", "copy"])
+        .arg(output)
+        .output()
+        .with_context(|| format!("spawning {ffmpeg}"))?;
+    if !out.status.success() {
+        let _ = fs::remove_file(output);
+        return Err(anyhow!("ffmpeg failed to mux the decrypted streams into {}: {}",
+                           output.display(), String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    info!("Muxed {} decrypted streams into {}", inputs.len(), output.display());
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! Writing chapter markers into the output file (--embed-chapters)
+//
+// When several Periods are concatenated into one output file, we add a chapter at the start of each
+// Period, and with --scte35-chapters at each splice point signalled by a SCTE-35 EventStream.
+// Chapter names are taken from the Period@id (unless it is an opaque identifier such as a UUID),
+// or from the title in the manifest's ProgramInformation element.
+//
+// Chapter positions are first determined in presentation time, then mapped to the timeline of the
+// output file, which is made of the intervals of presentation time that were downloaded (entire
+// Periods, or the sections selected by --start-time and --end-time) placed end to end. The
+// chapters are written by ffmpeg from an FFMETADATA file, without reencoding. ffmpeg writes them as
+// a QuickTime chapter track and a Nero chpl box in MP4 output, and as native chapters in Matroska.
+
+
+use std::path::Path;
+use fs_err as fs;
+use tracing::info;
+use anyhow::{anyhow, Result, Context};
+use dash_mpd::MPD;
+use crate::ads::scte35_events;
+use crate::manifest::{Manifest, period_start, period_duration};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    // Start of the chapter in seconds.
+    pub start: f64,
+    pub title: String,
+}
+
+// Whether a Period@id is an opaque identifier, such as a UUID or a number, rather than a name.
+fn is_opaque_id(id: &str) -> bool {
+    let hex_groups: Vec<&str> = id.split('-').collect();
+    let uuid = hex_groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12]) &&
+        hex_groups.iter().all(|g| g.chars().all(|c| c.is_ascii_hexdigit()));
+    uuid || id.chars().all(|c| c.is_ascii_digit())
+}
+
+fn period_title(mpd: &MPD, idx: usize) -> String {
+    if let Some(id) = &mpd.periods[idx].id {
+        if !is_opaque_id(id) {
+            return id.clone();
+        }
+    }
+    let title = mpd.ProgramInformation.as_ref()
+        .and_then(|pi| pi.Title.as_ref())
+        .and_then(|t| t.content.clone());
+    match title {
+        Some(t) if mpd.periods.len() == 1 => t,
+        Some(t) => format!("{t} ({})", idx + 1),
+        None => format!("Chapter {}", idx + 1),
+    }
+}
+
+// The chapters of the content described by the manifest, in presentation time.
+pub fn manifest_chapters(manifest: &Manifest, splice_points: bool) -> Vec<Chapter> {
+    let mpd = &manifest.mpd;
+    let mut chapters: Vec<Chapter> = (0..mpd.periods.len())
+        .map(|idx| Chapter { start: period_start(mpd, idx), title: period_title(mpd, idx) })
+        .collect();
+    if splice_points {
+        for (idx, start, duration) in scte35_events(mpd) {
+            chapters.push(Chapter { start, title: String::from("Ad break") });
+            // Return to the programme at the end of the break.
+            if let Some(d) = duration {
+                chapters.push(Chapter { start: start + d, title: period_title(mpd, idx) });
+            }
+        }
+        chapters.sort_by(|a, b| a.start.total_cmp(&b.start));
+    }
+    chapters
+}
+
+// The intervals of presentation time covered by each Period of the manifest.
+pub fn period_intervals(mpd: &MPD) -> Vec<(f64, f64)> {
+    (0..mpd.periods.len())
+        .map(|idx| {
+            let start = period_start(mpd, idx);
+            (start, start + period_duration(mpd, idx).unwrap_or(0.0))
+        })
+        .collect()
+}
+
+// Map chapters from presentation time to the timeline of an output file made of intervals of
+// presentation time placed end to end. A chapter that starts in a gap between intervals starts at
+// the beginning of the following interval, and chapters after the last interval are dropped. When
+// several chapters map to the same position, the last one wins.
+pub fn output_chapters(chapters: &[Chapter], intervals: &[(f64, f64)]) -> Vec<Chapter> {
+    let mut mapped: Vec<Chapter> = Vec::new();
+    for chapter in chapters {
+        let mut offset = 0.0;
+        for (from, to) in intervals {
+            if chapter.start < *to {
+                let start = offset + (chapter.start - from).max(0.0);
+                if mapped.last().is_some_and(|last| (last.start - start).abs() < 0.001) {
+                    mapped.pop();
+                }
+                mapped.push(Chapter { start, title: chapter.title.clone() });
+                break;
+            }
+            offset += to - from;
+        }
+    }
+    mapped
+}
+
+// Adjust chapters for an output file that has been cut to start offset seconds in (for
+// --exact-trim). The chapter in progress at the cut becomes the first chapter.
+pub fn trim_chapters(chapters: &[Chapter], offset: f64) -> Vec<Chapter> {
+    let mut trimmed: Vec<Chapter> = Vec::new();
+    for chapter in chapters {
+        let start = chapter.start - offset;
+        if start <= 0.0 {
+            trimmed.clear();
+        }
+        trimmed.push(Chapter { start: start.max(0.0), title: chapter.title.clone() });
+    }
+    trimmed
+}
+
+// Escape the special characters of the FFMETADATA format.
+fn escape_metadata(value: &str) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        match c {
+            '=' | ';' | '#' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            },
+            '\n' => out.push_str("\\\n"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn ffmetadata(chapters: &[Chapter], duration: f64) -> String {
+    let millis = |secs: f64| (secs * 1000.0).round() as u64;
+    let mut meta = String::from(";FFMETADATA1\n");
+    for (i, chapter) in chapters.iter().enumerate() {
+        let end = chapters.get(i + 1).map_or(duration, |next| next.start);
+        meta.push_str(&format!("[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+                               millis(chapter.start), millis(end), escape_metadata(&chapter.title)));
+    }
+    meta
+}
+
+// Write chapters into the media file at path, whose duration is duration seconds, using ffmpeg.
+pub fn embed_chapters(ffmpeg: &str, path: &Path, chapters: &[Chapter], duration: f64) -> Result<()> {
+    let chapters: Vec<Chapter> = chapters.iter()
+        .filter(|c| c.start < duration)
+        .cloned()
+        .collect();
+    if chapters.len() < 2 {
+        info!("Not adding chapters to {}, because the content has a single chapter", path.display());
+        return Ok(());
+    }
+    let meta = tempfile::Builder::new()
+        .prefix(".dash-mpd-cli-chapters-")
+        .suffix(".txt")
+        .tempfile()
+        .context("creating temporary chapter metadata file")?;
+    fs::write(&meta, ffmetadata(&chapters, duration))
+        .context("writing chapter metadata")?;
+    let mut tmp = path.to_path_buf();
+    tmp.set_extension(format!("chapters.{}", path.extension().unwrap_or_default().to_string_lossy()));
+    let out = std::process::Command::new(ffmpeg)
+        .args(["-hide_banner", "-nostats", "-loglevel", "error", "-y"])
+        .arg("-i").arg(path)
+        .arg("-i").arg(meta.path())
+        .args(["-map", "0", "-map_metadata", "0", "-map_chapters", "1", "-c", "copy"])
+        .arg(&tmp)
+        .output()
+        .with_context(|| format!("spawning {ffmpeg}"))?;
+    if !out.status.success() {
+        let _ = fs::remove_file(&tmp);
+        return Err(anyhow!("ffmpeg failed to add chapters to {}: {}",
+                           path.display(), String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    fs::rename(&tmp, path)?;
+    info!("Added {} chapters to {}", chapters.len(), path.display());
+    Ok(())
+}
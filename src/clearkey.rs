@@ -0,0 +1,128 @@
+//! ClearKey license acquisition
+//
+// Content protected with W3C ClearKey (the DRM system urn:uuid:e2719d58-a985-b3c9-781a-b030af78d30e)
+// may give the URL of a license server in a clearkey:Laurl (or dashif:laurl) element of its
+// ContentProtection. The license server returns the content keys in clear, as a JSON Web Key Set, in
+// response to the JSON license request defined by the W3C Encrypted Media Extensions, which lists
+// the KIDs as base64url strings. Before the download starts, we request the keys for the KIDs of the
+// selected AdaptationSets that we don't already have a key for, with the same HTTP client, headers
+// and authentication as the manifest, and pass them on to the decryption application like the keys
+// given with --key.
+
+
+use url::Url;
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde_json::{json, Value};
+use tracing::warn;
+use dash_mpd::ContentProtection;
+use crate::cenc::{parse_hex16, hex};
+use crate::keys::{Key, normalize_kid};
+use crate::manifest::{Manifest, ManifestFetcher};
+use crate::selection::{StreamPreferences, select_representation};
+
+
+const CLEARKEY_SCHEME: &str = "urn:uuid:e2719d58-a985-b3c9-781a-b030af78d30e";
+
+// A ClearKey license server, and the KIDs of the streams that it holds the keys for.
+pub struct LicenseRequest {
+    pub url: Url,
+    pub kids: Vec<String>,
+}
+
+fn license_url(cp: &ContentProtection) -> Option<&str> {
+    if !cp.schemeIdUri.eq_ignore_ascii_case(CLEARKEY_SCHEME) {
+        return None;
+    }
+    cp.clearkey_laurl.as_ref().and_then(|l| l.content.as_deref())
+        .or_else(|| cp.laurl.as_ref().and_then(|l| l.content.as_deref()))
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+}
+
+// The ClearKey license servers of the streams of each kind that are downloaded from the manifest,
+// with the default KIDs of every protected Representation of the selected AdaptationSets. We don't
+// rely on predicting which Representation the DashDownloader will choose.
+pub fn license_requests(manifest: &Manifest, prefs: &StreamPreferences, kinds: &[&'static str]) -> Vec<LicenseRequest> {
+    let mut requests: Vec<LicenseRequest> = Vec::new();
+    for period in &manifest.mpd.periods {
+        for &kind in kinds {
+            let Some(choice) = select_representation(period, kind, prefs) else {
+                continue;
+            };
+            let adaptation = &period.adaptations[choice.adaptation];
+            for rep in &adaptation.representations {
+                let protections: Vec<&ContentProtection> = adaptation.ContentProtection.iter()
+                    .chain(rep.ContentProtection.iter())
+                    .collect();
+                let Some(laurl) = protections.iter().find_map(|cp| license_url(cp)) else {
+                    continue;
+                };
+                let url = match manifest.url.join(laurl) {
+                    Ok(url) => url,
+                    Err(e) => {
+                        warn!("Ignoring invalid ClearKey license URL {laurl}: {e}");
+                        continue;
+                    },
+                };
+                let idx = match requests.iter().position(|r| r.url == url) {
+                    Some(idx) => idx,
+                    None => {
+                        requests.push(LicenseRequest { url, kids: Vec::new() });
+                        requests.len() - 1
+                    },
+                };
+                for kid in protections.iter().filter_map(|cp| cp.default_KID.as_deref().and_then(normalize_kid)) {
+                    if !requests[idx].kids.contains(&kid) {
+                        requests[idx].kids.push(kid);
+                    }
+                }
+            }
+        }
+    }
+    requests
+}
+
+// A KID or a key in a JSON Web Key: 16 bytes encoded in base64url, normally without padding.
+fn decode_jwk_bytes(s: &str) -> Result<[u8; 16]> {
+    let bytes = URL_SAFE_NO_PAD.decode(s.trim_end_matches('='))?;
+    bytes.try_into()
+        .map_err(|_| anyhow!("expecting 16 bytes"))
+}
+
+// Parse the JSON Web Key Set returned by a ClearKey license server.
+pub fn parse_license(license: &Value) -> Result<Vec<Key>> {
+    let jwks = license["keys"].as_array()
+        .ok_or_else(|| anyhow!("expecting a JSON Web Key Set"))?;
+    let mut keys = Vec::new();
+    for jwk in jwks {
+        // Content keys are symmetric keys.
+        if jwk["kty"].as_str().is_some_and(|kty| kty != "oct") {
+            continue;
+        }
+        let (Some(kid), Some(k)) = (jwk["kid"].as_str(), jwk["k"].as_str()) else {
+            return Err(anyhow!("missing kid or k in JSON Web Key"));
+        };
+        let kid = decode_jwk_bytes(kid)
+            .with_context(|| format!("decoding kid {kid}"))?;
+        let key = decode_jwk_bytes(k)
+            .with_context(|| format!("decoding the key for kid {}", hex(&kid)))?;
+        keys.push(Key { kid: hex(&kid), key: hex(&key) });
+    }
+    Ok(keys)
+}
+
+// Request the keys for the KIDs of request from its license server.
+pub async fn acquire_keys(fetcher: &ManifestFetcher, request: &LicenseRequest) -> Result<Vec<Key>> {
+    let kids: Vec<String> = request.kids.iter()
+        .filter_map(|kid| parse_hex16(kid))
+        .map(|kid| URL_SAFE_NO_PAD.encode(kid))
+        .collect();
+    let body = json!({ "kids": kids, "type": "temporary" });
+    let license: Value = fetcher.post_json(request.url.clone(), &body).await?
+        .error_for_status()?
+        .json().await
+        .context("parsing license response")?;
+    parse_license(&license)
+}
@@ -0,0 +1,336 @@
+//! Downloading a section of a static stream (--start-time and --end-time)
+//
+// The DashDownloader fetches every media segment of the selected Representations, so to download
+// only a section of the content we rewrite the manifest before handing it over. Each Representation
+// that uses SegmentTemplate, SegmentList or SegmentBase addressing is converted to an explicit
+// SegmentList that contains only the media segments which overlap the requested interval, with
+// absolute URLs. For SegmentBase addressing, the media segments are the subsegments listed in the
+// Segment Index box (sidx) designated by SegmentBase@indexRange, which are fetched as byte ranges of
+// the media file. Periods that don't overlap the interval are dropped.
+//
+// Since we select whole segments, the downloaded content generally starts a little before the
+// requested start time and ends a little after the requested end time. With --exact-trim, the muxed
+// output file is then cut at the precise timestamps using ffmpeg, which requires reencoding the
+// video stream.
+
+
+use std::path::Path;
+use std::time::Duration;
+use url::Url;
+use fs_err as fs;
+use tracing::{info, warn};
+use anyhow::{anyhow, Result, Context};
+use dash_mpd::{MPD, AdaptationSet, Representation, BaseURL, Initialization, SegmentBase, SegmentList,
+               SegmentURL};
+use crate::manifest::{Manifest, ManifestFetcher, RepresentationSegments, Segment, period_duration,
+                      period_start, representation_base_url, representation_segments};
+
+
+// The section of the content to download, in seconds from the start of the presentation.
+#[derive(Debug, Clone, Copy)]
+pub struct ClipRange {
+    pub start: f64,
+    pub end: Option<f64>,
+}
+
+impl ClipRange {
+    // Whether the interval [start, start + duration) overlaps the range. A segment of unknown
+    // (zero) duration is kept if it starts before the end of the range.
+    fn overlaps(&self, start: f64, duration: f64) -> bool {
+        let before_end = self.end.is_none_or(|end| start < end);
+        before_end && (duration <= 0.0 || start + duration > self.start)
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        self.end.map(|end| end - self.start)
+    }
+}
+
+pub struct ClippedManifest {
+    pub mpd: MPD,
+    // The presentation time, in seconds, at which the downloaded content starts: the start of the
+    // earliest selected media segment.
+    pub origin: f64,
+    // The interval of presentation time covered by the selected media segments of each Period.
+    pub intervals: Vec<(f64, f64)>,
+}
+
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u64> {
+    data.get(pos..pos + 4)
+        .map(|b| u64::from(u32::from_be_bytes([b[0], b[1], b[2], b[3]])))
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Option<u64> {
+    data.get(pos..pos + 8)
+        .map(|b| u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+}
+
+// A subsegment listed in a Segment Index box: its byte range in the media file, and its start time
+// and duration in seconds.
+#[derive(Debug, Clone, PartialEq)]
+struct Subsegment {
+    first_byte: u64,
+    last_byte: u64,
+    start: f64,
+    duration: f64,
+}
+
+// Parse the Segment Index box (sidx) found in data, which was read from byte offset data_offset of
+// the media file. Byte offsets in the sidx box are relative to the first byte after the box.
+fn parse_sidx(data: &[u8], data_offset: u64) -> Result<Vec<Subsegment>> {
+    let truncated = || anyhow!("truncated sidx box");
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let mut size = read_u32(data, pos).ok_or_else(truncated)?;
+        let mut header = 8;
+        if size == 1 {
+            size = read_u64(data, pos + 8).ok_or_else(truncated)?;
+            header = 16;
+        } else if size == 0 {
+            size = (data.len() - pos) as u64;
+        }
+        if size < header as u64 {
+            return Err(anyhow!("invalid MP4 box size"));
+        }
+        if &data[pos + 4..pos + 8] != b"sidx" {
+            pos += size as usize;
+            continue;
+        }
+        let mut p = pos + header;
+        let version = *data.get(p).ok_or_else(truncated)?;
+        // skip version, flags and reference_ID
+        p += 8;
+        let timescale = read_u32(data, p).ok_or_else(truncated)?.max(1) as f64;
+        p += 4;
+        let (earliest, first_offset) = if version == 0 {
+            p += 8;
+            (read_u32(data, p - 8).ok_or_else(truncated)?, read_u32(data, p - 4).ok_or_else(truncated)?)
+        } else {
+            p += 16;
+            (read_u64(data, p - 16).ok_or_else(truncated)?, read_u64(data, p - 8).ok_or_else(truncated)?)
+        };
+        // skip the reserved field
+        p += 2;
+        let count = data.get(p..p + 2).ok_or_else(truncated)?;
+        let count = u16::from_be_bytes([count[0], count[1]]);
+        p += 2;
+        let mut offset = data_offset + pos as u64 + size + first_offset;
+        let mut time = earliest;
+        let mut subsegments = Vec::new();
+        for _ in 0..count {
+            let reference = read_u32(data, p).ok_or_else(truncated)?;
+            let duration = read_u32(data, p + 4).ok_or_else(truncated)?;
+            p += 12;
+            if reference & 0x8000_0000 != 0 {
+                return Err(anyhow!("hierarchical sidx boxes are not supported"));
+            }
+            let referenced_size = reference & 0x7fff_ffff;
+            subsegments.push(Subsegment {
+                first_byte: offset,
+                last_byte: offset + referenced_size - 1,
+                start: time as f64 / timescale,
+                duration: duration as f64 / timescale,
+            });
+            offset += referenced_size;
+            time += duration;
+        }
+        return Ok(subsegments);
+    }
+    Err(anyhow!("no sidx box found in the index range"))
+}
+
+// The media segments of a Representation that uses SegmentBase addressing with the index range
+// index_range, from the Segment Index box of the media file at base.
+async fn indexed_segments(
+    fetcher: &ManifestFetcher,
+    base: &Url,
+    sb: &SegmentBase,
+    index_range: &str) -> Result<RepresentationSegments>
+{
+    let index_start: u64 = index_range.split('-').next()
+        .and_then(|s| s.trim().parse().ok())
+        .ok_or_else(|| anyhow!("invalid SegmentBase@indexRange {index_range}"))?;
+    let response = fetcher.fetch_segment(base.clone(), Some(&format!("bytes={index_range}"))).await?
+        .error_for_status()
+        .context("fetching segment index")?;
+    let data = response.bytes().await
+        .context("fetching segment index")?;
+    let pto = sb.presentationTimeOffset.unwrap_or(0) as f64 / sb.timescale.unwrap_or(1) as f64;
+    let mut segments = RepresentationSegments::default();
+    if let Some(init) = &sb.Initialization {
+        segments.init = Some(Segment {
+            url: match &init.sourceURL {
+                Some(su) => base.join(su)?,
+                None => base.clone(),
+            },
+            byte_range: init.range.clone(),
+            start: 0.0,
+            duration: 0.0,
+        });
+    }
+    for sub in parse_sidx(&data, index_start)? {
+        segments.media.push(Segment {
+            url: base.clone(),
+            byte_range: Some(format!("{}-{}", sub.first_byte, sub.last_byte)),
+            start: sub.start - pto,
+            duration: sub.duration,
+        });
+    }
+    Ok(segments)
+}
+
+fn segment_list(segments: &RepresentationSegments) -> SegmentList {
+    SegmentList {
+        Initialization: segments.init.as_ref().map(|init| Initialization {
+            sourceURL: Some(init.url.to_string()),
+            range: init.byte_range.clone(),
+            ..Default::default()
+        }),
+        segment_urls: segments.media.iter()
+            .map(|s| SegmentURL {
+                media: Some(s.url.to_string()),
+                mediaRange: s.byte_range.clone(),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+// Rewrite the manifest so that it only contains the media segments that overlap range.
+pub async fn clip_manifest(
+    fetcher: &ManifestFetcher,
+    manifest: &Manifest,
+    range: &ClipRange) -> Result<ClippedManifest>
+{
+    let original = &manifest.mpd;
+    let mut mpd = original.clone();
+    let mut origin: Option<f64> = None;
+    let mut total_duration = 0.0;
+    let mut periods = Vec::new();
+    let mut intervals = Vec::new();
+    for (pidx, mut period) in mpd.periods.drain(..).enumerate() {
+        let pstart = period_start(original, pidx);
+        let pduration = period_duration(original, pidx);
+        if !range.overlaps(pstart, pduration.unwrap_or(0.0)) {
+            continue;
+        }
+        let original_period = &original.periods[pidx];
+        // The span of the selected segments in this Period, relative to the start of the Period.
+        let mut span: Option<(f64, f64)> = None;
+        for (aidx, adaptation) in period.adaptations.iter_mut().enumerate() {
+            let original_adaptation = &original_period.adaptations[aidx];
+            for rep in adaptation.representations.iter_mut() {
+                let base = representation_base_url(manifest, original_period, original_adaptation, rep);
+                let segments = if merged_addressing(original_adaptation, rep) {
+                    Some(representation_segments(manifest, pidx, original_adaptation, rep)?)
+                } else {
+                    match rep.SegmentBase.as_ref().or(original_adaptation.SegmentBase.as_ref()) {
+                        Some(sb) => match &sb.indexRange {
+                            Some(ir) => Some(indexed_segments(fetcher, &base, sb, ir).await
+                                             .with_context(|| format!("reading segment index of {base}"))?),
+                            None => {
+                                warn!("Representation {} has no segment index (SegmentBase@indexRange), so it is downloaded in full, ignoring --start-time and --end-time",
+                                      rep.id.as_deref().unwrap_or("(no id)"));
+                                None
+                            },
+                        },
+                        None => None,
+                    }
+                };
+                rep.BaseURL = vec![BaseURL { base: base.to_string(), ..Default::default() }];
+                // A Representation that is a single media file (such as a subtitle track) is kept
+                // as is.
+                let Some(mut segments) = segments else {
+                    continue;
+                };
+                segments.media.retain(|s| range.overlaps(pstart + s.start, s.duration));
+                if segments.media.is_empty() {
+                    warn!("No media segments of Representation {} overlap the requested time range",
+                          rep.id.as_deref().unwrap_or("(no id)"));
+                }
+                if let (Some(first), Some(last)) = (segments.media.first(), segments.media.last()) {
+                    let (from, to) = span.unwrap_or((first.start, last.start + last.duration));
+                    span = Some((from.min(first.start), to.max(last.start + last.duration)));
+                }
+                rep.SegmentTemplate = None;
+                rep.SegmentBase = None;
+                rep.SegmentList = Some(segment_list(&segments));
+            }
+            adaptation.SegmentTemplate = None;
+            adaptation.SegmentList = None;
+            adaptation.SegmentBase = None;
+            adaptation.BaseURL.clear();
+        }
+        period.BaseURL.clear();
+        let (from, duration) = match span {
+            Some((from, to)) => (from, to - from),
+            None => (0.0, pduration.unwrap_or(0.0)),
+        };
+        origin.get_or_insert(pstart + from);
+        intervals.push((pstart + from, pstart + from + duration.max(0.0)));
+        period.start = None;
+        period.duration = Some(Duration::from_secs_f64(duration.max(0.0)));
+        total_duration += duration.max(0.0);
+        periods.push(period);
+    }
+    if periods.is_empty() {
+        return Err(anyhow!("the requested time range is beyond the end of the content"));
+    }
+    mpd.periods = periods;
+    mpd.base_url.clear();
+    mpd.mediaPresentationDuration = Some(Duration::from_secs_f64(total_duration));
+    let origin = origin.unwrap_or(range.start);
+    info!("Downloading {:.1}s of content starting at {:.1}s", total_duration, origin);
+    Ok(ClippedManifest { mpd, origin, intervals })
+}
+
+// Whether the Representation uses SegmentTemplate or SegmentList addressing (specified at the
+// AdaptationSet or Representation level).
+fn merged_addressing(adaptation: &AdaptationSet, rep: &Representation) -> bool {
+    rep.SegmentTemplate.is_some() || adaptation.SegmentTemplate.is_some() ||
+        rep.SegmentList.is_some() || adaptation.SegmentList.is_some()
+}
+
+// The ffmpeg arguments that select the encoder for the video stream of the output file at path.
+fn video_encoder(path: &Path) -> [&'static str; 6] {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("webm") => ["-c:v", "libvpx-vp9", "-crf", "31", "-b:v", "0"],
+        _ => ["-c:v", "libx264", "-crf", "18", "-preset", "medium"],
+    }
+}
+
+// Cut the muxed file at path to start offset seconds in and last duration seconds, using ffmpeg.
+// Cutting at timestamps that don't fall on a keyframe requires the video to be reencoded, which we
+// do explicitly with a high-quality setting; the audio and subtitle streams are copied.
+pub fn trim_exact(ffmpeg: &str, path: &Path, offset: f64, duration: Option<f64>) -> Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".trim");
+    let mut tmp = path.with_file_name(tmp_name);
+    if let Some(ext) = path.extension() {
+        tmp.set_extension(format!("trim.{}", ext.to_string_lossy()));
+    }
+    let mut cmd = std::process::Command::new(ffmpeg);
+    cmd.args(["-hide_banner", "-nostats", "-loglevel", "error", "-y",
+              "-ss", &format!("{offset:.3}")])
+        .arg("-i").arg(path);
+    if let Some(d) = duration {
+        cmd.args(["-t", &format!("{d:.3}")]);
+    }
+    let encoder = video_encoder(path);
+    info!("Reencoding the video stream with {} to cut it at the exact timestamps", encoder[1]);
+    cmd.args(["-map", "0", "-c", "copy"])
+        .args(encoder)
+        .arg(&tmp);
+    let out = cmd.output()
+        .with_context(|| format!("spawning {ffmpeg}"))?;
+    if !out.status.success() {
+        let _ = fs::remove_file(&tmp);
+        return Err(anyhow!("ffmpeg failed to trim {}: {}",
+                           path.display(), String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
@@ -0,0 +1,167 @@
+//! Default values for commandline arguments, read from a TOML configuration file
+//
+// The configuration file is located at $XDG_CONFIG_HOME/dash-mpd-cli/config.toml (defaulting to
+// ~/.config/dash-mpd-cli/config.toml, or %APPDATA%\dash-mpd-cli\config.toml on Windows). Keys are
+// the long names of commandline options. Settings in the [default] table are used on every
+// invocation, and settings in a [profile.NAME] table are added when --profile NAME is specified.
+// Values specified on the commandline override values from the configuration file.
+//
+//     [default]
+//     user-agent = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0"
+//     header = ["X-Custom: ized"]
+//     muxer-preference = ["mp4:mp4box,ffmpeg"]
+//
+//     [profile.archive]
+//     quality = "best"
+//     write-subs = true
+
+
+use std::env;
+use std::path::PathBuf;
+use fs_err as fs;
+use clap::{ArgAction, ArgMatches, Command};
+use clap::parser::ValueSource;
+use anyhow::{anyhow, Result, Context};
+
+
+pub fn config_path() -> Option<PathBuf> {
+    let dir = if let Some(xdg) = env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        PathBuf::from(xdg)
+    } else if cfg!(target_os = "windows") {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else {
+        PathBuf::from(env::var_os("HOME")?).join(".config")
+    };
+    Some(dir.join("dash-mpd-cli").join("config.toml"))
+}
+
+// Options that only make sense on the commandline.
+const NOT_CONFIGURABLE: [&str; 4] = ["profile", "print-config", "help", "version"];
+
+// Convert one setting from the configuration file into commandline arguments.
+fn setting_args(cmd: &Command, key: &str, value: &toml::Value) -> Result<Vec<String>> {
+    let arg = cmd.get_arguments()
+        .find(|a| a.get_long() == Some(key))
+        .filter(|_| !NOT_CONFIGURABLE.contains(&key))
+        .ok_or_else(|| anyhow!("unknown option {key} in configuration file"))?;
+    let scalar = |v: &toml::Value| -> Result<String> {
+        match v {
+            toml::Value::String(s) => Ok(s.clone()),
+            toml::Value::Integer(i) => Ok(i.to_string()),
+            toml::Value::Float(f) => Ok(f.to_string()),
+            toml::Value::Boolean(b) => Ok(b.to_string()),
+            _ => Err(anyhow!("invalid value for option {key} in configuration file")),
+        }
+    };
+    let mut args = Vec::new();
+    match (arg.get_action(), value) {
+        (ArgAction::SetTrue, toml::Value::Boolean(b)) => {
+            if *b {
+                args.push(format!("--{key}"));
+            }
+        },
+        (ArgAction::Count, toml::Value::Integer(n)) => {
+            for _ in 0..*n {
+                args.push(format!("--{key}"));
+            }
+        },
+        (ArgAction::SetTrue | ArgAction::Count, _) =>
+            return Err(anyhow!("invalid value for option {key} in configuration file")),
+        (ArgAction::Append, toml::Value::Array(values)) => {
+            for v in values {
+                args.push(format!("--{key}={}", scalar(v)?));
+            }
+        },
+        (_, v) => args.push(format!("--{key}={}", scalar(v)?)),
+    }
+    Ok(args)
+}
+
+// Returns the commandline arguments corresponding to the settings in the configuration file, for
+// the [default] table and for the selected profile. Settings for options that are present on the
+// commandline (or that conflict with an option present on the commandline) are omitted, so that
+// commandline values override configuration values.
+pub fn config_args(cmd: &Command, cmdline: &ArgMatches, profile: Option<&str>) -> Result<Vec<String>> {
+    let Some(path) = config_path().filter(|p| p.exists()) else {
+        if let Some(name) = profile {
+            return Err(anyhow!("can't find configuration file for profile {name}"));
+        }
+        return Ok(Vec::new());
+    };
+    let content = fs::read_to_string(&path)
+        .context("reading configuration file")?;
+    let config = content.parse::<toml::Table>()
+        .with_context(|| format!("parsing configuration file {}", path.display()))?;
+    let mut cmd = cmd.clone();
+    cmd.build();
+    let mut settings = toml::Table::new();
+    if let Some(defaults) = config.get("default") {
+        let defaults = defaults.as_table()
+            .ok_or_else(|| anyhow!("[default] in configuration file should be a table"))?;
+        settings.extend(defaults.clone());
+    }
+    if let Some(name) = profile {
+        let p = config.get("profile")
+            .and_then(|profiles| profiles.get(name))
+            .and_then(|p| p.as_table())
+            .ok_or_else(|| anyhow!("profile {name} not found in configuration file {}", path.display()))?;
+        settings.extend(p.clone());
+    }
+    let on_cmdline = |id: &str| cmdline.value_source(id) == Some(ValueSource::CommandLine);
+    let mut args = Vec::new();
+    for (key, value) in &settings {
+        let extra = setting_args(&cmd, key, value)?;
+        let arg = cmd.get_arguments()
+            .find(|a| a.get_long() == Some(key.as_str()))
+            .expect("option found in setting_args");
+        if on_cmdline(arg.get_id().as_str()) {
+            continue;
+        }
+        if cmd.get_arg_conflicts_with(arg).iter().any(|c| on_cmdline(c.get_id().as_str())) {
+            continue;
+        }
+        args.extend(extra);
+    }
+    Ok(args)
+}
+
+// Print the effective settings (merged from the configuration file and the commandline) in the
+// format of the configuration file. Arguments that were not present on the original commandline
+// are annotated as coming from the configuration file.
+pub fn print_config(cmd: &Command, merged: &ArgMatches, cmdline: &ArgMatches) {
+    let mut cmd = cmd.clone();
+    cmd.build();
+    if let Some(path) = config_path() {
+        println!("# Configuration file: {}", path.display());
+    }
+    for arg in cmd.get_arguments() {
+        let id = arg.get_id().as_str();
+        let Some(long) = arg.get_long() else { continue };
+        if NOT_CONFIGURABLE.contains(&long) ||
+            merged.value_source(id) != Some(ValueSource::CommandLine)
+        {
+            continue;
+        }
+        let value = match arg.get_action() {
+            ArgAction::SetTrue => String::from("true"),
+            ArgAction::Count => merged.get_count(id).to_string(),
+            ArgAction::Append => {
+                let values: Vec<toml::Value> = merged.get_raw(id)
+                    .into_iter()
+                    .flatten()
+                    .map(|v| toml::Value::String(v.to_string_lossy().to_string()))
+                    .collect();
+                toml::Value::Array(values).to_string()
+            },
+            _ => match merged.get_raw(id).and_then(|mut v| v.next()) {
+                Some(v) => toml::Value::String(v.to_string_lossy().to_string()).to_string(),
+                None => continue,
+            },
+        };
+        if cmdline.value_source(id) == Some(ValueSource::CommandLine) {
+            println!("{long} = {value}");
+        } else {
+            println!("{long} = {value}  # from configuration file");
+        }
+    }
+}
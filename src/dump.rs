@@ -0,0 +1,130 @@
+//! Describing the manifest and the streams that would be downloaded, as JSON (--dump-json)
+//
+// The JSON document contains a summary of the MPD, and for each Period the Representations that the
+// stream preferences on the commandline select (with the reasons for each choice), their resolved
+// segment URLs, an estimate of their size computed from their bandwidth and duration, and the
+// ContentProtection elements that apply to them. This lets scripts decide whether to download
+// without parsing our log messages. Nothing is downloaded apart from the manifest.
+
+
+use serde_json::{json, Value};
+use dash_mpd::{AdaptationSet, Representation, ContentProtection};
+use crate::formats::{content_type, drm_system_name};
+use crate::manifest::{Manifest, period_start, period_duration, representation_segments};
+use crate::selection::{StreamPreferences, select_representation};
+
+
+pub struct DumpOptions {
+    pub preferences: StreamPreferences,
+    pub video_only: bool,
+    pub audio_only: bool,
+    pub write_subs: bool,
+}
+
+fn content_protection_json(cp: &ContentProtection) -> Value {
+    json!({
+        "scheme_id_uri": cp.schemeIdUri,
+        "system": drm_system_name(&cp.schemeIdUri),
+        "value": cp.value,
+        "default_kid": cp.default_KID,
+    })
+}
+
+fn stream_json(
+    manifest: &Manifest,
+    period_idx: usize,
+    kind: &str,
+    adaptation: &AdaptationSet,
+    rep: &Representation,
+    reasons: &[String]) -> (Value, u64)
+{
+    let (segments, error) = match representation_segments(manifest, period_idx, adaptation, rep) {
+        Ok(s) => (s, None),
+        Err(e) => (Default::default(), Some(format!("{e:#}"))),
+    };
+    let media_duration: f64 = segments.media.iter().map(|s| s.duration).sum();
+    let duration = if media_duration > 0.0 {
+        media_duration
+    } else {
+        period_duration(&manifest.mpd, period_idx).unwrap_or(0.0)
+    };
+    let estimated_size = (rep.bandwidth.unwrap_or(0) as f64 * duration / 8.0).round() as u64;
+    let protection: Vec<Value> = adaptation.ContentProtection.iter()
+        .chain(rep.ContentProtection.iter())
+        .map(content_protection_json)
+        .collect();
+    let stream = json!({
+        "kind": kind,
+        "content_type": content_type(adaptation, rep),
+        "adaptation_id": adaptation.id,
+        "representation_id": rep.id,
+        "codecs": rep.codecs.as_ref().or(adaptation.codecs.as_ref()),
+        "bandwidth": rep.bandwidth,
+        "width": rep.width,
+        "height": rep.height,
+        "lang": adaptation.lang,
+        "roles": adaptation.Role.iter().filter_map(|r| r.value.as_ref()).collect::<Vec<_>>(),
+        "reasons": reasons,
+        "segments": {
+            "initialization": segments.init.as_ref().map(|s| s.url.as_str()),
+            "count": segments.media.len(),
+            "urls": segments.media.iter().map(|s| s.url.as_str()).collect::<Vec<_>>(),
+            "error": error,
+        },
+        "duration": duration,
+        "estimated_size": estimated_size,
+        "content_protection": protection,
+    });
+    (stream, estimated_size)
+}
+
+// The JSON description of the manifest and of the streams that would be downloaded.
+pub fn dump_json(manifest: &Manifest, opts: &DumpOptions) -> Value {
+    let mpd = &manifest.mpd;
+    let mut kinds = Vec::new();
+    if !opts.audio_only {
+        kinds.push("video");
+    }
+    if !opts.video_only {
+        kinds.push("audio");
+    }
+    if opts.write_subs {
+        kinds.push("text");
+    }
+    let mut total_size = 0;
+    let mut periods = Vec::new();
+    for (idx, period) in mpd.periods.iter().enumerate() {
+        let mut streams = Vec::new();
+        for kind in &kinds {
+            if let Some(choice) = select_representation(period, kind, &opts.preferences) {
+                let adaptation = &period.adaptations[choice.adaptation];
+                let rep = &adaptation.representations[choice.representation];
+                let (stream, size) = stream_json(manifest, idx, kind, adaptation, rep, &choice.reasons);
+                total_size += size;
+                streams.push(stream);
+            }
+        }
+        periods.push(json!({
+            "index": idx + 1,
+            "id": period.id,
+            "start": period_start(mpd, idx),
+            "duration": period_duration(mpd, idx),
+            "adaptation_sets": period.adaptations.len(),
+            "representations": period.adaptations.iter().map(|a| a.representations.len()).sum::<usize>(),
+            "selected": streams,
+        }));
+    }
+    let title = mpd.ProgramInformation.as_ref()
+        .and_then(|pi| pi.Title.as_ref())
+        .and_then(|t| t.content.as_ref());
+    json!({
+        "url": manifest.url.as_str(),
+        "type": mpd.mpdtype.as_deref().unwrap_or("static"),
+        "title": title,
+        "profiles": mpd.profiles,
+        "media_presentation_duration": mpd.mediaPresentationDuration.map(|d| d.as_secs_f64()),
+        "min_buffer_time": mpd.minBufferTime.map(|d| d.as_secs_f64()),
+        "periods": periods,
+        "estimated_size": total_size,
+    })
+}
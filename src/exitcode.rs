@@ -0,0 +1,122 @@
+//! Exit codes for the different classes of failure
+//
+// Each class of failure has its own exit code, so that calling scripts can distinguish for example
+// between a network failure that may be worth retrying later and a manifest that is broken. These
+// values are documented in the user manual, so should not be changed.
+
+
+use std::io::{self, ErrorKind};
+use dash_mpd::DashMpdError;
+
+
+// Download failure that isn't covered by one of the more specific codes below. Note that clap also
+// uses exit code 2 for commandline usage errors.
+pub const DOWNLOAD_FAILED: i32 = 2;
+// Not an error: we have listed the browsers that cookies can be read from.
+pub const LISTED_COOKIE_SOURCES: i32 = 3;
+pub const ROOT_CERTIFICATE_UNREADABLE: i32 = 5;
+pub const ROOT_CERTIFICATE_INVALID: i32 = 6;
+pub const CLIENT_CERTIFICATE_UNREADABLE: i32 = 7;
+pub const CLIENT_CERTIFICATE_INVALID: i32 = 8;
+pub const INVALID_BASE_URL: i32 = 9;
+// Network error or HTTP error status (worth retrying later).
+pub const NETWORK: i32 = 10;
+// Network timeout (worth retrying later).
+pub const NETWORK_TIMEOUT: i32 = 11;
+// The manifest is malformed (XML, duration or datetime parse errors).
+pub const PARSING: i32 = 12;
+// The manifest is for a live stream (dynamic MPD), and live streams are not enabled.
+pub const LIVE_STREAM: i32 = 13;
+// The manifest uses a media stream type or segment addressing scheme that we don't handle.
+pub const UNHANDLED_MEDIA_STREAM: i32 = 14;
+pub const MUXING: i32 = 15;
+pub const DECRYPTION: i32 = 16;
+// A helper application (ffmpeg, mkvmerge, mp4decrypt, xsltproc, etc.) is not installed.
+pub const MISSING_HELPER: i32 = 17;
+pub const IO: i32 = 18;
+
+
+// Whether the download failed because the manifest is dynamic and --enable-live-streams was not
+// specified.
+pub fn is_live_stream_refusal(e: &DashMpdError) -> bool {
+    e.to_string().contains("how to download dynamic MPD")
+}
+
+pub fn for_error(e: &DashMpdError) -> i32 {
+    if is_live_stream_refusal(e) {
+        return LIVE_STREAM;
+    }
+    match e {
+        DashMpdError::Network(_) | DashMpdError::NetworkConnect(_) => NETWORK,
+        DashMpdError::NetworkTimeout(_) => NETWORK_TIMEOUT,
+        DashMpdError::Parsing(_) |
+        DashMpdError::InvalidDuration(_) |
+        DashMpdError::InvalidDateTime(_) => PARSING,
+        DashMpdError::UnhandledMediaStream(_) => UNHANDLED_MEDIA_STREAM,
+        DashMpdError::Muxing(_) => MUXING,
+        DashMpdError::Decrypting(_) => DECRYPTION,
+        // dash-mpd reports a failure to run a helper application as an I/O error with a message of
+        // the form "spawning ffmpeg".
+        DashMpdError::Io(ioe, msg) => {
+            if ioe.kind() == ErrorKind::NotFound && msg.contains("spawning") {
+                MISSING_HELPER
+            } else {
+                IO
+            }
+        },
+        _ => DOWNLOAD_FAILED,
+    }
+}
+
+// The exit code for a set of downloads. When all the failed downloads failed for the same reason,
+// we use the corresponding exit code.
+pub fn for_errors<'a>(errors: impl IntoIterator<Item = &'a DashMpdError>) -> i32 {
+    let mut codes = errors.into_iter().map(for_error);
+    match codes.next() {
+        None => 0,
+        Some(first) => {
+            if codes.all(|c| c == first) {
+                first
+            } else {
+                DOWNLOAD_FAILED
+            }
+        },
+    }
+}
+
+// Convert an error from our own processing (for example fetching and rewriting the manifest) into a
+// DashMpdError, keeping the class of the underlying network, parsing or I/O error so that it is
+// reported with the corresponding exit code. The message includes the whole chain of context.
+pub fn classify(e: &anyhow::Error) -> DashMpdError {
+    let msg = format!("{e:#}");
+    for cause in e.chain() {
+        if let Some(re) = cause.downcast_ref::<reqwest::Error>() {
+            return if re.is_timeout() {
+                DashMpdError::NetworkTimeout(msg)
+            } else if re.is_connect() {
+                DashMpdError::NetworkConnect(msg)
+            } else {
+                DashMpdError::Network(msg)
+            };
+        }
+        if let Some(de) = cause.downcast_ref::<DashMpdError>() {
+            return match de {
+                DashMpdError::Network(_) => DashMpdError::Network(msg),
+                DashMpdError::NetworkTimeout(_) => DashMpdError::NetworkTimeout(msg),
+                DashMpdError::NetworkConnect(_) => DashMpdError::NetworkConnect(msg),
+                DashMpdError::Parsing(_) => DashMpdError::Parsing(msg),
+                DashMpdError::InvalidDuration(_) => DashMpdError::InvalidDuration(msg),
+                DashMpdError::InvalidDateTime(_) => DashMpdError::InvalidDateTime(msg),
+                DashMpdError::UnhandledMediaStream(_) => DashMpdError::UnhandledMediaStream(msg),
+                DashMpdError::Decrypting(_) => DashMpdError::Decrypting(msg),
+                DashMpdError::Muxing(_) => DashMpdError::Muxing(msg),
+                DashMpdError::Io(ioe, _) => DashMpdError::Io(io::Error::new(ioe.kind(), ioe.to_string()), msg),
+                _ => DashMpdError::Other(msg),
+            };
+        }
+        if let Some(ioe) = cause.downcast_ref::<io::Error>() {
+            return DashMpdError::Io(io::Error::new(ioe.kind(), ioe.to_string()), msg);
+        }
+    }
+    DashMpdError::Other(msg)
+}
@@ -0,0 +1,134 @@
+//! Reporting on the media segment that caused a download to fail (for --abort-on-error)
+//
+// Downloads with a resume journal (--continue) go through our resume proxy, which records the first
+// media segment request that failed and the reason for the failure. For other downloads, we look for
+// the URL of the failed request in the error returned by the DashDownloader, and request that segment
+// again to find out why it failed. The URL is located in the manifest to identify the Period,
+// AdaptationSet and Representation concerned. The resume journal tells us which segments had been
+// downloaded; without a journal, we know that the DashDownloader had fetched the preceding segments
+// of the same Representation. These are compared with what was actually written to the output file.
+
+
+use std::path::Path;
+use url::Url;
+use fs_err as fs;
+use tracing::error;
+use dash_mpd::DashMpdError;
+use crate::manifest::{ManifestFetcher, Segment, SegmentLocation, locate_segment};
+use crate::resume::ResumeSession;
+
+
+// All the http and https URLs mentioned in msg.
+fn urls_in(msg: &str) -> Vec<Url> {
+    msg.match_indices("http")
+        .filter_map(|(start, _)| {
+            let candidate = &msg[start..];
+            let end = candidate.find(|c: char| c.is_whitespace() || "\"'()<>".contains(c))
+                .unwrap_or(candidate.len());
+            Url::parse(candidate[..end].trim_end_matches([':', ',', '.', ';'])).ok()
+        })
+        .collect()
+}
+
+// The class of failure reported by the DashDownloader.
+fn failure_class(e: &DashMpdError) -> &'static str {
+    match e {
+        DashMpdError::Network(_) | DashMpdError::NetworkConnect(_) => "network error",
+        DashMpdError::NetworkTimeout(_) => "network timeout",
+        DashMpdError::InvalidMediaType(_) => "unexpected content type",
+        DashMpdError::Io(_, _) => "I/O error",
+        DashMpdError::Decrypting(_) => "decryption error",
+        DashMpdError::Muxing(_) => "muxing error",
+        _ => "download error",
+    }
+}
+
+// Request segment again, to find out why the DashDownloader failed to retrieve it.
+async fn probe_failure(fetcher: &ManifestFetcher, segment: &Segment) -> String {
+    let range = segment.byte_range.as_ref().map(|r| format!("bytes={r}"));
+    match fetcher.fetch_segment(segment.url.clone(), range.as_deref()).await {
+        Ok(response) if response.status().is_success() =>
+            String::from("the request succeeded when it was repeated, so the failure may have been transient"),
+        Ok(response) => {
+            let status = response.status();
+            format!("HTTP status {} {}", status.as_u16(), status.canonical_reason().unwrap_or(""))
+        },
+        Err(e) => format!("{e:#}"),
+    }
+}
+
+// Explain which media segment caused the download from the manifest at mpd_url to fail, how much had
+// been downloaded and how much was written to the output file.
+pub async fn report_failed_segment(
+    fetcher: &ManifestFetcher,
+    mpd_url: &str,
+    e: &DashMpdError,
+    session: Option<&ResumeSession>,
+    output: Option<&Path>)
+{
+    error!("Aborted at first error ({}): {e}", failure_class(e));
+    let failed = session.and_then(ResumeSession::failed_request);
+    if let Some(f) = &failed {
+        let range = f.range.as_ref().map(|r| format!(" ({r})")).unwrap_or_default();
+        error!("  The request for {}{range} failed: {}", f.url, f.reason);
+    }
+    let urls = match &failed {
+        Some(f) => vec![f.url.clone()],
+        None => urls_in(&e.to_string()),
+    };
+    if !urls.is_empty() {
+        match fetcher.fetch(mpd_url).await {
+            Ok(manifest) => {
+                let location = urls.iter().find_map(|url| locate_segment(&manifest, url));
+                match location {
+                    Some(loc) => {
+                        if failed.is_none() {
+                            let reason = probe_failure(fetcher, &loc.segment).await;
+                            error!("  The request for {} failed: {reason}", loc.segment.url);
+                        }
+                        report_location(&loc, session);
+                    },
+                    None => error!("  The failed request is not a media segment listed in the manifest."),
+                }
+            },
+            Err(_) => error!("  Unable to fetch the manifest to identify the failed segment."),
+        }
+    } else if session.is_none() {
+        error!("  The error doesn't identify the failed media segment; rerun with --continue to record it.");
+    }
+    if let Some(session) = session {
+        let (count, size) = session.saved_segments(None);
+        error!("  {count} segments ({size} octets) had been downloaded in total.");
+    }
+    if let Some(out) = output {
+        match fs::metadata(out) {
+            Ok(meta) => error!("  The output file {} has {} octets.", out.display(), meta.len()),
+            Err(_) => error!("  No output file was written."),
+        }
+    }
+}
+
+fn report_location(loc: &SegmentLocation, session: Option<&ResumeSession>) {
+    error!("  while fetching a media segment of:");
+    error!("  Period #{} (id {})", loc.period_idx + 1, loc.period_id.as_deref().unwrap_or("none"));
+    error!("  AdaptationSet #{} (id {})", loc.adaptation_idx + 1, loc.adaptation_id.as_deref().unwrap_or("none"));
+    error!("  Representation id {}", loc.representation_id.as_deref().unwrap_or("none"));
+    let range = match &loc.segment.byte_range {
+        Some(r) => format!(" (bytes {r})"),
+        None => String::new(),
+    };
+    if loc.segment_idx == 0 {
+        error!("  initialization segment {}{range}", loc.segment.url);
+    } else {
+        error!("  media segment {} of {}, starting {:.1}s into the Period, lasting {:.1}s: {}{range}",
+               loc.segment_idx, loc.segment_count, loc.segment.start, loc.segment.duration, loc.segment.url);
+    }
+    match session {
+        Some(session) => {
+            let (count, size) = session.saved_segments(Some(&loc.segment.url));
+            error!("  {count} segments of this Representation ({size} octets) had been downloaded.");
+        },
+        // The DashDownloader fetches the segments of a Representation in order.
+        None => error!("  The {} preceding segments of this Representation had been downloaded.", loc.segment_idx),
+    }
+}
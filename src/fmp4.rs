@@ -0,0 +1,591 @@
+//! Builtin muxer for fragmented MP4 streams (--muxer-preference mp4:builtin)
+//
+// The DashDownloader muxes the audio and video streams using an external application (ffmpeg, VLC,
+// mkvmerge or MP4Box). When none of these is available, for example in a minimal container, the
+// builtin muxer combines the streams without running an external process, provided that they are
+// fragmented MP4, which is the case for nearly all DASH content. The video and audio streams are
+// downloaded to separate files, and combined here with any wvtt or stpp subtitle track into a single
+// fragmented MP4 file:
+//
+//   - the moov box of the output contains the trak boxes of all the inputs, with new track ids, a
+//   trex box for each track in the mvex box, and the pssh boxes of the inputs;
+//
+//   - the fragments (a moof box followed by its mdat) of the inputs are copied unchanged apart from
+//   the track id in the tfhd box, the sequence number in the mfhd box and any explicit base data
+//   offset, and are interleaved in order of decode time.
+//
+// Boxes that describe the layout of the input files (sidx, ssix, mfra) and per-segment boxes (styp,
+// emsg, prft) are dropped. An input may contain several initialization segments, as happens when
+// Periods are concatenated, but only if they are identical: the builtin muxer can't describe a
+// change of codec parameters, so other inputs are rejected and should be muxed with ffmpeg.
+
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use fs_err::File;
+use tracing::info;
+use anyhow::{anyhow, Result, Context};
+
+
+pub type FourCC = [u8; 4];
+
+// The containers that the builtin muxer can produce.
+const CONTAINERS: [&str; 2] = ["mp4", "m4v"];
+
+// Check the use of "builtin" in the value of the --muxer-preference commandline option, which is
+// otherwise passed on to the DashDownloader.
+pub fn parse_muxer_preference(s: &str) -> Result<String, String> {
+    if let Some((container, ordering)) = s.split_once(':') {
+        let muxers: Vec<&str> = ordering.split(',').collect();
+        if let Some(pos) = muxers.iter().position(|m| *m == "builtin") {
+            if !CONTAINERS.contains(&container) {
+                return Err(format!("the builtin muxer only produces MP4 files, not {container}"));
+            }
+            if pos != 0 {
+                return Err(String::from("the builtin muxer must be the first in the ordering"));
+            }
+        }
+    }
+    Ok(s.to_string())
+}
+
+// Whether the builtin muxer comes first in a --muxer-preference ordering for the container (for
+// example "mp4:builtin"), or for any container if container is None.
+pub fn builtin_preferred<'a>(preferences: impl Iterator<Item = &'a String>, container: Option<&str>) -> bool {
+    preferences.filter_map(|mp| mp.split_once(':'))
+        .any(|(c, ordering)| container.is_none_or(|want| want.eq_ignore_ascii_case(c)) &&
+             ordering.split(',').next() == Some("builtin"))
+}
+
+// A box within a byte buffer: its type, and the offsets of its start, its payload and its end.
+#[derive(Debug, Clone, Copy)]
+pub struct BoxRange {
+    pub kind: FourCC,
+    pub start: usize,
+    pub payload: usize,
+    pub end: usize,
+}
+
+// The child boxes contained in data.
+pub fn child_boxes(data: &[u8]) -> Result<Vec<BoxRange>> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let mut size = u64::from(read_u32(data, pos));
+        let kind: FourCC = data[pos+4..pos+8].try_into().unwrap();
+        let mut header = 8;
+        if size == 1 {
+            if pos + 16 > data.len() {
+                return Err(anyhow!("truncated {} box", fourcc(&kind)));
+            }
+            size = read_u64(data, pos + 8);
+            header = 16;
+        } else if size == 0 {
+            size = (data.len() - pos) as u64;
+        }
+        if size < header as u64 || pos as u64 + size > data.len() as u64 {
+            return Err(anyhow!("invalid size for {} box", fourcc(&kind)));
+        }
+        let end = pos + size as usize;
+        boxes.push(BoxRange { kind, start: pos, payload: pos + header, end });
+        pos = end;
+    }
+    Ok(boxes)
+}
+
+pub fn find_child(data: &[u8], kind: &FourCC) -> Result<Option<BoxRange>> {
+    Ok(child_boxes(data)?.into_iter().find(|b| &b.kind == kind))
+}
+
+pub fn fourcc(kind: &FourCC) -> String {
+    String::from_utf8_lossy(kind).to_string()
+}
+
+pub fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos+4].try_into().unwrap())
+}
+
+pub fn read_u64(data: &[u8], pos: usize) -> u64 {
+    u64::from_be_bytes(data[pos..pos+8].try_into().unwrap())
+}
+
+fn write_u32(data: &mut [u8], pos: usize, value: u32) {
+    data[pos..pos+4].copy_from_slice(&value.to_be_bytes());
+}
+
+fn write_u64(data: &mut [u8], pos: usize, value: u64) {
+    data[pos..pos+8].copy_from_slice(&value.to_be_bytes());
+}
+
+fn make_box(kind: &FourCC, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 16);
+    let size = payload.len() as u64 + 8;
+    if size > u64::from(u32::MAX) {
+        out.extend_from_slice(&1u32.to_be_bytes());
+        out.extend_from_slice(kind);
+        out.extend_from_slice(&(size + 8).to_be_bytes());
+    } else {
+        out.extend_from_slice(&(size as u32).to_be_bytes());
+        out.extend_from_slice(kind);
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+// Check that a full box is large enough to contain len bytes of payload after its version and
+// flags, returning its version.
+pub fn full_box_version(data: &[u8], b: &BoxRange, len: usize) -> Result<u8> {
+    if b.payload + 4 + len > b.end {
+        return Err(anyhow!("truncated {} box", fourcc(&b.kind)));
+    }
+    Ok(data[b.payload])
+}
+
+// Read a 32-bit field of a full box, which is at a different offset in version 0 and version 1 boxes.
+pub fn versioned_u32(data: &[u8], b: &BoxRange, v0_offset: usize, v1_offset: usize) -> Result<u32> {
+    let version = full_box_version(data, b, 0)?;
+    let at = b.payload + if version == 1 { v1_offset } else { v0_offset };
+    if at + 4 > b.end {
+        return Err(anyhow!("truncated {} box", fourcc(&b.kind)));
+    }
+    Ok(read_u32(data, at))
+}
+
+// A track of an input file.
+#[derive(Debug, Clone)]
+struct Track {
+    id: u32,
+    timescale: u32,
+    // The trak box, and the trex box from the mvex box if present.
+    trak: Vec<u8>,
+    trex: Option<Vec<u8>>,
+}
+
+// The initialization data of an input file, from its moov box.
+#[derive(Debug, Clone, Default)]
+struct Movie {
+    timescale: u32,
+    mvhd: Vec<u8>,
+    // The duration of the presentation from the mehd box, in seconds.
+    fragment_duration: Option<f64>,
+    tracks: Vec<Track>,
+    pssh: Vec<Vec<u8>>,
+    // Other boxes of the moov, such as udta.
+    other: Vec<Vec<u8>>,
+}
+
+fn parse_moov(moov: &[u8]) -> Result<Movie> {
+    let mut movie = Movie::default();
+    let mut mehd_duration = None;
+    let mut trex: HashMap<u32, Vec<u8>> = HashMap::new();
+    for b in child_boxes(moov)? {
+        let bytes = moov[b.start..b.end].to_vec();
+        match &b.kind {
+            b"mvhd" => {
+                movie.timescale = versioned_u32(moov, &b, 12, 20)?;
+                movie.mvhd = bytes;
+            },
+            b"trak" => movie.tracks.push(parse_trak(&moov[b.payload..b.end], bytes)?),
+            b"mvex" => {
+                let mvex = &moov[b.payload..b.end];
+                for c in child_boxes(mvex)? {
+                    match &c.kind {
+                        b"trex" => {
+                            full_box_version(mvex, &c, 4)?;
+                            trex.insert(read_u32(mvex, c.payload + 4), mvex[c.start..c.end].to_vec());
+                        },
+                        b"mehd" => {
+                            let version = full_box_version(mvex, &c, 4)?;
+                            mehd_duration = Some(if version == 1 {
+                                full_box_version(mvex, &c, 8)?;
+                                read_u64(mvex, c.payload + 4)
+                            } else {
+                                u64::from(read_u32(mvex, c.payload + 4))
+                            });
+                        },
+                        _ => (),
+                    }
+                }
+            },
+            b"pssh" => movie.pssh.push(bytes),
+            _ => movie.other.push(bytes),
+        }
+    }
+    if movie.mvhd.is_empty() {
+        return Err(anyhow!("no mvhd box in moov"));
+    }
+    if movie.tracks.is_empty() {
+        return Err(anyhow!("no trak box in moov"));
+    }
+    for track in &mut movie.tracks {
+        track.trex = trex.remove(&track.id);
+    }
+    if movie.timescale > 0 {
+        movie.fragment_duration = mehd_duration.map(|d| d as f64 / f64::from(movie.timescale));
+    }
+    Ok(movie)
+}
+
+fn parse_trak(trak: &[u8], bytes: Vec<u8>) -> Result<Track> {
+    let tkhd = find_child(trak, b"tkhd")?
+        .ok_or_else(|| anyhow!("no tkhd box in trak"))?;
+    let id = versioned_u32(trak, &tkhd, 12, 20)?;
+    let mut timescale = 0;
+    if let Some(mdia) = find_child(trak, b"mdia")? {
+        let mdia = &trak[mdia.payload..mdia.end];
+        if let Some(mdhd) = find_child(mdia, b"mdhd")? {
+            timescale = versioned_u32(mdia, &mdhd, 12, 20)?;
+        }
+    }
+    Ok(Track { id, timescale, trak: bytes, trex: None })
+}
+
+// A fragment of an input file: a moof box and the boxes that follow it up to the next fragment,
+// which contain its media data.
+#[derive(Debug, Clone)]
+struct Fragment {
+    offset: u64,
+    end: u64,
+    moof: Vec<u8>,
+    // The decode time of the fragment in seconds, from the tfdt box of its first track fragment.
+    time: f64,
+}
+
+// The boxes that may follow a moof box within a fragment.
+fn is_fragment_data(kind: &FourCC) -> bool {
+    matches!(kind, b"mdat" | b"free" | b"skip")
+}
+
+struct Input {
+    path: PathBuf,
+    ftyp: Option<Vec<u8>>,
+    movie: Movie,
+    fragments: Vec<Fragment>,
+}
+
+pub fn read_box_header(file: &mut File, offset: u64, len: u64) -> Result<Option<(FourCC, u64, u64)>> {
+    if offset + 8 > len {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut header = [0u8; 16];
+    file.read_exact(&mut header[..8])?;
+    let kind: FourCC = header[4..8].try_into().unwrap();
+    let mut size = u64::from(read_u32(&header, 0));
+    let mut header_len = 8;
+    if size == 1 {
+        file.read_exact(&mut header[8..16])?;
+        size = read_u64(&header, 8);
+        header_len = 16;
+    } else if size == 0 {
+        size = len - offset;
+    }
+    if size < header_len || offset + size > len {
+        return Err(anyhow!("invalid size for {} box at offset {offset}", fourcc(&kind)));
+    }
+    Ok(Some((kind, header_len, size)))
+}
+
+pub fn read_range(file: &mut File, offset: u64, size: u64) -> Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0u8; size as usize];
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// The decode time in seconds of the first track fragment of a moof box.
+fn fragment_time(moof: &[u8], timescales: &HashMap<u32, u32>) -> Result<Option<f64>> {
+    let moof_box = child_boxes(moof)?.into_iter().next()
+        .ok_or_else(|| anyhow!("empty moof box"))?;
+    let content = &moof[moof_box.payload..moof_box.end];
+    let Some(traf) = find_child(content, b"traf")? else {
+        return Ok(None);
+    };
+    let traf = &content[traf.payload..traf.end];
+    let (Some(tfhd), Some(tfdt)) = (find_child(traf, b"tfhd")?, find_child(traf, b"tfdt")?) else {
+        return Ok(None);
+    };
+    full_box_version(traf, &tfhd, 4)?;
+    let id = read_u32(traf, tfhd.payload + 4);
+    let version = full_box_version(traf, &tfdt, 4)?;
+    let decode_time = if version == 1 {
+        full_box_version(traf, &tfdt, 8)?;
+        read_u64(traf, tfdt.payload + 4)
+    } else {
+        u64::from(read_u32(traf, tfdt.payload + 4))
+    };
+    Ok(timescales.get(&id)
+       .filter(|ts| **ts > 0)
+       .map(|ts| decode_time as f64 / f64::from(*ts)))
+}
+
+fn read_input(path: &Path) -> Result<Input> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut ftyp = None;
+    let mut movie: Option<Movie> = None;
+    let mut timescales = HashMap::new();
+    let mut fragments: Vec<Fragment> = Vec::new();
+    // The first moov box, which any later moov box must repeat.
+    let mut first_moov = Vec::new();
+    let mut offset = 0;
+    // Whether the previous box belongs to a fragment, so that the current box can extend it.
+    let mut in_fragment = false;
+    while let Some((kind, header_len, size)) = read_box_header(&mut file, offset, len)? {
+        match &kind {
+            b"ftyp" if ftyp.is_none() => ftyp = Some(read_range(&mut file, offset, size)?),
+            b"moov" => {
+                let moov = read_range(&mut file, offset, size)?;
+                if movie.is_none() {
+                    let m = parse_moov(&moov[header_len as usize..])
+                        .with_context(|| format!("reading initialization data of {}", path.display()))?;
+                    timescales = m.tracks.iter().map(|t| (t.id, t.timescale)).collect();
+                    movie = Some(m);
+                    first_moov = moov;
+                } else if moov != first_moov {
+                    return Err(anyhow!("{} contains several different initialization segments, which the builtin \
+                                        muxer can't combine (use another muxer with --muxer-preference)",
+                                       path.display()));
+                }
+            },
+            b"moof" => {
+                if movie.is_none() {
+                    return Err(anyhow!("{}: media fragment before the moov box", path.display()));
+                }
+                let moof = read_range(&mut file, offset, size)?;
+                // Without a tfdt box, a fragment follows the previous one of the same file.
+                let time = fragment_time(&moof, &timescales)?
+                    .or(fragments.last().map(|f| f.time))
+                    .unwrap_or(0.0);
+                fragments.push(Fragment { offset, end: offset + size, moof, time });
+                in_fragment = true;
+                offset += size;
+                continue;
+            },
+            k if in_fragment && is_fragment_data(k) => {
+                if let Some(f) = fragments.last_mut() {
+                    f.end = offset + size;
+                }
+                offset += size;
+                continue;
+            },
+            _ => (),
+        }
+        in_fragment = false;
+        offset += size;
+    }
+    let Some(movie) = movie else {
+        return Err(anyhow!("{} is not an MP4 file (no moov box)", path.display()));
+    };
+    if fragments.is_empty() {
+        return Err(anyhow!("{} is not a fragmented MP4 file (no moof box)", path.display()));
+    }
+    Ok(Input { path: path.to_path_buf(), ftyp, movie, fragments })
+}
+
+// Copy a trak box, replacing its track id and dropping any tref box (whose references are to the
+// track ids of the input file).
+fn renumber_trak(trak: &[u8], id: u32) -> Result<Vec<u8>> {
+    let b = child_boxes(trak)?.into_iter().next()
+        .ok_or_else(|| anyhow!("empty trak box"))?;
+    let content = &trak[b.payload..b.end];
+    let mut payload = Vec::with_capacity(content.len());
+    for c in child_boxes(content)? {
+        let mut bytes = content[c.start..c.end].to_vec();
+        match &c.kind {
+            b"tref" => continue,
+            b"tkhd" => {
+                versioned_u32(content, &c, 12, 20)?;
+                let at = c.payload - c.start + if content[c.payload] == 1 { 20 } else { 12 };
+                write_u32(&mut bytes, at, id);
+            },
+            _ => (),
+        }
+        payload.extend_from_slice(&bytes);
+    }
+    Ok(make_box(b"trak", &payload))
+}
+
+fn trex_box(trex: Option<&[u8]>, id: u32) -> Vec<u8> {
+    match trex {
+        Some(trex) => {
+            let mut bytes = trex.to_vec();
+            let header = if read_u32(trex, 0) == 1 { 16 } else { 8 };
+            write_u32(&mut bytes, header + 4, id);
+            bytes
+        },
+        None => {
+            // Version and flags, track id, default sample description index, duration, size and flags.
+            let mut payload = vec![0u8; 24];
+            write_u32(&mut payload, 4, id);
+            write_u32(&mut payload, 8, 1);
+            make_box(b"trex", &payload)
+        },
+    }
+}
+
+// For each input, the mapping from its track ids to the track ids of the output.
+type TrackIds = Vec<HashMap<u32, u32>>;
+
+// The moov box of the output file, and the new track ids.
+fn output_moov(inputs: &[Input]) -> Result<(Vec<u8>, TrackIds)> {
+    let first = &inputs[0].movie;
+    let mut traks = Vec::new();
+    let mut trexs = Vec::new();
+    let mut pssh: Vec<Vec<u8>> = Vec::new();
+    let mut id_maps = Vec::new();
+    let mut next_id = 1;
+    for input in inputs {
+        let mut map = HashMap::new();
+        for track in &input.movie.tracks {
+            map.insert(track.id, next_id);
+            traks.extend(renumber_trak(&track.trak, next_id)?);
+            trexs.extend(trex_box(track.trex.as_deref(), next_id));
+            next_id += 1;
+        }
+        for p in &input.movie.pssh {
+            if !pssh.contains(p) {
+                pssh.push(p.clone());
+            }
+        }
+        id_maps.push(map);
+    }
+    let mut mvhd = first.mvhd.clone();
+    let len = mvhd.len();
+    write_u32(&mut mvhd, len - 4, next_id);
+    let mut mvex = Vec::new();
+    let duration = inputs.iter()
+        .filter_map(|i| i.movie.fragment_duration)
+        .fold(None, |acc: Option<f64>, d| Some(acc.map_or(d, |a| a.max(d))));
+    if let Some(duration) = duration {
+        let ticks = (duration * f64::from(first.timescale)).round() as u64;
+        let mut payload = Vec::new();
+        if ticks > u64::from(u32::MAX) {
+            payload.extend_from_slice(&[1, 0, 0, 0]);
+            payload.extend_from_slice(&ticks.to_be_bytes());
+        } else {
+            payload.extend_from_slice(&[0, 0, 0, 0]);
+            payload.extend_from_slice(&(ticks as u32).to_be_bytes());
+        }
+        mvex.extend(make_box(b"mehd", &payload));
+    }
+    mvex.extend(trexs);
+    let mut payload = mvhd;
+    payload.extend(traks);
+    payload.extend(make_box(b"mvex", &mvex));
+    for p in pssh {
+        payload.extend(p);
+    }
+    for other in &first.other {
+        payload.extend_from_slice(other);
+    }
+    Ok((make_box(b"moov", &payload), id_maps))
+}
+
+// Patch a moof box for its position in the output file: the sequence number, the track ids and any
+// explicit base data offset, which is an absolute position in the file.
+fn patch_moof(moof: &mut [u8], sequence: u32, ids: &HashMap<u32, u32>, old_offset: u64, new_offset: u64) -> Result<()> {
+    let outer = child_boxes(moof)?.into_iter().next()
+        .ok_or_else(|| anyhow!("empty moof box"))?;
+    for c in child_boxes(&moof[outer.payload..outer.end])? {
+        let payload = outer.payload + c.payload;
+        let end = outer.payload + c.end;
+        match &c.kind {
+            b"mfhd" => {
+                if payload + 8 > end {
+                    return Err(anyhow!("truncated mfhd box"));
+                }
+                write_u32(moof, payload + 4, sequence);
+            },
+            b"traf" => {
+                let traf = child_boxes(&moof[payload..end])?;
+                let tfhd = traf.iter().find(|b| &b.kind == b"tfhd")
+                    .ok_or_else(|| anyhow!("no tfhd box in traf"))?;
+                let at = payload + tfhd.payload;
+                if at + 8 > payload + tfhd.end {
+                    return Err(anyhow!("truncated tfhd box"));
+                }
+                let flags = read_u32(moof, at) & 0x00ff_ffff;
+                let old_id = read_u32(moof, at + 4);
+                let new_id = ids.get(&old_id)
+                    .ok_or_else(|| anyhow!("media fragment for unknown track {old_id}"))?;
+                write_u32(moof, at + 4, *new_id);
+                if flags & 0x01 != 0 {
+                    if at + 16 > payload + tfhd.end {
+                        return Err(anyhow!("truncated tfhd box"));
+                    }
+                    let base = read_u64(moof, at + 8);
+                    let relative = base.checked_sub(old_offset)
+                        .ok_or_else(|| anyhow!("base data offset before the start of its fragment"))?;
+                    write_u64(moof, at + 8, new_offset + relative);
+                }
+            },
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn default_ftyp() -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(b"iso6");
+    payload.extend_from_slice(&0u32.to_be_bytes());
+    for brand in [b"iso6", b"mp41", b"dash"] {
+        payload.extend_from_slice(brand);
+    }
+    make_box(b"ftyp", &payload)
+}
+
+// Combine the fragmented MP4 files in inputs into a single fragmented MP4 file at output, with the
+// tracks in the order of the inputs.
+pub fn mux_fragmented(inputs: &[PathBuf], output: &Path) -> Result<()> {
+    if inputs.is_empty() {
+        return Err(anyhow!("no streams to mux"));
+    }
+    let inputs: Vec<Input> = inputs.iter()
+        .map(|p| read_input(p))
+        .collect::<Result<_>>()?;
+    let (moov, id_maps) = output_moov(&inputs)?;
+    let mut out = BufWriter::new(File::create(output)?);
+    let ftyp = inputs.iter().find_map(|i| i.ftyp.clone()).unwrap_or_else(default_ftyp);
+    out.write_all(&ftyp)?;
+    out.write_all(&moov)?;
+    let mut position = (ftyp.len() + moov.len()) as u64;
+    let mut files = inputs.iter()
+        .map(|i| File::open(&i.path))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    // Merge the fragments of the inputs in order of decode time, keeping the order of the fragments
+    // of each input.
+    let mut next = vec![0; inputs.len()];
+    let mut sequence = 1;
+    loop {
+        let candidate = inputs.iter().enumerate()
+            .filter_map(|(i, input)| input.fragments.get(next[i]).map(|f| (i, f.time)))
+            .fold(None, |best: Option<(usize, f64)>, (i, t)| match best {
+                Some((_, bt)) if bt <= t => best,
+                _ => Some((i, t)),
+            });
+        let Some((i, _)) = candidate else { break };
+        let fragment = &inputs[i].fragments[next[i]];
+        next[i] += 1;
+        let mut moof = fragment.moof.clone();
+        patch_moof(&mut moof, sequence, &id_maps[i], fragment.offset, position)
+            .with_context(|| format!("media fragment at offset {} of {}", fragment.offset, inputs[i].path.display()))?;
+        out.write_all(&moof)?;
+        let data_start = fragment.offset + moof.len() as u64;
+        let data_len = fragment.end - data_start;
+        let file = &mut files[i];
+        file.seek(SeekFrom::Start(data_start))?;
+        let copied = std::io::copy(&mut Read::by_ref(file).take(data_len), &mut out)?;
+        if copied != data_len {
+            return Err(anyhow!("{} is truncated", inputs[i].path.display()));
+        }
+        position += moof.len() as u64 + data_len;
+        sequence += 1;
+    }
+    out.flush()?;
+    let tracks: usize = id_maps.iter().map(HashMap::len).sum();
+    info!("Muxed {tracks} tracks into {} with the builtin muxer", output.display());
+    Ok(())
+}
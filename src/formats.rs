@@ -0,0 +1,251 @@
+//! Listing the Representations available in a manifest (--list-formats)
+//
+// Prints a table of every Representation in the manifest, grouped by Period, with the attributes
+// that are useful for choosing values for --want-video-id, --prefer-video-codecs,
+// --prefer-audio-language and so on. Attributes that are specified on the AdaptationSet apply to
+// each of its Representations. The same information is available in JSON format for use by other
+// tools, as one JSON object per manifest.
+
+
+use std::io::{self, Write};
+use serde_json::{json, Value};
+use dash_mpd::{AdaptationSet, Representation, ContentProtection};
+use crate::manifest::{Manifest, period_start, period_duration};
+
+
+// The DRM systems identified by the UUID in ContentProtection@schemeIdUri.
+const DRM_SYSTEMS: [(&str, &str); 7] = [
+    ("edef8ba9-79d6-4ace-a3c8-27dcd51d21ed", "Widevine"),
+    ("9a04f079-9840-4286-ab92-e65be0885f95", "PlayReady"),
+    ("94ce86fb-07ff-4f43-adb8-93d2fa968ca2", "FairPlay"),
+    ("e2719d58-a985-b3c9-781a-b030af78d30e", "ClearKey"),
+    ("1077efec-c0b2-4d02-ace3-3c1e52e2fb4b", "ClearKey"),
+    ("f239e769-efa3-4850-9c16-a903c6932efb", "PrimeTime"),
+    ("3d5e6d35-9b9a-41e8-b843-dd3c6e72c42c", "ChinaDRM"),
+];
+
+// The name of the DRM system identified by a ContentProtection@schemeIdUri, or None for the generic
+// mp4protection scheme (which only specifies the encryption scheme) and unknown systems.
+pub fn drm_system_name(scheme_id_uri: &str) -> Option<&'static str> {
+    let uuid = scheme_id_uri.to_lowercase();
+    let uuid = uuid.strip_prefix("urn:uuid:")?;
+    DRM_SYSTEMS.iter()
+        .find(|(id, _)| *id == uuid)
+        .map(|(_, name)| *name)
+}
+
+#[derive(Debug, Clone)]
+pub struct Format {
+    // Index of the Period in the manifest, counting from 1.
+    pub period: usize,
+    pub adaptation_id: Option<String>,
+    pub id: Option<String>,
+    pub content_type: String,
+    pub codecs: Option<String>,
+    pub bandwidth: Option<u64>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub frame_rate: Option<f64>,
+    pub lang: Option<String>,
+    pub roles: Vec<String>,
+    pub label: Option<String>,
+    pub audio_channels: Option<String>,
+    // The DRM systems, and the encryption scheme ("cenc" or "cbcs") if it is signalled.
+    pub drm: Vec<String>,
+}
+
+// The content type of a Representation: "video", "audio", "text", "image" or "unknown".
+pub fn content_type(adaptation: &AdaptationSet, rep: &Representation) -> String {
+    if let Some(ct) = &adaptation.contentType {
+        return ct.clone();
+    }
+    let mime = rep.mimeType.as_ref().or(adaptation.mimeType.as_ref());
+    if let Some((major, minor)) = mime.and_then(|m| m.split_once('/')) {
+        if major == "application" && (minor.contains("ttml") || minor.contains("vtt")) {
+            return String::from("text");
+        }
+        if major != "application" {
+            return major.to_string();
+        }
+    }
+    let codecs = rep.codecs.as_ref().or(adaptation.codecs.as_ref());
+    if codecs.is_some_and(|c| c.starts_with("stpp") || c.starts_with("wvtt")) {
+        return String::from("text");
+    }
+    String::from("unknown")
+}
+
+// Parse a frame rate in the format of the @frameRate attribute: "25" or "30000/1001".
+fn parse_frame_rate(fr: &str) -> Option<f64> {
+    match fr.split_once('/') {
+        Some((num, den)) => {
+            let den = den.trim().parse::<f64>().ok()?;
+            (den > 0.0).then_some(num.trim().parse::<f64>().ok()? / den)
+        },
+        None => fr.trim().parse().ok(),
+    }
+}
+
+fn drm_description(protections: &[&ContentProtection]) -> Vec<String> {
+    let mut drm: Vec<String> = Vec::new();
+    for cp in protections {
+        let name = match drm_system_name(&cp.schemeIdUri) {
+            Some(name) => name.to_string(),
+            None if cp.schemeIdUri.eq_ignore_ascii_case("urn:mpeg:dash:mp4protection:2011") => {
+                cp.value.clone().unwrap_or_else(|| String::from("cenc"))
+            },
+            None => cp.schemeIdUri.clone(),
+        };
+        if !drm.contains(&name) {
+            drm.push(name);
+        }
+    }
+    drm
+}
+
+pub fn representation_format(period: usize, adaptation: &AdaptationSet, rep: &Representation) -> Format {
+    let roles = adaptation.Role.iter()
+        .filter_map(|r| r.value.clone())
+        .collect();
+    let channels = rep.AudioChannelConfiguration.first()
+        .or(adaptation.AudioChannelConfiguration.first())
+        .and_then(|acc| acc.value.clone());
+    let protections: Vec<&ContentProtection> = adaptation.ContentProtection.iter()
+        .chain(rep.ContentProtection.iter())
+        .collect();
+    Format {
+        period,
+        adaptation_id: adaptation.id.clone(),
+        id: rep.id.clone(),
+        content_type: content_type(adaptation, rep),
+        codecs: rep.codecs.clone().or(adaptation.codecs.clone()),
+        bandwidth: rep.bandwidth,
+        width: rep.width,
+        height: rep.height,
+        frame_rate: rep.frameRate.as_ref()
+            .or(adaptation.frameRate.as_ref())
+            .and_then(|fr| parse_frame_rate(fr)),
+        lang: adaptation.lang.clone(),
+        roles,
+        label: adaptation.Label.first().map(|l| l.content.clone()),
+        audio_channels: channels,
+        drm: drm_description(&protections),
+    }
+}
+
+// Every Representation in the manifest, in manifest order.
+pub fn manifest_formats(manifest: &Manifest) -> Vec<Format> {
+    let mut formats = Vec::new();
+    for (idx, period) in manifest.mpd.periods.iter().enumerate() {
+        for adaptation in &period.adaptations {
+            for rep in &adaptation.representations {
+                formats.push(representation_format(idx + 1, adaptation, rep));
+            }
+        }
+    }
+    formats
+}
+
+fn format_bandwidth(bps: u64) -> String {
+    if bps >= 1_000_000 {
+        format!("{:.1}M", bps as f64 / 1_000_000.0)
+    } else {
+        format!("{}k", bps / 1000)
+    }
+}
+
+fn format_frame_rate(fps: f64) -> String {
+    if (fps - fps.round()).abs() < 0.001 {
+        format!("{fps:.0}")
+    } else {
+        format!("{fps:.3}")
+    }
+}
+
+fn table_row(f: &Format) -> Vec<String> {
+    let or_dash = |v: Option<String>| v.unwrap_or_else(|| String::from("-"));
+    let resolution = match (f.width, f.height) {
+        (Some(w), Some(h)) => Some(format!("{w}x{h}")),
+        (None, Some(h)) => Some(format!("{h}p")),
+        _ => None,
+    };
+    vec![
+        f.period.to_string(),
+        or_dash(f.adaptation_id.clone()),
+        or_dash(f.id.clone()),
+        f.content_type.clone(),
+        or_dash(f.codecs.clone()),
+        or_dash(f.bandwidth.map(format_bandwidth)),
+        or_dash(resolution),
+        or_dash(f.frame_rate.map(format_frame_rate)),
+        or_dash(f.lang.clone()),
+        or_dash((!f.roles.is_empty()).then(|| f.roles.join(","))),
+        or_dash(f.label.clone()),
+        or_dash(f.audio_channels.clone()),
+        or_dash((!f.drm.is_empty()).then(|| f.drm.join(","))),
+    ]
+}
+
+const TABLE_HEADER: [&str; 13] = [
+    "PERIOD", "ADAPT", "ID", "TYPE", "CODECS", "BANDWIDTH", "RESOLUTION", "FPS", "LANG", "ROLE",
+    "LABEL", "CHANNELS", "DRM"];
+
+// Print the formats as a table, with columns aligned to the widest value.
+pub fn write_format_table(out: &mut impl Write, formats: &[Format]) -> io::Result<()> {
+    let rows: Vec<Vec<String>> = formats.iter().map(table_row).collect();
+    let mut widths: Vec<usize> = TABLE_HEADER.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let header: Vec<String> = TABLE_HEADER.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row.iter().zip(&widths)
+            .map(|(cell, w)| format!("{cell:<w$}"))
+            .collect();
+        writeln!(out, "{}", line.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+fn format_json(f: &Format) -> Value {
+    json!({
+        "adaptation_id": f.adaptation_id,
+        "id": f.id,
+        "content_type": f.content_type,
+        "codecs": f.codecs,
+        "bandwidth": f.bandwidth,
+        "width": f.width,
+        "height": f.height,
+        "frame_rate": f.frame_rate,
+        "lang": f.lang,
+        "roles": f.roles,
+        "label": f.label,
+        "audio_channels": f.audio_channels,
+        "drm": f.drm,
+    })
+}
+
+// The formats of the manifest as a JSON object, with the formats grouped by Period.
+pub fn formats_json(manifest: &Manifest) -> Value {
+    let mpd = &manifest.mpd;
+    let formats = manifest_formats(manifest);
+    let periods: Vec<Value> = mpd.periods.iter().enumerate()
+        .map(|(idx, period)| json!({
+            "index": idx + 1,
+            "id": period.id,
+            "start": period_start(mpd, idx),
+            "duration": period_duration(mpd, idx),
+            "formats": formats.iter()
+                .filter(|f| f.period == idx + 1)
+                .map(format_json)
+                .collect::<Vec<Value>>(),
+        }))
+        .collect();
+    json!({
+        "url": manifest.url.as_str(),
+        "type": mpd.mpdtype.as_deref().unwrap_or("static"),
+        "periods": periods,
+    })
+}
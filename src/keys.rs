@@ -0,0 +1,160 @@
+//! Decryption keys (--key, --key-file)
+//
+// Decryption keys are given on the commandline with --key KID:KEY, or in a key file with --key-file,
+// which keeps them out of the shell history and of the process list. A key file contains either one
+// KID:KEY pair per line (blank lines and lines starting with # are ignored), or a JSON object that
+// maps KIDs to keys, such as a keystore exported from a license server. Keys are validated strictly:
+// a malformed KID or KEY is an error that identifies the line on which it appears.
+//
+// A key file may contain keys for many manifests. Before the download starts, we warn about any KID
+// that appears as the cenc:default_KID of a ContentProtection element of the selected streams and
+// for which we have no key (unless keys are given for a track id, which may cover any KID). All the
+// keys are passed on to the decryption application, because the default_KID doesn't necessarily
+// cover every KID of a stream (with key rotation, or KIDs that are only signalled in the media).
+
+
+use std::collections::HashMap;
+use fs_err as fs;
+use tracing::warn;
+use serde_json::Value;
+use dash_mpd::MPD;
+use crate::selection::{StreamPreferences, select_representation};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+    // A 128-bit KID as 32 lowercase hexadecimal characters, or a track id in decimal.
+    pub kid: String,
+    // 32 lowercase hexadecimal characters.
+    pub key: String,
+}
+
+impl Key {
+    // Track ids are 32-bit integers, so have fewer than 32 digits.
+    pub fn is_track_id(&self) -> bool {
+        self.kid.len() != 32
+    }
+}
+
+// A KID written as 32 hexadecimal characters, optionally in the hyphenated UUID form used by
+// cenc:default_KID, in lowercase without hyphens.
+pub fn normalize_kid(kid: &str) -> Option<String> {
+    let hex: String = kid.trim().chars().filter(|c| *c != '-').collect();
+    (hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| hex.to_ascii_lowercase())
+}
+
+fn parse_kid_key(kid: &str, key: &str) -> Result<Key, String> {
+    let kid = kid.trim();
+    let kid = match normalize_kid(kid) {
+        Some(kid) => kid,
+        None if kid.chars().all(|c| c.is_ascii_digit()) && kid.parse::<u32>().is_ok() => kid.to_string(),
+        None => return Err(format!("invalid KID {kid} (should be 32 hexadecimal characters or a track id)")),
+    };
+    let key = key.trim();
+    if key.len() != 32 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid KEY for KID {kid} (should be 32 hexadecimal characters)"));
+    }
+    Ok(Key { kid, key: key.to_ascii_lowercase() })
+}
+
+// Parse the value of the --key commandline option.
+pub fn parse_key(s: &str) -> Result<Key, String> {
+    let (kid, key) = s.split_once(':')
+        .ok_or_else(|| String::from("expecting KID:KEY"))?;
+    parse_kid_key(kid, key)
+}
+
+// The number of the line of content on which needle first appears.
+fn line_of(content: &str, needle: &str) -> usize {
+    content.find(needle)
+        .map_or(1, |pos| content[..pos].matches('\n').count() + 1)
+}
+
+fn parse_key_json(content: &str) -> Result<Vec<Key>, String> {
+    let map = match serde_json::from_str::<Value>(content) {
+        Ok(Value::Object(map)) => map,
+        Ok(_) => return Err(String::from("expecting a JSON object mapping KIDs to keys")),
+        Err(e) => return Err(format!("line {}: {e}", e.line())),
+    };
+    let mut keys = Vec::new();
+    for (kid, key) in &map {
+        let line = line_of(content, &format!("\"{kid}\""));
+        let key = key.as_str()
+            .ok_or_else(|| format!("line {line}: the key for KID {kid} should be a string"))?;
+        keys.push(parse_kid_key(kid, key).map_err(|e| format!("line {line}: {e}"))?);
+    }
+    Ok(keys)
+}
+
+fn parse_key_lines(content: &str) -> Result<Vec<Key>, String> {
+    let mut keys: Vec<Key> = Vec::new();
+    // The line on which each KID first appears.
+    let mut lines: HashMap<String, usize> = HashMap::new();
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let key = parse_key(line).map_err(|e| format!("line {}: {e}", n + 1))?;
+        match lines.get(&key.kid) {
+            Some(first) if keys.iter().any(|k| k.kid == key.kid && k.key != key.key) => {
+                return Err(format!("line {}: conflicting key for KID {} (see line {first})", n + 1, key.kid));
+            },
+            Some(_) => {},
+            None => {
+                lines.insert(key.kid.clone(), n + 1);
+                keys.push(key);
+            },
+        }
+    }
+    Ok(keys)
+}
+
+// Read the keys in the file given with --key-file.
+pub fn read_key_file(path: &str) -> Result<Vec<Key>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| e.to_string())?;
+    let keys = if content.trim_start().starts_with('{') {
+        parse_key_json(&content)
+    } else {
+        parse_key_lines(&content)
+    };
+    keys.map_err(|e| format!("{path}: {e}"))
+}
+
+// The default KIDs of the ContentProtection elements of the streams of each kind that are downloaded
+// from the manifest, with the kind of stream they protect.
+pub fn manifest_kids(mpd: &MPD, prefs: &StreamPreferences, kinds: &[&'static str]) -> Vec<(String, &'static str)> {
+    let mut kids: Vec<(String, &'static str)> = Vec::new();
+    for period in &mpd.periods {
+        for &kind in kinds {
+            let Some(choice) = select_representation(period, kind, prefs) else {
+                continue;
+            };
+            let adaptation = &period.adaptations[choice.adaptation];
+            let rep = &adaptation.representations[choice.representation];
+            for cp in adaptation.ContentProtection.iter().chain(rep.ContentProtection.iter()) {
+                if let Some(kid) = cp.default_KID.as_deref().and_then(normalize_kid) {
+                    if !kids.iter().any(|(k, _)| *k == kid) {
+                        kids.push((kid, kind));
+                    }
+                }
+            }
+        }
+    }
+    kids
+}
+
+// Warn about the KIDs of a manifest for which there is no key.
+pub fn warn_missing_keys(keys: &[Key], kids: &[(String, &'static str)]) {
+    // Keys given for a track id may cover any KID.
+    if keys.iter().any(Key::is_track_id) {
+        return;
+    }
+    for (kid, kind) in kids {
+        if !keys.iter().any(|k| k.kid == *kid) {
+            warn!("No decryption key for KID {kid} of the {kind} stream");
+        }
+    }
+}
@@ -0,0 +1,690 @@
+//! Recording live streams (dynamic MPD manifests)
+//
+// In a dynamic manifest, media segments become available progressively as they are produced, at a
+// time determined by MPD@availabilityStartTime, the start of the Period and the timing information
+// of the segment. Segments older than MPD@timeShiftBufferDepth are removed from the server. The
+// manifest is updated by the server, and should be refetched every MPD@minimumUpdatePeriod (in
+// particular when it uses a SegmentTimeline, to which new segments are appended).
+//
+// The recorder synchronizes its clock with the server using the UTCTiming elements of the
+// manifest, starts at the live edge (the current time minus MPD@suggestedPresentationDelay), or at
+// an earlier point in the timeshift buffer for --live-from-start and --live-start-offset, and
+// fetches each segment of the selected Representations once it becomes available, until the
+// requested recording duration has been recorded or the requested end time is reached (or the
+// user interrupts the recording with Ctrl-C). A segment that can't be fetched is retried until it
+// leaves the timeshift buffer, or for SEGMENT_RETRY_PERIOD. Segments are appended to one file per
+// Representation and per Period. We then generate a static manifest that refers to these local
+// files, which the DashDownloader muxes like any other static manifest.
+//
+// A single Ctrl-C handler is shared by all the recordings, which may run in parallel with --jobs.
+// While recordings are in progress, Ctrl-C stops them, so that what has been recorded is muxed as
+// usual; otherwise (or on a second Ctrl-C) it terminates the program, as by default.
+
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+use fs_err as fs;
+use tempfile::TempDir;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::watch;
+use tracing::{info, warn};
+use anyhow::{anyhow, Result, Context};
+use dash_mpd::{MPD, Period, AdaptationSet, Representation, BaseURL};
+use crate::manifest::{Manifest, ManifestFetcher, fill_template, merged_template, parse_http_date,
+                      period_duration, period_start, representation_base_url, representation_segments};
+use crate::selection::{StreamPreferences, content_kind, select_representation};
+
+
+#[derive(Debug, Clone, Default)]
+pub struct LiveOptions {
+    // Duration of media to record, in seconds.
+    pub record_duration: Option<f64>,
+    // Wall-clock time at which to stop recording.
+    pub record_until: Option<SystemTime>,
+    pub preferences: StreamPreferences,
+    pub video_only: bool,
+    pub audio_only: bool,
+    pub fragment_retries: u32,
+    // Start recording at the oldest segment in the timeshift buffer rather than at the live edge.
+    pub from_start: bool,
+    // Start recording at this offset (in seconds) into the timeshift buffer, or before the live
+    // edge if negative.
+    pub start_offset: Option<f64>,
+}
+
+// Margin in seconds after the start of the timeshift buffer, for --live-from-start.
+const TIMESHIFT_MARGIN: f64 = 2.0;
+// Interval in seconds at which we refetch a manifest that has no MPD@minimumUpdatePeriod, when we
+// can't tell from the manifest when the next segment will become available (for example with a
+// SegmentTimeline).
+const FALLBACK_UPDATE_PERIOD: f64 = 2.0;
+// Recording stops when a manifest without MPD@minimumUpdatePeriod announces no new segments for
+// this many seconds.
+const STALL_TIMEOUT: f64 = 60.0;
+// Period in seconds during which we retry fetching a media segment that remains in the timeshift
+// buffer, before skipping it.
+const SEGMENT_RETRY_PERIOD: f64 = 30.0;
+// Delay in seconds before we retry fetching a media segment.
+const SEGMENT_RETRY_DELAY: f64 = 1.0;
+
+// Ctrl-C handling shared by all the recordings in progress.
+struct Interrupt {
+    recordings: AtomicUsize,
+    stop: watch::Sender<bool>,
+}
+
+// Decrements the count of recordings in progress when dropped.
+struct RecordingGuard(&'static Interrupt);
+
+impl Drop for RecordingGuard {
+    fn drop(&mut self) {
+        self.0.recordings.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Interrupt {
+    // The Interrupt, whose signal handler is installed the first time it is needed.
+    fn get() -> &'static Interrupt {
+        static INTERRUPT: OnceLock<Interrupt> = OnceLock::new();
+        INTERRUPT.get_or_init(|| {
+            tokio::spawn(async {
+                while tokio::signal::ctrl_c().await.is_ok() {
+                    let interrupt = Interrupt::get();
+                    if interrupt.recordings.load(Ordering::SeqCst) == 0 || *interrupt.stop.borrow() {
+                        std::process::exit(130);
+                    }
+                    info!("Stopping live recordings (press Ctrl-C again to abort)");
+                    interrupt.stop.send_replace(true);
+                }
+            });
+            Interrupt { recordings: AtomicUsize::new(0), stop: watch::channel(false).0 }
+        })
+    }
+
+    fn start_recording(&'static self) -> RecordingGuard {
+        self.recordings.fetch_add(1, Ordering::SeqCst);
+        RecordingGuard(self)
+    }
+
+    fn stop_requested(&self) -> watch::Receiver<bool> {
+        self.stop.subscribe()
+    }
+}
+
+fn unix_secs(t: SystemTime) -> f64 {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
+
+// Parse an xs:dateTime value, such as those returned by UTCTiming sources. A value without a
+// timezone is interpreted as UTC.
+fn parse_xs_datetime(s: &str) -> Option<SystemTime> {
+    let s = s.trim();
+    let no_tz = time::macros::format_description!(
+        "[year]-[month]-[day]T[hour]:[minute]:[second][optional [.[subsecond]]]");
+    time::OffsetDateTime::parse(s, &time::format_description::well_known::Rfc3339).ok()
+        .or_else(|| time::PrimitiveDateTime::parse(s, &no_tz).ok().map(|dt| dt.assume_utc()))
+        .map(SystemTime::from)
+}
+
+// Parse the argument to --record-until: either a date and time in RFC 3339 format, or a local time
+// of day in the format HH:MM or HH:MM:SS (the next occurrence of that time).
+pub fn parse_wall_clock(s: &str) -> Result<SystemTime, String> {
+    if let Some(t) = parse_xs_datetime(s) {
+        return Ok(t);
+    }
+    let parts: Vec<&str> = s.split(':').collect();
+    let field = |i: usize| -> Result<u8, String> {
+        parts.get(i).map_or(Ok(0), |p| p.parse::<u8>().map_err(|_| format!("invalid time {s}")))
+    };
+    if parts.len() < 2 || parts.len() > 3 {
+        return Err(format!("invalid time {s} (expecting HH:MM[:SS] or an RFC 3339 date and time)"));
+    }
+    let tod = time::Time::from_hms(field(0)?, field(1)?, field(2)?)
+        .map_err(|e| format!("invalid time {s}: {e}"))?;
+    let offset = time::UtcOffset::current_local_offset()
+        .unwrap_or(time::UtcOffset::UTC);
+    let now = time::OffsetDateTime::now_utc().to_offset(offset);
+    let mut at = now.replace_time(tod);
+    if at <= now {
+        at += time::Duration::days(1);
+    }
+    Ok(SystemTime::from(at))
+}
+
+
+// The server's wall clock, as indicated by the UTCTiming elements of the manifest. Times are in
+// seconds since the Unix epoch.
+struct Clock {
+    offset: f64,
+}
+
+impl Clock {
+    fn now(&self) -> f64 {
+        unix_secs(SystemTime::now()) + self.offset
+    }
+
+    async fn synchronize(fetcher: &ManifestFetcher, mpd: &MPD) -> Clock {
+        for timing in &mpd.UTCTiming {
+            let (Some(scheme), Some(value)) = (&timing.schemeIdUri, &timing.value) else {
+                continue;
+            };
+            match server_time(fetcher, scheme, value).await {
+                Ok(Some(t)) => {
+                    let offset = unix_secs(t) - unix_secs(SystemTime::now());
+                    if offset.abs() > 1.0 {
+                        info!("Local clock differs by {offset:.1}s from the live stream clock");
+                    }
+                    return Clock { offset };
+                },
+                Ok(None) => continue,
+                Err(e) => warn!("Ignoring UTCTiming source {value}: {e:#}"),
+            }
+        }
+        Clock { offset: 0.0 }
+    }
+}
+
+async fn server_time(fetcher: &ManifestFetcher, scheme: &str, value: &str) -> Result<Option<SystemTime>> {
+    // Schemes are defined with a :2012 and a :2014 suffix.
+    let scheme = scheme.rsplit_once(':').map_or(scheme, |(s, _)| s);
+    match scheme {
+        "urn:mpeg:dash:utc:direct" => Ok(parse_xs_datetime(value)),
+        "urn:mpeg:dash:utc:http-iso" | "urn:mpeg:dash:utc:http-xsdate" => {
+            let body = fetcher.get(Url::parse(value)?).await?
+                .error_for_status()?
+                .text().await?;
+            Ok(parse_xs_datetime(&body))
+        },
+        "urn:mpeg:dash:utc:http-head" => {
+            let response = fetcher.get(Url::parse(value)?).await?;
+            Ok(response.headers().get(reqwest::header::DATE)
+               .and_then(|v| v.to_str().ok())
+               .and_then(parse_http_date))
+        },
+        _ => Ok(None),
+    }
+}
+
+
+#[derive(Debug, Clone)]
+struct LiveSegment {
+    url: Url,
+    // Presentation time of the start and end of the segment, in seconds from availabilityStartTime.
+    start: f64,
+    end: f64,
+}
+
+// The media segments of a Representation that are available at time now and that end after time
+// from (both in seconds from availabilityStartTime), taking into account the timeshift buffer. Also
+// returns the time at which the next segment will become available, if it can be determined without
+// refetching the manifest.
+fn available_segments(
+    manifest: &Manifest,
+    period_idx: usize,
+    adaptation: &AdaptationSet,
+    rep: &Representation,
+    now: f64,
+    from: f64) -> Result<(Vec<LiveSegment>, Option<f64>)>
+{
+    let mpd = &manifest.mpd;
+    let period = &mpd.periods[period_idx];
+    let pstart = period_start(mpd, period_idx);
+    let pend = period_duration(mpd, period_idx).map(|d| pstart + d);
+    let earliest = match mpd.timeShiftBufferDepth {
+        Some(tsbd) => from.max(now - tsbd.as_secs_f64()),
+        None => from,
+    };
+    let base = representation_base_url(manifest, period, adaptation, rep);
+    let rep_id = rep.id.clone().unwrap_or_default();
+    let bandwidth = rep.bandwidth.unwrap_or(0);
+    let join = |path: &str| base.join(path)
+        .with_context(|| format!("resolving segment URL {path}"));
+    let mut segments = Vec::new();
+    let Some(st) = merged_template(adaptation, rep) else {
+        let Some(sl) = rep.SegmentList.as_ref().or(adaptation.SegmentList.as_ref()) else {
+            return Err(anyhow!("Representation {rep_id} uses a form of segment addressing that is not supported for live streams"));
+        };
+        let timescale = sl.timescale.unwrap_or(1) as f64;
+        let duration = sl.duration
+            .map(|d| d as f64 / timescale)
+            .ok_or_else(|| anyhow!("SegmentList in live stream has no @duration"))?;
+        for (i, su) in sl.segment_urls.iter().enumerate() {
+            let start = pstart + i as f64 * duration;
+            let end = start + duration;
+            if end > earliest && end <= now {
+                let url = match &su.media {
+                    Some(m) => join(m)?,
+                    None => base.clone(),
+                };
+                segments.push(LiveSegment { url, start, end });
+            }
+        }
+        return Ok((segments, None));
+    };
+    let media = st.media.as_ref()
+        .ok_or_else(|| anyhow!("SegmentTemplate for Representation {rep_id} has no @media"))?;
+    let timescale = st.timescale.unwrap_or(1) as f64;
+    let pto = st.presentationTimeOffset.unwrap_or(0);
+    let start_number = st.startNumber.unwrap_or(1);
+    if let Some(timeline) = &st.SegmentTimeline {
+        let mut number = start_number;
+        let mut time = 0;
+        for (i, s) in timeline.segments.iter().enumerate() {
+            if let Some(t) = s.t {
+                time = t;
+            }
+            let repeats = match s.r.unwrap_or(0) {
+                r if r >= 0 => r as u64,
+                _ => {
+                    // Repeat until the start of the next S element, or until the current time.
+                    let horizon = pend.map_or(now, |e| e.min(now));
+                    let until = timeline.segments.get(i + 1)
+                        .and_then(|next| next.t)
+                        .unwrap_or(pto + ((horizon - pstart).max(0.0) * timescale) as u64);
+                    (until.saturating_sub(time) / s.d.max(1)).saturating_sub(1)
+                },
+            };
+            for _ in 0..=repeats {
+                let start = pstart + (time as f64 - pto as f64) / timescale;
+                let end = start + s.d as f64 / timescale;
+                if end > earliest && end <= now {
+                    let url = join(&fill_template(media, &rep_id, bandwidth, number, time))?;
+                    segments.push(LiveSegment { url, start, end });
+                }
+                number += 1;
+                time += s.d;
+            }
+        }
+        return Ok((segments, None));
+    }
+    let seg_duration = st.duration
+        .ok_or_else(|| anyhow!("SegmentTemplate for Representation {rep_id} has neither @duration nor SegmentTimeline"))?;
+    let seg_secs = seg_duration / timescale;
+    // Segment k (counting from zero) covers [pstart + k*seg_secs, pstart + (k+1)*seg_secs) and
+    // becomes available once it is complete.
+    let mut k = ((earliest - pstart) / seg_secs).floor().max(0.0) as u64;
+    loop {
+        let start = pstart + k as f64 * seg_secs;
+        let end = start + seg_secs;
+        if pend.is_some_and(|e| start >= e - 1e-6) ||
+            st.endNumber.is_some_and(|last| start_number + k > last)
+        {
+            return Ok((segments, None));
+        }
+        if end > now {
+            return Ok((segments, Some(end)));
+        }
+        let time = pto + (k as f64 * seg_duration) as u64;
+        let url = join(&fill_template(media, &rep_id, bandwidth, start_number + k, time))?;
+        segments.push(LiveSegment { url, start, end });
+        k += 1;
+    }
+}
+
+
+// The end of the presentation, in seconds from availabilityStartTime, if the manifest announces it.
+fn presentation_end(mpd: &MPD) -> Option<f64> {
+    let last = mpd.periods.len().checked_sub(1)?;
+    period_duration(mpd, last).map(|d| period_start(mpd, last) + d)
+}
+
+fn period_key(period: &Period, idx: usize) -> String {
+    period.id.clone().unwrap_or_else(|| format!("#{idx}"))
+}
+
+// A Representation that we are recording in one Period.
+struct Track {
+    kind: &'static str,
+    period_key: String,
+    adaptation: AdaptationSet,
+    representation: Representation,
+    path: PathBuf,
+    file: tokio::fs::File,
+    // Presentation time (from availabilityStartTime) of the start of the first recorded segment and
+    // of the end of the last recorded segment.
+    first: Option<f64>,
+    next: f64,
+    complete: bool,
+    // The segment that we are failing to fetch, and the clock time of the first failure.
+    failing: Option<(Url, f64)>,
+}
+
+impl Track {
+    // Find our AdaptationSet and Representation in the refreshed manifest.
+    fn locate<'a>(&self, period: &'a Period) -> Option<(&'a AdaptationSet, &'a Representation)> {
+        period.adaptations.iter()
+            .filter(|a| a.id == self.adaptation.id && content_kind(a) == Some(self.kind))
+            .find_map(|a| a.representations.iter()
+                      .find(|r| r.id == self.representation.id)
+                      .map(|r| (a, r)))
+    }
+}
+
+async fn fetch_segment(fetcher: &ManifestFetcher, url: &Url, retries: u32) -> Result<Vec<u8>> {
+    let mut attempt = 0;
+    loop {
+        let outcome = match fetcher.fetch_segment(url.clone(), None).await {
+            Ok(response) => match response.error_for_status() {
+                Ok(r) => r.bytes().await.map_err(anyhow::Error::from),
+                Err(e) => Err(e.into()),
+            },
+            Err(e) => Err(e),
+        };
+        match outcome {
+            Ok(bytes) => return Ok(bytes.to_vec()),
+            Err(e) if attempt >= retries => return Err(e),
+            Err(_) => {
+                attempt += 1;
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            },
+        }
+    }
+}
+
+
+// The recorded media, in a temporary directory, described by a static manifest.
+pub struct LiveRecording {
+    _dir: TempDir,
+    manifest_url: String,
+    // The publication time of the live stream's manifest when the recording started.
+    publication_time: Option<SystemTime>,
+}
+
+impl LiveRecording {
+    pub fn manifest_url(&self) -> &str {
+        &self.manifest_url
+    }
+
+    pub fn publication_time(&self) -> Option<SystemTime> {
+        self.publication_time
+    }
+}
+
+// Generate a static manifest that describes the recorded tracks, with one Period for each Period
+// of the live stream that we have recorded.
+fn recording_manifest(tracks: &[Track]) -> Result<MPD> {
+    let mut periods: Vec<Period> = Vec::new();
+    let mut total = 0.0;
+    let mut keys: Vec<&str> = Vec::new();
+    for t in tracks.iter().filter(|t| t.first.is_some()) {
+        if !keys.contains(&t.period_key.as_str()) {
+            keys.push(&t.period_key);
+        }
+    }
+    for key in keys {
+        let recorded: Vec<&Track> = tracks.iter()
+            .filter(|t| t.period_key == key && t.first.is_some())
+            .collect();
+        let start = recorded.iter().filter_map(|t| t.first).fold(f64::INFINITY, f64::min);
+        let end = recorded.iter().map(|t| t.next).fold(f64::NEG_INFINITY, f64::max);
+        total += end - start;
+        let mut adaptations = Vec::new();
+        for t in recorded {
+            let mut rep = t.representation.clone();
+            rep.SegmentTemplate = None;
+            rep.SegmentList = None;
+            rep.SegmentBase = None;
+            let file_url = Url::from_file_path(&t.path)
+                .map_err(|_| anyhow!("invalid path for recorded media"))?;
+            rep.BaseURL = vec![BaseURL { base: file_url.to_string(), ..Default::default() }];
+            let mut adaptation = t.adaptation.clone();
+            adaptation.SegmentTemplate = None;
+            adaptation.SegmentList = None;
+            adaptation.SegmentBase = None;
+            adaptation.BaseURL.clear();
+            adaptation.representations = vec![rep];
+            adaptations.push(adaptation);
+        }
+        periods.push(Period {
+            id: Some(key.to_string()),
+            duration: Some(Duration::from_secs_f64(end - start)),
+            adaptations,
+            ..Default::default()
+        });
+    }
+    Ok(MPD {
+        mpdtype: Some(String::from("static")),
+        mediaPresentationDuration: Some(Duration::from_secs_f64(total)),
+        periods,
+        ..Default::default()
+    })
+}
+
+// Record from the manifest at mpd_url if it is dynamic. Returns None if the manifest is static, or if
+// it is a pseudo-live stream whose media segments are all available already, in which case it should
+// be downloaded as usual. The recorded media is saved in a temporary directory alongside output.
+pub async fn record_live(
+    fetcher: &ManifestFetcher,
+    mpd_url: &str,
+    output: Option<&Path>,
+    opts: &LiveOptions) -> Result<Option<LiveRecording>>
+{
+    let mut manifest = fetcher.fetch(mpd_url).await?;
+    if manifest.mpd.mpdtype.as_deref() != Some("dynamic") {
+        return Ok(None);
+    }
+    let publication_time = manifest.publication_time();
+    let ast = manifest.mpd.availabilityStartTime
+        .map(|t| unix_secs(SystemTime::from(t)))
+        .unwrap_or(0.0);
+    let clock = Clock::synchronize(fetcher, &manifest.mpd).await;
+    let delay = manifest.mpd.suggestedPresentationDelay
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    // Presentation times (in seconds from availabilityStartTime) of the start and end of the
+    // recording. The oldest segments in the timeshift buffer may be removed from the server while
+    // we are fetching them, so we start a little after the start of the buffer.
+    let live_edge = clock.now() - ast - delay;
+    let first_period = if manifest.mpd.periods.is_empty() { 0.0 } else { period_start(&manifest.mpd, 0) };
+    let window_start = match manifest.mpd.timeShiftBufferDepth {
+        Some(tsbd) => (clock.now() - ast - tsbd.as_secs_f64() + TIMESHIFT_MARGIN).max(first_period),
+        None => first_period,
+    }.min(live_edge);
+    let rec_start = if opts.from_start {
+        window_start
+    } else if let Some(offset) = opts.start_offset {
+        if offset < 0.0 {
+            (live_edge + offset).max(window_start)
+        } else {
+            (window_start + offset).min(live_edge)
+        }
+    } else {
+        live_edge
+    };
+    if presentation_end(&manifest.mpd).is_some_and(|end| end <= rec_start) {
+        info!("The live stream has ended and all its media segments are available; downloading it like a static manifest");
+        return Ok(None);
+    }
+    if rec_start < live_edge - 1.0 {
+        info!("Recording starts {:.1}s behind the live edge", live_edge - rec_start);
+    }
+    let mut rec_end = opts.record_duration.map_or(f64::INFINITY, |d| rec_start + d);
+    let until = opts.record_until.map(unix_secs);
+    if let Some(u) = until {
+        rec_end = rec_end.min(u - ast - delay);
+    }
+    let parent = output.and_then(Path::parent)
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let dir = tempfile::Builder::new()
+        .prefix(".dash-mpd-cli-live-")
+        .tempdir_in(parent)
+        .context("creating directory for live recording")?;
+    let dir_path = fs::canonicalize(dir.path())?;
+    let mut kinds = Vec::new();
+    if !opts.audio_only {
+        kinds.push("video");
+    }
+    if !opts.video_only {
+        kinds.push("audio");
+    }
+    if rec_end.is_finite() {
+        info!("Recording {:.1}s from live stream", rec_end - rec_start);
+    } else {
+        info!("Recording from live stream until it ends (interrupt with Ctrl-C to stop recording)");
+    }
+    let mut tracks: Vec<Track> = Vec::new();
+    let mut last_fetch = clock.now();
+    let mut last_progress = clock.now();
+    let interrupt = Interrupt::get();
+    let recording = interrupt.start_recording();
+    let mut stop = interrupt.stop_requested();
+    loop {
+        if *stop.borrow() {
+            info!("Recording interrupted");
+            break;
+        }
+        let now = clock.now() - ast;
+        let mut next_available = f64::INFINITY;
+        // Clock time at which to retry fetching segments that failed.
+        let mut retry_at = f64::INFINITY;
+        for pidx in 0..manifest.mpd.periods.len() {
+            let period = &manifest.mpd.periods[pidx];
+            let pstart = period_start(&manifest.mpd, pidx);
+            let pend = period_duration(&manifest.mpd, pidx).map(|d| pstart + d);
+            if pstart >= rec_end || pstart > now || pend.is_some_and(|e| e <= rec_start) {
+                continue;
+            }
+            let key = period_key(period, pidx);
+            for &kind in &kinds {
+                if tracks.iter().any(|t| t.period_key == key && t.kind == *kind) {
+                    continue;
+                }
+                let Some(choice) = select_representation(period, kind, &opts.preferences) else {
+                    continue;
+                };
+                let adaptation = &period.adaptations[choice.adaptation];
+                let rep = &adaptation.representations[choice.representation];
+                let path = dir_path.join(format!("{kind}-{}.mp4", tracks.len() + 1));
+                let mut file = tokio::fs::File::create(&path).await
+                    .with_context(|| format!("creating {}", path.display()))?;
+                if let Some(init) = representation_segments(&manifest, pidx, adaptation, rep)?.init {
+                    let data = fetch_segment(fetcher, &init.url, opts.fragment_retries).await
+                        .with_context(|| format!("fetching initialization segment {}", init.url))?;
+                    file.write_all(&data).await?;
+                }
+                info!("Recording {kind} Representation {} in Period {key}", rep.id.as_deref().unwrap_or("(no id)"));
+                tracks.push(Track {
+                    kind,
+                    period_key: key.clone(),
+                    adaptation: adaptation.clone(),
+                    representation: rep.clone(),
+                    path,
+                    file,
+                    first: None,
+                    next: rec_start.max(pstart),
+                    complete: false,
+                    failing: None,
+                });
+            }
+            for track in tracks.iter_mut().filter(|t| t.period_key == key && !t.complete) {
+                let Some((adaptation, rep)) = track.locate(period) else {
+                    warn!("{} Representation has disappeared from the manifest", track.kind);
+                    track.complete = true;
+                    continue;
+                };
+                let (segments, next) = available_segments(&manifest, pidx, adaptation, rep, now, track.next)?;
+                for seg in segments {
+                    if seg.start >= rec_end {
+                        track.complete = true;
+                        break;
+                    }
+                    match fetch_segment(fetcher, &seg.url, opts.fragment_retries).await {
+                        Ok(data) => {
+                            track.file.write_all(&data).await?;
+                            track.first.get_or_insert(seg.start);
+                            track.failing = None;
+                            last_progress = clock.now();
+                        },
+                        Err(e) => {
+                            // Segments are appended in order, so the following segments of this
+                            // track wait until this one has been fetched or skipped.
+                            let since = match &track.failing {
+                                Some((url, since)) if *url == seg.url => *since,
+                                _ => clock.now(),
+                            };
+                            if clock.now() - since < SEGMENT_RETRY_PERIOD {
+                                warn!("Can't fetch live media segment {}, will retry: {e:#}", seg.url);
+                                track.failing = Some((seg.url.clone(), since));
+                                retry_at = retry_at.min(clock.now() + SEGMENT_RETRY_DELAY);
+                                break;
+                            }
+                            warn!("Skipping live media segment {}: {e:#}", seg.url);
+                            track.failing = None;
+                        },
+                    }
+                    track.next = seg.end;
+                }
+                if track.next >= rec_end - 1e-3 || pend.is_some_and(|e| track.next >= e - 1e-3) {
+                    track.complete = true;
+                } else if let Some(n) = next.filter(|_| track.failing.is_none()) {
+                    next_available = next_available.min(n);
+                }
+            }
+        }
+        let recorded_to = tracks.iter().map(|t| t.next).fold(rec_start, f64::max);
+        if !tracks.is_empty() && tracks.iter().all(|t| t.complete) && recorded_to >= rec_end - 1e-3 {
+            break;
+        }
+        if manifest.mpd.mpdtype.as_deref() == Some("static") {
+            info!("The live stream has ended");
+            break;
+        }
+        if until.is_some_and(|u| clock.now() >= u) {
+            break;
+        }
+        // Sleep until the next segment becomes available, until failed segments should be retried,
+        // or until the manifest should be refreshed. A manifest without MPD@minimumUpdatePeriod which
+        // doesn't tell us when the next segment will become available is refreshed periodically.
+        let refresh_at = match manifest.mpd.minimumUpdatePeriod {
+            Some(mup) => last_fetch + mup.as_secs_f64(),
+            None if !next_available.is_finite() && retry_at.is_infinite() => {
+                if clock.now() - last_progress > STALL_TIMEOUT {
+                    warn!("The manifest hasn't announced any new media segments for {STALL_TIMEOUT}s; stopping recording");
+                    break;
+                }
+                last_fetch + FALLBACK_UPDATE_PERIOD
+            },
+            None => f64::INFINITY,
+        };
+        let mut wake_at = refresh_at.min(ast + next_available).min(retry_at);
+        if let Some(u) = until {
+            wake_at = wake_at.min(u);
+        }
+        let pause = Duration::from_secs_f64((wake_at - clock.now()).max(0.1));
+        tokio::select! {
+            _ = tokio::time::sleep(pause) => (),
+            _ = stop.changed() => (),
+        }
+        if clock.now() >= refresh_at {
+            last_fetch = clock.now();
+            match fetcher.fetch(mpd_url).await {
+                Ok(m) => manifest = m,
+                Err(e) => warn!("Can't refresh live manifest: {e:#}"),
+            }
+        }
+    }
+    for t in tracks.iter_mut() {
+        t.file.flush().await?;
+    }
+    // Ctrl-C during the muxing stage terminates the program.
+    drop(recording);
+    if tracks.iter().all(|t| t.first.is_none()) {
+        return Err(anyhow!("no media segments were recorded from the live stream"));
+    }
+    let mpd = recording_manifest(&tracks)?;
+    let xml = quick_xml::se::to_string(&mpd)
+        .context("serializing manifest for live recording")?;
+    let mpd_path = dir_path.join("recording.mpd");
+    fs::write(&mpd_path, xml)?;
+    let manifest_url = Url::from_file_path(&mpd_path)
+        .map_err(|_| anyhow!("invalid path for live recording manifest"))?
+        .to_string();
+    Ok(Some(LiveRecording { _dir: dir, manifest_url, publication_time }))
+}
@@ -20,26 +20,84 @@
 // Example usage: dash-mpd-cli --timeout 5 --output=/tmp/foo.mp4 https://v.redd.it/zv89llsvexdz/DASHPlaylist.mpd
 
 use std::env;
+use std::ffi::OsString;
 use std::io::{self, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::net::IpAddr;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use std::sync::Arc;
 use std::collections::HashMap;
 use url::Url;
 use fs_err as fs;
 use reqwest::header;
-use clap::{Arg, ArgAction, ValueHint};
+use clap::{Arg, ArgAction, ArgMatches, ValueHint};
+use clap::parser::ValueSource;
 use unit_prefix::{NumberPrefix, Prefix};
-use indicatif::{ProgressBar, ProgressStyle};
-use anyhow::{Result, Context};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use anyhow::{anyhow, Result, Context};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tracing_subscriber::EnvFilter;
-use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
 use tracing_subscriber::prelude::*;
 use tracing::{info, warn, error, Level};
+use dash_mpd::{BaseURL, DashMpdError};
 use dash_mpd::fetch::{DashDownloader, ProgressObserver};
 
+mod ads;
+mod batch;
+mod cenc;
+mod chapters;
+mod clearkey;
+mod clip;
+mod config;
+mod dump;
+mod exitcode;
+mod failure_report;
+mod fmp4;
+mod formats;
+mod keys;
+mod live;
+mod manifest;
+mod metadata;
+mod periods;
+mod ratelimit;
+mod resume;
+mod selector;
+mod selection;
+mod subconvert;
+mod subtitles;
+mod template;
+mod tracks;
+use crate::batch::{BatchItem, read_batch_file};
+use crate::cenc::{DecryptionKeys, decrypt_file, mux_streams};
+use crate::clearkey::{license_requests, acquire_keys};
+use crate::chapters::{Chapter, manifest_chapters, period_intervals, output_chapters, trim_chapters,
+                      embed_chapters};
+use crate::clip::{ClipRange, clip_manifest, trim_exact};
+use crate::config::{config_args, print_config};
+use crate::dump::{DumpOptions, dump_json};
+use crate::fmp4::{parse_muxer_preference, builtin_preferred, mux_fragmented};
+use crate::failure_report::report_failed_segment;
+use crate::formats::{manifest_formats, write_format_table, formats_json};
+use crate::keys::{Key, parse_key, read_key_file, manifest_kids, warn_missing_keys};
+use crate::manifest::{Manifest, ManifestFetcher};
+use crate::metadata::{Metadata, SelectedStream, selected_streams, embed_metadata, write_info_json};
+use crate::periods::{PeriodSelection, parse_period_selection, log_periods, select_periods};
+use crate::ratelimit::{ThrottlingProxy, TokenBucket};
+use crate::resume::{ResumeSession, resume_dir};
+use crate::selection::{StreamPreferences, select_representation};
+use crate::selector::{FormatSelector, parse_format_selector, apply_format_selector};
+use crate::subconvert::TargetFormat;
+use crate::template::{OutputTemplate, parse_output_template, template_fields, url_stem};
+use crate::subtitles::{SubtitleTrack, subtitle_tracks, download_subtitles, embed_subtitles,
+                       convert_downloaded_subtitles, preferred_subtitle_track, save_fragmented_track,
+                       save_sidecar_track};
+use crate::tracks::{AudioTrack, LanguageSelection, parse_language_selection, audio_tracks,
+                    with_audio_track, mux_audio_tracks};
+use crate::live::{LiveOptions, record_live, parse_wall_clock};
+
 #[cfg(feature = "cookies")]
 mod cookies;
 #[cfg(feature = "cookies")]
@@ -66,6 +124,18 @@ impl DownloadProgressBar {
                         .progress_chars("#>-"));
         Self { bar: b }
     }
+
+    // When several downloads run in parallel, each job has its own line in a MultiProgress display,
+    // labelled with the job number.
+    pub fn for_job(multi: &MultiProgress, job: usize) -> Self {
+        let b = multi.add(ProgressBar::new(100))
+            .with_style(ProgressStyle::default_bar()
+                        .template("{prefix} [{elapsed}] [{bar:40.cyan/blue}] {wide_msg}")
+                        .expect("building progress bar")
+                        .progress_chars("#>-"))
+            .with_prefix(format!("#{job}"));
+        Self { bar: b }
+    }
 }
 
 impl ProgressObserver for DownloadProgressBar {
@@ -88,23 +158,36 @@ impl ProgressObserver for DownloadProgressBar {
 }
 
 struct DownloadProgressJson {
+    job: Option<usize>,
 }
 
 impl DownloadProgressJson {
     pub fn new() -> Self {
-        Self { }
+        Self { job: None }
+    }
+
+    // Progress events are tagged with the job number, when several downloads run in parallel.
+    pub fn for_job(job: usize) -> Self {
+        Self { job: Some(job) }
     }
 }
 
-// Prints newline-delimited JSON to stderr.
+// Prints newline-delimited JSON to stderr. Each line is written at once, so that the lines of
+// downloads running in parallel aren't interleaved.
 impl ProgressObserver for DownloadProgressJson {
     fn update(&self, percent: u32, bandwidth: u64, message: &str) {
-        eprint!("{{\"type\": \"progress\", \"percent\": {percent}, \"bandwidth\": {bandwidth}, \"message\": \"");
+        let mut line = String::from("{\"type\": \"progress\", ");
+        if let Some(job) = self.job {
+            line.push_str(&format!("\"job\": {job}, "));
+        }
+        line.push_str(&format!("\"percent\": {percent}, \"bandwidth\": {bandwidth}, \"message\": \""));
         for str in json_escape::escape_str(message) {
-            eprint!("{str}");
+            line.push_str(str);
         }
-        eprintln!("\"}}");
-        let _ = io::stderr().flush();
+        line.push_str("\"}\n");
+        let mut stderr = io::stderr().lock();
+        let _ = stderr.write_all(line.as_bytes());
+        let _ = stderr.flush();
     }
 }
 
@@ -136,6 +219,1298 @@ async fn check_newer_version() -> Result<()> {
 }
 
 
+// Parse a duration specified as a number of seconds (possibly floating point) or as [HH:]MM:SS[.sss].
+fn parse_duration_secs(s: &str) -> Result<f64, String> {
+    let mut secs = 0.0;
+    for part in s.split(':') {
+        let value = part.parse::<f64>()
+            .map_err(|_| format!("invalid duration {s} (expecting seconds or HH:MM:SS)"))?;
+        if value < 0.0 {
+            return Err(format!("invalid negative duration {s}"));
+        }
+        secs = secs * 60.0 + value;
+    }
+    Ok(secs)
+}
+
+// A duration that may be preceded by a minus sign, for --live-start-offset.
+fn parse_start_offset(s: &str) -> Result<f64, String> {
+    match s.strip_prefix('-') {
+        Some(d) => parse_duration_secs(d).map(|secs| -secs),
+        None => parse_duration_secs(s),
+    }
+}
+
+// State shared by the downloads that run in parallel when --jobs is greater than one.
+#[derive(Clone)]
+struct JobContext {
+    // The number of downloads that run concurrently.
+    concurrency: usize,
+    progress: Option<MultiProgress>,
+    // The bandwidth in octets per second that each DashDownloader is limited to. This is None when
+    // parallel downloads share the --limit-rate bandwidth through our ThrottlingProxy.
+    rate_limit: Option<u64>,
+}
+
+// The --limit-rate bandwidth in octets per second.
+fn limit_rate(matches: &ArgMatches) -> Option<u64> {
+    let limit = matches.get_one::<String>("limit-rate")?;
+    // We allow k, M, G, T suffixes, as per 100k, 1M, 0.4G
+    let Ok(np) = limit.parse::<NumberPrefix<f64>>() else {
+        warn!("Ignoring invalid value for limit-rate");
+        return None;
+    };
+    let bps = match np {
+        NumberPrefix::Standalone(bps) => bps,
+        NumberPrefix::Prefixed(pfx, n) => match pfx {
+            Prefix::Kilo => n * 1024.0,
+            Prefix::Mega => n * 1024.0 * 1024.0,
+            Prefix::Giga => n * 1024.0 * 1024.0 * 1024.0,
+            Prefix::Tera => n * 1024.0 * 1024.0 * 1024.0 * 1024.0,
+            _ => {
+                warn!("Ignoring unrecognized suffix on limit-rate");
+                0.0
+            },
+        },
+    };
+    if bps > 0.0 {
+        Some(bps as u64)
+    } else {
+        warn!("Ignoring negative value for limit-rate");
+        None
+    }
+}
+
+// The http proxy that our ThrottlingProxy should forward connections to, or an error if the proxy
+// configuration can't be combined with the ThrottlingProxy.
+fn throttling_upstream(matches: &ArgMatches) -> Result<Option<String>> {
+    if matches.get_flag("no-proxy") {
+        return Ok(None);
+    }
+    if let Some(p) = matches.get_one::<String>("proxy") {
+        let url = Url::parse(p)
+            .context("parsing --proxy URL")?;
+        if url.scheme() != "http" || !url.username().is_empty() || url.password().is_some() {
+            return Err(anyhow!("a shared bandwidth limit requires an http proxy without credentials"));
+        }
+        let host = url.host_str()
+            .ok_or_else(|| anyhow!("--proxy URL has no host"))?;
+        let port = url.port_or_known_default().unwrap_or(80);
+        return Ok(Some(format!("{host}:{port}")));
+    }
+    let environment = ["HTTP_PROXY", "HTTPS_PROXY", "ALL_PROXY", "http_proxy", "https_proxy", "all_proxy"];
+    if environment.iter().any(|var| env::var(var).is_ok_and(|v| !v.is_empty())) {
+        return Err(anyhow!("a shared bandwidth limit can't be combined with a proxy configured in the environment"));
+    }
+    Ok(None)
+}
+
+// Build a DashDownloader for the manifest at url, configured according to our commandline arguments.
+// job is the index of this download in the list of manifests, used to label progress reports.
+fn make_downloader(
+    matches: &ArgMatches,
+    client: &reqwest::Client,
+    url: &str,
+    job: usize,
+    ctx: &JobContext) -> Result<DashDownloader>
+{
+    let verbosity = matches.get_count("verbose");
+    let mut dl = DashDownloader::new(url)
+        .with_http_client(client.clone());
+    if let Some(url) = matches.get_one::<String>("referer") {
+        dl = dl.with_referer(url.clone());
+    }
+    let mut progress_type = ProgressType::Bar;
+    if matches.get_flag("no-progress") || matches.get_flag("quiet") {
+        progress_type = ProgressType::None;
+    }
+    if let Some(ptype) = matches.get_one::<String>("progress") {
+        if ptype.eq("json") {
+            progress_type = ProgressType::Json;
+        } else if !ptype.eq("bar") {
+            warn!("Ignoring invalid value for --progress");
+        }
+    }
+    match progress_type {
+        ProgressType::Bar => match &ctx.progress {
+            Some(multi) => dl = dl.add_progress_observer(Arc::new(DownloadProgressBar::for_job(multi, job))),
+            None => dl = dl.add_progress_observer(Arc::new(DownloadProgressBar::new())),
+        },
+        ProgressType::Json => if ctx.concurrency > 1 {
+            dl = dl.add_progress_observer(Arc::new(DownloadProgressJson::for_job(job)));
+        } else {
+            dl = dl.add_progress_observer(Arc::new(DownloadProgressJson::new()));
+        },
+        ProgressType::None => {},
+    }
+    if let Some(seconds) = matches.get_one::<u8>("sleep-requests") {
+        dl = dl.sleep_between_requests(*seconds);
+    }
+    // When a live stream has nothing left to record, the DashDownloader downloads it instead.
+    if live_streams_enabled(matches) {
+        dl = dl.allow_live_streams(true);
+    }
+    if let Some(seconds) = matches.get_one::<f64>("force-duration") {
+        dl = dl.force_duration(*seconds);
+    }
+    if let Some(bu) = matches.get_one::<String>("base-url") {
+        if let Err(e) = Url::parse(bu) {
+            error!("Invalid URL for --base-url: {e}");
+            std::process::exit(exitcode::INVALID_BASE_URL);
+        }
+        dl = dl.with_base_url(String::from(bu));
+    }
+    if let Some(bps) = ctx.rate_limit {
+        dl = dl.with_rate_limit(bps);
+    }
+    if let Some(count) = matches.get_one::<u32>("fragment-retries") {
+        dl = dl.fragment_retry_count(*count);
+    }
+    if let Some(count) = matches.get_one::<u32>("max-error-count") {
+        dl = dl.max_error_count(*count);
+    }
+    // Failed fragments are still retried, and the download is aborted once a fragment has exhausted
+    // its retries.
+    if matches.get_flag("abort-on-error") {
+        dl = dl.max_error_count(0);
+    }
+    if matches.get_flag("audio-only") {
+        dl = dl.audio_only();
+    }
+    if matches.get_flag("video-only") {
+        dl = dl.video_only();
+    }
+    if matches.get_flag("simulate") {
+        dl = dl.fetch_audio(false)
+            .fetch_video(false)
+            .fetch_subtitles(false);
+    }
+    if matches.get_flag("no-period-concatenation") {
+        dl = dl.concatenate_periods(false);
+    } else {
+        dl = dl.concatenate_periods(true);
+    }
+    if let Some(mps) = matches.get_many::<String>("muxer-preference") {
+        for mp in mps.collect::<Vec<_>>() {
+            if let Some((container, ordering)) = mp.split_once(':') {
+                // The builtin muxer is not run by the DashDownloader (see download_builtin).
+                let others: Vec<&str> = ordering.split(',')
+                    .filter(|m| *m != "builtin")
+                    .collect();
+                if !others.is_empty() {
+                    dl = dl.with_muxer_preference(container, &others.join(","));
+                }
+            } else {
+                warn!("Ignoring badly formatted container:ordering argument to --muxer-preference");
+            }
+        }
+    }
+    if let Some(mps) = matches.get_many::<String>("concat-preference") {
+        for mp in mps.collect::<Vec<_>>() {
+            if let Some((container, ordering)) = mp.split_once(':') {
+                dl = dl.with_concat_preference(container, ordering);
+            } else {
+                warn!("Ignoring badly formatted container:ordering argument to --concat-preference");
+            }
+        }
+    }
+    if let Some(rp) = matches.get_one::<String>("role-preference") {
+        let ordering: Vec<String> = rp.split(',')
+            .map(str::to_string)
+            .collect();
+        if !ordering.is_empty() {
+            dl = dl.prefer_roles(ordering);
+        } else {
+            warn!("Ignoring badly formatted role1,role2,role3 argument to --role-preference");
+        }
+    }
+    if matches.get_flag("ignore-content-type") {
+        dl = dl.without_content_type_checks();
+    }
+    if let Some(ffmpeg_path) = matches.get_one::<String>("ffmpeg-location") {
+        dl = dl.with_ffmpeg(ffmpeg_path);
+    }
+    if let Some(path) = matches.get_one::<String>("vlc-location") {
+        dl = dl.with_vlc(path);
+    }
+    if let Some(path) = matches.get_one::<String>("mkvmerge-location") {
+        dl = dl.with_mkvmerge(path);
+    }
+    if let Some(path) = matches.get_one::<String>("mp4box-location") {
+        dl = dl.with_mp4box(path);
+    }
+    if let Some(path) = matches.get_one::<String>("mp4decrypt-location") {
+        dl = dl.with_mp4decrypt(path);
+    }
+    if let Some(path) = matches.get_one::<String>("shaka-packager-location") {
+        dl = dl.with_shaka_packager(path);
+    }
+    if let Some(w) = matches.get_one::<u64>("prefer-video-width") {
+        dl = dl.prefer_video_width(*w);
+    }
+    if let Some(h) = matches.get_one::<u64>("prefer-video-height") {
+        dl = dl.prefer_video_height(*h);
+    }
+    if let Some(codec_list) = matches.get_one::<String>("prefer-video-codecs") {
+        let ordering: Vec<String> = codec_list.split(',')
+            .map(str::to_string)
+            .collect();
+        if !ordering.is_empty() {
+            dl = dl.prefer_video_codecs(ordering);
+        } else {
+            warn!("Ignoring badly formatted codec1,codec2 argument to --prefer-video-codecs");
+        }
+    }
+    if let Some(substring) = matches.get_one::<String>("want-video-id") {
+        dl = dl.want_video_id_substring(substring.clone());
+    }
+    // It's possible to specify both prefer-video-width/height and quality. The former is not
+    // relevant concerning the audio stream, where the quality preference will be used. For the
+    // choice of video stream if both are specified, preference will be given to the preferred
+    // width, then height, then quality.
+    if let Some(q) = matches.get_one::<String>("quality") {
+        // DashDownloader defaults to worst quality
+        if q.eq("best") {
+            dl = dl.best_quality();
+        } else if q.eq("intermediate") {
+            dl = dl.intermediate_quality();
+        }
+    }
+    if let Some(lang) = matches.get_one::<String>("prefer-language") {
+        dl = dl.prefer_language(lang.clone());
+    }
+    if let Some(lang) = matches.get_one::<String>("prefer-audio-language") {
+        dl = dl.prefer_audio_language(lang.clone());
+    }
+    if let Some(lang) = matches.get_one::<String>("prefer-subtitle-language") {
+        dl = dl.prefer_subtitle_language(lang.clone());
+    }
+    if let Some(stylesheets) = matches.get_many::<String>("xslt-stylesheet") {
+        for stylesheet in stylesheets {
+            dl = dl.with_xslt_stylesheet(stylesheet);
+        }
+    }
+    if let Some(xpaths) = matches.get_many::<String>("drop-elements") {
+        for xpath in xpaths {
+            let xslt = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+  <xsl:stylesheet version="1.0"
+     xmlns:xsl="http://www.w3.org/1999/XSL/Transform"
+     xmlns:mpd="urn:mpeg:dash:schema:mpd:2011">
+  <xsl:template match="@*|node()">
+    <xsl:copy><xsl:apply-templates select="@*|node()"/></xsl:copy></xsl:template>
+  <xsl:template match="{xpath}" />
+</xsl:stylesheet>"#);
+            let stylesheet = tempfile::Builder::new()
+                .suffix(".xslt")
+                .rand_bytes(7)
+                .tempfile()
+                .context("creating temporary XSLT stylesheet")?;
+            fs::write(&stylesheet, xslt)
+                .context("writing XSLT to temporary stylesheet file")?;
+            let (_, stylesheet_path) = stylesheet.keep()?;
+            dl = dl.with_xslt_stylesheet(stylesheet_path);
+        }
+    }
+    if let Some(secs) = matches.get_one::<u64>("minimum-period-duration") {
+        dl = dl.minimum_period_duration(Duration::from_secs(*secs));
+    }
+    if let Some(user) = matches.get_one::<String>("auth-username") {
+        if let Some(password) = matches.get_one::<String>("auth-password") {
+            dl = dl.with_authentication(user, password);
+        }
+    }
+    if let Some(token) = matches.get_one::<String>("auth-bearer") {
+        dl = dl.with_auth_bearer(token);
+    }
+    #[cfg(feature = "sandbox")]
+    {
+        if matches.get_flag("sandbox") {
+            dl = dl.sandbox(true);
+        }
+    }
+    dl = dl.verbosity(verbosity);
+    Ok(dl)
+}
+
+// Our own requests for the manifest use the same HTTP client and authentication as the DashDownloader.
+fn manifest_fetcher(matches: &ArgMatches, client: &reqwest::Client) -> ManifestFetcher {
+    let mut fetcher = ManifestFetcher::new(client.clone());
+    if let Some(url) = matches.get_one::<String>("referer") {
+        fetcher = fetcher.with_referer(url.clone());
+    }
+    if let Some(user) = matches.get_one::<String>("auth-username") {
+        if let Some(password) = matches.get_one::<String>("auth-password") {
+            fetcher = fetcher.with_authentication(user, password);
+        }
+    }
+    if let Some(token) = matches.get_one::<String>("auth-bearer") {
+        fetcher = fetcher.with_auth_bearer(token);
+    }
+    fetcher
+}
+
+// Fetch the manifest at mpd_url, with segment URLs resolved relative to --base-url if specified.
+async fn fetch_manifest(matches: &ArgMatches, fetcher: &ManifestFetcher, mpd_url: &str) -> Result<Manifest> {
+    let mut manifest = fetcher.fetch(mpd_url).await?;
+    if let Some(bu) = matches.get_one::<String>("base-url") {
+        manifest.url = Url::parse(bu).context("parsing --base-url")?;
+    }
+    Ok(manifest)
+}
+
+// The section of the content requested with --start-time and --end-time, if any.
+fn clip_range(matches: &ArgMatches) -> Result<Option<ClipRange>> {
+    let start = matches.get_one::<f64>("start-time").copied();
+    let end = matches.get_one::<f64>("end-time").copied();
+    if start.is_none() && end.is_none() {
+        return Ok(None);
+    }
+    let start = start.unwrap_or(0.0);
+    if let Some(end) = end {
+        if end <= start {
+            return Err(anyhow!("--end-time must be later than --start-time"));
+        }
+    }
+    Ok(Some(ClipRange { start, end }))
+}
+
+// The commandline options that determine which Representations are selected for download. A change
+// to these options between runs invalidates the resume journal.
+const SELECTION_OPTIONS: [&str; 21] = [
+    "quality", "prefer-video-width", "prefer-video-height", "prefer-video-codecs", "want-video-id",
+    "prefer-language", "prefer-audio-language", "prefer-subtitle-language", "role-preference",
+    "video-only", "audio-only", "write-subs", "xslt-stylesheet", "drop-elements",
+    "minimum-period-duration", "periods", "skip-ads", "start-time", "end-time", "format",
+    "audio-tracks"];
+
+fn selection_options(matches: &ArgMatches) -> String {
+    SELECTION_OPTIONS.iter()
+        .map(|id| {
+            let values: Vec<String> = matches.get_raw(id)
+                .into_iter()
+                .flatten()
+                .map(|v| v.to_string_lossy().to_string())
+                .collect();
+            format!("{id}={}", values.join(","))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// With --continue, the download goes through a resume session, which serves the media segments saved
+// by a previous interrupted run and records newly downloaded media segments in a journal.
+async fn start_resume_session(
+    matches: &ArgMatches,
+    client: &reqwest::Client,
+    manifest: &Manifest,
+    item: &BatchItem) -> Result<ResumeSession>
+{
+    let Some(output) = &item.output else {
+        return Err(anyhow!("--continue requires an output path (--output or in the batch file)"));
+    };
+    let fragments_dir = matches.get_one::<String>("save-fragments").map(Path::new);
+    let dir = resume_dir(Path::new(output), fragments_dir);
+    ResumeSession::start(manifest_fetcher(matches, client), manifest, &item.url, &dir,
+                         &selection_options(matches)).await
+}
+
+// Clean up the resume session once the download has completed, or keep its journal if it failed.
+fn end_resume_session(session: Option<ResumeSession>, outcome: &Result<PathBuf, DashMpdError>) {
+    if let Some(session) = session {
+        if outcome.is_ok() {
+            if let Err(e) = session.finish() {
+                warn!("Can't clean up resume journal: {e:#}");
+            }
+        } else {
+            session.keep();
+        }
+    }
+}
+
+fn stream_preferences(matches: &ArgMatches) -> StreamPreferences {
+    let list = |id: &str| matches.get_one::<String>(id)
+        .map(|l| l.split(',').map(str::to_string).collect())
+        .unwrap_or_default();
+    StreamPreferences {
+        quality: matches.get_one::<String>("quality").cloned(),
+        prefer_video_width: matches.get_one::<u64>("prefer-video-width").copied(),
+        prefer_video_height: matches.get_one::<u64>("prefer-video-height").copied(),
+        prefer_video_codecs: list("prefer-video-codecs"),
+        want_video_id: matches.get_one::<String>("want-video-id").cloned(),
+        prefer_audio_language: matches.get_one::<String>("prefer-audio-language")
+            .or(matches.get_one::<String>("prefer-language"))
+            .cloned(),
+        prefer_subtitle_language: matches.get_one::<String>("prefer-subtitle-language")
+            .or(matches.get_one::<String>("prefer-language"))
+            .cloned(),
+        role_preference: list("role-preference"),
+    }
+}
+
+// Whether live streams are recorded, which is implied by the options that control the recording.
+fn live_streams_enabled(matches: &ArgMatches) -> bool {
+    matches.get_flag("enable-live-streams") ||
+        matches.get_flag("live-from-start") ||
+        matches.contains_id("live-start-offset") ||
+        matches.contains_id("record-duration") ||
+        matches.contains_id("record-until")
+}
+
+fn live_options(matches: &ArgMatches) -> LiveOptions {
+    LiveOptions {
+        record_duration: matches.get_one::<f64>("record-duration").copied(),
+        record_until: matches.get_one::<SystemTime>("record-until").copied(),
+        preferences: stream_preferences(matches),
+        video_only: matches.get_flag("video-only"),
+        audio_only: matches.get_flag("audio-only"),
+        fragment_retries: *matches.get_one::<u32>("fragment-retries").unwrap_or(&10),
+        from_start: matches.get_flag("live-from-start"),
+        start_offset: matches.get_one::<f64>("live-start-offset").copied(),
+    }
+}
+
+// The manifest to be downloaded by the DashDownloader, after the steps that prepare it.
+#[derive(Default)]
+struct PreparedManifest {
+    // The URL of the rewritten manifest, if it was rewritten.
+    url: Option<String>,
+    session: Option<ResumeSession>,
+    // Keeps the rewritten manifest file alive until the download has completed.
+    _file: Option<tempfile::NamedTempFile>,
+    // The presentation time at which the downloaded content starts, with --start-time.
+    clip_origin: Option<f64>,
+    // Chapters in the timeline of the output file, and the duration of the output file, with
+    // --embed-chapters.
+    chapters: Option<(Vec<Chapter>, f64)>,
+    // The audio tracks to be included in the output file with --audio-tracks. The first track is
+    // downloaded with the video, the others from the manifests in extra_audio.
+    audio_tracks: Vec<AudioTrack>,
+    extra_audio: Vec<(String, tempfile::NamedTempFile)>,
+    // The subtitle tracks to be saved with --sub-langs.
+    subtitles: Vec<SubtitleTrack>,
+    // Provenance metadata for --embed-metadata and --write-info-json.
+    metadata: Option<Metadata>,
+    // The output path and the --keep-video and --keep-audio paths expanded from --output-template.
+    output: Option<String>,
+    keep_video: Option<String>,
+    keep_audio: Option<String>,
+    // With the builtin muxer or the builtin decryptor, the kinds of media ("video", "audio") to
+    // download to separate files, and the subtitle track selected by --write-subs.
+    media_kinds: Vec<&'static str>,
+    builtin_subtitles: Option<SubtitleTrack>,
+    // The decryption keys given by the user and obtained from ClearKey license servers, if the
+    // manifest was analyzed.
+    keys: Option<Vec<Key>>,
+    // The publication time of the content, for --mtime.
+    publication_time: Option<SystemTime>,
+}
+
+// Prepare the manifest of item for the DashDownloader: list its Periods in simulation mode, select
+// Periods, Representations and a section of the content, determine chapters, acquire ClearKey
+// licenses, select decryption keys, note the publication time of the content for --mtime and start
+// a resume session.
+async fn prepare_manifest(
+    matches: &ArgMatches,
+    client: &reqwest::Client,
+    fetcher: &ManifestFetcher,
+    item: &BatchItem) -> Result<PreparedManifest>
+{
+    let mut prepared = PreparedManifest::default();
+    let simulate = matches.get_flag("simulate");
+    let clip = clip_range(matches)?;
+    let periods = matches.get_one::<PeriodSelection>("periods");
+    let skip_ads = matches.get_flag("skip-ads");
+    let splice_chapters = matches.get_flag("scte35-chapters");
+    let embed_chapters = matches.get_flag("embed-chapters") || splice_chapters;
+    let format = matches.get_one::<FormatSelector>("format");
+    let tracks = matches.get_one::<LanguageSelection>("audio-tracks");
+    let sub_langs = matches.get_one::<LanguageSelection>("sub-langs");
+    let want_metadata = matches.get_flag("embed-metadata") || matches.get_flag("write-info-json");
+    let template = matches.get_one::<OutputTemplate>("output-template");
+    let builtin = builtin_preferred(matches.get_many::<String>("muxer-preference").into_iter().flatten(), None);
+    let decrypt = builtin_decryption(matches);
+    let mut keys = all_keys(matches);
+    let resume = matches.get_flag("continue") && !simulate;
+    let mtime = matches.get_flag("mtime") && !simulate;
+    let rewrite = clip.is_some() || periods.is_some() || skip_ads || format.is_some() || tracks.is_some();
+    let analyze = embed_chapters || sub_langs.is_some() || want_metadata || template.is_some() || builtin || decrypt
+        || !keys.is_empty() || mtime;
+    if !(resume || rewrite || simulate || analyze) {
+        return Ok(prepared);
+    }
+    let mut manifest = match fetch_manifest(matches, fetcher, &item.url).await {
+        Ok(m) => m,
+        // When the manifest is only analyzed (to list its Periods in simulation mode or to look for
+        // ClearKey license servers), failing to fetch it isn't fatal; the DashDownloader will report
+        // the problem.
+        Err(e) if !(resume || rewrite || sub_langs.is_some() || want_metadata || template.is_some() || builtin || decrypt) => {
+            warn!("Can't analyze the manifest: {e:#}");
+            return Ok(prepared);
+        },
+        Err(e) => return Err(e),
+    };
+    prepared.publication_time = manifest.publication_time();
+    if simulate {
+        log_periods(&manifest, periods);
+    }
+    let prefs = stream_preferences(matches);
+    // The kinds of media that may be encrypted.
+    let media: Vec<&'static str> = download_kinds(matches).into_iter()
+        .filter(|kind| *kind != "text")
+        .collect();
+    let mut extra_streams = Vec::new();
+    let mut kids = Vec::new();
+    let mut licenses = Vec::new();
+    if manifest.mpd.mpdtype.as_deref() == Some("dynamic") {
+        let ignored: Vec<&str> = ["periods", "skip-ads", "start-time", "end-time", "format", "embed-chapters",
+                                  "audio-tracks", "sub-langs"]
+            .into_iter()
+            .filter(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+            .collect();
+        if !ignored.is_empty() {
+            warn!("Ignoring --{} for live stream", ignored.join(", --"));
+        }
+        if decrypt {
+            warn!("The builtin decryptor doesn't handle live streams, which will be saved without decryption");
+        }
+    } else {
+        select_streams(matches, &mut manifest)?;
+        let mut chapters = None;
+        if embed_chapters {
+            if matches.get_flag("no-period-concatenation") && manifest.mpd.periods.len() > 1 {
+                warn!("Not adding chapters, because Periods are saved to separate files with --no-period-concatenation");
+            } else {
+                chapters = Some(manifest_chapters(&manifest, splice_chapters));
+            }
+        }
+        let mut intervals = period_intervals(&manifest.mpd);
+        if let Some(range) = &clip {
+            let clipped = clip_manifest(fetcher, &manifest, range).await
+                .context("selecting time range")?;
+            manifest.mpd = clipped.mpd;
+            prepared.clip_origin = Some(clipped.origin);
+            intervals = clipped.intervals;
+        }
+        if let Some(chapters) = chapters {
+            let duration = intervals.iter().map(|(from, to)| to - from).sum();
+            prepared.chapters = Some((output_chapters(&chapters, &intervals), duration));
+        }
+        if let Some(selection) = sub_langs {
+            prepared.subtitles = subtitle_tracks(&manifest, selection)?;
+        }
+        if let Some(selection) = tracks {
+            prepared.audio_tracks = audio_tracks(&manifest.mpd, selection)?;
+            for track in &prepared.audio_tracks[1..] {
+                let mpd = with_audio_track(&manifest.mpd, track, true)?;
+                extra_streams.extend(selected_streams(&mpd, &prefs, &["audio"]).into_iter()
+                                     .map(|s| SelectedStream { key: None, ..s }));
+                kids.extend(manifest_kids(&mpd, &prefs, &["audio"]));
+                let extra = Manifest { mpd, url: manifest.url.clone(), last_modified: None };
+                licenses.extend(license_requests(&extra, &prefs, &["audio"]));
+                let file = write_manifest(&extra)?;
+                prepared.extra_audio.push((manifest_file_url(&file)?, file));
+            }
+            manifest.mpd = with_audio_track(&manifest.mpd, &prepared.audio_tracks[0], false)?;
+        }
+        if rewrite {
+            let file = write_manifest(&manifest)?;
+            prepared.url = Some(manifest_file_url(&file)?);
+            prepared._file = Some(file);
+        }
+    }
+    if want_metadata {
+        let mut streams = selected_streams(&manifest.mpd, &prefs, &download_kinds(matches));
+        streams.extend(extra_streams);
+        prepared.metadata = Some(Metadata::new(&manifest.mpd, &item.url, streams));
+    }
+    if let Some(template) = template {
+        expand_output_template(matches, template, &manifest, item, &mut prepared)?;
+    }
+    if manifest.mpd.mpdtype.as_deref() != Some("dynamic") {
+        licenses.extend(license_requests(&manifest, &prefs, &media));
+    }
+    for mut request in licenses {
+        if request.kids.is_empty() {
+            warn!("Not requesting a ClearKey license from {}: the manifest doesn't declare the KIDs of the streams", request.url);
+            continue;
+        }
+        request.kids.retain(|kid| !keys.iter().any(|k| k.kid == *kid));
+        if request.kids.is_empty() {
+            continue;
+        }
+        let acquired = acquire_keys(fetcher, &request).await
+            .with_context(|| format!("requesting ClearKey license from {}", request.url))?;
+        info!("Obtained {} decryption keys from ClearKey license server {}", acquired.len(), request.url);
+        keys.extend(acquired);
+    }
+    if !keys.is_empty() {
+        for kid in manifest_kids(&manifest.mpd, &prefs, &media) {
+            if !kids.iter().any(|(k, _)| *k == kid.0) {
+                kids.push(kid);
+            }
+        }
+        warn_missing_keys(&keys, &kids);
+        prepared.keys = Some(keys);
+    }
+    if (builtin || decrypt) && manifest.mpd.mpdtype.as_deref() != Some("dynamic") {
+        if let Some(period) = manifest.mpd.periods.first() {
+            prepared.media_kinds = download_kinds(matches).into_iter()
+                .filter(|kind| *kind != "text" && select_representation(period, kind, &prefs).is_some())
+                .collect();
+        }
+        if matches.get_flag("write-subs") && sub_langs.is_none() {
+            prepared.builtin_subtitles = preferred_subtitle_track(&manifest, &prefs)?;
+        }
+    }
+    if resume {
+        let item = BatchItem { url: item.url.clone(), output: prepared.output.clone().or(item.output.clone()) };
+        let session = start_resume_session(matches, client, &manifest, &item).await?;
+        prepared.url = Some(session.manifest_url().to_string());
+        prepared.session = Some(session);
+    }
+    Ok(prepared)
+}
+
+// The kinds of media that are downloaded from each Period.
+fn download_kinds(matches: &ArgMatches) -> Vec<&'static str> {
+    let mut kinds = Vec::new();
+    if !matches.get_flag("audio-only") {
+        kinds.push("video");
+    }
+    if !matches.get_flag("video-only") {
+        kinds.push("audio");
+    }
+    if matches.get_flag("write-subs") && !matches.contains_id("sub-langs") {
+        kinds.push("text");
+    }
+    kinds
+}
+
+// Build the output path of item from --output-template, if no output path was specified for it, and
+// the --keep-video and --keep-audio paths, creating any missing directories.
+fn expand_output_template(
+    matches: &ArgMatches,
+    template: &OutputTemplate,
+    manifest: &Manifest,
+    item: &BatchItem,
+    prepared: &mut PreparedManifest) -> Result<()>
+{
+    let simulate = matches.get_flag("simulate");
+    let fields = template_fields(&manifest.mpd, &item.url, &stream_preferences(matches), &download_kinds(matches));
+    let create_dirs = |path: &str| -> Result<()> {
+        if let Some(dir) = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+            if !simulate {
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("creating output directory {}", dir.display()))?;
+            }
+        }
+        Ok(())
+    };
+    if item.output.is_none() {
+        let preferred_container = matches.get_many::<String>("muxer-preference").into_iter().flatten()
+            .find_map(|mp| mp.split_once(':').map(|(container, _)| container));
+        let output = template.expand(&fields, &fields.output_extension(preferred_container));
+        create_dirs(&output)?;
+        if simulate {
+            info!("Output would be saved to {output}");
+        }
+        prepared.output = Some(output);
+    }
+    let keep = [("keep-video", fields.video_ext.as_deref()), ("keep-audio", fields.audio_ext.as_deref())];
+    for (id, ext) in keep {
+        if let Some(path) = matches.get_one::<String>(id) {
+            let path = parse_output_template(path)
+                .map_err(|e| anyhow!("--{id}: {e}"))?
+                .expand(&fields, ext.unwrap_or("mp4"));
+            create_dirs(&path)?;
+            if id == "keep-video" {
+                prepared.keep_video = Some(path);
+            } else {
+                prepared.keep_audio = Some(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Whether the media streams are to be decrypted by the builtin decryptor.
+fn builtin_decryption(matches: &ArgMatches) -> bool {
+    matches.get_one::<String>("decryption-application").is_some_and(|app| app == "builtin")
+}
+
+// The decryption keys given with --key and --key-file.
+fn all_keys(matches: &ArgMatches) -> Vec<Key> {
+    let mut keys: Vec<Key> = matches.get_many::<Key>("key").into_iter().flatten().cloned().collect();
+    keys.extend(matches.get_many::<Vec<Key>>("key-file").into_iter().flatten().flatten().cloned());
+    keys
+}
+
+// The decryption keys for the manifest: our keys together with those obtained from ClearKey license
+// servers, or only our keys if the manifest wasn't analyzed.
+fn manifest_keys(matches: &ArgMatches, prepared: &PreparedManifest) -> Vec<Key> {
+    prepared.keys.clone().unwrap_or_else(|| all_keys(matches))
+}
+
+// Pass the decryption keys and the choice of decryption application to the DashDownloader, unless
+// the builtin decryptor is used (see download_builtin).
+fn with_decryption(mut dl: DashDownloader, matches: &ArgMatches, prepared: &PreparedManifest) -> DashDownloader {
+    if builtin_decryption(matches) {
+        return dl;
+    }
+    for key in manifest_keys(matches, prepared) {
+        dl = dl.add_decryption_key(key.kid, key.key);
+    }
+    if let Some(app) = matches.get_one::<String>("decryption-application") {
+        dl = dl.with_decryptor_preference(app);
+    }
+    dl
+}
+
+// Whether the builtin muxer comes first in the --muxer-preference ordering for the container of path.
+fn builtin_muxing(matches: &ArgMatches, path: &Path) -> bool {
+    let container = path.extension().unwrap_or_default().to_string_lossy().to_string();
+    builtin_preferred(matches.get_many::<String>("muxer-preference").into_iter().flatten(), Some(container.as_str()))
+}
+
+// The output path, if the streams are to be downloaded to separate files by download_builtin: to be
+// decrypted by the builtin decryptor, or to be combined by the builtin muxer when it comes first in
+// the --muxer-preference ordering for the output container and there are several streams to combine.
+fn builtin_output(
+    matches: &ArgMatches,
+    url: &str,
+    output: Option<&str>,
+    prepared: &PreparedManifest) -> Option<PathBuf>
+{
+    if matches.get_flag("simulate") {
+        return None;
+    }
+    let path = output.map_or_else(|| PathBuf::from(format!("{}.mp4", url_stem(url))), PathBuf::from);
+    if builtin_decryption(matches) && !prepared.media_kinds.is_empty() {
+        return Some(path);
+    }
+    if matches.get_flag("audio-only") || matches.get_flag("video-only") {
+        return None;
+    }
+    let fragmented_subs = prepared.builtin_subtitles.as_ref().is_some_and(|t| t.format.is_fragmented());
+    if prepared.media_kinds.len() + usize::from(fragmented_subs) < 2 {
+        return None;
+    }
+    builtin_muxing(matches, &path).then_some(path)
+}
+
+// Download the video and audio streams to separate files, decrypt them with the builtin decryptor,
+// and combine them with a wvtt or stpp subtitle track selected by --write-subs into the output file
+// using the builtin muxer. When the builtin muxer isn't preferred for the output container, the
+// decrypted streams are combined by ffmpeg. Sidecar subtitles are saved next to the output file.
+async fn download_builtin(
+    matches: &ArgMatches,
+    client: &reqwest::Client,
+    mpd_url: &str,
+    prepared: &PreparedManifest,
+    out: &Path,
+    job: usize,
+    ctx: &JobContext) -> Result<PathBuf, DashMpdError>
+{
+    let fetcher = manifest_fetcher(matches, client);
+    let ffmpeg = ffmpeg_path(matches);
+    let mux_builtin = builtin_muxing(matches, out);
+    let keys = builtin_decryption(matches).then(|| DecryptionKeys::new(&manifest_keys(matches, prepared)));
+    let mut files = Vec::new();
+    for kind in &prepared.media_kinds {
+        let path = out.with_extension(format!("{kind}.mp4"));
+        let dl = make_downloader(matches, client, mpd_url, job, ctx)
+            .map_err(|e| DashMpdError::Other(format!("{e:#}")))?;
+        let dl = with_decryption(with_side_effects(dl, matches), matches, prepared)
+            .fetch_subtitles(false);
+        let dl = if *kind == "video" { dl.video_only() } else { dl.audio_only() };
+        let mut outcome = dl.download_to(&path).await.map(|_| ());
+        files.push(path.clone());
+        if let Some(keys) = keys.as_ref().filter(|_| outcome.is_ok()) {
+            let keys = keys.clone();
+            outcome = tokio::task::spawn_blocking(move || decrypt_file(&path, &keys)).await
+                .map_err(|e| DashMpdError::Other(format!("decrypting {kind} stream: {e}")))
+                .and_then(|decrypted| decrypted.map(|_| ()).map_err(|e| DashMpdError::Decrypting(format!("{e:#}"))));
+        }
+        if let Err(e) = outcome {
+            for f in &files {
+                let _ = fs::remove_file(f);
+            }
+            return Err(e);
+        }
+    }
+    if let Some(track) = &prepared.builtin_subtitles {
+        if track.format.is_fragmented() && mux_builtin {
+            let path = out.with_extension("subs.mp4");
+            match save_fragmented_track(&fetcher, track, &path).await {
+                Ok(()) => files.push(path),
+                Err(e) => {
+                    warn!("Can't download subtitles: {e:#}");
+                    let _ = fs::remove_file(&path);
+                },
+            }
+        } else if let Err(e) = save_sidecar_track(&fetcher, &ffmpeg, track, out).await {
+            warn!("Can't download subtitles: {e:#}");
+        }
+    }
+    let kept = [("video", prepared.keep_video.as_ref().or(matches.get_one::<String>("keep-video"))),
+                ("audio", prepared.keep_audio.as_ref().or(matches.get_one::<String>("keep-audio")))];
+    for (kind, keep) in kept {
+        let stream = out.with_extension(format!("{kind}.mp4"));
+        if let Some(keep) = keep.filter(|_| files.contains(&stream)) {
+            if let Err(e) = fs::copy(&stream, keep) {
+                warn!("Can't keep {kind} stream: {e}");
+            }
+        }
+    }
+    let output = out.to_path_buf();
+    let inputs = files.clone();
+    let mp4 = ["mp4", "m4v", "m4a"].iter()
+        .any(|ext| out.extension().is_some_and(|e| e.eq_ignore_ascii_case(ext)));
+    let muxed = tokio::task::spawn_blocking(move || {
+        if mux_builtin {
+            mux_fragmented(&inputs, &output)
+        } else if inputs.len() == 1 && mp4 {
+            Ok(fs::rename(&inputs[0], &output)?)
+        } else {
+            mux_streams(&ffmpeg, &inputs, &output)
+        }
+    }).await
+        .map_err(|e| DashMpdError::Other(format!("muxing streams: {e}")));
+    for f in files.iter().filter(|f| f.exists()) {
+        if let Err(e) = fs::remove_file(f) {
+            warn!("Can't remove temporary media file: {e}");
+        }
+    }
+    muxed?.map_err(|e| DashMpdError::Muxing(format!("{e:#}")))?;
+    Ok(out.to_path_buf())
+}
+
+// Save the video and audio streams to the --keep-video and --keep-audio paths (as expanded from
+// --output-template). download_builtin copies the streams it downloads to these paths itself.
+fn with_kept_streams(mut dl: DashDownloader, matches: &ArgMatches, prepared: &PreparedManifest) -> DashDownloader {
+    if let Some(path) = prepared.keep_video.as_ref().or(matches.get_one::<String>("keep-video")) {
+        dl = dl.keep_video_as(path);
+    }
+    if let Some(path) = prepared.keep_audio.as_ref().or(matches.get_one::<String>("keep-audio")) {
+        dl = dl.keep_audio_as(path);
+    }
+    dl
+}
+
+// The side effects of the main download of an item, which the auxiliary downloads of additional audio
+// tracks must not repeat: saving the media fragments, fetching the subtitles and recording the
+// provenance of the output file in extended attributes.
+fn with_side_effects(mut dl: DashDownloader, matches: &ArgMatches) -> DashDownloader {
+    if let Some(fragments_dir) = matches.get_one::<String>("save-fragments") {
+        dl = dl.save_fragments_to(Path::new(fragments_dir));
+    }
+    // With --sub-langs, we download the subtitles ourselves.
+    if matches.get_flag("write-subs") && !matches.contains_id("sub-langs") && !matches.get_flag("simulate") {
+        dl = dl.fetch_subtitles(true);
+    }
+    if matches.get_flag("no-xattr") {
+        dl = dl.record_metainformation(false);
+    }
+    dl
+}
+
+// Download the audio tracks after the first one requested with --audio-tracks, and add them to the
+// output file.
+async fn add_audio_tracks(
+    matches: &ArgMatches,
+    client: &reqwest::Client,
+    prepared: &PreparedManifest,
+    out: &Path,
+    job: usize,
+    ctx: &JobContext) -> Result<(), DashMpdError>
+{
+    let mut files = Vec::new();
+    for (n, (url, _)) in prepared.extra_audio.iter().enumerate() {
+        let path = out.with_extension(format!("audio{}.mp4", n + 2));
+        let dl = make_downloader(matches, client, url, job, ctx)
+            .map_err(|e| DashMpdError::Other(format!("{e:#}")))?;
+        let dl = with_decryption(dl, matches, prepared)
+            .fetch_subtitles(false)
+            .record_metainformation(false)
+            .audio_only();
+        let outcome = dl.download_to(&path).await;
+        files.push(path);
+        if let Err(e) = outcome {
+            for f in &files {
+                let _ = fs::remove_file(f);
+            }
+            return Err(e);
+        }
+    }
+    let ffmpeg = ffmpeg_path(matches);
+    let path = out.to_path_buf();
+    let tracks = prepared.audio_tracks.clone();
+    let extra = files.clone();
+    let muxed = tokio::task::spawn_blocking(move || mux_audio_tracks(&ffmpeg, &path, &extra, &tracks)).await
+        .map_err(|e| DashMpdError::Other(format!("adding audio tracks: {e}")));
+    for f in &files {
+        if let Err(e) = fs::remove_file(f) {
+            warn!("Can't remove temporary audio file: {e}");
+        }
+    }
+    muxed?.map_err(|e| DashMpdError::Muxing(format!("{e:#}")))
+}
+
+// Save the subtitle tracks selected by --sub-langs next to the output file, and add them to the
+// output file with --embed-subs.
+async fn add_subtitles(
+    matches: &ArgMatches,
+    fetcher: &ManifestFetcher,
+    prepared: &PreparedManifest,
+    out: &Path) -> Result<(), DashMpdError>
+{
+    let ffmpeg = ffmpeg_path(matches);
+    let convert = convert_subs(matches);
+    let saved = download_subtitles(fetcher, &ffmpeg, &prepared.subtitles, convert, out).await;
+    if matches.get_flag("embed-subs") && !saved.is_empty() {
+        let path = out.to_path_buf();
+        tokio::task::spawn_blocking(move || embed_subtitles(&ffmpeg, &path, &saved)).await
+            .map_err(|e| DashMpdError::Other(format!("adding subtitles: {e}")))?
+            .map_err(|e| DashMpdError::Muxing(format!("{e:#}")))?;
+    }
+    Ok(())
+}
+
+// Record the provenance of the download in the output file with --embed-metadata, and in a sidecar
+// JSON file with --write-info-json.
+async fn add_metadata(
+    matches: &ArgMatches,
+    metadata: &Metadata,
+    out: &Path) -> Result<(), DashMpdError>
+{
+    if matches.get_flag("write-info-json") {
+        write_info_json(out, metadata)
+            .map_err(|e| DashMpdError::Other(format!("{e:#}")))?;
+    }
+    if matches.get_flag("embed-metadata") {
+        let ffmpeg = ffmpeg_path(matches);
+        let path = out.to_path_buf();
+        let metadata = metadata.clone();
+        tokio::task::spawn_blocking(move || embed_metadata(&ffmpeg, &path, &metadata)).await
+            .map_err(|e| DashMpdError::Other(format!("adding metadata: {e}")))?
+            .map_err(|e| DashMpdError::Muxing(format!("{e:#}")))?;
+    }
+    Ok(())
+}
+
+fn convert_subs(matches: &ArgMatches) -> Option<TargetFormat> {
+    matches.get_one::<String>("convert-subs")
+        .and_then(|name| TargetFormat::from_name(name))
+}
+
+fn ffmpeg_path(matches: &ArgMatches) -> String {
+    matches.get_one::<String>("ffmpeg-location")
+        .cloned()
+        .unwrap_or_else(|| String::from("ffmpeg"))
+}
+
+// Postprocess the output file written by the DashDownloader: cut it at the precise --start-time and
+// --end-time with --exact-trim, and add chapters with --embed-chapters.
+async fn finish_output(
+    matches: &ArgMatches,
+    prepared: &PreparedManifest,
+    out: &Path) -> Result<(), DashMpdError>
+{
+    let mut chapters = prepared.chapters.clone();
+    let clip = clip_range(matches)
+        .map_err(|e| DashMpdError::Other(format!("{e:#}")))?;
+    if let (Some(range), Some(origin)) = (clip, prepared.clip_origin) {
+        if matches.get_flag("exact-trim") {
+            let ffmpeg = ffmpeg_path(matches);
+            let path = out.to_path_buf();
+            let offset = (range.start - origin).max(0.0);
+            tokio::task::spawn_blocking(move || trim_exact(&ffmpeg, &path, offset, range.duration())).await
+                .map_err(|e| DashMpdError::Other(format!("trimming output: {e}")))?
+                .map_err(|e| DashMpdError::Muxing(format!("{e:#}")))?;
+            info!("Trimmed output to the requested time range");
+            if let Some((ch, duration)) = chapters {
+                let remaining = duration - offset;
+                let duration = range.duration().map_or(remaining, |d| d.min(remaining));
+                chapters = Some((trim_chapters(&ch, offset), duration));
+            }
+        }
+    }
+    if let Some((ch, duration)) = chapters {
+        let ffmpeg = ffmpeg_path(matches);
+        let path = out.to_path_buf();
+        tokio::task::spawn_blocking(move || embed_chapters(&ffmpeg, &path, &ch, duration)).await
+            .map_err(|e| DashMpdError::Other(format!("adding chapters: {e}")))?
+            .map_err(|e| DashMpdError::Muxing(format!("{e:#}")))?;
+    }
+    Ok(())
+}
+
+// Download from the manifest of one item, including the steps that surround the work of the
+// DashDownloader: recording a live stream, preparing the manifest, resuming an interrupted
+// download, postprocessing the output, setting the output file mtime and reporting on a failed
+// segment.
+async fn download_item(
+    matches: &ArgMatches,
+    client: &reqwest::Client,
+    item: &BatchItem,
+    job: usize,
+    ctx: &JobContext) -> Result<PathBuf, DashMpdError>
+{
+    let fetcher = manifest_fetcher(matches, client);
+    let simulate = matches.get_flag("simulate");
+    let mut mpd_url = item.url.clone();
+    let mut recording = None;
+    let mut publication_time = None;
+    if live_streams_enabled(matches) && !simulate {
+        let output = item.output.as_deref().map(Path::new);
+        recording = record_live(&fetcher, &item.url, output, &live_options(matches)).await
+            .map_err(|e| exitcode::classify(&e.context("recording live stream")))?;
+        if let Some(r) = &recording {
+            mpd_url = r.manifest_url().to_string();
+            publication_time = r.publication_time();
+            if matches.contains_id("output-template") {
+                warn!("Ignoring --output-template when recording a live stream");
+            }
+            if builtin_decryption(matches) {
+                warn!("The builtin decryptor doesn't handle live streams, which will be saved without decryption");
+            }
+            let ignored: Vec<&str> = ["write-subs", "sub-langs", "prefer-subtitle-language"]
+                .into_iter()
+                .filter(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+                .collect();
+            if !ignored.is_empty() {
+                warn!("Ignoring --{} when recording a live stream, because subtitles are not recorded", ignored.join(", --"));
+            }
+        }
+    }
+    let mut prepared = PreparedManifest::default();
+    if recording.is_none() {
+        prepared = prepare_manifest(matches, client, &fetcher, item).await
+            .map_err(|e| exitcode::classify(&e))?;
+        if let Some(url) = &prepared.url {
+            mpd_url = url.clone();
+        }
+        publication_time = prepared.publication_time;
+    }
+    let output = prepared.output.clone().or(item.output.clone());
+    let outcome = match builtin_output(matches, &item.url, output.as_deref(), &prepared) {
+        Some(out) => download_builtin(matches, client, &mpd_url, &prepared, &out, job, ctx).await,
+        None => {
+            let dl = make_downloader(matches, client, &mpd_url, job, ctx)
+                .map_err(|e| DashMpdError::Other(format!("{e:#}")))?;
+            let dl = with_side_effects(with_kept_streams(dl, matches, &prepared), matches);
+            download(with_decryption(dl, matches, &prepared), output.as_deref()).await
+        },
+    };
+    if let Err(e) = &outcome {
+        if matches.get_flag("abort-on-error") {
+            report_failed_segment(&fetcher, &item.url, e, prepared.session.as_ref(),
+                                  output.as_deref().map(Path::new)).await;
+        }
+    }
+    if let (Some(session), Some(metadata)) = (&prepared.session, &mut prepared.metadata) {
+        metadata.record_downloaded(&session.downloaded_representations());
+    }
+    end_resume_session(prepared.session.take(), &outcome);
+    if let Ok(out) = &outcome {
+        if !simulate {
+            if !prepared.extra_audio.is_empty() {
+                add_audio_tracks(matches, client, &prepared, out, job, ctx).await?;
+            }
+            finish_output(matches, &prepared, out).await?;
+            if !prepared.subtitles.is_empty() {
+                add_subtitles(matches, &fetcher, &prepared, out).await?;
+            }
+            if let Some(format) = convert_subs(matches) {
+                if matches.get_flag("write-subs") && !matches.contains_id("sub-langs") {
+                    if let Err(e) = convert_downloaded_subtitles(out, format) {
+                        warn!("Can't convert subtitles: {e:#}");
+                    }
+                }
+            }
+            if let Some(metadata) = &prepared.metadata {
+                add_metadata(matches, metadata, out).await?;
+            }
+        }
+        if matches.get_flag("mtime") && !simulate {
+            if let Err(e) = set_output_mtime(out, publication_time) {
+                warn!("Can't set modification time of {}: {e:#}", out.display());
+            }
+        }
+    }
+    outcome
+}
+
+// What to print about each manifest, instead of downloading it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Report {
+    FormatTable,
+    FormatJson,
+    Dump,
+}
+
+// Remove from a static manifest the Periods that are not selected by --periods and --skip-ads, and
+// the Representations that are not selected by --format.
+fn select_streams(matches: &ArgMatches, manifest: &mut Manifest) -> Result<()> {
+    let periods = matches.get_one::<PeriodSelection>("periods");
+    let skip_ads = matches.get_flag("skip-ads");
+    if periods.is_some() || skip_ads {
+        manifest.mpd = select_periods(manifest, periods, skip_ads)?;
+    }
+    if let Some(selector) = matches.get_one::<FormatSelector>("format") {
+        let quality = matches.get_one::<String>("quality").map(String::as_str);
+        manifest.mpd = apply_format_selector(&manifest.mpd, selector, quality)?;
+    }
+    Ok(())
+}
+
+// Print a report on the manifest of each item (for --list-formats and --dump-json), returning the
+// process exit code.
+async fn print_reports(
+    matches: &ArgMatches,
+    client: &reqwest::Client,
+    items: &[BatchItem],
+    report: Report) -> i32
+{
+    let fetcher = manifest_fetcher(matches, client);
+    let mut code = 0;
+    for item in items {
+        let mut manifest = match fetch_manifest(matches, &fetcher, &item.url).await {
+            Ok(m) => m,
+            Err(e) => {
+                error!("Can't fetch manifest {}: {e:#}", item.url);
+                code = exitcode::DOWNLOAD_FAILED;
+                continue;
+            },
+        };
+        let mut stdout = io::stdout().lock();
+        let written = match report {
+            Report::FormatTable => {
+                info!("Formats available in {}:", item.url);
+                write_format_table(&mut stdout, &manifest_formats(&manifest))
+            },
+            Report::FormatJson => writeln!(stdout, "{}", formats_json(&manifest)),
+            Report::Dump => {
+                if manifest.mpd.mpdtype.as_deref() != Some("dynamic") {
+                    if let Err(e) = select_streams(matches, &mut manifest) {
+                        error!("{}: {e:#}", item.url);
+                        code = exitcode::DOWNLOAD_FAILED;
+                        continue;
+                    }
+                }
+                let opts = DumpOptions {
+                    preferences: stream_preferences(matches),
+                    video_only: matches.get_flag("video-only"),
+                    audio_only: matches.get_flag("audio-only"),
+                    write_subs: matches.get_flag("write-subs"),
+                };
+                writeln!(stdout, "{}", dump_json(&manifest, &opts))
+            },
+        };
+        if let Err(e) = written {
+            error!("Can't write to stdout: {e}");
+            return exitcode::IO;
+        }
+    }
+    code
+}
+
+// Download from each of the items, running up to --jobs downloads in parallel. The HTTP client is
+// shared by all the downloads. A failed download does not abort the others. Returns the outcome of
+// each download, in the same order as items.
+async fn download_all(
+    matches: &ArgMatches,
+    client: &reqwest::Client,
+    items: &[BatchItem],
+    rate_limit: Option<u64>) -> Result<Vec<Result<PathBuf, DashMpdError>>>
+{
+    let jobs = usize::from(*matches.get_one::<u16>("jobs").unwrap_or(&1)).min(items.len());
+    // When the bandwidth isn't shared through our ThrottlingProxy, each DashDownloader throttles
+    // itself, so the bandwidth is split evenly between the jobs, whether or not the other jobs are
+    // still running.
+    let rate_limit = rate_limit.map(|bps| bps / jobs as u64);
+    let mut ctx = JobContext { concurrency: jobs, progress: None, rate_limit };
+    if jobs > 1 {
+        ctx.progress = Some(MultiProgress::new());
+    }
+    let semaphore = Arc::new(Semaphore::new(jobs));
+    let mut running = JoinSet::new();
+    for (idx, item) in items.iter().enumerate() {
+        let matches = matches.clone();
+        let client = client.clone();
+        let item = item.clone();
+        let ctx = ctx.clone();
+        let semaphore = Arc::clone(&semaphore);
+        running.spawn(async move {
+            let _permit = semaphore.acquire_owned().await
+                .expect("acquiring download job permit");
+            let outcome = download_item(&matches, &client, &item, idx + 1, &ctx).await;
+            if let Err(e) = &outcome {
+                error!("Download of {} failed: {e}", item.url);
+            }
+            (idx, outcome)
+        });
+    }
+    let mut outcomes: Vec<Option<Result<PathBuf, DashMpdError>>> = items.iter().map(|_| None).collect();
+    while let Some(joined) = running.join_next().await {
+        let (idx, outcome) = joined.context("waiting for download job")?;
+        outcomes[idx] = Some(outcome);
+    }
+    Ok(outcomes.into_iter().flatten().collect())
+}
+
+// Save the manifest rewritten for --periods, --skip-ads, --start-time, --end-time, --format or
+// --audio-tracks to a temporary file, for the DashDownloader.
+fn write_manifest(manifest: &Manifest) -> Result<tempfile::NamedTempFile> {
+    // The rewritten manifest is saved to a local file, so relative BaseURLs must be resolved against
+    // the URL of the original manifest.
+    let mut mpd = manifest.mpd.clone();
+    let base = match mpd.base_url.first() {
+        Some(bu) => manifest.url.join(&bu.base).unwrap_or_else(|_| manifest.url.clone()),
+        None => manifest.url.clone(),
+    };
+    mpd.base_url = vec![BaseURL { base: base.to_string(), ..Default::default() }];
+    let xml = quick_xml::se::to_string(&mpd)
+        .context("serializing rewritten manifest")?;
+    let file = tempfile::Builder::new()
+        .prefix(".dash-mpd-cli-")
+        .suffix(".mpd")
+        .tempfile()
+        .context("creating temporary file for rewritten manifest")?;
+    fs::write(&file, xml)
+        .context("writing rewritten manifest")?;
+    Ok(file)
+}
+
+fn manifest_file_url(file: &tempfile::NamedTempFile) -> Result<String> {
+    Url::from_file_path(file.path())
+        .map(|u| u.to_string())
+        .map_err(|_| anyhow!("invalid path for rewritten manifest"))
+}
+
+// Set the modification time of the output file to the publication time of the content (for --mtime),
+// determined when the manifest was fetched before the download.
+fn set_output_mtime(out: &Path, publication_time: Option<SystemTime>) -> Result<()> {
+    let Some(mtime) = publication_time else {
+        warn!("Manifest has no Last-Modified header, publishTime or availabilityStartTime; not setting mtime");
+        return Ok(());
+    };
+    let file = fs::File::options().write(true).open(out)?;
+    file.file().set_modified(mtime)
+        .with_context(|| format!("setting modification time of {}", out.display()))?;
+    Ok(())
+}
+
+// Download media content from the manifest to output, or to a path derived from the manifest URL if
+// no output path is specified. Returns the path of the output file.
+async fn download(dl: DashDownloader, output: Option<&str>) -> Result<PathBuf, DashMpdError> {
+    match output {
+        Some(out) => dl.download_to(out).await,
+        None => dl.download().await,
+    }
+}
+
+
 #[tokio::main]
 async fn main () -> Result<()> {
     #[allow(unused_mut)]
@@ -183,13 +1558,55 @@ async fn main () -> Result<()> {
         .arg(Arg::new("enable-live-streams")
              .long("enable-live-streams")
              .num_args(0)
-             .help("Attempt to download from a live media stream (dynamic MPD manifest). Downloading from a genuinely live stream won't work well, because we don't implement the clock-related throttling needed to only download media segments when they become available. However, some media sources publish pseudo-live streams where all media segments are in fact available, which we will be able to download. You might also have some success in combination with the --sleep-requests argument."))
+             .help("Record from a live media stream (dynamic MPD manifest). Media segments are fetched as they become available, starting at the live edge, until the duration specified by --record-duration has been recorded, the time specified by --record-until is reached, the stream ends or the recording is interrupted with Ctrl-C."))
+        .arg(Arg::new("record-duration")
+             .long("record-duration")
+             .value_name("DURATION")
+             .num_args(1)
+             .value_parser(parse_duration_secs)
+             .help("Duration of media to record from a live stream, in seconds or as HH:MM:SS (implies --enable-live-streams)."))
+        .arg(Arg::new("record-until")
+             .long("record-until")
+             .value_name("TIME")
+             .num_args(1)
+             .value_parser(parse_wall_clock)
+             .help("Stop recording from a live stream at this wall-clock time, either a local time of day HH:MM[:SS] or an RFC 3339 date and time (implies --enable-live-streams)."))
+        .arg(Arg::new("live-from-start")
+             .long("live-from-start")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .conflicts_with("live-start-offset")
+             .help("Record a live stream from the oldest segment in its timeshift (DVR) window, then catch up to live and keep recording (implies --enable-live-streams)."))
+        .arg(Arg::new("live-start-offset")
+             .long("live-start-offset")
+             .value_name("DURATION")
+             .num_args(1)
+             .allow_hyphen_values(true)
+             .value_parser(parse_start_offset)
+             .help("Record a live stream starting DURATION into its timeshift (DVR) window, or DURATION before the live edge if negative (e.g. -10:00), then catch up to live and keep recording (implies --enable-live-streams)."))
         .arg(Arg::new("force-duration")
              .long("force-duration")
              .value_name("SECONDS")
              .num_args(1)
              .value_parser(clap::value_parser!(f64))
              .help("Specify a number of seconds (possibly floating point) to download from the media stream. This may be necessary to download from a live stream, where the duration is often not specified in the DASH manifest. It may also be used to download only the first part of a static stream."))
+        .arg(Arg::new("start-time")
+             .long("start-time")
+             .value_name("TIME")
+             .num_args(1)
+             .value_parser(parse_duration_secs)
+             .help("Download only the part of the content that follows this time, as a number of seconds or HH:MM:SS. Only the media segments that overlap the requested section are downloaded."))
+        .arg(Arg::new("end-time")
+             .long("end-time")
+             .value_name("TIME")
+             .num_args(1)
+             .value_parser(parse_duration_secs)
+             .help("Download only the part of the content that precedes this time, as a number of seconds or HH:MM:SS. Only the media segments that overlap the requested section are downloaded."))
+        .arg(Arg::new("exact-trim")
+             .long("exact-trim")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .help("Cut the output file at the precise --start-time and --end-time using ffmpeg (requires reencoding), rather than at media segment boundaries."))
         .arg(Arg::new("base-url")
             .long("base-url")
             .value_name("URL")
@@ -215,6 +1632,13 @@ async fn main () -> Result<()> {
              .value_parser(clap::value_parser!(u32))
              .help("Abort after COUNT non-transient network errors.")
              .long_help("Maximum number of non-transient network errors that should be ignored before a download is aborted (default is 30)."))
+        .arg(Arg::new("abort-on-error")
+             .long("abort-on-error")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .conflicts_with_all(["max-error-count", "ignore-content-type"])
+             .help("Abort the download at the first media fragment that can't be retrieved.")
+             .long_help("Abort the download at the first media fragment that can't be retrieved once its retries (see --fragment-retries) are exhausted, without skipping any failed fragments, and with content-type checks enabled. The error report identifies the Period, AdaptationSet, Representation and URL of the failed segment, and the media segments that had already been written."))
         .arg(Arg::new("source-address")
              .long("source-address")
              .num_args(1)
@@ -259,11 +1683,25 @@ async fn main () -> Result<()> {
              .num_args(1)
              .value_parser(["best", "intermediate", "worst"])
              .help("Prefer best quality (and highest bandwidth) representation, or lowest quality. Default is the worst quality (smallest file size)."))
+        .arg(Arg::new("format")
+             .long("format")
+             .short('f')
+             .value_name("SELECTOR")
+             .num_args(1)
+             .value_parser(parse_format_selector)
+             .help("Choose the video and audio streams with a format selector expression, such as v[height<=720][codec^=hev1]/v[height<=720]+a[lang=fr][channels>=6]/a (see the user manual)."))
         .arg(Arg::new("prefer-language")
              .long("prefer-language")
              .value_name("LANG")
              .num_args(1)
              .long_help("Preferred language when multiple audio streams and subtitle tracks with different languages are available. Must be in RFC 5646 format (e.g. fr or en-AU). If a preference is not specified and multiple audio streams and subtitles are present, the first one listed in the DASH manifest will be downloaded."))
+        .arg(Arg::new("audio-tracks")
+             .long("audio-tracks")
+             .value_name("LANGS")
+             .num_args(1)
+             .value_parser(parse_language_selection)
+             .conflicts_with_all(["video-only", "format"])
+             .help("Download several audio tracks and mux them into the output file, with language tags and titles: all to include every audio track, or a comma-separated list of languages (e.g. fr,en,de). The first track is marked as the default."))
         .arg(Arg::new("prefer-audio-language")
              .long("prefer-audio-language")
              .value_name("LANG")
@@ -293,6 +1731,17 @@ async fn main () -> Result<()> {
              .value_parser(clap::value_parser!(u64))
              .num_args(1)
              .help("Do not download periods whose duration is less than this value."))
+        .arg(Arg::new("periods")
+             .long("periods")
+             .value_name("SELECTION")
+             .num_args(1)
+             .value_parser(parse_period_selection)
+             .help("Only download the Periods that match SELECTION, a comma-separated list of Period indexes or index ranges counting from 1 (e.g. 2-4), Period@id patterns introduced by @ (e.g. @main-*), and start-time windows (e.g. time=10:00-45:00)."))
+        .arg(Arg::new("skip-ads")
+             .long("skip-ads")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .help("Do not download Periods that are classified as ads, on the basis of SCTE-35 signals, known ad insertion servers, Period ids and encoding discontinuities. Each decision is logged."))
         .arg(Arg::new("video-only")
              .long("video-only")
              .action(ArgAction::SetTrue)
@@ -305,6 +1754,21 @@ async fn main () -> Result<()> {
              .num_args(0)
              .conflicts_with("video-only")
              .help("If media stream has separate audio and video streams, only download the audio stream."))
+        .arg(Arg::new("list-formats")
+             .long("list-formats")
+             .short('F')
+             .value_name("STYLE")
+             .num_args(0..=1)
+             .require_equals(true)
+             .default_missing_value("table")
+             .value_parser(["table", "json"])
+             .help("List every Representation in the manifest, as a table or (with --list-formats=json) as JSON, then exit without downloading."))
+        .arg(Arg::new("dump-json")
+             .long("dump-json")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .conflicts_with("list-formats")
+             .help("Print a JSON description of the manifest and of the streams that would be downloaded (with the reasons for their selection, their segment URLs and estimated size), then exit without downloading."))
         .arg(Arg::new("simulate")
              .long("simulate")
              .action(ArgAction::SetTrue)
@@ -317,7 +1781,25 @@ async fn main () -> Result<()> {
              .long("write-subs")
              .action(ArgAction::SetTrue)
              .num_args(0)
-             .help("Download and save subtitle file, if subtitles are available."))
+             .help("Download and save subtitle file, if subtitles are available."))
+        .arg(Arg::new("sub-langs")
+             .long("sub-langs")
+             .value_name("LANGS")
+             .num_args(1)
+             .value_parser(parse_language_selection)
+             .help("Download every subtitle track in the listed languages (a comma-separated list such as fr,en), or all subtitle tracks, and save each one as NAME.LANG.EXT next to the output file."))
+        .arg(Arg::new("embed-subs")
+             .long("embed-subs")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .requires("sub-langs")
+             .help("Also add the subtitle tracks downloaded with --sub-langs to the output file, with language, forced and SDH metadata."))
+        .arg(Arg::new("convert-subs")
+             .long("convert-subs")
+             .value_name("FORMAT")
+             .num_args(1)
+             .value_parser(["srt", "vtt", "ass"])
+             .help("Convert the subtitles saved with --write-subs or --sub-langs from TTML or WebVTT to SRT, WebVTT or ASS format."))
         .arg(Arg::new("keep-video")
              .long("keep-video")
              .value_name("VIDEO-PATH")
@@ -335,12 +1817,23 @@ async fn main () -> Result<()> {
              .num_args(0)
              .action(ArgAction::SetTrue)
              .help("Never attempt to concatenate media from different Periods (keep one output file per Period)."))
+        .arg(Arg::new("embed-chapters")
+             .long("embed-chapters")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .help("Add a chapter to the output file at the start of each Period, named after the Period id or the programme title (requires ffmpeg)."))
+        .arg(Arg::new("scte35-chapters")
+             .long("scte35-chapters")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .help("Also add a chapter at each splice point signalled by SCTE-35 events in the manifest (implies --embed-chapters)."))
         .arg(Arg::new("muxer-preference")
              .long("muxer-preference")
              .value_name("CONTAINER:ORDERING")
              .num_args(1)
              .action(ArgAction::Append)
-             .help("When muxing into CONTAINER, try muxing applications in order ORDERING. You can use this option multiple times."))
+             .value_parser(parse_muxer_preference)
+             .help("When muxing into CONTAINER, try muxing applications in order ORDERING. You can use this option multiple times. Use mp4:builtin to combine fragmented MP4 streams without an external application."))
         .arg(Arg::new("concat-preference")
              .long("concat-preference")
              .value_name("CONTAINER:ORDERING")
@@ -358,13 +1851,29 @@ async fn main () -> Result<()> {
              .value_name("KID:KEY")
              .num_args(1)
              .action(ArgAction::Append)
+             .value_parser(parse_key)
              .long_help("Use KID:KEY to decrypt encrypted media streams. KID should be either a track id in decimal (e.g. 1), or a 128-bit keyid (32 hexadecimal characters). KEY should be 32 hexadecimal characters. Example: --key eb676abbcb345e96bbcf616630f1a3da:100b6c20940f779a4589152b57d2dacb. You can use this option multiple times."))
+        .arg(Arg::new("key-file")
+             .long("key-file")
+             .value_name("PATH")
+             .value_hint(ValueHint::FilePath)
+             .num_args(1)
+             .action(ArgAction::Append)
+             .value_parser(read_key_file)
+             .help("Read decryption keys from PATH, either as KID:KEY lines or as a JSON object mapping KIDs to keys.")
+             .long_help("Read decryption keys from PATH, which contains either one KID:KEY pair per line (blank lines and lines starting with # are ignored), or a JSON object mapping KIDs to keys. A malformed key is an error. A warning is printed for each cenc:default_KID of the streams to be downloaded that has no key. You can use this option multiple times."))
         .arg(Arg::new("decryption-application")
              .long("decryption-application")
              .value_name("APP")
              .num_args(1)
-             .value_parser(["mp4decrypt", "shaka"])
-             .help("Application to use to decrypt encrypted media streams (either mp4decrypt or shaka)."))
+             .value_parser(["mp4decrypt", "shaka", "builtin"])
+             .help("Application to use to decrypt encrypted media streams (either mp4decrypt, shaka or builtin)."))
+        .arg(Arg::new("continue")
+             .long("continue")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .help("Resume an interrupted download, skipping the media segments that were already downloaded.")
+             .long_help("Record the media segments that have been downloaded in a resume journal (located next to the output file, or in the --save-fragments directory), which is deleted once the download completes, and skip the media segments listed in the resume journal of a previous interrupted run of the same command. The journal is invalidated if the manifest or the selected Representations change between runs. Requires an output path (--output or in a batch file)."))
         .arg(Arg::new("save-fragments")
              .long("save-fragments")
              .value_name("FRAGMENTS-DIR")
@@ -421,6 +1930,28 @@ async fn main () -> Result<()> {
              .action(ArgAction::SetTrue)
              .num_args(0)
              .help("Don't record metainformation as extended attributes in the output file."))
+        .arg(Arg::new("embed-metadata")
+             .long("embed-metadata")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .help("Write the title, copyright and source of the content, the manifest URL, the downloaded Representations and the download date as tags in the output container (requires ffmpeg)."))
+        .arg(Arg::new("write-info-json")
+             .long("write-info-json")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .help("Save the metadata of the download to a JSON file next to the output file, with extension .info.json."))
+        .arg(Arg::new("mtime")
+             .long("mtime")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .conflicts_with("no-mtime")
+             .help("Set the modification time of the output file from the Last-Modified header of the manifest.")
+             .long_help("Set the modification time of the output file from the Last-Modified header of the HTTP response for the manifest, falling back to the MPD@publishTime or MPD@availabilityStartTime attributes of the manifest if the header is absent."))
+        .arg(Arg::new("no-mtime")
+             .long("no-mtime")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .help("Leave the modification time of the output file as the time of download (the default; overrides a setting in the configuration file)."))
         .arg(Arg::new("no-version-check")
              .long("no-version-check")
              .action(ArgAction::SetTrue)
@@ -469,13 +2000,48 @@ async fn main () -> Result<()> {
              .short('o')
              .num_args(1)
              .help("Save media content to this file."))
+        .arg(Arg::new("output-template")
+             .long("output-template")
+             .value_name("TEMPLATE")
+             .num_args(1)
+             .value_parser(parse_output_template)
+             .conflicts_with("output-file")
+             .help("Build the output path from a template such as \"{title}/{date}-{height}p.{ext}\", for manifests without an output path. Placeholders are {title}, {period_id}, {width}, {height}, {resolution}, {vcodec}, {acodec}, {lang}, {date}, {url_stem}, {id} and {ext}; they can also be used in the paths given to --keep-video and --keep-audio."))
+        .arg(Arg::new("batch-file")
+             .long("batch-file")
+             .short('a')
+             .value_name("FILE")
+             .num_args(1)
+             .value_hint(ValueHint::FilePath)
+             .help("Download from each of the manifests listed in FILE (one \"MPD-URL [OUTPUT-PATH]\" per line, or - for stdin).")
+             .long_help("Download from each of the manifests listed in FILE, which contains one item per line in the format \"MPD-URL [OUTPUT-PATH]\". Blank lines and lines starting with # are ignored. If no output path is specified for an item, it is derived from the manifest URL. Use - to read the list from stdin. A failed download does not abort the rest of the batch; a summary of the status of each item is printed at the end."))
+        .arg(Arg::new("profile")
+             .long("profile")
+             .value_name("NAME")
+             .num_args(1)
+             .help("Use the settings in profile NAME of the configuration file.")
+             .long_help("Use the settings in the [profile.NAME] table of the configuration file, in addition to those in the [default] table. The configuration file is $XDG_CONFIG_HOME/dash-mpd-cli/config.toml (by default ~/.config/dash-mpd-cli/config.toml). Values specified on the commandline override values from the configuration file."))
+        .arg(Arg::new("print-config")
+             .long("print-config")
+             .action(ArgAction::SetTrue)
+             .num_args(0)
+             .help("Print the effective settings (merged from the configuration file and the commandline), then exit."))
+        .arg(Arg::new("jobs")
+             .long("jobs")
+             .short('j')
+             .value_name("N")
+             .num_args(1)
+             .value_parser(clap::value_parser!(u16).range(1..))
+             .help("Download from up to N manifests in parallel (default 1).")
+             .long_help("When downloading from several manifests (multiple MPD-URL arguments or a --batch-file), download from up to N manifests in parallel (default 1). The downloads share the same HTTP client and the bandwidth specified by --limit-rate, which is relayed through a local proxy that forwards to the --proxy, if any. When the proxy configuration doesn't allow this (a SOCKS or https proxy, a proxy with credentials or a proxy configured in the environment), each download is instead limited to 1/N of the bandwidth."))
         .arg(Arg::new("url")
              .value_name("MPD-URL")
              .value_hint(ValueHint::Url)
-             .required(true)
-             .num_args(1)
+             .required_unless_present_any(["batch-file", "print-config"])
+             .num_args(1..)
+             .action(ArgAction::Append)
              .index(1)
-             .help("URL of the DASH manifest to retrieve."));
+             .help("URL of the DASH manifest(s) to retrieve."));
     #[cfg(feature = "cookies")]
     {
         clap = clap
@@ -500,16 +2066,34 @@ async fn main () -> Result<()> {
                  .num_args(0)
                  .help("Enable experimental security sandboxing for our code and helper applications."))
     }
-    // TODO: add --abort-on-error
-    // TODO: add --mtime arg (Last-modified header)
-    let matches = clap.get_matches();
+    // Settings from the configuration file are inserted before the commandline arguments, unless
+    // they are overridden by an argument on the commandline.
+    let cmdline_args: Vec<OsString> = env::args_os().collect();
+    let cmdline = clap.clone().get_matches_from(&cmdline_args);
+    let profile = cmdline.get_one::<String>("profile").map(String::as_str);
+    let config_settings = config_args(&clap, &cmdline, profile)?;
+    let mut args: Vec<OsString> = cmdline_args.iter().take(1).cloned().collect();
+    args.extend(config_settings.into_iter().map(OsString::from));
+    args.extend(cmdline_args.iter().skip(1).cloned());
+    let matches = clap.clone().get_matches_from(args);
+    if matches.get_flag("print-config") {
+        print_config(&clap, &matches, &cmdline);
+        std::process::exit(0);
+    }
 
     let time_fmt = time::format_description::parse_borrowed::<3>("[hour]:[minute]:[second]").unwrap();
     let time_offset = time::UtcOffset::current_local_offset()
         .unwrap_or(time::UtcOffset::UTC);
     let timer = tracing_subscriber::fmt::time::OffsetTime::new(time_offset, time_fmt);
-    // Logs of level >= INFO go to stdout, otherwise (warnings and errors) to stderr.
-    let stderr = std::io::stderr.with_max_level(Level::WARN);
+    // Logs of level >= INFO go to stdout, otherwise (warnings and errors) to stderr. When we print a
+    // JSON document, stdout is reserved for it and all logs go to stderr.
+    let json_report = matches.get_flag("dump-json") ||
+        matches.get_one::<String>("list-formats").is_some_and(|s| s == "json");
+    let writer = if json_report {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stderr.with_max_level(Level::WARN).or_else(std::io::stdout))
+    };
     let filter_layer = EnvFilter::try_from_default_env()
         // The sqlx crate is used by the decrypt-cookies crate
         .or_else(|_| EnvFilter::try_new("info,reqwest=warn,hyper=warn,h2=warn,sqlx=warn"))
@@ -529,7 +2113,7 @@ async fn main () -> Result<()> {
             .init();
     } else {
         let fmt_layer = tracing_subscriber::fmt::layer()
-            .map_writer(move |w| stderr.or_else(w))
+            .with_writer(writer)
             .compact()
             .with_target(false)
             .with_timer(timer);
@@ -548,7 +2132,7 @@ async fn main () -> Result<()> {
         for (browser, count) in &list_cookie_sources().await {
             info!("  {browser:?} ({count} cookies)");
         }
-        std::process::exit(3);
+        std::process::exit(exitcode::LISTED_COOKIE_SOURCES);
     }
     let verbosity = matches.get_count("verbose");
     let ua = match matches.get_one::<String>("user-agent") {
@@ -573,14 +2157,35 @@ async fn main () -> Result<()> {
     if verbosity > 2 {
        cb = cb.connection_verbose(true);
     }
-    if let Some(p) = matches.get_one::<String>("proxy") {
-        let proxy = reqwest::Proxy::all(p)
+    // Downloads that run in parallel share the --limit-rate bandwidth through our ThrottlingProxy,
+    // which forwards connections to the --proxy, if any.
+    let jobs = *matches.get_one::<u16>("jobs").unwrap_or(&1);
+    let bandwidth = limit_rate(&matches);
+    let mut throttle = None;
+    if let Some(bps) = bandwidth.filter(|_| jobs > 1) {
+        match throttling_upstream(&matches) {
+            Ok(upstream) => throttle = Some(ThrottlingProxy::start(Arc::new(TokenBucket::new(bps)), upstream).await?),
+            Err(e) => warn!("{e:#}; each download is limited to 1/{jobs} of the --limit-rate bandwidth"),
+        }
+    }
+    let proxy_url = match &throttle {
+        Some(t) => Some(t.url()),
+        None => matches.get_one::<String>("proxy").cloned(),
+    };
+    if let Some(p) = proxy_url {
+        let mut proxy = reqwest::Proxy::all(p)
             .context("connecting to HTTP proxy")?;
+        if matches.get_flag("continue") {
+            // Requests to our local resume proxy must not go through the HTTP proxy.
+            proxy = proxy.no_proxy(reqwest::NoProxy::from_string("127.0.0.1"));
+        }
         cb = cb.proxy(proxy);
     }
-    if matches.get_flag("no-proxy") {
+    if matches.get_flag("no-proxy") && throttle.is_none() {
         cb = cb.no_proxy();
     }
+    // The bandwidth that the DashDownloaders must limit themselves to.
+    let rate_limit = if throttle.is_some() { None } else { bandwidth };
     if let Some(src) = matches.get_one::<String>("source-address") {
        if let Ok(local_addr) = IpAddr::from_str(src) {
           cb = cb.local_address(local_addr);
@@ -631,13 +2236,13 @@ async fn main () -> Result<()> {
                         },
                         Err(e) => {
                             error!("Can't decode root certificate: {e}");
-                            std::process::exit(6);
+                            std::process::exit(exitcode::ROOT_CERTIFICATE_INVALID);
                         },
                     }
                 },
                 Err(e) => {
                     error!("Can't read root certificate: {e}");
-                    std::process::exit(5);
+                    std::process::exit(exitcode::ROOT_CERTIFICATE_UNREADABLE);
                 },
             }
         }
@@ -660,7 +2265,7 @@ async fn main () -> Result<()> {
                     },
                     Err(e) => {
                         error!("Can't decode client certificate: {e}");
-                        std::process::exit(8);
+                        std::process::exit(exitcode::CLIENT_CERTIFICATE_INVALID);
                     },
                 }
                 #[cfg(feature = "native-tls")]
@@ -670,288 +2275,73 @@ async fn main () -> Result<()> {
                     },
                     Err(e) => {
                         error!("Can't decode client certificate: {e}");
-                        std::process::exit(8);
+                        std::process::exit(exitcode::CLIENT_CERTIFICATE_INVALID);
                     },
                 }
             },
             Err(e) => {
                 error!("Can't read client certificate: {e}");
-                std::process::exit(7);
+                std::process::exit(exitcode::CLIENT_CERTIFICATE_UNREADABLE);
             },
         }
     }
     let client = cb.build()
         .context("creating HTTP client")?;
-    let url = matches.get_one::<String>("url").unwrap();
-    let mut dl = DashDownloader::new(url)
-        .with_http_client(client);
-    if let Some(url) = matches.get_one::<String>("referer") {
-        dl = dl.with_referer(url.clone());
-    }
-    let mut progress_type = ProgressType::Bar;
-    if matches.get_flag("no-progress") || matches.get_flag("quiet") {
-        progress_type = ProgressType::None;
-    }
-    if let Some(ptype) = matches.get_one::<String>("progress") {
-        if ptype.eq("json") {
-            progress_type = ProgressType::Json;
-        } else if !ptype.eq("bar") {
-            warn!("Ignoring invalid value for --progress");
-        }
-    }
-    match progress_type {
-        ProgressType::Bar => dl = dl.add_progress_observer(Arc::new(DownloadProgressBar::new())),
-        ProgressType::Json => dl = dl.add_progress_observer(Arc::new(DownloadProgressJson::new())),
-        ProgressType::None => {},
-    }
-    if let Some(seconds) = matches.get_one::<u8>("sleep-requests") {
-        dl = dl.sleep_between_requests(*seconds);
-    }
-    if matches.get_flag("enable-live-streams") {
-        dl = dl.allow_live_streams(true);
-    }
-    if let Some(seconds) = matches.get_one::<f64>("force-duration") {
-        dl = dl.force_duration(*seconds);
-    }
-    if let Some(bu) = matches.get_one::<String>("base-url") {
-        if let Err(e) = Url::parse(bu) {
-            error!("Invalid URL for --base-url: {e}");
-            std::process::exit(9);
+    let mut items: Vec<BatchItem> = Vec::new();
+    if let Some(urls) = matches.get_many::<String>("url") {
+        let urls: Vec<&String> = urls.collect();
+        let output = matches.get_one::<String>("output-file");
+        if urls.len() > 1 && output.is_some() {
+            return Err(anyhow!("--output can't be used when downloading from several manifests"));
         }
-        dl = dl.with_base_url(String::from(bu));
-    }
-    if let Some(limit) = matches.get_one::<String>("limit-rate") {
-        // We allow k, M, G, T suffixes, as per 100k, 1M, 0.4G
-        if let Ok(np) = limit.parse::<NumberPrefix<f64>>() {
-            let bps = match np {
-                NumberPrefix::Standalone(bps) => bps,
-                NumberPrefix::Prefixed(pfx, n) => match pfx {
-                    Prefix::Kilo => n * 1024.0,
-                    Prefix::Mega => n * 1024.0 * 1024.0,
-                    Prefix::Giga => n * 1024.0 * 1024.0 * 1024.0,
-                    Prefix::Tera => n * 1024.0 * 1024.0 * 1024.0 * 1024.0,
-                    _ => {
-                        warn!("Ignoring unrecognized suffix on limit-rate");
-                        0.0
-                    },
-                },
-            };
-            if bps > 0.0 {
-                dl = dl.with_rate_limit(bps as u64);
-            } else {
-                warn!("Ignoring negative value for limit-rate");
-            }
-        } else {
-            warn!("Ignoring invalid value for limit-rate");
+        for url in urls {
+            items.push(BatchItem { url: url.clone(), output: output.cloned() });
         }
     }
-    if let Some(count) = matches.get_one::<u32>("fragment-retries") {
-        dl = dl.fragment_retry_count(*count);
-    }
-    if let Some(count) = matches.get_one::<u32>("max-error-count") {
-        dl = dl.max_error_count(*count);
-    }
-    if matches.get_flag("audio-only") {
-        dl = dl.audio_only();
-    }
-    if matches.get_flag("video-only") {
-        dl = dl.video_only();
-    }
-    if matches.get_flag("simulate") {
-        dl = dl.fetch_audio(false)
-            .fetch_video(false)
-            .fetch_subtitles(false);
-    }
-    if let Some(path) = matches.get_one::<String>("keep-video") {
-        dl = dl.keep_video_as(path);
-    }
-    if let Some(path) = matches.get_one::<String>("keep-audio") {
-        dl = dl.keep_audio_as(path);
-    }
-    if matches.get_flag("no-period-concatenation") {
-        dl = dl.concatenate_periods(false);
-    } else {
-        dl = dl.concatenate_periods(true);
-    }
-    if let Some(mps) = matches.get_many::<String>("muxer-preference") {
-        for mp in mps.collect::<Vec<_>>() {
-            if let Some((container, ordering)) = mp.split_once(':') {
-                dl = dl.with_muxer_preference(container, ordering);
-            } else {
-                warn!("Ignoring badly formatted container:ordering argument to --muxer-preference");
-            }
-        }
+    if let Some(batch_file) = matches.get_one::<String>("batch-file") {
+        items.extend(read_batch_file(batch_file)?);
     }
-    if let Some(mps) = matches.get_many::<String>("concat-preference") {
-        for mp in mps.collect::<Vec<_>>() {
-            if let Some((container, ordering)) = mp.split_once(':') {
-                dl = dl.with_concat_preference(container, ordering);
-            } else {
-                warn!("Ignoring badly formatted container:ordering argument to --concat-preference");
+    let report = match matches.get_one::<String>("list-formats").map(String::as_str) {
+        Some("json") => Some(Report::FormatJson),
+        Some(_) => Some(Report::FormatTable),
+        None if matches.get_flag("dump-json") => Some(Report::Dump),
+        None => None,
+    };
+    if let Some(report) = report {
+        std::process::exit(print_reports(&matches, &client, &items, report).await);
+    }
+    if items.is_empty() {
+        return Err(anyhow!("no manifests to download"));
+    }
+    // A batch file gets a summary even if it lists a single manifest.
+    if items.len() > 1 || matches.contains_id("batch-file") {
+        let outcomes = download_all(&matches, &client, &items, rate_limit).await?;
+        let failures = outcomes.iter().filter(|o| o.is_err()).count();
+        info!("Batch download summary: {} succeeded, {failures} failed", outcomes.len() - failures);
+        for (item, outcome) in items.iter().zip(&outcomes) {
+            match outcome {
+                Ok(out) => info!("  OK      {} -> {}", item.url, out.display()),
+                Err(e) => error!("  FAILED  {}: {e}", item.url),
             }
         }
-    }
-    if let Some(rp) = matches.get_one::<String>("role-preference") {
-        let ordering: Vec<String> = rp.split(',')
-            .map(str::to_string)
-            .collect();
-        if !ordering.is_empty() {
-            dl = dl.prefer_roles(ordering);
-        } else {
-            warn!("Ignoring badly formatted role1,role2,role3 argument to --role-preference");
-        }
-    }
-    if let Some(kvs) = matches.get_many::<String>("key") {
-        for kv in kvs.collect::<Vec<_>>() {
-            if let Some((kid, key)) = kv.split_once(':') {
-                if key.len() == 32 {
-                    dl = dl.add_decryption_key(String::from(kid), String::from(key));
-                } else {
-                    warn!("Ignoring invalid format for KEY (should be 32 hex digits)");
-                }
-            } else {
-                warn!("Ignoring badly formed KID:KEY argument to --key");
+        std::process::exit(exitcode::for_errors(outcomes.iter().filter_map(|o| o.as_ref().err())));
+    }
+    let item = &items[0];
+    let ctx = JobContext { concurrency: 1, progress: None, rate_limit };
+    match download_item(&matches, &client, item, 1, &ctx).await {
+        Ok(out) => {
+            if !matches.get_flag("simulate") {
+                info!("Downloaded DASH content to {out:?}");
             }
-        }
-    }
-    if let Some(app) = matches.get_one::<String>("decryption-application") {
-        dl = dl.with_decryptor_preference(app);
-    }
-    if let Some(fragments_dir) = matches.get_one::<String>("save-fragments") {
-        dl = dl.save_fragments_to(Path::new(fragments_dir));
-    }
-    if matches.get_flag("write-subs") {
-        dl = dl.fetch_subtitles(true);
-    }
-    if matches.get_flag("ignore-content-type") {
-        dl = dl.without_content_type_checks();
-    }
-    if matches.get_flag("no-xattr") {
-        dl = dl.record_metainformation(false);
-    }
-    if let Some(ffmpeg_path) = matches.get_one::<String>("ffmpeg-location") {
-        dl = dl.with_ffmpeg(ffmpeg_path);
-    }
-    if let Some(path) = matches.get_one::<String>("vlc-location") {
-        dl = dl.with_vlc(path);
-    }
-    if let Some(path) = matches.get_one::<String>("mkvmerge-location") {
-        dl = dl.with_mkvmerge(path);
-    }
-    if let Some(path) = matches.get_one::<String>("mp4box-location") {
-        dl = dl.with_mp4box(path);
-    }
-    if let Some(path) = matches.get_one::<String>("mp4decrypt-location") {
-        dl = dl.with_mp4decrypt(path);
-    }
-    if let Some(path) = matches.get_one::<String>("shaka-packager-location") {
-        dl = dl.with_shaka_packager(path);
-    }
-    if let Some(w) = matches.get_one::<u64>("prefer-video-width") {
-        dl = dl.prefer_video_width(*w);
-    }
-    if let Some(h) = matches.get_one::<u64>("prefer-video-height") {
-        dl = dl.prefer_video_height(*h);
-    }
-    if let Some(codec_list) = matches.get_one::<String>("prefer-video-codecs") {
-        let ordering: Vec<String> = codec_list.split(',')
-            .map(str::to_string)
-            .collect();
-        if !ordering.is_empty() {
-            dl = dl.prefer_video_codecs(ordering);
-        } else {
-            warn!("Ignoring badly formatted codec1,codec2 argument to --prefer-video-codecs");
-        }
-    }
-    if let Some(substring) = matches.get_one::<String>("want-video-id") {
-        dl = dl.want_video_id_substring(substring.clone());
-    }
-    // It's possible to specify both prefer-video-width/height and quality. The former is not
-    // relevant concerning the audio stream, where the quality preference will be used. For the
-    // choice of video stream if both are specified, preference will be given to the preferred
-    // width, then height, then quality.
-    if let Some(q) = matches.get_one::<String>("quality") {
-        // DashDownloader defaults to worst quality
-        if q.eq("best") {
-            dl = dl.best_quality();
-        } else if q.eq("intermediate") {
-            dl = dl.intermediate_quality();
-        }
-    }
-    if let Some(lang) = matches.get_one::<String>("prefer-language") {
-        dl = dl.prefer_language(lang.clone());
-    }
-    if let Some(lang) = matches.get_one::<String>("prefer-audio-language") {
-        dl = dl.prefer_audio_language(lang.clone());
-    }
-    if let Some(lang) = matches.get_one::<String>("prefer-subtitle-language") {
-        dl = dl.prefer_subtitle_language(lang.clone());
-    }
-    if let Some(stylesheets) = matches.get_many::<String>("xslt-stylesheet") {
-        for stylesheet in stylesheets {
-            dl = dl.with_xslt_stylesheet(stylesheet);
-        }
-    }
-    if let Some(xpaths) = matches.get_many::<String>("drop-elements") {
-        for xpath in xpaths {
-            let xslt = format!(r#"<?xml version="1.0" encoding="utf-8"?>
-  <xsl:stylesheet version="1.0"
-     xmlns:xsl="http://www.w3.org/1999/XSL/Transform"
-     xmlns:mpd="urn:mpeg:dash:schema:mpd:2011">
-  <xsl:template match="@*|node()">
-    <xsl:copy><xsl:apply-templates select="@*|node()"/></xsl:copy></xsl:template>
-  <xsl:template match="{xpath}" />
-</xsl:stylesheet>"#);
-            let stylesheet = tempfile::Builder::new()
-                .suffix(".xslt")
-                .rand_bytes(7)
-                .tempfile()
-                .context("creating temporary XSLT stylesheet")?;
-            fs::write(&stylesheet, xslt)
-                .context("writing XSLT to temporary stylesheet file")?;
-            let (_, stylesheet_path) = stylesheet.keep()?;
-            dl = dl.with_xslt_stylesheet(stylesheet_path);
-        }
-    }
-    if let Some(secs) = matches.get_one::<u64>("minimum-period-duration") {
-        dl = dl.minimum_period_duration(Duration::from_secs(*secs));
-    }
-    if let Some(user) = matches.get_one::<String>("auth-username") {
-        if let Some(password) = matches.get_one::<String>("auth-password") {
-            dl = dl.with_authentication(user, password);
-        }
-    }
-    if let Some(token) = matches.get_one::<String>("auth-bearer") {
-        dl = dl.with_auth_bearer(token);
-    }
-    #[cfg(feature = "sandbox")]
-    {
-        if matches.get_flag("sandbox") {
-            dl = dl.sandbox(true);
-        }
-    }
-    dl = dl.verbosity(verbosity);
-    if let Some(out) = matches.get_one::<String>("output-file") {
-        if let Err(e) = dl.download_to(out).await {
+        },
+        Err(e) => {
             error!("Download failed: {e}");
-            std::process::exit(2);
-        }
-    } else {
-        match dl.download().await {
-            Ok(out) => {
-                if !matches.get_flag("simulate") {
-                    info!("Downloaded DASH content to {out:?}");
-                }
-            },
-            Err(e) => {
-                error!("Download failed: {e}");
-                if e.to_string().contains("how to download dynamic MPD") {
-                    info!("See the help for the --enable-live-streams commandline option.");
-                }
-                // TODO we could return different exit codes for different error types
-                std::process::exit(2);
-            },
-        }
+            if exitcode::is_live_stream_refusal(&e) {
+                info!("See the help for the --enable-live-streams commandline option.");
+            }
+            std::process::exit(exitcode::for_error(&e));
+        },
     }
     std::process::exit(0)
 }
+
@@ -0,0 +1,452 @@
+//! Fetching and analyzing the MPD manifest, outside of the DashDownloader
+//
+// Some of our functionality (such as reporting which media segment failed to download) needs to
+// know which media segments a Representation is made of. This module retrieves and parses the
+// manifest using the same HTTP client as the DashDownloader, and resolves the URLs and timing of
+// media segments for the different forms of segment addressing.
+
+
+use std::time::SystemTime;
+use url::Url;
+use fs_err as fs;
+use anyhow::{anyhow, Result, Context};
+use dash_mpd::{MPD, Period, AdaptationSet, Representation, SegmentTemplate, BaseURL};
+
+
+pub struct Manifest {
+    pub mpd: MPD,
+    // The URL of the manifest, after following any HTTP redirects.
+    pub url: Url,
+    // From the Last-Modified header of the HTTP response, or the modification time of a local file.
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Manifest {
+    // When the content described by the manifest was published: the Last-Modified time of the
+    // manifest, falling back to MPD@publishTime then to MPD@availabilityStartTime.
+    pub fn publication_time(&self) -> Option<SystemTime> {
+        self.last_modified
+            .or_else(|| self.mpd.publishTime.map(SystemTime::from))
+            .or_else(|| self.mpd.availabilityStartTime.map(SystemTime::from))
+    }
+}
+
+// Parse an HTTP date such as "Wed, 21 Oct 2015 07:28:00 GMT" (the IMF-fixdate format of RFC 9110).
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let fmt = time::macros::format_description!(
+        "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT");
+    time::PrimitiveDateTime::parse(value.trim(), &fmt).ok()
+        .map(|dt| SystemTime::from(dt.assume_utc()))
+}
+
+// Our requests for the manifest use the same HTTP client and authentication information as the
+// DashDownloader.
+#[derive(Clone)]
+pub struct ManifestFetcher {
+    client: reqwest::Client,
+    referer: Option<String>,
+    basic_auth: Option<(String, String)>,
+    bearer: Option<String>,
+}
+
+impl ManifestFetcher {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client, referer: None, basic_auth: None, bearer: None }
+    }
+
+    pub fn with_referer(mut self, referer: String) -> Self {
+        self.referer = Some(referer);
+        self
+    }
+
+    pub fn with_authentication(mut self, username: &str, password: &str) -> Self {
+        self.basic_auth = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    pub fn with_auth_bearer(mut self, token: &str) -> Self {
+        self.bearer = Some(token.to_string());
+        self
+    }
+
+    fn request(&self, url: Url) -> reqwest::RequestBuilder {
+        self.authorize(self.client.get(url))
+    }
+
+    // Add the Referer and authentication headers to req.
+    fn authorize(&self, mut req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(referer) = &self.referer {
+            req = req.header("Referer", referer);
+        }
+        if let Some((username, password)) = &self.basic_auth {
+            req = req.basic_auth(username, Some(password));
+        }
+        if let Some(token) = &self.bearer {
+            req = req.bearer_auth(token);
+        }
+        req
+    }
+
+    pub async fn get(&self, url: Url) -> Result<reqwest::Response> {
+        self.request(url).send().await
+            .context("HTTP request")
+    }
+
+    // POST a JSON document, such as a ClearKey license request. The caller is responsible for checking
+    // the response status.
+    pub async fn post_json(&self, url: Url, body: &serde_json::Value) -> Result<reqwest::Response> {
+        self.authorize(self.client.post(url)).json(body).send().await
+            .context("HTTP request")
+    }
+
+    // Request a media segment (or a byte range of a media segment, when range is the value of an
+    // HTTP Range header). The caller is responsible for checking the response status.
+    pub async fn fetch_segment(&self, url: Url, range: Option<&str>) -> Result<reqwest::Response> {
+        let mut req = self.request(url);
+        if let Some(r) = range {
+            req = req.header(reqwest::header::RANGE, r);
+        }
+        req.send().await
+            .context("requesting media segment")
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Manifest> {
+        let url = Url::parse(url)
+            .context("parsing manifest URL")?;
+        if url.scheme() == "file" {
+            let path = url.to_file_path()
+                .map_err(|_| anyhow!("invalid file URL for manifest"))?;
+            let xml = fs::read_to_string(&path)?;
+            let mpd = dash_mpd::parse(&xml)
+                .context("parsing DASH manifest")?;
+            let last_modified = fs::metadata(&path).ok()
+                .and_then(|m| m.modified().ok());
+            return Ok(Manifest { mpd, url, last_modified });
+        }
+        let response = self.request(url)
+            .header("Accept", "application/dash+xml,video/vnd.mpeg.dash.mpd")
+            .send().await
+            .context("requesting DASH manifest")?
+            .error_for_status()
+            .context("requesting DASH manifest")?;
+        let url = response.url().clone();
+        let last_modified = response.headers().get(reqwest::header::LAST_MODIFIED)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_http_date);
+        let xml = response.text().await
+            .context("fetching DASH manifest")?;
+        let mpd = dash_mpd::parse(&xml)
+            .context("parsing DASH manifest")?;
+        Ok(Manifest { mpd, url, last_modified })
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub url: Url,
+    // Byte range in the format used by the HTTP Range header ("first-last"), for SegmentList@mediaRange
+    // and SegmentBase addressing.
+    pub byte_range: Option<String>,
+    // Start time and duration of the segment relative to the start of the Period, in seconds.
+    pub start: f64,
+    pub duration: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RepresentationSegments {
+    pub init: Option<Segment>,
+    pub media: Vec<Segment>,
+}
+
+
+// The start time of Period number idx, in seconds from the start of the presentation.
+pub fn period_start(mpd: &MPD, idx: usize) -> f64 {
+    if let Some(start) = mpd.periods[idx].start {
+        return start.as_secs_f64();
+    }
+    if idx == 0 {
+        return 0.0;
+    }
+    period_start(mpd, idx - 1) + period_duration(mpd, idx - 1).unwrap_or(0.0)
+}
+
+// The duration of Period number idx, in seconds, if it can be determined from the manifest.
+pub fn period_duration(mpd: &MPD, idx: usize) -> Option<f64> {
+    let period = &mpd.periods[idx];
+    if let Some(d) = period.duration {
+        return Some(d.as_secs_f64());
+    }
+    if let Some(next) = mpd.periods.get(idx + 1) {
+        if let Some(next_start) = next.start {
+            return Some(next_start.as_secs_f64() - period_start(mpd, idx));
+        }
+    }
+    if idx == mpd.periods.len() - 1 {
+        if let Some(total) = mpd.mediaPresentationDuration {
+            return Some(total.as_secs_f64() - period_start(mpd, idx));
+        }
+    }
+    None
+}
+
+fn resolve_base(base: &Url, bus: &[BaseURL]) -> Url {
+    match bus.first() {
+        Some(bu) => base.join(&bu.base).unwrap_or_else(|_| base.clone()),
+        None => base.clone(),
+    }
+}
+
+// The base URL for media segments of a Representation, taking into account the BaseURL elements at
+// the MPD, Period, AdaptationSet and Representation levels.
+pub fn representation_base_url(
+    manifest: &Manifest,
+    period: &Period,
+    adaptation: &AdaptationSet,
+    rep: &Representation) -> Url
+{
+    let mut base = resolve_base(&manifest.url, &manifest.mpd.base_url);
+    base = resolve_base(&base, &period.BaseURL);
+    base = resolve_base(&base, &adaptation.BaseURL);
+    resolve_base(&base, &rep.BaseURL)
+}
+
+// Substitute the $RepresentationID$, $Number$, $Time$ and $Bandwidth$ identifiers (possibly with a
+// printf-style width such as $Number%05d$) in a SegmentTemplate@media or @initialization.
+pub fn fill_template(tmpl: &str, rep_id: &str, bandwidth: u64, number: u64, time: u64) -> String {
+    let mut out = String::new();
+    let mut parts = tmpl.split('$');
+    if let Some(first) = parts.next() {
+        out.push_str(first);
+    }
+    let mut in_ident = true;
+    for part in parts {
+        if !in_ident {
+            out.push_str(part);
+            in_ident = true;
+            continue;
+        }
+        in_ident = false;
+        if part.is_empty() {
+            // "$$" is an escaped dollar sign
+            out.push('$');
+            continue;
+        }
+        let (name, format) = match part.split_once('%') {
+            Some((n, f)) => (n, Some(f)),
+            None => (part, None),
+        };
+        let value = match name {
+            "RepresentationID" => {
+                out.push_str(rep_id);
+                continue;
+            },
+            "Number" => number,
+            "Time" => time,
+            "Bandwidth" => bandwidth,
+            _ => {
+                out.push('$');
+                out.push_str(part);
+                out.push('$');
+                continue;
+            },
+        };
+        let width = format
+            .and_then(|f| f.trim_start_matches('0').trim_end_matches('d').parse::<usize>().ok())
+            .unwrap_or(0);
+        out.push_str(&format!("{value:0width$}"));
+    }
+    out
+}
+
+// Fields of a SegmentTemplate in the Representation override those of the SegmentTemplate in the
+// parent AdaptationSet.
+pub fn merged_template(adaptation: &AdaptationSet, rep: &Representation) -> Option<SegmentTemplate> {
+    match (&adaptation.SegmentTemplate, &rep.SegmentTemplate) {
+        (None, None) => None,
+        (Some(a), None) => Some(a.clone()),
+        (None, Some(r)) => Some(r.clone()),
+        (Some(a), Some(r)) => Some(SegmentTemplate {
+            initialization: r.initialization.clone().or_else(|| a.initialization.clone()),
+            media: r.media.clone().or_else(|| a.media.clone()),
+            startNumber: r.startNumber.or(a.startNumber),
+            endNumber: r.endNumber.or(a.endNumber),
+            duration: r.duration.or(a.duration),
+            timescale: r.timescale.or(a.timescale),
+            presentationTimeOffset: r.presentationTimeOffset.or(a.presentationTimeOffset),
+            SegmentTimeline: r.SegmentTimeline.clone().or_else(|| a.SegmentTimeline.clone()),
+            ..r.clone()
+        }),
+    }
+}
+
+// The initialization and media segments of a Representation, for a static manifest.
+pub fn representation_segments(
+    manifest: &Manifest,
+    period_idx: usize,
+    adaptation: &AdaptationSet,
+    rep: &Representation) -> Result<RepresentationSegments>
+{
+    let mpd = &manifest.mpd;
+    let period = &mpd.periods[period_idx];
+    let base = representation_base_url(manifest, period, adaptation, rep);
+    let period_duration = period_duration(mpd, period_idx).unwrap_or(0.0);
+    let rep_id = rep.id.clone().unwrap_or_default();
+    let bandwidth = rep.bandwidth.unwrap_or(0);
+    let join = |path: &str| base.join(path)
+        .with_context(|| format!("resolving segment URL {path}"));
+    let mut segments = RepresentationSegments::default();
+    if let Some(st) = merged_template(adaptation, rep) {
+        let timescale = st.timescale.unwrap_or(1) as f64;
+        let pto = st.presentationTimeOffset.unwrap_or(0);
+        if let Some(init) = &st.initialization {
+            segments.init = Some(Segment {
+                url: join(&fill_template(init, &rep_id, bandwidth, 0, 0))?,
+                byte_range: None,
+                start: 0.0,
+                duration: 0.0,
+            });
+        }
+        let Some(media) = &st.media else {
+            return Ok(segments);
+        };
+        let mut number = st.startNumber.unwrap_or(1);
+        if let Some(timeline) = &st.SegmentTimeline {
+            let mut time = 0;
+            let end_time = pto + (period_duration * timescale) as u64;
+            for (i, s) in timeline.segments.iter().enumerate() {
+                if let Some(t) = s.t {
+                    time = t;
+                }
+                let mut repeats = s.r.unwrap_or(0);
+                if repeats < 0 {
+                    // Repeat until the start of the next S element, or the end of the Period.
+                    let until = timeline.segments.get(i + 1)
+                        .and_then(|next| next.t)
+                        .unwrap_or(end_time);
+                    repeats = (until.saturating_sub(time) / s.d.max(1)) as i64 - 1;
+                }
+                for _ in 0..=repeats {
+                    segments.media.push(Segment {
+                        url: join(&fill_template(media, &rep_id, bandwidth, number, time))?,
+                        byte_range: None,
+                        start: time.saturating_sub(pto) as f64 / timescale,
+                        duration: s.d as f64 / timescale,
+                    });
+                    number += 1;
+                    time += s.d;
+                }
+            }
+        } else if let Some(seg_duration) = st.duration {
+            let seg_secs = seg_duration / timescale;
+            let count = match st.endNumber {
+                Some(end) => end.saturating_sub(number) + 1,
+                None => (period_duration / seg_secs).ceil() as u64,
+            };
+            for i in 0..count {
+                let time = pto + (i as f64 * seg_duration) as u64;
+                segments.media.push(Segment {
+                    url: join(&fill_template(media, &rep_id, bandwidth, number, time))?,
+                    byte_range: None,
+                    start: i as f64 * seg_secs,
+                    duration: seg_secs,
+                });
+                number += 1;
+            }
+        }
+        return Ok(segments);
+    }
+    if let Some(sl) = rep.SegmentList.as_ref().or(adaptation.SegmentList.as_ref()) {
+        let timescale = sl.timescale.unwrap_or(1) as f64;
+        let seg_secs = sl.duration.map(|d| d as f64 / timescale).unwrap_or(0.0);
+        if let Some(init) = &sl.Initialization {
+            segments.init = Some(Segment {
+                url: match &init.sourceURL {
+                    Some(su) => join(su)?,
+                    None => base.clone(),
+                },
+                byte_range: init.range.clone(),
+                start: 0.0,
+                duration: 0.0,
+            });
+        }
+        for (i, su) in sl.segment_urls.iter().enumerate() {
+            segments.media.push(Segment {
+                url: match &su.media {
+                    Some(m) => join(m)?,
+                    None => base.clone(),
+                },
+                byte_range: su.mediaRange.clone(),
+                start: i as f64 * seg_secs,
+                duration: seg_secs,
+            });
+        }
+        return Ok(segments);
+    }
+    // SegmentBase addressing, or a single media file designated by the BaseURL: the whole Period is
+    // a single resource.
+    if let Some(sb) = rep.SegmentBase.as_ref().or(adaptation.SegmentBase.as_ref()) {
+        if let Some(init) = &sb.Initialization {
+            segments.init = Some(Segment {
+                url: match &init.sourceURL {
+                    Some(su) => join(su)?,
+                    None => base.clone(),
+                },
+                byte_range: init.range.clone(),
+                start: 0.0,
+                duration: 0.0,
+            });
+        }
+    }
+    segments.media.push(Segment {
+        url: base,
+        byte_range: None,
+        start: 0.0,
+        duration: period_duration,
+    });
+    Ok(segments)
+}
+
+
+// The position of a media segment in the manifest.
+#[derive(Debug, Clone)]
+pub struct SegmentLocation {
+    pub period_idx: usize,
+    pub period_id: Option<String>,
+    pub adaptation_idx: usize,
+    pub adaptation_id: Option<String>,
+    pub representation_id: Option<String>,
+    // Index of the segment in the list of media segments of the Representation (0 for the
+    // initialization segment, 1 for the first media segment).
+    pub segment_idx: usize,
+    pub segment_count: usize,
+    pub segment: Segment,
+}
+
+// Find the Period, AdaptationSet and Representation which contain the segment at url.
+pub fn locate_segment(manifest: &Manifest, url: &Url) -> Option<SegmentLocation> {
+    for (period_idx, period) in manifest.mpd.periods.iter().enumerate() {
+        for (adaptation_idx, adaptation) in period.adaptations.iter().enumerate() {
+            for rep in &adaptation.representations {
+                let Ok(segments) = representation_segments(manifest, period_idx, adaptation, rep) else {
+                    continue;
+                };
+                let init = segments.init.iter().map(|s| (0, s));
+                let media = segments.media.iter().enumerate().map(|(i, s)| (i + 1, s));
+                if let Some((segment_idx, s)) = init.chain(media).find(|(_, s)| s.url.eq(url)) {
+                    return Some(SegmentLocation {
+                        period_idx,
+                        period_id: period.id.clone(),
+                        adaptation_idx,
+                        adaptation_id: adaptation.id.clone(),
+                        representation_id: rep.id.clone(),
+                        segment_idx,
+                        segment_count: segments.media.len(),
+                        segment: s.clone(),
+                    });
+                }
+            }
+        }
+    }
+    None
+}
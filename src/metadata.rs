@@ -0,0 +1,238 @@
+//! Provenance metadata for the output file (--embed-metadata, --write-info-json)
+//
+// The DashDownloader records the origin of the content in extended attributes of the output file,
+// which are lost when the file is copied to another filesystem or to object storage. With
+// --embed-metadata we also write metadata into the container itself using ffmpeg, which stores it in
+// the udta/ilst atoms of MP4 files and as tags in Matroska files, and with --write-info-json we save
+// it to a sidecar NAME.info.json file. The metadata includes the title, source and copyright from
+// the ProgramInformation element of the manifest, the manifest URL, the Representations that were
+// downloaded, and the date of the download.
+//
+// The Representations that were downloaded are taken from the resume journal, which records the
+// media segments fetched by the DashDownloader. Downloads without a journal (run without
+// --continue) record the Representations that we expect the DashDownloader to select, labelled as
+// predicted.
+
+
+use std::path::{Path, PathBuf};
+use fs_err as fs;
+use tracing::info;
+use anyhow::{anyhow, Result, Context};
+use serde_json::{json, Value};
+use dash_mpd::MPD;
+use crate::selection::{StreamPreferences, content_kind, select_representation};
+use crate::resume::representation_key;
+
+
+#[derive(Debug, Clone)]
+pub struct SelectedStream {
+    // Index of the Period, counting from 1.
+    pub period: usize,
+    pub kind: &'static str,
+    pub id: Option<String>,
+    pub codecs: Option<String>,
+    pub bandwidth: Option<u64>,
+    // The key of the Representation in the resume journal, if it is downloaded from the manifest
+    // that the journal was started with.
+    pub key: Option<String>,
+    // Whether this is the Representation that we expect the DashDownloader to select, rather than
+    // one that it was seen to download.
+    pub predicted: bool,
+}
+
+impl SelectedStream {
+    fn new(mpd: &MPD, pidx: usize, aidx: usize, ridx: usize, kind: &'static str) -> SelectedStream {
+        let adaptation = &mpd.periods[pidx].adaptations[aidx];
+        let rep = &adaptation.representations[ridx];
+        SelectedStream {
+            period: pidx + 1,
+            kind,
+            id: rep.id.clone(),
+            codecs: rep.codecs.clone().or_else(|| adaptation.codecs.clone()),
+            bandwidth: rep.bandwidth,
+            key: Some(representation_key(pidx, aidx, ridx, rep)),
+            predicted: true,
+        }
+    }
+}
+
+// The Representations of each kind that are downloaded from the manifest.
+pub fn selected_streams(mpd: &MPD, prefs: &StreamPreferences, kinds: &[&'static str]) -> Vec<SelectedStream> {
+    let mut streams = Vec::new();
+    for (idx, period) in mpd.periods.iter().enumerate() {
+        for &kind in kinds {
+            if let Some(choice) = select_representation(period, kind, prefs) {
+                streams.push(SelectedStream::new(mpd, idx, choice.adaptation, choice.representation, kind));
+            }
+        }
+    }
+    streams
+}
+
+// All the audio, video and subtitle Representations of the manifest.
+fn all_streams(mpd: &MPD) -> Vec<SelectedStream> {
+    let mut streams = Vec::new();
+    for (pidx, period) in mpd.periods.iter().enumerate() {
+        for (aidx, adaptation) in period.adaptations.iter().enumerate() {
+            let Some(kind) = content_kind(adaptation) else {
+                continue;
+            };
+            for ridx in 0..adaptation.representations.len() {
+                streams.push(SelectedStream::new(mpd, pidx, aidx, ridx, kind));
+            }
+        }
+    }
+    streams
+}
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub source: Option<String>,
+    pub copyright: Option<String>,
+    pub more_information_url: Option<String>,
+    pub manifest_url: String,
+    pub streams: Vec<SelectedStream>,
+    // RFC 3339 timestamp, in UTC.
+    pub download_date: String,
+    // Every Representation of the manifest, from which those recorded in the resume journal are
+    // taken.
+    candidates: Vec<SelectedStream>,
+}
+
+impl Metadata {
+    pub fn new(mpd: &MPD, manifest_url: &str, streams: Vec<SelectedStream>) -> Metadata {
+        let pi = mpd.ProgramInformation.as_ref();
+        let now = time::OffsetDateTime::now_utc()
+            .replace_nanosecond(0)
+            .unwrap_or_else(|_| time::OffsetDateTime::now_utc());
+        Metadata {
+            title: pi.and_then(|pi| pi.Title.as_ref()).and_then(|t| t.content.clone()),
+            source: pi.and_then(|pi| pi.Source.as_ref()).and_then(|s| s.content.clone()),
+            copyright: pi.and_then(|pi| pi.Copyright.as_ref()).and_then(|c| c.content.clone()),
+            more_information_url: pi.and_then(|pi| pi.moreInformationURL.clone()),
+            manifest_url: manifest_url.to_string(),
+            streams,
+            download_date: now.format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default(),
+            candidates: all_streams(mpd),
+        }
+    }
+
+    // Replace the predicted streams by the Representations that media segments were downloaded of,
+    // as recorded in the resume journal (see ResumeSession::downloaded_representations). Streams
+    // without a journal key, such as additional audio tracks which are downloaded separately,
+    // remain predicted.
+    pub fn record_downloaded(&mut self, downloaded: &[String]) {
+        let separate: Vec<SelectedStream> = self.streams.iter()
+            .filter(|s| s.key.is_none())
+            .cloned()
+            .collect();
+        self.streams = self.candidates.iter()
+            .filter(|c| c.key.as_ref().is_some_and(|k| downloaded.contains(k)))
+            .map(|c| SelectedStream { predicted: false, ..c.clone() })
+            .chain(separate)
+            .collect();
+    }
+
+    // The downloaded Representations as "kind:id" in the order of the manifest, without the
+    // duplicates of multi-Period content.
+    fn representation_list(&self) -> String {
+        let mut list: Vec<String> = Vec::new();
+        for s in &self.streams {
+            let item = format!("{}:{}", s.kind, s.id.as_deref().unwrap_or("?"));
+            if !list.contains(&item) {
+                list.push(item);
+            }
+        }
+        list.join(" ")
+    }
+
+    pub fn to_json(&self, output: &Path) -> Value {
+        let streams: Vec<Value> = self.streams.iter()
+            .map(|s| json!({
+                "period": s.period,
+                "kind": s.kind,
+                "id": s.id,
+                "codecs": s.codecs,
+                "bandwidth": s.bandwidth,
+                "predicted": s.predicted,
+            }))
+            .collect();
+        json!({
+            "title": self.title,
+            "source": self.source,
+            "copyright": self.copyright,
+            "more_information_url": self.more_information_url,
+            "manifest_url": self.manifest_url,
+            "output": output.to_string_lossy(),
+            "representations": streams,
+            "download_date": self.download_date,
+            "downloader": concat!("dash-mpd-cli/", env!("CARGO_PKG_VERSION")),
+        })
+    }
+
+    // Container tags, using the names that ffmpeg maps to the standard MP4 atoms (title, copyright,
+    // comment) where they exist.
+    fn tags(&self) -> Vec<(&'static str, String)> {
+        let mut tags = Vec::new();
+        if let Some(title) = &self.title {
+            tags.push(("title", title.clone()));
+        }
+        if let Some(copyright) = &self.copyright {
+            tags.push(("copyright", copyright.clone()));
+        }
+        if let Some(source) = &self.source {
+            tags.push(("source", source.clone()));
+        }
+        tags.push(("comment", format!("Downloaded from {} on {}", self.manifest_url, self.download_date)));
+        tags.push(("source_url", self.manifest_url.clone()));
+        if self.streams.iter().any(|s| s.predicted) {
+            tags.push(("predicted_representations", self.representation_list()));
+        } else {
+            tags.push(("representations", self.representation_list()));
+        }
+        tags.push(("download_date", self.download_date.clone()));
+        tags.push(("encoded_by", concat!("dash-mpd-cli/", env!("CARGO_PKG_VERSION")).to_string()));
+        tags
+    }
+}
+
+// Write the metadata into the container of the media file at path.
+pub fn embed_metadata(ffmpeg: &str, path: &Path, metadata: &Metadata) -> Result<()> {
+    let ext = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+    let mut tmp = path.to_path_buf();
+    tmp.set_extension(format!("meta.{ext}"));
+    let mut cmd = std::process::Command::new(ffmpeg);
+    cmd.args(["-hide_banner", "-nostats", "-loglevel", "error", "-y"])
+        .arg("-i").arg(path)
+        .args(["-map", "0", "-map_metadata", "0", "-map_chapters", "0", "-c", "copy"]);
+    // Without this flag, the MP4 muxer drops tags that don't correspond to a standard atom.
+    if matches!(ext.as_str(), "mp4" | "m4v" | "m4a" | "mov") {
+        cmd.args(["-movflags", "use_metadata_tags"]);
+    }
+    for (key, value) in metadata.tags() {
+        cmd.arg("-metadata").arg(format!("{key}={value}"));
+    }
+    let out = cmd.arg(&tmp)
+        .output()
+        .with_context(|| format!("spawning {ffmpeg}"))?;
+    if !out.status.success() {
+        let _ = fs::remove_file(&tmp);
+        return Err(anyhow!("ffmpeg failed to add metadata to {}: {}",
+                           path.display(), String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    fs::rename(&tmp, path)?;
+    info!("Added metadata to {}", path.display());
+    Ok(())
+}
+
+// Save the metadata to NAME.info.json next to the output file.
+pub fn write_info_json(output: &Path, metadata: &Metadata) -> Result<PathBuf> {
+    let path = output.with_extension("info.json");
+    let json = serde_json::to_string_pretty(&metadata.to_json(output))?;
+    fs::write(&path, json + "\n")
+        .with_context(|| format!("writing {}", path.display()))?;
+    info!("Wrote metadata to {}", path.display());
+    Ok(path)
+}
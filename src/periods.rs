@@ -0,0 +1,200 @@
+//! Selecting which Periods to download (--periods)
+//
+// A Period selection is a comma-separated list of items, and a Period is downloaded if it matches
+// any of the items:
+//
+//   - an index or a range of indexes in the manifest, counting from 1: "2", "2-4", "3-" or "-2"
+//
+//   - a pattern for Period@id, introduced by "@", in which "*" matches any sequence of characters
+//   and "?" matches any single character: "@main-*"
+//
+//   - a window for the Period start time, introduced by "time=", with each bound as a number of
+//   seconds or HH:MM:SS and either bound optional: "time=10:00-45:00" selects the Periods that start
+//   at or after 10 minutes into the presentation and before 45 minutes.
+//
+// Periods that aren't selected (or that contain ads, with --skip-ads) are removed from the manifest
+// before it is handed to the DashDownloader. The remaining Periods are given explicit start times
+// and durations, so that their timing does not depend on the Periods that were removed.
+
+
+use std::time::Duration;
+use tracing::info;
+use anyhow::{anyhow, Result};
+use dash_mpd::MPD;
+use crate::manifest::{Manifest, period_start, period_duration};
+use crate::ads::detect_ads;
+use crate::parse_duration_secs;
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum PeriodSpec {
+    // Inclusive range of indexes, counting from 1.
+    Indexes(usize, Option<usize>),
+    Id(String),
+    // Window for the start time of the Period, in seconds.
+    StartTime(f64, Option<f64>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodSelection(Vec<PeriodSpec>);
+
+fn parse_index(s: &str) -> Result<usize, String> {
+    match s.trim().parse::<usize>() {
+        Ok(0) => Err(String::from("Period indexes start at 1")),
+        Ok(i) => Ok(i),
+        Err(_) => Err(format!("invalid Period index {s}")),
+    }
+}
+
+fn parse_spec(item: &str) -> Result<PeriodSpec, String> {
+    if let Some(pattern) = item.strip_prefix('@') {
+        if pattern.is_empty() {
+            return Err(String::from("empty Period id pattern"));
+        }
+        return Ok(PeriodSpec::Id(pattern.to_string()));
+    }
+    if let Some(window) = item.strip_prefix("time=") {
+        let (from, to) = window.split_once('-')
+            .ok_or_else(|| format!("expecting time=START-END, found {item}"))?;
+        let from = if from.trim().is_empty() { 0.0 } else { parse_duration_secs(from.trim())? };
+        let to = if to.trim().is_empty() { None } else { Some(parse_duration_secs(to.trim())?) };
+        return Ok(PeriodSpec::StartTime(from, to));
+    }
+    match item.split_once('-') {
+        None => {
+            let i = parse_index(item)?;
+            Ok(PeriodSpec::Indexes(i, Some(i)))
+        },
+        Some((from, to)) => {
+            let from = if from.trim().is_empty() { 1 } else { parse_index(from)? };
+            let to = if to.trim().is_empty() { None } else { Some(parse_index(to)?) };
+            if to.is_some_and(|to| to < from) {
+                return Err(format!("empty Period range {item}"));
+            }
+            Ok(PeriodSpec::Indexes(from, to))
+        },
+    }
+}
+
+// Parse the value of the --periods commandline option.
+pub fn parse_period_selection(s: &str) -> Result<PeriodSelection, String> {
+    let specs = s.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(parse_spec)
+        .collect::<Result<Vec<PeriodSpec>, String>>()?;
+    if specs.is_empty() {
+        return Err(String::from("empty Period selection"));
+    }
+    Ok(PeriodSelection(specs))
+}
+
+// Whether text matches pattern, where "*" matches any sequence of characters and "?" matches any
+// single character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    // Position in the pattern of the last "*" seen, and the position in the text that it matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut pi, mut ti) = (0, 0);
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+impl PeriodSelection {
+    // Whether Period number idx (counting from 0) of the manifest is selected.
+    pub fn selects(&self, mpd: &MPD, idx: usize) -> bool {
+        let period = &mpd.periods[idx];
+        self.0.iter().any(|spec| match spec {
+            PeriodSpec::Indexes(from, to) => idx + 1 >= *from && to.is_none_or(|to| idx < to),
+            PeriodSpec::Id(pattern) => period.id.as_deref().is_some_and(|id| glob_match(pattern, id)),
+            PeriodSpec::StartTime(from, to) => {
+                let start = period_start(mpd, idx);
+                start >= *from && to.is_none_or(|to| start < to)
+            },
+        })
+    }
+}
+
+fn format_secs(secs: f64) -> String {
+    let whole = secs as u64;
+    format!("{:02}:{:02}:{:06.3}", whole / 3600, (whole / 60) % 60, secs - (whole - whole % 60) as f64)
+}
+
+// Print the list of Periods in the manifest with their start time and duration, and whether they
+// are selected for download (for --simulate).
+pub fn log_periods(manifest: &Manifest, selection: Option<&PeriodSelection>) {
+    let mpd = &manifest.mpd;
+    info!("Manifest contains {} Period(s)", mpd.periods.len());
+    for (idx, period) in mpd.periods.iter().enumerate() {
+        let id = period.id.as_deref()
+            .map(|id| format!(" (id {id})"))
+            .unwrap_or_default();
+        let duration = period_duration(mpd, idx)
+            .map(format_secs)
+            .unwrap_or_else(|| String::from("unknown"));
+        let skipped = match selection {
+            Some(sel) if !sel.selects(mpd, idx) => ", skipped",
+            _ => "",
+        };
+        info!("  Period {}{id}: start {}, duration {duration}{skipped}",
+              idx + 1, format_secs(period_start(mpd, idx)));
+    }
+}
+
+// Remove the Periods that are not selected from the manifest, and if skip_ads is set, those that
+// contain ads.
+pub fn select_periods(
+    manifest: &Manifest,
+    selection: Option<&PeriodSelection>,
+    skip_ads: bool) -> Result<MPD>
+{
+    let original = &manifest.mpd;
+    let mut keep: Vec<bool> = (0..original.periods.len())
+        .map(|idx| selection.is_none_or(|sel| sel.selects(original, idx)))
+        .collect();
+    if skip_ads {
+        for (k, ad) in keep.iter_mut().zip(detect_ads(manifest)) {
+            *k = *k && !ad;
+        }
+    }
+    let mut mpd = original.clone();
+    mpd.periods = original.periods.iter().enumerate()
+        .filter(|(idx, _)| keep[*idx])
+        .map(|(idx, period)| {
+            let mut period = period.clone();
+            period.start = Some(Duration::from_secs_f64(period_start(original, idx)));
+            period.duration = period_duration(original, idx).map(Duration::from_secs_f64);
+            period
+        })
+        .collect();
+    if mpd.periods.is_empty() {
+        return Err(match selection {
+            Some(_) => anyhow!("no Periods match the --periods selection"),
+            None => anyhow!("all Periods were classified as ads"),
+        });
+    }
+    info!("Selected {} of {} Periods", mpd.periods.len(), original.periods.len());
+    // The presentation now ends with the last selected Period.
+    mpd.mediaPresentationDuration = None;
+    if let Some(last) = mpd.periods.last() {
+        if let (Some(start), Some(duration)) = (last.start, last.duration) {
+            mpd.mediaPresentationDuration = Some(start + duration);
+        }
+    }
+    Ok(mpd)
+}
@@ -0,0 +1,206 @@
+//! Bandwidth limit shared by parallel downloads (--limit-rate with --jobs)
+//
+// Each DashDownloader can only throttle its own requests, so when several downloads run in parallel
+// they share the --limit-rate bandwidth through a small relay on the loopback interface, which our
+// HTTP client uses as its proxy. The relay draws every octet that it receives from the network
+// from a single token bucket, so that a download may use the bandwidth left over by the others,
+// for example once they have completed. Requests for https URLs arrive as CONNECT requests, for
+// which the relay opens a tunnel to the origin server without looking inside the TLS connection.
+// Requests for http URLs (which a client sends to a proxy with an absolute URL) are forwarded
+// unchanged to the origin server, which is required to accept them. When the user specified an
+// http proxy with --proxy, every connection is instead forwarded unchanged to that proxy.
+
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use url::Url;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::warn;
+use anyhow::{anyhow, Result, Context};
+
+
+// Requests with a larger head than this are rejected.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+
+// A token bucket holding up to one second of bandwidth. A transfer that takes more tokens than are
+// available leaves the bucket in debt, and waits until the debt has been repaid, so that
+// concurrent transfers are served in turn.
+pub struct TokenBucket {
+    // Octets per second.
+    rate: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        let rate = rate as f64;
+        Self { rate, state: Mutex::new(BucketState { tokens: rate, updated: Instant::now() }) }
+    }
+
+    // Wait until we are allowed to transfer count octets.
+    pub async fn take(&self, count: usize) {
+        let wait = {
+            let Ok(mut state) = self.state.lock() else {
+                return;
+            };
+            let now = Instant::now();
+            let refill = now.duration_since(state.updated).as_secs_f64() * self.rate;
+            state.tokens = (state.tokens + refill).min(self.rate) - count as f64;
+            state.updated = now;
+            if state.tokens < 0.0 {
+                Duration::from_secs_f64(-state.tokens / self.rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// The loopback relay, which runs until it is dropped.
+pub struct ThrottlingProxy {
+    addr: SocketAddr,
+    server: JoinHandle<()>,
+}
+
+impl ThrottlingProxy {
+    // Start relaying connections with the bandwidth of bucket. upstream is the address (host:port)
+    // of the http proxy that connections are forwarded to, if any.
+    pub async fn start(bucket: Arc<TokenBucket>, upstream: Option<String>) -> Result<ThrottlingProxy> {
+        let listener = TcpListener::bind("127.0.0.1:0").await
+            .context("starting bandwidth limiting proxy")?;
+        let addr = listener.local_addr()?;
+        let upstream: Option<Arc<str>> = upstream.map(Arc::from);
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let bucket = Arc::clone(&bucket);
+                let upstream = upstream.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, &bucket, upstream.as_deref()).await {
+                        warn!("Bandwidth limiting proxy: {e:#}");
+                    }
+                });
+            }
+        });
+        Ok(ThrottlingProxy { addr, server })
+    }
+
+    // The URL to configure as the proxy of our HTTP client.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+impl Drop for ThrottlingProxy {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+// Read the head of the request (up to and including the empty line that ends the headers) from
+// client. Any octets that follow the head are also returned.
+async fn read_head(client: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let count = client.read(&mut buf).await?;
+        if count == 0 {
+            return Err(anyhow!("connection closed before the end of the request head"));
+        }
+        head.extend_from_slice(&buf[..count]);
+        if head.windows(4).any(|w| w == b"\r\n\r\n") {
+            return Ok(head);
+        }
+        if head.len() > MAX_HEAD_SIZE {
+            return Err(anyhow!("request head too large"));
+        }
+    }
+}
+
+// The address of the origin server that a request line of the form "METHOD TARGET HTTP/1.1" is for.
+fn origin_address(request_line: &str) -> Result<(bool, String)> {
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next()
+        .ok_or_else(|| anyhow!("malformed request line {request_line}"))?;
+    if method.eq_ignore_ascii_case("CONNECT") {
+        return Ok((true, target.to_string()));
+    }
+    let url = Url::parse(target)
+        .with_context(|| format!("parsing request target {target}"))?;
+    let host = url.host_str()
+        .ok_or_else(|| anyhow!("request target {target} has no host"))?;
+    let port = url.port_or_known_default()
+        .ok_or_else(|| anyhow!("request target {target} has no port"))?;
+    Ok((false, format!("{host}:{port}")))
+}
+
+async fn handle_connection(mut client: TcpStream, bucket: &TokenBucket, upstream: Option<&str>) -> Result<()> {
+    let head = read_head(&mut client).await?;
+    let request_line = String::from_utf8_lossy(&head).lines().next().unwrap_or_default().to_string();
+    let (tunnel, address) = match upstream {
+        Some(proxy) => (false, proxy.to_string()),
+        None => origin_address(&request_line)?,
+    };
+    let mut server = match TcpStream::connect(&address).await {
+        Ok(s) => s,
+        Err(e) => {
+            let _ = client.write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+            return Err(e).with_context(|| format!("connecting to {address}"));
+        },
+    };
+    if tunnel {
+        // The TLS handshake follows the empty line that ends the CONNECT request, so any octets
+        // after the head belong to the tunnel.
+        let end = head.windows(4).position(|w| w == b"\r\n\r\n").unwrap_or(head.len()) + 4;
+        client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await?;
+        server.write_all(&head[end.min(head.len())..]).await?;
+    } else {
+        server.write_all(&head).await?;
+    }
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut server_read, mut server_write) = server.into_split();
+    let upload = async {
+        let outcome = tokio::io::copy(&mut client_read, &mut server_write).await;
+        let _ = server_write.shutdown().await;
+        outcome
+    };
+    let download = async {
+        let outcome = relay_throttled(&mut server_read, &mut client_write, bucket).await;
+        let _ = client_write.shutdown().await;
+        outcome
+    };
+    let (up, down) = tokio::join!(upload, download);
+    up?;
+    down?;
+    Ok(())
+}
+
+// Copy from reader to writer, taking the octets from bucket.
+async fn relay_throttled<R, W>(reader: &mut R, writer: &mut W, bucket: &TokenBucket) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin
+{
+    let mut buf = vec![0u8; 16 * 1024];
+    loop {
+        let count = reader.read(&mut buf).await?;
+        if count == 0 {
+            return Ok(());
+        }
+        bucket.take(count).await;
+        writer.write_all(&buf[..count]).await?;
+    }
+}
@@ -0,0 +1,705 @@
+//! Resumable downloads (--continue)
+//
+// The DashDownloader always fetches every media segment, so to be able to resume an interrupted
+// download we interpose a small caching HTTP server on the loopback interface between the
+// DashDownloader and the origin servers. We rewrite the manifest so that every media segment URL
+// points to this server, and give the DashDownloader the rewritten manifest as a local file. Each
+// segment that the DashDownloader requests is fetched from the origin server (using the same HTTP
+// client and authentication), streamed to the DashDownloader while it is saved in the resume
+// directory, and recorded in a journal once complete. This only happens for downloads run with
+// --continue, so that plain downloads don't write every segment twice. When the download is rerun
+// with --continue, segments listed in the journal are served from disk, and only the missing
+// segments are fetched from the network before the content is muxed as usual. The proxy only serves the media
+// segments listed in the rewritten manifest, so that it can't be used to send our credentials to
+// other hosts.
+//
+// The resume directory is named after the output file, and is located next to the output file or
+// in the --save-fragments directory. It is deleted when the download completes successfully.
+//
+// The journal records, for each Representation whose segments were requested, a fingerprint of the
+// Representation (its id, bandwidth, codecs and segment URLs), and a fingerprint of the commandline
+// options that determine which Representations are selected. If the manifest has changed between
+// runs or different Representations would be selected, the journal is invalidated and the download
+// restarts from the beginning.
+
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use url::Url;
+use fs_err as fs;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use anyhow::{anyhow, Result, Context};
+use dash_mpd::{MPD, BaseURL, Representation};
+use crate::manifest::{Manifest, ManifestFetcher, merged_template, representation_base_url,
+                      representation_segments};
+
+
+const JOURNAL_VERSION: u64 = 1;
+
+
+// The location of the resume directory for the download to output.
+pub fn resume_dir(output: &Path, fragments_dir: Option<&Path>) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".resume");
+    match fragments_dir {
+        Some(dir) => dir.join(name),
+        None => output.with_file_name(name),
+    }
+}
+
+// 64-bit FNV-1a hash, which unlike the hasher from the standard library is stable across releases,
+// as needed for values that are saved in the journal.
+fn fnv1a(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for b in part.bytes().chain(std::iter::once(0)) {
+            hash ^= u64::from(b);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+#[derive(Debug, Clone)]
+struct CachedSegment {
+    url: String,
+    range: Option<String>,
+    // Name of the file in the resume directory that contains the segment data.
+    file: String,
+    size: u64,
+    status: u16,
+    content_type: Option<String>,
+    content_range: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RepresentationJournal {
+    fingerprint: String,
+    // Completed segments, keyed by the hash of their URL and byte range.
+    segments: BTreeMap<String, CachedSegment>,
+}
+
+#[derive(Debug, Default)]
+struct Journal {
+    manifest: String,
+    selection: String,
+    representations: BTreeMap<String, RepresentationJournal>,
+}
+
+impl Journal {
+    fn to_json(&self) -> serde_json::Value {
+        let reps: serde_json::Map<String, serde_json::Value> = self.representations.iter()
+            .map(|(key, rj)| {
+                let segments: Vec<serde_json::Value> = rj.segments.values()
+                    .map(|cs| serde_json::json!({
+                        "url": cs.url,
+                        "range": cs.range,
+                        "file": cs.file,
+                        "size": cs.size,
+                        "status": cs.status,
+                        "content_type": cs.content_type,
+                        "content_range": cs.content_range,
+                    }))
+                    .collect();
+                (key.clone(), serde_json::json!({
+                    "fingerprint": rj.fingerprint,
+                    "segments": segments,
+                }))
+            })
+            .collect();
+        serde_json::json!({
+            "version": JOURNAL_VERSION,
+            "manifest": self.manifest,
+            "selection": self.selection,
+            "representations": reps,
+        })
+    }
+
+    fn from_json(json: &serde_json::Value) -> Result<Journal> {
+        if json["version"].as_u64() != Some(JOURNAL_VERSION) {
+            return Err(anyhow!("unsupported journal version"));
+        }
+        let string = |v: &serde_json::Value| v.as_str().map(String::from);
+        let mut journal = Journal {
+            manifest: string(&json["manifest"]).unwrap_or_default(),
+            selection: string(&json["selection"]).unwrap_or_default(),
+            representations: BTreeMap::new(),
+        };
+        let reps = json["representations"].as_object()
+            .ok_or_else(|| anyhow!("journal has no representations"))?;
+        for (key, rj) in reps {
+            let mut rep = RepresentationJournal {
+                fingerprint: string(&rj["fingerprint"]).unwrap_or_default(),
+                segments: BTreeMap::new(),
+            };
+            for seg in rj["segments"].as_array().into_iter().flatten() {
+                let cs = CachedSegment {
+                    url: string(&seg["url"]).ok_or_else(|| anyhow!("journal segment has no URL"))?,
+                    range: string(&seg["range"]),
+                    file: string(&seg["file"]).ok_or_else(|| anyhow!("journal segment has no file"))?,
+                    size: seg["size"].as_u64().unwrap_or(0),
+                    status: seg["status"].as_u64().and_then(|s| u16::try_from(s).ok()).unwrap_or(200),
+                    content_type: string(&seg["content_type"]),
+                    content_range: string(&seg["content_range"]),
+                };
+                rep.segments.insert(segment_key(&cs.url, cs.range.as_deref()), cs);
+            }
+            journal.representations.insert(key.clone(), rep);
+        }
+        Ok(journal)
+    }
+
+    fn segment_count(&self) -> usize {
+        self.representations.values().map(|rj| rj.segments.len()).sum()
+    }
+}
+
+fn read_journal(path: &Path) -> Result<Journal> {
+    let json = fs::read_to_string(path)?;
+    Journal::from_json(&serde_json::from_str(&json)?)
+}
+
+fn segment_key(url: &str, range: Option<&str>) -> String {
+    fnv1a(&[url, range.unwrap_or("")])
+}
+
+// The reasons for which the journal from a previous run can't be used for this download, if any.
+fn journal_invalid_reason(
+    journal: &Journal,
+    mpd_url: &str,
+    selection: &str,
+    fingerprints: &HashMap<String, String>) -> Option<String>
+{
+    if journal.manifest != mpd_url {
+        return Some(String::from("it concerns a different manifest URL"));
+    }
+    if journal.selection != selection {
+        return Some(String::from("the options that select Representations have changed"));
+    }
+    for (key, rj) in &journal.representations {
+        match fingerprints.get(key) {
+            None => return Some(format!("Representation {key} is no longer in the manifest")),
+            Some(fp) if *fp != rj.fingerprint =>
+                return Some(format!("Representation {key} has changed in the manifest")),
+            _ => (),
+        }
+    }
+    None
+}
+
+
+// A media segment request that failed, and the reason for the failure.
+#[derive(Debug, Clone)]
+pub struct FailedRequest {
+    pub url: Url,
+    pub range: Option<String>,
+    pub reason: String,
+}
+
+struct ResumeState {
+    fetcher: ManifestFetcher,
+    dir: PathBuf,
+    journal: Mutex<Journal>,
+    // The Representation (identified by its key in the journal) that each segment URL belongs to,
+    // and the fingerprint of each Representation.
+    owners: HashMap<Url, String>,
+    fingerprints: HashMap<String, String>,
+    // The first media segment request that failed.
+    failure: Mutex<Option<FailedRequest>>,
+}
+
+impl ResumeState {
+    fn record_failure(&self, url: &Url, range: Option<&str>, reason: String) {
+        if let Ok(mut failure) = self.failure.lock() {
+            failure.get_or_insert_with(|| FailedRequest {
+                url: url.clone(),
+                range: range.map(String::from),
+                reason,
+            });
+        }
+    }
+
+    // A segment saved by a previous run, and the file that contains it.
+    async fn cached(&self, rep_key: &str, key: &str) -> Option<(CachedSegment, tokio::fs::File)> {
+        let cs = self.journal.lock().ok()?
+            .representations.get(rep_key)?
+            .segments.get(key)?
+            .clone();
+        let file = tokio::fs::File::open(self.dir.join(&cs.file)).await.ok()?;
+        if file.metadata().await.ok()?.len() != cs.size {
+            return None;
+        }
+        Some((cs, file))
+    }
+
+    fn save_journal(&self, journal: &Journal) -> Result<()> {
+        let tmp = self.dir.join("journal.json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&journal.to_json())?)?;
+        fs::rename(&tmp, self.dir.join("journal.json"))?;
+        Ok(())
+    }
+
+    // Record the segment, whose content has been saved to the file named cs.file with a .part suffix.
+    fn record(&self, rep_key: &str, key: &str, cs: CachedSegment) -> Result<()> {
+        fs::rename(self.dir.join(format!("{}.part", cs.file)), self.dir.join(&cs.file))?;
+        let mut journal = self.journal.lock()
+            .map_err(|_| anyhow!("resume journal lock poisoned"))?;
+        let rj = journal.representations.entry(rep_key.to_string())
+            .or_insert_with(|| RepresentationJournal {
+                fingerprint: self.fingerprints.get(rep_key).cloned().unwrap_or_default(),
+                segments: BTreeMap::new(),
+            });
+        rj.segments.insert(key.to_string(), cs);
+        self.save_journal(&journal)
+    }
+
+    // Fetch the segment from the origin server, and stream it to writer while saving it in the
+    // resume directory.
+    async fn forward<W>(
+        &self,
+        writer: &mut W,
+        rep_key: &str,
+        origin: Url,
+        range: Option<String>,
+        head_only: bool) -> Result<()>
+    where
+        W: AsyncWrite + Unpin
+    {
+        let mut response = match self.fetcher.fetch_segment(origin.clone(), range.as_deref()).await {
+            Ok(r) => r,
+            Err(e) => {
+                self.record_failure(&origin, range.as_deref(), format!("{e:#}"));
+                return send_error(writer, 502, &format!("{e:#}")).await;
+            },
+        };
+        let status = response.status().as_u16();
+        let headers = response.headers();
+        let content_type = headers.get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let content_range = headers.get(reqwest::header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let length = response.content_length();
+        send_head(writer, status, length, content_type.as_deref(), content_range.as_deref()).await?;
+        let key = segment_key(origin.as_str(), range.as_deref());
+        let file = format!("{key}.seg");
+        // Only successful responses are saved.
+        let mut saved = None;
+        if (200..300).contains(&status) {
+            match tokio::fs::File::create(self.dir.join(format!("{file}.part"))).await {
+                Ok(f) => saved = Some(f),
+                Err(e) => warn!("Can't save media segment in resume directory: {e}"),
+            }
+        } else {
+            let reason = response.status().canonical_reason().unwrap_or("");
+            self.record_failure(&origin, range.as_deref(), format!("HTTP status {status} {reason}"));
+        }
+        let mut body = BodyWriter { writer, chunked: length.is_none(), head_only };
+        let mut size = 0;
+        loop {
+            let chunk = match response.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    self.record_failure(&origin, range.as_deref(), format!("receiving media segment: {e}"));
+                    return Err(anyhow!(e).context("receiving media segment"));
+                },
+            };
+            body.write(&chunk).await?;
+            size += chunk.len() as u64;
+            if let Some(f) = saved.as_mut() {
+                if let Err(e) = f.write_all(&chunk).await {
+                    warn!("Can't save media segment in resume directory: {e}");
+                    saved = None;
+                }
+            }
+        }
+        body.finish().await?;
+        if let Some(mut f) = saved {
+            f.flush().await?;
+            let cs = CachedSegment {
+                url: origin.to_string(),
+                range,
+                file,
+                size,
+                status,
+                content_type,
+                content_range,
+            };
+            if let Err(e) = self.record(rep_key, &key, cs) {
+                warn!("Can't record media segment in resume journal: {e:#}");
+            }
+        }
+        Ok(())
+    }
+}
+
+
+async fn send_head<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    status: u16,
+    length: Option<u64>,
+    content_type: Option<&str>,
+    content_range: Option<&str>) -> Result<()>
+{
+    let reason = reqwest::StatusCode::from_u16(status).ok()
+        .and_then(|s| s.canonical_reason())
+        .unwrap_or("");
+    let mut head = format!("HTTP/1.1 {status} {reason}\r\nConnection: close\r\n");
+    match length {
+        Some(length) => head += &format!("Content-Length: {length}\r\n"),
+        // The chunked encoding lets the DashDownloader distinguish between the end of the body and
+        // a connection that we drop because the origin server failed while sending the segment.
+        None => head += "Transfer-Encoding: chunked\r\n",
+    }
+    if let Some(ct) = content_type {
+        head += &format!("Content-Type: {ct}\r\n");
+    }
+    if let Some(cr) = content_range {
+        head += &format!("Content-Range: {cr}\r\n");
+    }
+    head += "\r\n";
+    writer.write_all(head.as_bytes()).await?;
+    Ok(())
+}
+
+async fn send_error<W: AsyncWrite + Unpin>(writer: &mut W, status: u16, msg: &str) -> Result<()> {
+    send_head(writer, status, Some(msg.len() as u64), Some("text/plain"), None).await?;
+    writer.write_all(msg.as_bytes()).await?;
+    Ok(())
+}
+
+// Writes the body of a response, in the chunked transfer encoding when its length isn't known in
+// advance.
+struct BodyWriter<'a, W> {
+    writer: &'a mut W,
+    chunked: bool,
+    head_only: bool,
+}
+
+impl<W: AsyncWrite + Unpin> BodyWriter<'_, W> {
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.head_only || data.is_empty() {
+            return Ok(());
+        }
+        if self.chunked {
+            self.writer.write_all(format!("{:x}\r\n", data.len()).as_bytes()).await?;
+            self.writer.write_all(data).await?;
+            self.writer.write_all(b"\r\n").await?;
+        } else {
+            self.writer.write_all(data).await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(self) -> Result<()> {
+        if self.chunked && !self.head_only {
+            self.writer.write_all(b"0\r\n\r\n").await?;
+        }
+        Ok(())
+    }
+}
+
+
+// Our proxy URLs have the form http://127.0.0.1:PORT/abs/SCHEME/HOST/PATH?QUERY
+fn proxy_url(proxy: &Url, origin: &Url) -> String {
+    let authority = &origin[url::Position::BeforeHost..url::Position::BeforePath];
+    let path = &origin[url::Position::BeforePath..url::Position::AfterQuery];
+    format!("{proxy}abs/{}/{authority}{path}", origin.scheme())
+}
+
+fn origin_url(path: &str) -> Option<Url> {
+    let rest = path.strip_prefix("/abs/")?;
+    let (scheme, rest) = rest.split_once('/')?;
+    Url::parse(&format!("{scheme}://{rest}")).ok()
+}
+
+fn resolve_and_proxy(proxy: &Url, base: &Url, target: &str) -> Result<String> {
+    let origin = base.join(target)
+        .with_context(|| format!("resolving URL {target}"))?;
+    Ok(proxy_url(proxy, &origin))
+}
+
+// Rewrite the manifest so that all media segment URLs point to our proxy. Segment addressing
+// information from the AdaptationSet is moved down to each Representation, and URLs are made
+// absolute, so that they don't depend on the BaseURL hierarchy.
+fn rewrite_manifest(manifest: &Manifest, proxy: &Url) -> Result<MPD> {
+    let mut mpd = manifest.mpd.clone();
+    for (pidx, period) in mpd.periods.iter_mut().enumerate() {
+        let original_period = &manifest.mpd.periods[pidx];
+        for (aidx, adaptation) in period.adaptations.iter_mut().enumerate() {
+            let original_adaptation = &original_period.adaptations[aidx];
+            for rep in adaptation.representations.iter_mut() {
+                let base = representation_base_url(manifest, original_period, original_adaptation, rep);
+                if let Some(mut st) = merged_template(original_adaptation, rep) {
+                    if let Some(init) = &st.initialization {
+                        st.initialization = Some(resolve_and_proxy(proxy, &base, init)?);
+                    }
+                    if let Some(media) = &st.media {
+                        st.media = Some(resolve_and_proxy(proxy, &base, media)?);
+                    }
+                    rep.SegmentTemplate = Some(st);
+                }
+                if rep.SegmentList.is_none() {
+                    rep.SegmentList = original_adaptation.SegmentList.clone();
+                }
+                if let Some(sl) = rep.SegmentList.as_mut() {
+                    if let Some(init) = sl.Initialization.as_mut() {
+                        if let Some(su) = &init.sourceURL {
+                            init.sourceURL = Some(resolve_and_proxy(proxy, &base, su)?);
+                        }
+                    }
+                    for su in sl.segment_urls.iter_mut() {
+                        if let Some(m) = &su.media {
+                            su.media = Some(resolve_and_proxy(proxy, &base, m)?);
+                        }
+                    }
+                }
+                if rep.SegmentBase.is_none() {
+                    rep.SegmentBase = original_adaptation.SegmentBase.clone();
+                }
+                if let Some(init) = rep.SegmentBase.as_mut().and_then(|sb| sb.Initialization.as_mut()) {
+                    if let Some(su) = &init.sourceURL {
+                        init.sourceURL = Some(resolve_and_proxy(proxy, &base, su)?);
+                    }
+                }
+                rep.BaseURL = vec![BaseURL { base: proxy_url(proxy, &base), ..Default::default() }];
+            }
+            adaptation.SegmentTemplate = None;
+            adaptation.SegmentList = None;
+            adaptation.SegmentBase = None;
+            adaptation.BaseURL.clear();
+        }
+        period.BaseURL.clear();
+    }
+    mpd.base_url.clear();
+    Ok(mpd)
+}
+
+// The key that identifies a Representation in the journal: the index of its Period and
+// AdaptationSet, and its id (or its position in the AdaptationSet if it has none).
+pub fn representation_key(pidx: usize, aidx: usize, ridx: usize, rep: &Representation) -> String {
+    let rep_id = rep.id.clone().unwrap_or_else(|| format!("#{}", ridx + 1));
+    format!("p{pidx}/a{aidx}/{rep_id}")
+}
+
+// The Representation that each media segment URL belongs to, and a fingerprint of each
+// Representation.
+fn representation_index(manifest: &Manifest) -> Result<(HashMap<Url, String>, HashMap<String, String>)> {
+    let mut owners = HashMap::new();
+    let mut fingerprints = HashMap::new();
+    for (pidx, period) in manifest.mpd.periods.iter().enumerate() {
+        for (aidx, adaptation) in period.adaptations.iter().enumerate() {
+            for (ridx, rep) in adaptation.representations.iter().enumerate() {
+                let rep_id = rep.id.clone().unwrap_or_else(|| format!("#{}", ridx + 1));
+                let rep_key = representation_key(pidx, aidx, ridx, rep);
+                let bandwidth = rep.bandwidth.unwrap_or(0).to_string();
+                let codecs = rep.codecs.clone()
+                    .or_else(|| adaptation.codecs.clone())
+                    .unwrap_or_default();
+                let mut parts = vec![rep_id.clone(), bandwidth, codecs];
+                let segments = representation_segments(manifest, pidx, adaptation, rep)
+                    .with_context(|| format!("listing the media segments of Representation {rep_id}"))?;
+                for s in segments.init.iter().chain(segments.media.iter()) {
+                    owners.insert(s.url.clone(), rep_key.clone());
+                    parts.push(s.url.to_string());
+                    parts.push(s.byte_range.clone().unwrap_or_default());
+                }
+                let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+                fingerprints.insert(rep_key, fnv1a(&parts));
+            }
+        }
+    }
+    Ok((owners, fingerprints))
+}
+
+
+async fn handle_connection(state: &ResumeState, mut stream: TcpStream) -> Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut range = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("range") {
+                range = Some(value.trim().to_string());
+            }
+        }
+    }
+    let mut words = request_line.split_whitespace();
+    let method = words.next().unwrap_or("");
+    let path = words.next().unwrap_or("");
+    let head_only = method == "HEAD";
+    let Some(origin) = origin_url(path) else {
+        return send_error(&mut writer, 404, "not a proxied URL").await;
+    };
+    let Some(rep_key) = state.owners.get(&origin) else {
+        state.record_failure(&origin, range.as_deref(), String::from("not a media segment of the manifest"));
+        return send_error(&mut writer, 403, "not a media segment of the manifest").await;
+    };
+    let key = segment_key(origin.as_str(), range.as_deref());
+    if let Some((cs, mut file)) = state.cached(rep_key, &key).await {
+        send_head(&mut writer, cs.status, Some(cs.size),
+                  cs.content_type.as_deref(), cs.content_range.as_deref()).await?;
+        if !head_only {
+            tokio::io::copy(&mut file, &mut writer).await?;
+        }
+    } else {
+        state.forward(&mut writer, rep_key, origin, range, head_only).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+
+pub struct ResumeSession {
+    dir: PathBuf,
+    manifest_url: String,
+    state: Arc<ResumeState>,
+    server: JoinHandle<()>,
+}
+
+impl ResumeSession {
+    // Prepare to download from manifest, which was retrieved from mpd_url, with a resume journal in
+    // dir. selection is a description of the commandline options that determine which
+    // Representations and media segments are selected. The segments listed in the journal of a
+    // previous run are reused if the journal is still valid.
+    pub async fn start(
+        fetcher: ManifestFetcher,
+        manifest: &Manifest,
+        mpd_url: &str,
+        dir: &Path,
+        selection: &str) -> Result<ResumeSession>
+    {
+        if manifest.mpd.mpdtype.as_deref() == Some("dynamic") {
+            return Err(anyhow!("--continue is not supported for live streams (dynamic manifests)"));
+        }
+        let (owners, fingerprints) = representation_index(manifest)?;
+        let journal_path = dir.join("journal.json");
+        let mut journal = None;
+        if dir.exists() {
+            if journal_path.exists() {
+                match read_journal(&journal_path) {
+                    Ok(j) => match journal_invalid_reason(&j, mpd_url, selection, &fingerprints) {
+                        None => journal = Some(j),
+                        Some(reason) => warn!("Discarding resume journal, because {reason}; restarting download from the beginning"),
+                    },
+                    Err(e) => warn!("Discarding unreadable resume journal: {e:#}"),
+                }
+            }
+            if journal.is_none() {
+                fs::remove_dir_all(dir)
+                    .context("removing invalidated resume directory")?;
+            }
+        }
+        fs::create_dir_all(dir)
+            .context("creating resume directory")?;
+        let dir = fs::canonicalize(dir)?;
+        let journal = match journal {
+            Some(j) => {
+                info!("Resuming download: {} media segments of {} Representations already downloaded",
+                      j.segment_count(), j.representations.len());
+                j
+            },
+            None => Journal {
+                manifest: mpd_url.to_string(),
+                selection: selection.to_string(),
+                ..Default::default()
+            },
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await
+            .context("starting resume proxy")?;
+        let proxy = Url::parse(&format!("http://{}/", listener.local_addr()?))?;
+        let mpd = rewrite_manifest(manifest, &proxy)?;
+        let xml = quick_xml::se::to_string(&mpd)
+            .context("serializing rewritten manifest")?;
+        let mpd_path = dir.join("manifest.mpd");
+        fs::write(&mpd_path, xml)?;
+        let manifest_url = Url::from_file_path(&mpd_path)
+            .map_err(|_| anyhow!("invalid path for rewritten manifest"))?
+            .to_string();
+        let state = Arc::new(ResumeState {
+            fetcher,
+            dir: dir.clone(),
+            journal: Mutex::new(journal),
+            owners,
+            fingerprints,
+            failure: Mutex::new(None),
+        });
+        let server_state = Arc::clone(&state);
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = Arc::clone(&server_state);
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(&state, stream).await {
+                        warn!("Resume proxy: {e:#}");
+                    }
+                });
+            }
+        });
+        Ok(ResumeSession { dir, manifest_url, state, server })
+    }
+
+    // The URL of the rewritten manifest, to be downloaded by the DashDownloader.
+    pub fn manifest_url(&self) -> &str {
+        &self.manifest_url
+    }
+
+    // The first media segment request that failed during the download, if any.
+    pub fn failed_request(&self) -> Option<FailedRequest> {
+        self.state.failure.lock().ok()?.clone()
+    }
+
+    // The number and total size of the segments that have been saved of the Representation that
+    // the segment at url belongs to, or of all Representations if url is None.
+    pub fn saved_segments(&self, url: Option<&Url>) -> (usize, u64) {
+        let Ok(journal) = self.state.journal.lock() else {
+            return (0, 0);
+        };
+        let owner = url.and_then(|u| self.state.owners.get(u));
+        journal.representations.iter()
+            .filter(|(key, _)| url.is_none() || owner == Some(*key))
+            .flat_map(|(_, rj)| rj.segments.values())
+            .fold((0, 0), |(count, size), cs| (count + 1, size + cs.size))
+    }
+
+    // The keys of the Representations that media segments have been saved of, in this run or in
+    // the run that is resumed.
+    pub fn downloaded_representations(&self) -> Vec<String> {
+        match self.state.journal.lock() {
+            Ok(journal) => journal.representations.iter()
+                .filter(|(_, rj)| !rj.segments.is_empty())
+                .map(|(key, _)| key.clone())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // The download completed successfully, so the journal and cached segments are no longer needed.
+    pub fn finish(self) -> Result<()> {
+        self.server.abort();
+        fs::remove_dir_all(&self.dir)
+            .context("removing resume directory")?;
+        Ok(())
+    }
+
+    // The download failed; keep the journal so that it can be resumed.
+    pub fn keep(self) {
+        self.server.abort();
+        info!("Resume journal saved in {}; rerun with --continue to resume the download", self.dir.display());
+    }
+}
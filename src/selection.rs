@@ -0,0 +1,178 @@
+//! Choosing Representations according to the stream preferences of the commandline
+//
+// The DashDownloader makes its own choice of Representations for static manifests. We need to make
+// the same choice when we fetch media segments ourselves (when recording a live stream), and to
+// report it (for --dump-json), so this module reimplements its preference rules, recording the
+// reason for each step of the choice:
+//
+//   - audio and subtitle AdaptationSets in the preferred language, if any;
+//   - AdaptationSets with the most preferred role in --role-preference, if any;
+//   - for audio and subtitles, the first of the remaining AdaptationSets;
+//   - for video, the Representation whose id is --want-video-id, or else the Representations whose
+//   id contains it, then those that use the first of --prefer-video-codecs that is available, then
+//   the Representation whose width (or height) is closest to --prefer-video-width (or
+//   --prefer-video-height);
+//   - the Representation with the lowest, intermediate or highest bandwidth according to --quality
+//   (the DashDownloader defaults to the lowest).
+//
+// tests/selection_agreement.rs checks that both choices agree for each of these options, including
+// ties in bandwidth, so that a change in the DashDownloader's rules is noticed.
+
+
+use dash_mpd::{AdaptationSet, Representation, Period};
+use crate::formats::content_type;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct StreamPreferences {
+    pub quality: Option<String>,
+    pub prefer_video_width: Option<u64>,
+    pub prefer_video_height: Option<u64>,
+    pub prefer_video_codecs: Vec<String>,
+    pub want_video_id: Option<String>,
+    pub prefer_audio_language: Option<String>,
+    pub prefer_subtitle_language: Option<String>,
+    pub role_preference: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Choice {
+    // Indexes of the AdaptationSet in the Period and of the Representation in the AdaptationSet.
+    pub adaptation: usize,
+    pub representation: usize,
+    // The steps that led to this choice, in order.
+    pub reasons: Vec<String>,
+}
+
+// The kind of media of an AdaptationSet: "video", "audio" or "text".
+pub fn content_kind(adaptation: &AdaptationSet) -> Option<&'static str> {
+    let rep = adaptation.representations.first()?;
+    match content_type(adaptation, rep).as_str() {
+        "video" => Some("video"),
+        "audio" => Some("audio"),
+        "text" => Some("text"),
+        _ => None,
+    }
+}
+
+type Candidate<'a> = (usize, usize, &'a AdaptationSet, &'a Representation);
+
+pub fn matches_language(adaptation: &AdaptationSet, lang: &str) -> bool {
+    adaptation.lang.as_ref()
+        .is_some_and(|l| l.to_lowercase().starts_with(&lang.to_lowercase()))
+}
+
+// The rank of the AdaptationSet in the list of preferred roles, if it has one of these roles.
+fn role_rank(adaptation: &AdaptationSet, roles: &[String]) -> Option<usize> {
+    adaptation.Role.iter()
+        .filter_map(|r| r.value.as_ref())
+        .filter_map(|value| roles.iter().position(|p| p == value))
+        .min()
+}
+
+// Choose a Representation of the given kind in the Period. Returns None if the Period contains no
+// media of this kind.
+pub fn select_representation(period: &Period, kind: &str, prefs: &StreamPreferences) -> Option<Choice> {
+    let mut reasons = Vec::new();
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for (aidx, adaptation) in period.adaptations.iter().enumerate() {
+        if content_kind(adaptation) != Some(kind) {
+            continue;
+        }
+        for (ridx, rep) in adaptation.representations.iter().enumerate() {
+            candidates.push((aidx, ridx, adaptation, rep));
+        }
+    }
+    if candidates.is_empty() {
+        return None;
+    }
+    let (lang, option) = match kind {
+        "audio" => (prefs.prefer_audio_language.as_ref(), "--prefer-audio-language"),
+        "text" => (prefs.prefer_subtitle_language.as_ref(), "--prefer-subtitle-language"),
+        _ => (None, ""),
+    };
+    if let Some(lang) = lang {
+        let matching: Vec<Candidate> = candidates.iter()
+            .filter(|(_, _, a, _)| matches_language(a, lang))
+            .copied()
+            .collect();
+        if matching.is_empty() {
+            reasons.push(format!("no {kind} in language {lang} requested by {option}"));
+        } else {
+            reasons.push(format!("language matches {lang} requested by {option}"));
+            candidates = matching;
+        }
+    }
+    let roles = &prefs.role_preference;
+    if let Some(best) = candidates.iter().filter_map(|(_, _, a, _)| role_rank(a, roles)).min() {
+        candidates.retain(|(_, _, a, _)| role_rank(a, roles) == Some(best));
+        reasons.push(format!("role {} preferred by --role-preference", roles[best]));
+    }
+    // Like the DashDownloader, use the first of the remaining audio or subtitle AdaptationSets.
+    if kind != "video" {
+        let first = candidates[0].0;
+        candidates.retain(|c| c.0 == first);
+    }
+    if kind == "video" {
+        if let Some(want) = &prefs.want_video_id {
+            let wanted = candidates.iter()
+                .find(|(_, _, _, r)| r.id.as_ref() == Some(want));
+            if let Some(c) = wanted {
+                reasons.push(format!("id {want} requested by --want-video-id"));
+                return Some(Choice { adaptation: c.0, representation: c.1, reasons });
+            }
+            // A substring of the id is combined with the other preferences.
+            let matching: Vec<Candidate> = candidates.iter()
+                .filter(|(_, _, _, r)| r.id.as_ref().is_some_and(|id| id.contains(want.as_str())))
+                .copied()
+                .collect();
+            if matching.is_empty() {
+                reasons.push(format!("no Representation id contains {want} requested by --want-video-id"));
+            } else {
+                reasons.push(format!("id contains {want} requested by --want-video-id"));
+                candidates = matching;
+            }
+        }
+        for codec in &prefs.prefer_video_codecs {
+            let matching: Vec<Candidate> = candidates.iter()
+                .filter(|(_, _, a, r)| r.codecs.as_ref().or(a.codecs.as_ref())
+                        .is_some_and(|c| c.starts_with(codec.as_str())))
+                .copied()
+                .collect();
+            if !matching.is_empty() {
+                reasons.push(format!("codec {codec} preferred by --prefer-video-codecs"));
+                candidates = matching;
+                break;
+            }
+        }
+        if let Some(w) = prefs.prefer_video_width {
+            if let Some(c) = candidates.iter().min_by_key(|c| c.3.width.unwrap_or(0).abs_diff(w)) {
+                reasons.push(format!("width {} closest to --prefer-video-width {w}",
+                                     c.3.width.unwrap_or(0)));
+                return Some(Choice { adaptation: c.0, representation: c.1, reasons });
+            }
+        }
+        if let Some(h) = prefs.prefer_video_height {
+            if let Some(c) = candidates.iter().min_by_key(|c| c.3.height.unwrap_or(0).abs_diff(h)) {
+                reasons.push(format!("height {} closest to --prefer-video-height {h}",
+                                     c.3.height.unwrap_or(0)));
+                return Some(Choice { adaptation: c.0, representation: c.1, reasons });
+            }
+        }
+    }
+    candidates.sort_by_key(|(_, _, _, r)| r.bandwidth.unwrap_or(0));
+    let count = candidates.len();
+    let (chosen, which) = match prefs.quality.as_deref() {
+        Some("best") => (candidates.last(), "highest bandwidth (--quality best)"),
+        Some("intermediate") => (candidates.get(count / 2),
+                                 "intermediate bandwidth (--quality intermediate)"),
+        _ => (candidates.first(), "lowest bandwidth (default quality)"),
+    };
+    let c = chosen?;
+    if count > 1 {
+        reasons.push(format!("{which} of {count} candidates"));
+    } else if reasons.is_empty() {
+        reasons.push(format!("only {kind} Representation"));
+    }
+    Some(Choice { adaptation: c.0, representation: c.1, reasons })
+}
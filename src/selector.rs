@@ -0,0 +1,503 @@
+//! The format selector expression language (--format)
+//
+// A format selector chooses the video and audio Representations to download in each Period, in the
+// spirit of the format selection of yt-dlp. Its grammar is:
+//
+//    selector    := alternative ( "/" alternative )*
+//    alternative := stream ( "+" stream )*
+//    stream      := kind filter* sort?
+//    kind        := "v" | "video" | "a" | "audio"
+//    filter      := "[" key op "?"? value "]"
+//    op          := "=" | "!=" | "<" | "<=" | ">" | ">=" | "^=" | "$=" | "*="
+//    sort        := "(" sortkey ( "," sortkey )* ")"
+//    sortkey     := "+"? key
+//
+// The alternatives are tried from left to right, and the first one for which each stream matches
+// a Representation in the Period is used. A filter with "?" after the operator also accepts
+// Representations that don't specify the attribute. The Representations that pass the filters are
+// ordered by the sort keys, preferring the highest value of each key (or the lowest value with
+// "+"); without sort keys, they are ordered by bandwidth according to --quality. The kinds of media
+// that aren't mentioned in the chosen alternative are not downloaded, so "v" downloads only video.
+//
+// The selection is applied by rewriting the manifest so that it only contains the chosen
+// Representations (and any subtitle AdaptationSets), which leaves the DashDownloader no other
+// choice.
+
+
+use std::cmp::Ordering;
+use tracing::info;
+use anyhow::{anyhow, Result};
+use dash_mpd::MPD;
+use crate::formats::{Format, representation_format};
+use crate::selection::content_kind;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Id,
+    Codec,
+    Width,
+    Height,
+    Bandwidth,
+    Fps,
+    Lang,
+    Role,
+    Label,
+    Channels,
+}
+
+const KEYS: [(&str, Key); 16] = [
+    ("id", Key::Id),
+    ("codec", Key::Codec),
+    ("codecs", Key::Codec),
+    ("width", Key::Width),
+    ("height", Key::Height),
+    ("res", Key::Height),
+    ("bandwidth", Key::Bandwidth),
+    ("br", Key::Bandwidth),
+    ("fps", Key::Fps),
+    ("framerate", Key::Fps),
+    ("lang", Key::Lang),
+    ("language", Key::Lang),
+    ("role", Key::Role),
+    ("label", Key::Label),
+    ("channels", Key::Channels),
+    ("ch", Key::Channels),
+];
+
+impl Key {
+    fn is_numeric(self) -> bool {
+        matches!(self, Key::Width | Key::Height | Key::Bandwidth | Key::Fps | Key::Channels)
+    }
+
+    fn number(self, f: &Format) -> Option<f64> {
+        match self {
+            Key::Width => f.width.map(|w| w as f64),
+            Key::Height => f.height.map(|h| h as f64),
+            Key::Bandwidth => f.bandwidth.map(|b| b as f64),
+            Key::Fps => f.frame_rate,
+            Key::Channels => f.audio_channels.as_ref().and_then(|c| c.parse().ok()),
+            _ => None,
+        }
+    }
+
+    // The string values of the attribute (a Representation can have several roles).
+    fn strings(self, f: &Format) -> Vec<String> {
+        match self {
+            Key::Id => f.id.iter().cloned().collect(),
+            Key::Codec => f.codecs.iter().cloned().collect(),
+            Key::Lang => f.lang.iter().cloned().collect(),
+            Key::Role => f.roles.clone(),
+            Key::Label => f.label.iter().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Prefix,
+    Suffix,
+    Contains,
+}
+
+// Longer operators first, so that "<=" is not read as "<".
+const OPS: [(&str, Op); 9] = [
+    ("!=", Op::Ne),
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    ("^=", Op::Prefix),
+    ("$=", Op::Suffix),
+    ("*=", Op::Contains),
+    ("=", Op::Eq),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    key: Key,
+    op: Op,
+    operand: Operand,
+    // Whether Representations that don't specify the attribute pass the filter.
+    optional: bool,
+}
+
+impl Filter {
+    fn accepts(&self, f: &Format) -> bool {
+        match &self.operand {
+            Operand::Number(n) => match self.key.number(f) {
+                None => self.optional,
+                Some(v) => match self.op {
+                    Op::Eq => (v - n).abs() < 1e-6,
+                    Op::Ne => (v - n).abs() >= 1e-6,
+                    Op::Lt => v < *n,
+                    Op::Le => v <= *n,
+                    Op::Gt => v > *n,
+                    Op::Ge => v >= *n,
+                    _ => false,
+                },
+            },
+            Operand::Text(t) => {
+                let values = self.key.strings(f);
+                if values.is_empty() {
+                    return self.optional;
+                }
+                let t = t.to_lowercase();
+                let matches = |v: &String| {
+                    let v = v.to_lowercase();
+                    match self.op {
+                        Op::Eq | Op::Ne => v == t,
+                        Op::Prefix => v.starts_with(&t),
+                        Op::Suffix => v.ends_with(&t),
+                        Op::Contains => v.contains(&t),
+                        _ => false,
+                    }
+                };
+                if self.op == Op::Ne {
+                    !values.iter().any(matches)
+                } else {
+                    values.iter().any(matches)
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SortKey {
+    key: Key,
+    ascending: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Video,
+    Audio,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Video => "video",
+            Kind::Audio => "audio",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct StreamSpec {
+    kind: Kind,
+    filters: Vec<Filter>,
+    sort: Vec<SortKey>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatSelector {
+    alternatives: Vec<Vec<StreamSpec>>,
+    source: String,
+}
+
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> String {
+        let column = self.src[..self.pos].chars().count();
+        format!("{msg} at position {}\n  {}\n  {}^", column + 1, self.src, " ".repeat(column))
+    }
+
+    fn rest(&self) -> &str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.src.len() - trimmed.len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected \"{token}\"")))
+        }
+    }
+
+    fn word(&mut self) -> &str {
+        self.skip_whitespace();
+        let start = self.pos;
+        let len = self.rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.rest().len());
+        self.pos += len;
+        &self.src[start..self.pos]
+    }
+
+    fn key(&mut self) -> Result<Key, String> {
+        let start = self.pos;
+        let word = self.word().to_lowercase();
+        if word.is_empty() {
+            return Err(self.error("expected an attribute name"));
+        }
+        KEYS.iter()
+            .find(|(name, _)| *name == word)
+            .map(|(_, key)| *key)
+            .ok_or_else(|| {
+                self.pos = start;
+                self.skip_whitespace();
+                let names: Vec<&str> = KEYS.iter().map(|(name, _)| *name).collect();
+                self.error(&format!("unknown attribute \"{word}\" (expected one of {})", names.join(", ")))
+            })
+    }
+
+    fn operand(&mut self, key: Key) -> Result<Operand, String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let text = if let Some(quote) = self.rest().chars().next().filter(|c| *c == '"' || *c == '\'') {
+            let Some(len) = self.rest()[1..].find(quote) else {
+                return Err(self.error("unterminated quoted value"));
+            };
+            let text = self.rest()[1..=len].to_string();
+            self.pos += len + 2;
+            text
+        } else {
+            let len = self.rest().find(']').unwrap_or(self.rest().len());
+            let text = self.rest()[..len].trim().to_string();
+            self.pos += len;
+            text
+        };
+        if text.is_empty() {
+            self.pos = start;
+            return Err(self.error("expected a value"));
+        }
+        if !key.is_numeric() {
+            return Ok(Operand::Text(text));
+        }
+        let (digits, multiplier) = match text.chars().last() {
+            Some('k' | 'K') => (&text[..text.len() - 1], 1e3),
+            Some('m' | 'M') => (&text[..text.len() - 1], 1e6),
+            _ => (text.as_str(), 1.0),
+        };
+        match digits.parse::<f64>() {
+            Ok(n) => Ok(Operand::Number(n * multiplier)),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error(&format!("expected a number, found \"{text}\"")))
+            },
+        }
+    }
+
+    fn filter(&mut self) -> Result<Filter, String> {
+        let key = self.key()?;
+        self.skip_whitespace();
+        let op_pos = self.pos;
+        let Some((token, op)) = OPS.iter().find(|(token, _)| self.rest().starts_with(token)) else {
+            return Err(self.error("expected a comparison operator (=, !=, <, <=, >, >=, ^=, $= or *=)"));
+        };
+        self.pos += token.len();
+        let text_op = matches!(op, Op::Prefix | Op::Suffix | Op::Contains);
+        let ordering_op = matches!(op, Op::Lt | Op::Le | Op::Gt | Op::Ge);
+        if (key.is_numeric() && text_op) || (!key.is_numeric() && ordering_op) {
+            self.pos = op_pos;
+            let kind = if key.is_numeric() { "a numeric" } else { "a text" };
+            return Err(self.error(&format!("operator {token} can't be used with {kind} attribute")));
+        }
+        let optional = self.eat("?");
+        let operand = self.operand(key)?;
+        self.expect("]")?;
+        Ok(Filter { key, op: *op, operand, optional })
+    }
+
+    fn sort_keys(&mut self) -> Result<Vec<SortKey>, String> {
+        let mut keys = Vec::new();
+        loop {
+            let ascending = self.eat("+");
+            let key = self.key()?;
+            if !key.is_numeric() {
+                return Err(self.error("only numeric attributes can be used as sort keys"));
+            }
+            keys.push(SortKey { key, ascending });
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect(")")?;
+        Ok(keys)
+    }
+
+    fn stream(&mut self) -> Result<StreamSpec, String> {
+        let start = self.pos;
+        let kind = match self.word() {
+            "v" | "video" => Kind::Video,
+            "a" | "audio" => Kind::Audio,
+            _ => {
+                self.pos = start;
+                self.skip_whitespace();
+                return Err(self.error("expected a stream kind (v or a)"));
+            },
+        };
+        let mut filters = Vec::new();
+        while self.eat("[") {
+            filters.push(self.filter()?);
+        }
+        let sort = if self.eat("(") { self.sort_keys()? } else { Vec::new() };
+        Ok(StreamSpec { kind, filters, sort })
+    }
+
+    fn alternative(&mut self) -> Result<Vec<StreamSpec>, String> {
+        let mut streams = vec![self.stream()?];
+        while self.eat("+") {
+            let pos = self.pos;
+            let stream = self.stream()?;
+            if streams.iter().any(|s| s.kind == stream.kind) {
+                self.pos = pos;
+                self.skip_whitespace();
+                return Err(self.error(&format!("{} stream is already selected in this alternative",
+                                               stream.kind.name())));
+            }
+            streams.push(stream);
+        }
+        Ok(streams)
+    }
+}
+
+// Parse the value of the --format commandline option.
+pub fn parse_format_selector(s: &str) -> Result<FormatSelector, String> {
+    let mut parser = Parser { src: s, pos: 0 };
+    let mut alternatives = vec![parser.alternative()?];
+    while parser.eat("/") {
+        alternatives.push(parser.alternative()?);
+    }
+    parser.skip_whitespace();
+    if !parser.rest().is_empty() {
+        return Err(parser.error("expected \"/\", \"+\", \"[\" or \"(\""));
+    }
+    Ok(FormatSelector { alternatives, source: s.to_string() })
+}
+
+
+// A Representation of a Period: index of its AdaptationSet, its index in the AdaptationSet, and its
+// attributes.
+type Candidate = (usize, usize, Format);
+
+fn compare(a: &Format, b: &Format, sort: &[SortKey]) -> Ordering {
+    for sk in sort {
+        // Representations that don't specify the attribute come last.
+        let ord = match (sk.key.number(a), sk.key.number(b)) {
+            (Some(x), Some(y)) if sk.ascending => y.total_cmp(&x),
+            (Some(x), Some(y)) => x.total_cmp(&y),
+            (Some(_), None) => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (None, None) => Ordering::Equal,
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+fn choose(candidates: &[Candidate], spec: &StreamSpec, quality: Option<&str>) -> Option<Candidate> {
+    let mut matching: Vec<&Candidate> = candidates.iter()
+        .filter(|(_, _, f)| f.content_type == spec.kind.name())
+        .filter(|(_, _, f)| spec.filters.iter().all(|filter| filter.accepts(f)))
+        .collect();
+    if !spec.sort.is_empty() {
+        // The first of several equally preferred Representations, in manifest order.
+        return matching.into_iter()
+            .reduce(|best, c| match compare(&c.2, &best.2, &spec.sort) {
+                Ordering::Greater => c,
+                _ => best,
+            })
+            .cloned();
+    }
+    matching.sort_by_key(|(_, _, f)| f.bandwidth.unwrap_or(0));
+    let chosen = match quality {
+        Some("best") => matching.last(),
+        Some("intermediate") => matching.get(matching.len() / 2),
+        _ => matching.first(),
+    };
+    chosen.map(|c| (*c).clone())
+}
+
+impl FormatSelector {
+    // The first alternative that can be satisfied in the Period, and the Representations chosen for
+    // each of its streams.
+    fn evaluate(&self, mpd: &MPD, idx: usize, quality: Option<&str>) -> Option<(usize, Vec<Candidate>)> {
+        let period = &mpd.periods[idx];
+        let mut candidates: Vec<Candidate> = Vec::new();
+        for (aidx, adaptation) in period.adaptations.iter().enumerate() {
+            for (ridx, rep) in adaptation.representations.iter().enumerate() {
+                candidates.push((aidx, ridx, representation_format(idx + 1, adaptation, rep)));
+            }
+        }
+        self.alternatives.iter().enumerate().find_map(|(n, streams)| {
+            streams.iter()
+                .map(|spec| choose(&candidates, spec, quality))
+                .collect::<Option<Vec<Candidate>>>()
+                .map(|chosen| (n, chosen))
+        })
+    }
+}
+
+// Remove from the manifest the video and audio Representations that are not chosen by the format
+// selector. Subtitle AdaptationSets are kept.
+pub fn apply_format_selector(mpd: &MPD, selector: &FormatSelector, quality: Option<&str>) -> Result<MPD> {
+    let mut out = mpd.clone();
+    for (idx, period) in out.periods.iter_mut().enumerate() {
+        let Some((alternative, chosen)) = selector.evaluate(mpd, idx, quality) else {
+            return Err(anyhow!("no Representations in Period {} match --format {}", idx + 1, selector.source));
+        };
+        let description: Vec<String> = chosen.iter()
+            .map(|(_, _, f)| format!("{} {}", f.content_type, f.id.as_deref().unwrap_or("(no id)")))
+            .collect();
+        info!("Period {}: --format alternative {} selects {}",
+              idx + 1, alternative + 1, description.join(" and "));
+        let adaptations = std::mem::take(&mut period.adaptations);
+        for (aidx, mut adaptation) in adaptations.into_iter().enumerate() {
+            if !matches!(content_kind(&adaptation), Some("video" | "audio")) {
+                period.adaptations.push(adaptation);
+                continue;
+            }
+            let keep: Vec<usize> = chosen.iter()
+                .filter(|(a, _, _)| *a == aidx)
+                .map(|(_, r, _)| *r)
+                .collect();
+            if keep.is_empty() {
+                continue;
+            }
+            adaptation.representations = adaptation.representations.into_iter().enumerate()
+                .filter(|(ridx, _)| keep.contains(ridx))
+                .map(|(_, rep)| rep)
+                .collect();
+            period.adaptations.push(adaptation);
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,783 @@
+//! Converting subtitles between formats (--convert-subs)
+//
+// Subtitles are parsed into a list of cues, each with its timing, its lines of text with italic and
+// bold runs, and its vertical position on the screen, and written in the requested format: SubRip,
+// WebVTT or Advanced SubStation Alpha. The input formats are TTML (the IMSC1 text profile used in
+// DASH streams) and WebVTT (or SubRip, which is close enough to be read by the same parser).
+//
+// TTML documents are read with a small tokenizer rather than a full XML parser: IMSC1 documents
+// don't use DTDs or processing instructions beyond the XML declaration. We handle nested timing
+// (begin and end relative to the parent element), the clock-time and offset-time expressions with
+// frame and tick rates, italic and bold styles (inline or referenced from the head), and the origin,
+// extent and displayAlign of regions. The position of a cue is carried over where the target format
+// allows: as line settings in WebVTT, as the \an8 override for subtitles at the top of the screen in
+// SubRip, and as the alignment and vertical margin in ASS.
+
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use fs_err as fs;
+use tracing::info;
+use anyhow::{anyhow, Result, Context};
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Run {
+    pub text: String,
+    pub italic: bool,
+    pub bold: bool,
+}
+
+// The vertical position of a cue, as a percentage of the video height: the position of the top edge
+// of the cue, or of its bottom edge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Vertical {
+    Top(f64),
+    Bottom(f64),
+}
+
+impl Vertical {
+    fn is_upper_half(self) -> bool {
+        match self {
+            Vertical::Top(y) | Vertical::Bottom(y) => y < 50.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub lines: Vec<Vec<Run>>,
+    pub vertical: Option<Vertical>,
+}
+
+impl Cue {
+    pub fn shifted(mut self, offset: f64) -> Cue {
+        self.start += offset;
+        self.end += offset;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetFormat {
+    SubRip,
+    WebVtt,
+    Ass,
+}
+
+impl TargetFormat {
+    pub fn from_name(name: &str) -> Option<TargetFormat> {
+        match name {
+            "srt" => Some(TargetFormat::SubRip),
+            "vtt" => Some(TargetFormat::WebVtt),
+            "ass" => Some(TargetFormat::Ass),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            TargetFormat::SubRip => "srt",
+            TargetFormat::WebVtt => "vtt",
+            TargetFormat::Ass => "ass",
+        }
+    }
+}
+
+
+// Append text to a line, merging it with the last run if it has the same style.
+fn push_text(line: &mut Vec<Run>, text: &str, italic: bool, bold: bool) {
+    if text.is_empty() {
+        return;
+    }
+    if let Some(last) = line.last_mut() {
+        if last.italic == italic && last.bold == bold {
+            last.text.push_str(text);
+            return;
+        }
+    }
+    line.push(Run { text: text.to_string(), italic, bold });
+}
+
+// Remove the leading and trailing whitespace of a line, and drop empty lines at the start and end
+// of the cue.
+fn tidy_lines(lines: Vec<Vec<Run>>) -> Vec<Vec<Run>> {
+    let mut out: Vec<Vec<Run>> = lines.into_iter()
+        .map(|mut line| {
+            if let Some(first) = line.first_mut() {
+                first.text = first.text.trim_start().to_string();
+            }
+            if let Some(last) = line.last_mut() {
+                last.text = last.text.trim_end().to_string();
+            }
+            line.retain(|r| !r.text.is_empty());
+            line
+        })
+        .collect();
+    while out.last().is_some_and(Vec::is_empty) {
+        out.pop();
+    }
+    while out.first().is_some_and(Vec::is_empty) {
+        out.remove(0);
+    }
+    out
+}
+
+fn decode_entity(entity: &str) -> Option<String> {
+    let c = match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "lrm" => '\u{200e}',
+        "rlm" => '\u{200f}',
+        _ => {
+            let code = if let Some(hex) = entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                u32::from_str_radix(hex, 16).ok()?
+            } else {
+                entity.strip_prefix('#')?.parse().ok()?
+            };
+            char::from_u32(code)?
+        },
+    };
+    Some(c.to_string())
+}
+
+// Replace character and entity references in text.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        let after = &rest[amp + 1..];
+        let semi = after.find(';').filter(|&semi| semi <= 10);
+        match semi.and_then(|semi| decode_entity(&after[..semi])) {
+            Some(decoded) => {
+                out.push_str(&decoded);
+                rest = &after[semi.unwrap_or(0) + 1..];
+            },
+            None => {
+                out.push('&');
+                rest = after;
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+
+// WebVTT and SubRip
+
+// Parse a WebVTT timestamp (mm:ss.ttt or hh:mm:ss.ttt) or a SubRip timestamp (hh:mm:ss,ttt).
+fn parse_vtt_timestamp(ts: &str) -> Option<f64> {
+    let ts = ts.trim().replace(',', ".");
+    let parts: Vec<&str> = ts.split(':').collect();
+    let (h, m, s) = match parts.as_slice() {
+        [m, s] => (0.0, m.parse::<f64>().ok()?, s.parse::<f64>().ok()?),
+        [h, m, s] => (h.parse::<f64>().ok()?, m.parse::<f64>().ok()?, s.parse::<f64>().ok()?),
+        _ => return None,
+    };
+    Some(h * 3600.0 + m * 60.0 + s)
+}
+
+// The vertical position of a cue from its WebVTT settings ("line:10%", "line:85%,end", "line:0").
+fn vtt_vertical(settings: &str) -> Option<Vertical> {
+    let line = settings.split_whitespace().find_map(|s| s.strip_prefix("line:"))?;
+    let (value, align) = line.split_once(',').unwrap_or((line, "start"));
+    if let Some(pct) = value.strip_suffix('%') {
+        let y = pct.parse::<f64>().ok()?;
+        return Some(if align == "end" { Vertical::Bottom(y) } else { Vertical::Top(y) });
+    }
+    // A line number counts from the top when positive, and from the bottom (the default position)
+    // when negative.
+    let n = value.parse::<i32>().ok()?;
+    (n >= 0).then(|| Vertical::Top(f64::from(n) * 100.0 / 15.0))
+}
+
+// Parse the text of a WebVTT or SubRip cue, keeping the italic and bold tags. Other tags (classes,
+// voices, timestamps, SubRip font tags) are dropped, and SubRip position overrides such as {\an8}
+// are returned as the position of the cue.
+fn parse_cue_text(lines: &[&str]) -> (Vec<Vec<Run>>, Option<Vertical>) {
+    let mut italic = 0;
+    let mut bold = 0;
+    let mut vertical = None;
+    let mut out = Vec::new();
+    for line in lines {
+        let mut runs = Vec::new();
+        let mut rest = *line;
+        while !rest.is_empty() {
+            let next = rest.find(['<', '{']).unwrap_or(rest.len());
+            push_text(&mut runs, &unescape(&rest[..next]), italic > 0, bold > 0);
+            rest = &rest[next..];
+            let close = match rest.chars().next() {
+                Some('<') => '>',
+                Some('{') => '}',
+                _ => break,
+            };
+            let Some(end) = rest.find(close) else {
+                push_text(&mut runs, &unescape(rest), italic > 0, bold > 0);
+                break;
+            };
+            let tag = &rest[1..end];
+            if close == '}' {
+                match tag.strip_prefix("\\an") {
+                    Some("7" | "8" | "9") => vertical = Some(Vertical::Top(10.0)),
+                    Some(_) => (),
+                    None if !tag.starts_with('\\') => push_text(&mut runs, &rest[..=end], italic > 0, bold > 0),
+                    None => (),
+                }
+            } else {
+                let name = tag.split(['.', ' ']).next().unwrap_or("").to_lowercase();
+                match name.as_str() {
+                    "i" => italic += 1,
+                    "/i" => italic = (italic - 1).max(0),
+                    "b" => bold += 1,
+                    "/b" => bold = (bold - 1).max(0),
+                    _ => (),
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push(runs);
+    }
+    (tidy_lines(out), vertical)
+}
+
+// Parse a WebVTT file, or a SubRip file.
+pub fn parse_webvtt(text: &str) -> Vec<Cue> {
+    let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+    let mut cues = Vec::new();
+    for block in text.split("\n\n") {
+        let lines: Vec<&str> = block.trim_matches('\n').lines().collect();
+        // The header and NOTE, STYLE and REGION blocks have no timing line. The timing line of a
+        // cue can be preceded by an identifier (the sequence number in SubRip).
+        let Some(timing) = lines.iter().take(2).position(|l| l.contains("-->")) else {
+            continue;
+        };
+        let Some((start, rest)) = lines[timing].split_once("-->") else { continue };
+        let rest = rest.trim();
+        let (end, settings) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let (Some(start), Some(end)) = (parse_vtt_timestamp(start), parse_vtt_timestamp(end)) else {
+            continue;
+        };
+        let (lines, override_vertical) = parse_cue_text(&lines[timing + 1..]);
+        let vertical = vtt_vertical(settings).or(override_vertical);
+        cues.push(Cue { start, end, lines, vertical });
+    }
+    cues
+}
+
+
+// TTML
+
+#[derive(Debug)]
+enum Token {
+    Start { name: String, attrs: Vec<(String, String)>, empty: bool },
+    End(String),
+    Text(String),
+}
+
+// The local part of a qualified name such as tts:fontStyle.
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn parse_attributes(s: &str) -> Result<Vec<(String, String)>> {
+    let mut attrs = Vec::new();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let eq = rest.find('=').ok_or_else(|| anyhow!("malformed attribute in <{s}>"))?;
+        let name = rest[..eq].trim();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| anyhow!("unquoted attribute value in <{s}>"))?;
+        let close = value[1..].find(quote).ok_or_else(|| anyhow!("unterminated attribute value in <{s}>"))?;
+        attrs.push((local_name(name).to_string(), unescape(&value[1..=close])));
+        rest = value[close + 2..].trim_start();
+    }
+    Ok(attrs)
+}
+
+fn tokenize(doc: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = doc;
+    while !rest.is_empty() {
+        let Some(lt) = rest.find('<') else {
+            tokens.push(Token::Text(unescape(rest)));
+            break;
+        };
+        if lt > 0 {
+            tokens.push(Token::Text(unescape(&rest[..lt])));
+        }
+        rest = &rest[lt..];
+        let (skip_to, terminator) = if rest.starts_with("<!--") {
+            (None, "-->")
+        } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").ok_or_else(|| anyhow!("unterminated CDATA section"))?;
+            tokens.push(Token::Text(cdata[..end].to_string()));
+            (Some(9 + end + 3), "")
+        } else if rest.starts_with("<?") {
+            (None, "?>")
+        } else if rest.starts_with("<!") {
+            (None, ">")
+        } else {
+            let gt = rest.find('>').ok_or_else(|| anyhow!("unterminated tag"))?;
+            let tag = &rest[1..gt];
+            if let Some(name) = tag.strip_prefix('/') {
+                tokens.push(Token::End(local_name(name.trim()).to_string()));
+            } else {
+                let empty = tag.ends_with('/');
+                let tag = tag.trim_end_matches('/');
+                let (name, attrs) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+                tokens.push(Token::Start {
+                    name: local_name(name).to_string(),
+                    attrs: parse_attributes(attrs)?,
+                    empty,
+                });
+            }
+            (Some(gt + 1), "")
+        };
+        let skip = match skip_to {
+            Some(n) => n,
+            None => rest.find(terminator).ok_or_else(|| anyhow!("unterminated markup"))? + terminator.len(),
+        };
+        rest = &rest[skip..];
+    }
+    Ok(tokens)
+}
+
+struct TimeBase {
+    frame_rate: f64,
+    tick_rate: f64,
+}
+
+// Parse a TTML time expression: a clock time (hh:mm:ss.fff or hh:mm:ss:frames) or an offset time
+// (such as 10.5s, 200ms, 25f or 9000t).
+fn parse_ttml_time(expr: &str, base: &TimeBase) -> Option<f64> {
+    let expr = expr.trim();
+    if expr.contains(':') {
+        let parts: Vec<&str> = expr.split(':').collect();
+        let h = parts.first()?.parse::<f64>().ok()?;
+        let m = parts.get(1)?.parse::<f64>().ok()?;
+        let s = parts.get(2)?.parse::<f64>().ok()?;
+        let frames = match parts.get(3) {
+            Some(f) => f.parse::<f64>().ok()? / base.frame_rate,
+            None => 0.0,
+        };
+        return Some(h * 3600.0 + m * 60.0 + s + frames);
+    }
+    let split = expr.find(|c: char| c.is_ascii_alphabetic())?;
+    let (count, metric) = expr.split_at(split);
+    let count = count.parse::<f64>().ok()?;
+    match metric {
+        "h" => Some(count * 3600.0),
+        "m" => Some(count * 60.0),
+        "s" => Some(count),
+        "ms" => Some(count / 1000.0),
+        "f" => Some(count / base.frame_rate),
+        "t" => Some(count / base.tick_rate),
+        _ => None,
+    }
+}
+
+// Style properties of a style or region element, or set inline on a content element.
+#[derive(Debug, Clone, Default)]
+struct Style {
+    italic: Option<bool>,
+    bold: Option<bool>,
+    origin: Option<String>,
+    extent: Option<String>,
+    display_align: Option<String>,
+    // Referenced styles, whose properties apply unless overridden.
+    refs: Vec<String>,
+}
+
+impl Style {
+    fn from_attrs(attrs: &[(String, String)]) -> Style {
+        let mut style = Style::default();
+        for (name, value) in attrs {
+            match name.as_str() {
+                "fontStyle" => style.italic = Some(value == "italic" || value == "oblique"),
+                "fontWeight" => style.bold = Some(value == "bold"),
+                "origin" => style.origin = Some(value.clone()),
+                "extent" => style.extent = Some(value.clone()),
+                "displayAlign" => style.display_align = Some(value.clone()),
+                "style" => style.refs = value.split_whitespace().map(str::to_string).collect(),
+                _ => (),
+            }
+        }
+        style
+    }
+
+    // Set the properties that are not set from other.
+    fn fill_from(&mut self, other: &Style) {
+        self.italic = self.italic.or(other.italic);
+        self.bold = self.bold.or(other.bold);
+        self.origin = self.origin.take().or_else(|| other.origin.clone());
+        self.extent = self.extent.take().or_else(|| other.extent.clone());
+        self.display_align = self.display_align.take().or_else(|| other.display_align.clone());
+    }
+
+    // Set the properties that are not set from the referenced styles.
+    fn resolve(&self, styles: &HashMap<String, Style>, depth: usize) -> Style {
+        let mut out = self.clone();
+        if depth > 8 {
+            return out;
+        }
+        for id in &self.refs {
+            if let Some(s) = styles.get(id) {
+                out.fill_from(&s.resolve(styles, depth + 1));
+            }
+        }
+        out
+    }
+}
+
+// Parse a pair of TTML lengths ("10% 80%" or "192px 864px") as percentages of the root extent.
+fn parse_lengths(value: &str, root_extent: Option<(f64, f64)>) -> Option<(f64, f64)> {
+    let mut parts = value.split_whitespace();
+    let mut length = |full: Option<f64>| -> Option<f64> {
+        let v = parts.next()?;
+        if let Some(pct) = v.strip_suffix('%') {
+            pct.parse().ok()
+        } else {
+            let px = v.strip_suffix("px")?.parse::<f64>().ok()?;
+            Some(px * 100.0 / full?)
+        }
+    };
+    let x = length(root_extent.map(|e| e.0))?;
+    let y = length(root_extent.map(|e| e.1))?;
+    Some((x, y))
+}
+
+fn region_vertical(region: &Style, root_extent: Option<(f64, f64)>) -> Option<Vertical> {
+    let (_, top) = parse_lengths(region.origin.as_deref()?, root_extent)?;
+    let height = region.extent.as_deref()
+        .and_then(|e| parse_lengths(e, root_extent))
+        .map(|(_, h)| h);
+    match (region.display_align.as_deref(), height) {
+        (Some("after"), Some(h)) => Some(Vertical::Bottom(top + h)),
+        (Some("center"), Some(h)) => Some(Vertical::Top(top + h / 2.0)),
+        _ => Some(Vertical::Top(top)),
+    }
+}
+
+// The timing and inherited properties of an open element in the body.
+struct Scope {
+    name: String,
+    begin: f64,
+    end: Option<f64>,
+    italic: bool,
+    bold: bool,
+    region: Option<String>,
+}
+
+// Parse a TTML document into cues. Each p element with text is a cue.
+pub fn parse_ttml(doc: &str) -> Result<Vec<Cue>> {
+    let tokens = tokenize(doc)?;
+    let mut base = TimeBase { frame_rate: 30.0, tick_rate: 1.0 };
+    let mut root_extent = None;
+    let mut styles: HashMap<String, Style> = HashMap::new();
+    let mut regions: HashMap<String, Style> = HashMap::new();
+    let mut scopes: Vec<Scope> = Vec::new();
+    // The region being defined in the head, whose nested style elements apply to it.
+    let mut open_region: Option<String> = None;
+    let mut in_body = false;
+    let mut cue: Option<(f64, Option<f64>, Option<String>, Vec<Vec<Run>>)> = None;
+    let mut cues = Vec::new();
+    for token in tokens {
+        match token {
+            Token::Start { name, attrs, empty } => {
+                let attr = |n: &str| attrs.iter().find(|(k, _)| k == n).map(|(_, v)| v.as_str());
+                match name.as_str() {
+                    "tt" => {
+                        let multiplier = attr("frameRateMultiplier")
+                            .and_then(|m| m.split_once(' '))
+                            .and_then(|(n, d)| Some(n.parse::<f64>().ok()? / d.parse::<f64>().ok()?))
+                            .unwrap_or(1.0);
+                        if let Some(fr) = attr("frameRate").and_then(|f| f.parse::<f64>().ok()) {
+                            base.frame_rate = fr * multiplier;
+                            base.tick_rate = base.frame_rate;
+                        }
+                        if let Some(tr) = attr("tickRate").and_then(|t| t.parse::<f64>().ok()) {
+                            base.tick_rate = tr;
+                        }
+                        root_extent = attr("extent").and_then(|e| {
+                            let mut px = e.split_whitespace().map(|v| v.strip_suffix("px")?.parse::<f64>().ok());
+                            Some((px.next()??, px.next()??))
+                        });
+                        continue;
+                    },
+                    "style" if !in_body => {
+                        let style = Style::from_attrs(&attrs);
+                        match (attr("id"), &open_region) {
+                            (Some(id), _) => { styles.insert(id.to_string(), style); },
+                            (None, Some(region)) => {
+                                if let Some(r) = regions.get_mut(region) {
+                                    r.fill_from(&style);
+                                    r.refs.extend(style.refs);
+                                }
+                            },
+                            _ => (),
+                        }
+                        continue;
+                    },
+                    "region" if !in_body => {
+                        if let Some(id) = attr("id") {
+                            regions.insert(id.to_string(), Style::from_attrs(&attrs));
+                            if !empty {
+                                open_region = Some(id.to_string());
+                            }
+                        }
+                        continue;
+                    },
+                    "body" => in_body = true,
+                    _ => (),
+                }
+                if !in_body {
+                    continue;
+                }
+                if name == "br" {
+                    if let Some((_, _, _, lines)) = &mut cue {
+                        lines.push(Vec::new());
+                    }
+                    continue;
+                }
+                let (parent_begin, parent_end, parent_italic, parent_bold, parent_region) = match scopes.last() {
+                    Some(s) => (s.begin, s.end, s.italic, s.bold, s.region.clone()),
+                    None => (0.0, None, false, false, None),
+                };
+                let begin = parent_begin + attr("begin").and_then(|b| parse_ttml_time(b, &base)).unwrap_or(0.0);
+                let end = match (attr("end"), attr("dur")) {
+                    (Some(e), _) => parse_ttml_time(e, &base).map(|e| parent_begin + e),
+                    (None, Some(d)) => parse_ttml_time(d, &base).map(|d| begin + d),
+                    (None, None) => parent_end,
+                };
+                let style = Style::from_attrs(&attrs).resolve(&styles, 0);
+                let scope = Scope {
+                    begin,
+                    end,
+                    italic: style.italic.unwrap_or(parent_italic),
+                    bold: style.bold.unwrap_or(parent_bold),
+                    region: attr("region").map(str::to_string).or(parent_region),
+                    name,
+                };
+                if scope.name == "p" {
+                    cue = Some((scope.begin, scope.end, scope.region.clone(), vec![Vec::new()]));
+                }
+                if !empty {
+                    scopes.push(scope);
+                }
+            },
+            Token::End(name) => {
+                if name == "region" {
+                    open_region = None;
+                }
+                if !in_body {
+                    continue;
+                }
+                if name == "body" {
+                    in_body = false;
+                }
+                if let Some(pos) = scopes.iter().rposition(|s| s.name == name) {
+                    scopes.truncate(pos);
+                }
+                if name == "p" {
+                    if let Some((start, end, region, lines)) = cue.take() {
+                        let lines = tidy_lines(lines);
+                        let vertical = region.as_ref()
+                            .and_then(|r| regions.get(r))
+                            .and_then(|r| region_vertical(&r.resolve(&styles, 0), root_extent));
+                        if let Some(end) = end.filter(|e| *e > start && !lines.is_empty()) {
+                            cues.push(Cue { start, end, lines, vertical });
+                        }
+                    }
+                }
+            },
+            Token::Text(text) => {
+                let Some((_, _, _, lines)) = &mut cue else { continue };
+                let Some(line) = lines.last_mut() else { continue };
+                // Whitespace is collapsed to single spaces, including across elements.
+                let mut collapsed = String::new();
+                let mut space = line.last().is_none_or(|r| r.text.ends_with(' '));
+                for c in text.chars() {
+                    if !c.is_whitespace() {
+                        collapsed.push(c);
+                        space = false;
+                    } else if !space {
+                        collapsed.push(' ');
+                        space = true;
+                    }
+                }
+                let (italic, bold) = scopes.last().map_or((false, false), |s| (s.italic, s.bold));
+                push_text(line, &collapsed, italic, bold);
+            },
+        }
+    }
+    cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(cues)
+}
+
+// Whether a subtitle file is TTML, rather than WebVTT or SubRip.
+pub fn is_ttml(text: &str) -> bool {
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    text.starts_with('<') && text.contains("<tt")
+}
+
+pub fn parse_subtitles(text: &str) -> Result<Vec<Cue>> {
+    if is_ttml(text) {
+        parse_ttml(text).context("parsing TTML subtitles")
+    } else {
+        Ok(parse_webvtt(text))
+    }
+}
+
+
+// Writing subtitles
+
+// A time as hours, minutes, seconds and milliseconds.
+fn hmsm(t: f64) -> (u64, u64, u64, u64) {
+    let ms = (t.max(0.0) * 1000.0).round() as u64;
+    (ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+// A percentage without trailing zeros.
+fn percent(v: f64) -> String {
+    format!("{}%", (v.clamp(0.0, 100.0) * 100.0).round() / 100.0)
+}
+
+fn tagged_text(runs: &[Run], escape: bool) -> String {
+    let mut out = String::new();
+    for run in runs {
+        let mut text = run.text.clone();
+        if escape {
+            text = text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+        }
+        if run.bold {
+            text = format!("<b>{text}</b>");
+        }
+        if run.italic {
+            text = format!("<i>{text}</i>");
+        }
+        out.push_str(&text);
+    }
+    out
+}
+
+pub fn write_srt(cues: &[Cue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        let (h1, m1, s1, ms1) = hmsm(cue.start);
+        let (h2, m2, s2, ms2) = hmsm(cue.end);
+        out.push_str(&format!("{}\n{h1:02}:{m1:02}:{s1:02},{ms1:03} --> {h2:02}:{m2:02}:{s2:02},{ms2:03}\n", i + 1));
+        // The {\an8} override, understood by most players, places the subtitle at the top.
+        if cue.vertical.is_some_and(Vertical::is_upper_half) {
+            out.push_str("{\\an8}");
+        }
+        let lines: Vec<String> = cue.lines.iter().map(|l| tagged_text(l, false)).collect();
+        out.push_str(&lines.join("\n"));
+        out.push_str("\n\n");
+    }
+    out
+}
+
+pub fn write_webvtt(cues: &[Cue]) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in cues {
+        let (h1, m1, s1, ms1) = hmsm(cue.start);
+        let (h2, m2, s2, ms2) = hmsm(cue.end);
+        out.push_str(&format!("{h1:02}:{m1:02}:{s1:02}.{ms1:03} --> {h2:02}:{m2:02}:{s2:02}.{ms2:03}"));
+        match cue.vertical {
+            Some(Vertical::Top(y)) => out.push_str(&format!(" line:{}", percent(y))),
+            Some(Vertical::Bottom(y)) => out.push_str(&format!(" line:{},end", percent(y))),
+            None => (),
+        }
+        out.push('\n');
+        let lines: Vec<String> = cue.lines.iter().map(|l| tagged_text(l, true)).collect();
+        out.push_str(&lines.join("\n"));
+        out.push_str("\n\n");
+    }
+    out
+}
+
+// The script resolution of the ASS output, against which margins are expressed.
+const ASS_HEIGHT: f64 = 288.0;
+
+const ASS_HEADER: &str = "[Script Info]
+ScriptType: v4.00+
+PlayResX: 384
+PlayResY: 288
+WrapStyle: 0
+ScaledBorderAndShadow: yes
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Default,Arial,16,&H00FFFFFF,&H000000FF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,1,0,2,10,10,10,1
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+";
+
+fn ass_time(t: f64) -> String {
+    let cs = (t.max(0.0) * 100.0).round() as u64;
+    format!("{}:{:02}:{:02}.{:02}", cs / 360_000, cs / 6000 % 60, cs / 100 % 60, cs % 100)
+}
+
+pub fn write_ass(cues: &[Cue]) -> String {
+    let mut out = String::from(ASS_HEADER);
+    for cue in cues {
+        // Subtitles at the top are aligned on their top edge (\an8), and others on their bottom
+        // edge, at a vertical margin from that edge of the screen.
+        let (align, margin) = match cue.vertical {
+            Some(Vertical::Top(y)) => ("{\\an8}", y),
+            Some(Vertical::Bottom(y)) => ("", 100.0 - y),
+            None => ("", 0.0),
+        };
+        let margin = (margin.clamp(0.0, 100.0) * ASS_HEIGHT / 100.0).round() as u64;
+        let lines: Vec<String> = cue.lines.iter()
+            .map(|line| line.iter()
+                 .map(|r| {
+                     let text = r.text.replace('{', "(").replace('}', ")");
+                     match (r.italic, r.bold) {
+                         (true, true) => format!("{{\\i1\\b1}}{text}{{\\i0\\b0}}"),
+                         (true, false) => format!("{{\\i1}}{text}{{\\i0}}"),
+                         (false, true) => format!("{{\\b1}}{text}{{\\b0}}"),
+                         (false, false) => text,
+                     }
+                 })
+                 .collect())
+            .collect();
+        out.push_str(&format!("Dialogue: 0,{},{},Default,,0,0,{margin},,{align}{}\n",
+                              ass_time(cue.start), ass_time(cue.end), lines.join("\\N")));
+    }
+    out
+}
+
+pub fn write_subtitles(cues: &[Cue], format: TargetFormat) -> String {
+    match format {
+        TargetFormat::SubRip => write_srt(cues),
+        TargetFormat::WebVtt => write_webvtt(cues),
+        TargetFormat::Ass => write_ass(cues),
+    }
+}
+
+// Convert the subtitle file at path to the target format, saving it with the extension of that
+// format and removing the original file. Returns the path of the converted file.
+pub fn convert_subtitle_file(path: &Path, format: TargetFormat) -> Result<PathBuf> {
+    let target = path.with_extension(format.extension());
+    if target == path {
+        return Ok(target);
+    }
+    let bytes = fs::read(path)?;
+    let cues = parse_subtitles(&String::from_utf8_lossy(&bytes))
+        .with_context(|| format!("converting {}", path.display()))?;
+    fs::write(&target, write_subtitles(&cues, format))?;
+    fs::remove_file(path)?;
+    info!("Converted subtitles {} to {}", path.display(), target.display());
+    Ok(target)
+}
@@ -0,0 +1,754 @@
+//! Downloading subtitles in several languages (--sub-langs, --embed-subs)
+//
+// The DashDownloader saves a single subtitle track. With --sub-langs, we fetch the segments of every
+// matching subtitle AdaptationSet ourselves, and save each track next to the output file as
+// NAME.LANG.EXT. The subtitle formats handled are:
+//
+//   - sidecar WebVTT, TTML and SubRip files (possibly split into several segments);
+//   - TTML in fragmented MP4 (codecs stpp), from which the TTML documents are extracted from the
+//   mdat boxes and merged into one document;
+//   - WebVTT in fragmented MP4 (codecs wvtt), which is converted to a WebVTT file by ffmpeg.
+//
+// With --convert-subs, the subtitles of each Period are parsed and shifted to the timeline of the
+// output file, in which the Periods are concatenated, and written in the requested format.
+//
+// With --embed-subs the tracks are also added to the output file, with their language, a title
+// taken from the AdaptationSet Label, and the forced and hearing impaired (SDH) dispositions taken
+// from the Role and Accessibility descriptors. ffmpeg can't read TTML, so TTML tracks are only saved
+// as sidecar files.
+
+
+use std::path::{Path, PathBuf};
+use fs_err as fs;
+use tracing::{info, warn};
+use anyhow::{anyhow, Result, Context};
+use dash_mpd::{AdaptationSet, Representation};
+use crate::manifest::{Manifest, ManifestFetcher, Segment, period_start, merged_template,
+                      representation_segments};
+use crate::subconvert::{TargetFormat, Cue, parse_subtitles, write_subtitles, convert_subtitle_file};
+use crate::selection::{StreamPreferences, content_kind, matches_language, select_representation};
+use crate::tracks::{LanguageSelection, iso639_2};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubtitleFormat {
+    WebVtt,
+    Ttml,
+    SubRip,
+    // TTML in fragmented MP4
+    Stpp,
+    // WebVTT in fragmented MP4
+    Wvtt,
+}
+
+impl SubtitleFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            SubtitleFormat::WebVtt | SubtitleFormat::Wvtt => "vtt",
+            SubtitleFormat::Ttml | SubtitleFormat::Stpp => "ttml",
+            SubtitleFormat::SubRip => "srt",
+        }
+    }
+
+    // Whether the subtitles are carried in fragmented MP4, and can be muxed by the builtin muxer.
+    pub fn is_fragmented(self) -> bool {
+        matches!(self, SubtitleFormat::Stpp | SubtitleFormat::Wvtt)
+    }
+}
+
+pub fn subtitle_format(adaptation: &AdaptationSet, rep: &Representation) -> Option<SubtitleFormat> {
+    let mime = rep.mimeType.as_ref().or(adaptation.mimeType.as_ref())
+        .map(|m| m.to_lowercase())
+        .unwrap_or_default();
+    let codecs = rep.codecs.as_ref().or(adaptation.codecs.as_ref())
+        .map(|c| c.to_lowercase())
+        .unwrap_or_default();
+    match mime.as_str() {
+        "text/vtt" => Some(SubtitleFormat::WebVtt),
+        "application/ttml+xml" => Some(SubtitleFormat::Ttml),
+        "application/x-subrip" | "text/srt" => Some(SubtitleFormat::SubRip),
+        _ if codecs.starts_with("stpp") => Some(SubtitleFormat::Stpp),
+        _ if codecs.starts_with("wvtt") => Some(SubtitleFormat::Wvtt),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubtitleTrack {
+    pub lang: Option<String>,
+    pub label: Option<String>,
+    pub format: SubtitleFormat,
+    pub forced: bool,
+    pub sdh: bool,
+    pub periods: Vec<SubtitlePeriod>,
+}
+
+// The segments of a subtitle track in one Period.
+#[derive(Debug, Clone)]
+pub struct SubtitlePeriod {
+    // The time to add to the cue times in this Period to obtain times in the output file: the start
+    // of the Period in the output file, less the presentationTimeOffset.
+    pub offset: f64,
+    pub init: Option<Segment>,
+    pub segments: Vec<Segment>,
+}
+
+impl SubtitleTrack {
+    fn describe(&self) -> String {
+        let mut desc = self.lang.clone().unwrap_or_else(|| String::from("und"));
+        if self.forced {
+            desc += " forced";
+        }
+        if self.sdh {
+            desc += " SDH";
+        }
+        desc
+    }
+}
+
+fn is_forced(adaptation: &AdaptationSet) -> bool {
+    adaptation.Role.iter()
+        .filter_map(|r| r.value.as_deref())
+        .any(|v| v == "forced-subtitle" || v == "forced_subtitle")
+}
+
+// Subtitles for the deaf and hard of hearing, signalled by the caption Role (in a Role or an
+// Accessibility descriptor), or the TV-Anytime purpose "for the hard of hearing".
+fn is_sdh(adaptation: &AdaptationSet) -> bool {
+    adaptation.Role.iter()
+        .any(|r| r.value.as_deref() == Some("caption")) ||
+        adaptation.Accessibility.iter()
+        .any(|a| match a.value.as_deref() {
+            Some("caption" | "sdh") => true,
+            Some("2") => a.schemeIdUri == "urn:tva:metadata:cs:AudioPurposeCS:2007",
+            _ => false,
+        })
+}
+
+fn same_track(a: &AdaptationSet, b: &AdaptationSet) -> bool {
+    a.lang == b.lang && is_forced(a) == is_forced(b) && is_sdh(a) == is_sdh(b)
+}
+
+// The subtitle track of an AdaptationSet of the first Period, with the segments of the corresponding
+// AdaptationSet in each Period. Returns None if the subtitles are in an unsupported format.
+fn subtitle_track(manifest: &Manifest, adaptation: &AdaptationSet) -> Result<Option<SubtitleTrack>> {
+    let Some(rep) = adaptation.representations.first() else {
+        return Ok(None);
+    };
+    let Some(format) = subtitle_format(adaptation, rep) else {
+        warn!("Skipping subtitles in unsupported format (AdaptationSet {})",
+              adaptation.id.as_deref().unwrap_or("with no id"));
+        return Ok(None);
+    };
+    let mut track = SubtitleTrack {
+        lang: adaptation.lang.clone(),
+        label: adaptation.Label.first().map(|l| l.content.clone()),
+        format,
+        forced: is_forced(adaptation),
+        sdh: is_sdh(adaptation),
+        periods: Vec::new(),
+    };
+    for (idx, p) in manifest.mpd.periods.iter().enumerate() {
+        let candidates = || p.adaptations.iter().filter(|a| content_kind(a) == Some("text"));
+        let found = candidates().find(|a| a.id == adaptation.id && same_track(a, adaptation))
+            .or_else(|| candidates().find(|a| same_track(a, adaptation)));
+        let Some(a) = found else { continue };
+        let Some(r) = a.representations.first() else { continue };
+        let segments = representation_segments(manifest, idx, a, r)
+            .with_context(|| format!("listing subtitle segments in Period {}", idx + 1))?;
+        let pto = merged_template(a, r)
+            .map_or(0.0, |st| st.presentationTimeOffset.unwrap_or(0) as f64 / st.timescale.unwrap_or(1) as f64);
+        let start = period_start(&manifest.mpd, idx) - period_start(&manifest.mpd, 0);
+        track.periods.push(SubtitlePeriod {
+            offset: start - pto,
+            init: segments.init,
+            segments: segments.media,
+        });
+    }
+    Ok(Some(track))
+}
+
+// The subtitle track that --write-subs downloads, chosen with the stream preferences.
+pub fn preferred_subtitle_track(manifest: &Manifest, prefs: &StreamPreferences) -> Result<Option<SubtitleTrack>> {
+    let Some(period) = manifest.mpd.periods.first() else {
+        return Ok(None);
+    };
+    match select_representation(period, "text", prefs) {
+        Some(choice) => subtitle_track(manifest, &period.adaptations[choice.adaptation]),
+        None => Ok(None),
+    }
+}
+
+// The subtitle tracks of the manifest that match the selection. Tracks are identified in the first
+// Period, and the segments of the corresponding AdaptationSet in each Period are collected.
+pub fn subtitle_tracks(manifest: &Manifest, selection: &LanguageSelection) -> Result<Vec<SubtitleTrack>> {
+    let Some(period) = manifest.mpd.periods.first() else {
+        return Err(anyhow!("manifest contains no Periods"));
+    };
+    let text: Vec<&AdaptationSet> = period.adaptations.iter()
+        .filter(|a| content_kind(a) == Some("text"))
+        .collect();
+    let mut chosen: Vec<&AdaptationSet> = Vec::new();
+    match selection {
+        LanguageSelection::All => chosen = text,
+        LanguageSelection::Languages(langs) => {
+            for lang in langs {
+                let before = chosen.len();
+                for a in text.iter().filter(|a| matches_language(a, lang)) {
+                    if !chosen.iter().any(|c| std::ptr::eq(*c, *a)) {
+                        chosen.push(*a);
+                    }
+                }
+                if chosen.len() == before {
+                    warn!("No subtitles in language {lang}");
+                }
+            }
+        },
+    }
+    let mut tracks = Vec::new();
+    for adaptation in chosen {
+        if let Some(track) = subtitle_track(manifest, adaptation)? {
+            tracks.push(track);
+        }
+    }
+    if tracks.is_empty() {
+        warn!("No subtitle tracks match --sub-langs");
+    } else {
+        let names: Vec<String> = tracks.iter().map(SubtitleTrack::describe).collect();
+        info!("Downloading {} subtitle tracks: {}", tracks.len(), names.join(", "));
+    }
+    Ok(tracks)
+}
+
+async fn fetch(fetcher: &ManifestFetcher, segment: &Segment) -> Result<Vec<u8>> {
+    let range = segment.byte_range.as_ref().map(|r| format!("bytes={r}"));
+    let response = fetcher.fetch_segment(segment.url.clone(), range.as_deref()).await?
+        .error_for_status()
+        .with_context(|| format!("fetching subtitle segment {}", segment.url))?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+// The payloads of the top-level mdat boxes of a fragmented MP4 stream.
+fn mdat_payloads(data: &[u8]) -> Vec<&[u8]> {
+    let mut payloads = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let mut size = u64::from(u32::from_be_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3]]));
+        let boxtype = &data[pos+4..pos+8];
+        let mut header = 8;
+        if size == 1 && pos + 16 <= data.len() {
+            let mut large = [0u8; 8];
+            large.copy_from_slice(&data[pos+8..pos+16]);
+            size = u64::from_be_bytes(large);
+            header = 16;
+        } else if size == 0 {
+            size = (data.len() - pos) as u64;
+        }
+        if size < header as u64 {
+            break;
+        }
+        let end = (pos as u64 + size).min(data.len() as u64) as usize;
+        if boxtype == b"mdat" {
+            payloads.push(&data[pos+header..end]);
+        }
+        pos = end;
+    }
+    payloads
+}
+
+// An element of a TTML document (which may use a namespace prefix such as tt:body): its qualified
+// name, the byte range of the whole element and of its content (None for an empty element).
+struct Element<'a> {
+    qname: &'a str,
+    start: usize,
+    end: usize,
+    content: Option<(usize, usize)>,
+}
+
+// The first element with local name name in doc. The elements we look for (head, styling, layout and
+// body) are not nested in elements of the same name.
+fn find_element<'a>(doc: &'a str, name: &str) -> Option<Element<'a>> {
+    let mut pos = 0;
+    while let Some(lt) = doc[pos..].find('<').map(|i| pos + i) {
+        let gt = lt + doc[lt..].find('>')?;
+        let tag = &doc[lt + 1..gt];
+        let qname = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+        if !qname.is_empty() && qname.rsplit(':').next() == Some(name) {
+            if tag.ends_with('/') {
+                return Some(Element { qname, start: lt, end: gt + 1, content: None });
+            }
+            let close = gt + 1 + doc[gt + 1..].find(&format!("</{qname}"))?;
+            let end = close + doc[close..].find('>')? + 1;
+            return Some(Element { qname, start: lt, end, content: Some((gt + 1, close)) });
+        }
+        pos = lt + 1;
+    }
+    None
+}
+
+// The byte range of the content of the body element of a TTML document.
+fn body_content(doc: &str) -> Option<(usize, usize)> {
+    find_element(doc, "body")?.content
+}
+
+// The top-level elements in the content of an element, such as the style elements of styling.
+fn child_elements(content: &str) -> Vec<&str> {
+    let mut children = Vec::new();
+    let mut depth = 0;
+    let mut child_start = 0;
+    let mut pos = 0;
+    while let Some(lt) = content[pos..].find('<').map(|i| pos + i) {
+        let terminator = if content[lt..].starts_with("<!--") { "-->" } else { ">" };
+        let Some(gt) = content[lt..].find(terminator).map(|i| lt + i + terminator.len() - 1) else {
+            break;
+        };
+        let tag = &content[lt + 1..gt];
+        if tag.starts_with('/') {
+            depth -= 1;
+            if depth == 0 {
+                children.push(&content[child_start..=gt]);
+            }
+        } else if !tag.starts_with('!') && !tag.starts_with('?') {
+            if depth == 0 {
+                child_start = lt;
+            }
+            if !tag.ends_with('/') {
+                depth += 1;
+            } else if depth == 0 {
+                children.push(&content[lt..=gt]);
+            }
+        }
+        pos = gt + 1;
+    }
+    children
+}
+
+// Rewrite the values of the attributes called name in the markup text.
+fn map_attribute(text: &str, name: &str, mut f: impl FnMut(&str) -> String) -> String {
+    let pattern = format!("{name}=");
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(&pattern) {
+        let after = i + pattern.len();
+        let is_attribute = rest[..i].ends_with(char::is_whitespace);
+        let quote = rest[after..].chars().next().filter(|c| *c == '"' || *c == '\'');
+        match quote.and_then(|q| rest[after + 1..].find(q)) {
+            Some(len) if is_attribute => {
+                out.push_str(&rest[..=after]);
+                out.push_str(&f(&rest[after + 1..after + 1 + len]));
+                rest = &rest[after + 1 + len..];
+            },
+            _ => {
+                out.push_str(&rest[..after]);
+                rest = &rest[after..];
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn xml_id(element: &str) -> Option<String> {
+    let tag = &element[..element.find('>')?];
+    let mut id = None;
+    map_attribute(tag, "xml:id", |v| {
+        id.get_or_insert_with(|| v.to_string());
+        v.to_string()
+    });
+    id
+}
+
+// Apply the renamed ids to the style and region references in text.
+fn rename_references(text: &str, renamed: &[(String, String)]) -> String {
+    if renamed.is_empty() {
+        return text.to_string();
+    }
+    let rename = |refs: &str| refs.split_whitespace()
+        .map(|r| renamed.iter().find(|(old, _)| old == r).map_or(r, |(_, new)| new.as_str()))
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let text = map_attribute(text, "style", rename);
+    map_attribute(&text, "region", rename)
+}
+
+// Add the style or region definitions of a later document to those of the merged document, whose
+// definitions by id are in defined. A definition that is identical to an existing one is dropped; a
+// different definition with the same id is renamed, which is recorded in renamed.
+fn merge_definitions(
+    children: Vec<&str>,
+    defined: &mut Vec<(String, String)>,
+    renamed: &mut Vec<(String, String)>,
+    added: &mut Vec<String>)
+{
+    for child in children {
+        let child = rename_references(child, renamed);
+        let Some(id) = xml_id(&child) else {
+            added.push(child);
+            continue;
+        };
+        let mut new_id = id.clone();
+        let mut n = 2;
+        loop {
+            let definition = map_attribute(&child, "xml:id", |v| if v == id { new_id.clone() } else { v.to_string() });
+            match defined.iter().find(|(i, _)| *i == new_id) {
+                Some((_, d)) if *d == definition => break,
+                Some(_) => {
+                    new_id = format!("{id}-{n}");
+                    n += 1;
+                },
+                None => {
+                    defined.push((new_id.clone(), definition.clone()));
+                    added.push(definition);
+                    break;
+                },
+            }
+        }
+        if new_id != id {
+            renamed.push((id, new_id));
+        }
+    }
+}
+
+// Merge the TTML documents of successive segments, by appending the content of their body to the
+// body of the first document. The style and region definitions in the head of each document are
+// added to those of the first document, renaming those whose id is already used for a different
+// definition.
+fn merge_ttml(docs: &[String]) -> String {
+    let Some(first) = docs.first() else {
+        return String::new();
+    };
+    let Some(body) = find_element(first, "body") else {
+        return first.clone();
+    };
+    let Some((_, body_end)) = body.content else {
+        return first.clone();
+    };
+    let container_children = |doc: &'_ str, name: &str| -> Vec<String> {
+        find_element(doc, name)
+            .and_then(|e| e.content)
+            .map(|(s, e)| child_elements(&doc[s..e]).into_iter().map(String::from).collect())
+            .unwrap_or_default()
+    };
+    let mut defined: Vec<(String, String)> = Vec::new();
+    for child in container_children(first, "styling").into_iter().chain(container_children(first, "layout")) {
+        if let Some(id) = xml_id(&child) {
+            defined.push((id, child));
+        }
+    }
+    let mut styles = Vec::new();
+    let mut regions = Vec::new();
+    let mut content = String::new();
+    for doc in &docs[1..] {
+        let mut renamed = Vec::new();
+        let doc_styles = container_children(doc, "styling");
+        merge_definitions(doc_styles.iter().map(String::as_str).collect(), &mut defined, &mut renamed, &mut styles);
+        let doc_regions = container_children(doc, "layout");
+        merge_definitions(doc_regions.iter().map(String::as_str).collect(), &mut defined, &mut renamed, &mut regions);
+        if let Some((s, e)) = body_content(doc) {
+            content.push_str(&rename_references(&doc[s..e], &renamed));
+        }
+    }
+    // The insertions into the first document, as (start, end, replacement).
+    let mut edits: Vec<(usize, usize, String)> = Vec::new();
+    let prefix = body.qname.strip_suffix("body").unwrap_or("");
+    let head = find_element(first, "head");
+    let mut new_containers = String::new();
+    for (name, added) in [("styling", styles), ("layout", regions)] {
+        if added.is_empty() {
+            continue;
+        }
+        let added = added.concat();
+        match find_element(first, name) {
+            Some(Element { content: Some((_, end)), .. }) => edits.push((end, end, added)),
+            Some(e) => edits.push((e.start, e.end, format!("<{0}>{added}</{0}>", e.qname))),
+            None => {
+                let container = format!("<{prefix}{name}>{added}</{prefix}{name}>");
+                // The styling element precedes the layout element in the head.
+                match find_element(first, "layout") {
+                    Some(layout) if name == "styling" => edits.push((layout.start, layout.start, container)),
+                    _ => new_containers.push_str(&container),
+                }
+            },
+        }
+    }
+    if !new_containers.is_empty() {
+        match head {
+            Some(Element { content: Some((_, end)), .. }) => edits.push((end, end, new_containers)),
+            Some(e) => edits.push((e.start, e.end, format!("<{0}>{new_containers}</{0}>", e.qname))),
+            None => edits.push((body.start, body.start, format!("<{prefix}head>{new_containers}</{prefix}head>"))),
+        }
+    }
+    edits.push((body_end, body_end, content));
+    edits.sort_by_key(|(start, _, _)| *start);
+    let mut merged = String::new();
+    let mut pos = 0;
+    for (start, end, replacement) in edits {
+        merged.push_str(&first[pos..start]);
+        merged.push_str(&replacement);
+        pos = end;
+    }
+    merged.push_str(&first[pos..]);
+    merged
+}
+
+// Concatenate the WebVTT files of successive segments, dropping the header of all but the first.
+fn merge_webvtt(parts: &[String]) -> String {
+    let mut merged = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        let part = part.replace("\r\n", "\n");
+        if i == 0 {
+            merged.push_str(part.trim_end());
+        } else if let Some(pos) = part.find("\n\n") {
+            merged.push_str("\n\n");
+            merged.push_str(part[pos..].trim());
+        }
+    }
+    merged.push('\n');
+    merged
+}
+
+// The path of the sidecar file for a subtitle track: NAME.LANG.EXT next to the output file, with a
+// suffix to distinguish forced and SDH tracks, or several tracks in the same language.
+fn sidecar_path(out: &Path, track: &SubtitleTrack, ext: &str, taken: &[PathBuf]) -> PathBuf {
+    let mut tag = track.lang.clone().unwrap_or_else(|| String::from("und"));
+    if track.forced {
+        tag += ".forced";
+    }
+    if track.sdh {
+        tag += ".sdh";
+    }
+    let mut path = out.with_extension(format!("{tag}.{ext}"));
+    let mut n = 2;
+    while taken.contains(&path) {
+        path = out.with_extension(format!("{tag}.{n}.{ext}"));
+        n += 1;
+    }
+    path
+}
+
+async fn fetch_period(fetcher: &ManifestFetcher, period: &SubtitlePeriod) -> Result<Vec<Vec<u8>>> {
+    let mut parts = Vec::new();
+    if let Some(init) = &period.init {
+        // With SegmentBase addressing the initialization range is part of the single media file.
+        let whole_file = period.segments.iter().any(|s| s.url == init.url && s.byte_range.is_none());
+        if !whole_file {
+            parts.push(fetch(fetcher, init).await?);
+        }
+    }
+    for segment in &period.segments {
+        parts.push(fetch(fetcher, segment).await?);
+    }
+    Ok(parts)
+}
+
+// The subtitle document of a Period, from its segments.
+fn period_text(format: SubtitleFormat, parts: &[Vec<u8>]) -> Result<String> {
+    match format {
+        SubtitleFormat::WebVtt => {
+            let texts: Vec<String> = parts.iter().map(|p| String::from_utf8_lossy(p).to_string()).collect();
+            Ok(merge_webvtt(&texts))
+        },
+        SubtitleFormat::Stpp => {
+            let data = parts.concat();
+            let docs: Vec<String> = mdat_payloads(&data).into_iter()
+                .map(|p| String::from_utf8_lossy(p).trim().to_string())
+                .filter(|d| !d.is_empty())
+                .collect();
+            if docs.is_empty() {
+                return Err(anyhow!("no TTML documents in the stpp subtitle stream"));
+            }
+            Ok(merge_ttml(&docs))
+        },
+        _ => Ok(String::from_utf8_lossy(&parts.concat()).to_string()),
+    }
+}
+
+// Convert WebVTT in fragmented MP4 to WebVTT text using ffmpeg. The fragmented MP4 stream is saved
+// next to path for ffmpeg to read.
+fn convert_wvtt(ffmpeg: &str, data: &[u8], path: &Path) -> Result<String> {
+    let mp4 = path.with_extension("mp4");
+    fs::write(&mp4, data)?;
+    let out = std::process::Command::new(ffmpeg)
+        .args(["-hide_banner", "-nostats", "-loglevel", "error", "-y"])
+        .arg("-i").arg(&mp4)
+        .args(["-map", "0:s:0", "-c:s", "webvtt", "-f", "webvtt", "pipe:1"])
+        .output()
+        .with_context(|| format!("spawning {ffmpeg}"))?;
+    if !out.status.success() {
+        return Err(anyhow!("ffmpeg failed to convert wvtt subtitles, which are saved in {}: {}",
+                           mp4.display(), String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    fs::remove_file(&mp4)?;
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+// Download a subtitle track and save it to path, in the requested format.
+async fn save_track(
+    fetcher: &ManifestFetcher,
+    ffmpeg: &str,
+    track: &SubtitleTrack,
+    convert: Option<TargetFormat>,
+    path: &Path) -> Result<()>
+{
+    let mut texts = Vec::new();
+    for period in &track.periods {
+        let parts = fetch_period(fetcher, period).await?;
+        let text = match track.format {
+            SubtitleFormat::Wvtt => convert_wvtt(ffmpeg, &parts.concat(), path)?,
+            format => period_text(format, &parts)?,
+        };
+        texts.push((period.offset, text));
+    }
+    // The WebVTT converted from each Period of a wvtt stream has timestamps relative to the start of
+    // the Period, so it is always shifted to the timeline of the output file.
+    let convert = match track.format {
+        SubtitleFormat::Wvtt => convert.or(Some(TargetFormat::WebVtt)),
+        _ => convert,
+    };
+    let content = match convert {
+        Some(format) => {
+            let mut cues: Vec<Cue> = Vec::new();
+            for (offset, text) in texts {
+                cues.extend(parse_subtitles(&text)?.into_iter().map(|c| c.shifted(offset)));
+            }
+            write_subtitles(&cues, format)
+        },
+        None => {
+            let texts: Vec<String> = texts.into_iter().map(|(_, text)| text).collect();
+            match track.format {
+                SubtitleFormat::WebVtt => merge_webvtt(&texts),
+                SubtitleFormat::Ttml | SubtitleFormat::Stpp => merge_ttml(&texts),
+                _ => texts.join("\n"),
+            }
+        },
+    };
+    fs::write(path, content)?;
+    Ok(())
+}
+
+// Download a subtitle track in fragmented MP4 (stpp or wvtt) to path, as a single fragmented MP4
+// stream, for the builtin muxer.
+pub async fn save_fragmented_track(fetcher: &ManifestFetcher, track: &SubtitleTrack, path: &Path) -> Result<()> {
+    let mut data = Vec::new();
+    for period in &track.periods {
+        for part in fetch_period(fetcher, period).await? {
+            data.extend(part);
+        }
+    }
+    fs::write(path, data)?;
+    Ok(())
+}
+
+// Save a sidecar subtitle track (WebVTT, TTML or SubRip) next to the output file as NAME.EXT, as the
+// DashDownloader does for --write-subs. Returns the path of the subtitle file.
+pub async fn save_sidecar_track(
+    fetcher: &ManifestFetcher,
+    ffmpeg: &str,
+    track: &SubtitleTrack,
+    out: &Path) -> Result<PathBuf>
+{
+    let path = out.with_extension(track.format.extension());
+    save_track(fetcher, ffmpeg, track, None, &path).await?;
+    info!("Saved {} subtitles to {}", track.describe(), path.display());
+    Ok(path)
+}
+
+// Download the subtitle tracks to sidecar files next to the output file. A track that can't be
+// downloaded is skipped with a warning. Returns the tracks that were saved and their paths.
+pub async fn download_subtitles(
+    fetcher: &ManifestFetcher,
+    ffmpeg: &str,
+    tracks: &[SubtitleTrack],
+    convert: Option<TargetFormat>,
+    out: &Path) -> Vec<(SubtitleTrack, PathBuf)>
+{
+    let mut saved: Vec<(SubtitleTrack, PathBuf)> = Vec::new();
+    for track in tracks {
+        let taken: Vec<PathBuf> = saved.iter().map(|(_, p)| p.clone()).collect();
+        let ext = convert.map_or_else(|| track.format.extension(), TargetFormat::extension);
+        let path = sidecar_path(out, track, ext, &taken);
+        match save_track(fetcher, ffmpeg, track, convert, &path).await {
+            Ok(()) => {
+                info!("Saved {} subtitles to {}", track.describe(), path.display());
+                saved.push((track.clone(), path));
+            },
+            Err(e) => warn!("Can't download {} subtitles: {e:#}", track.describe()),
+        }
+    }
+    saved
+}
+
+// Add the subtitle files to the media file at path, as subtitle streams with language, title and
+// disposition metadata.
+pub fn embed_subtitles(ffmpeg: &str, path: &Path, subtitles: &[(SubtitleTrack, PathBuf)]) -> Result<()> {
+    let embeddable: Vec<&(SubtitleTrack, PathBuf)> = subtitles.iter()
+        .filter(|(track, path)| {
+            let ttml = path.extension().is_some_and(|e| e == "ttml");
+            if ttml {
+                info!("Not embedding {} TTML subtitles, which ffmpeg can't read", track.describe());
+            }
+            !ttml
+        })
+        .collect();
+    if embeddable.is_empty() {
+        return Ok(());
+    }
+    let ext = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+    // MP4 files only support the 3GPP timed text format, and WebM files only support WebVTT.
+    let codec = match ext.as_str() {
+        "mp4" | "m4v" | "mov" => "mov_text",
+        "webm" => "webvtt",
+        _ => "copy",
+    };
+    let mut tmp = path.to_path_buf();
+    tmp.set_extension(format!("subs.{ext}"));
+    let mut cmd = std::process::Command::new(ffmpeg);
+    cmd.args(["-hide_banner", "-nostats", "-loglevel", "error", "-y"])
+        .arg("-i").arg(path);
+    for (_, file) in &embeddable {
+        cmd.arg("-i").arg(file);
+    }
+    cmd.args(["-map", "0"]);
+    for n in 1..=embeddable.len() {
+        cmd.args(["-map", &n.to_string()]);
+    }
+    cmd.args(["-c", "copy", "-c:s", codec]);
+    // The DashDownloader doesn't fetch subtitles with --sub-langs, so the output file contains no
+    // other subtitle streams.
+    for (s, (track, _)) in embeddable.iter().enumerate() {
+        cmd.arg(format!("-metadata:s:s:{s}"))
+            .arg(format!("language={}", iso639_2(track.lang.as_deref())));
+        if let Some(label) = &track.label {
+            cmd.arg(format!("-metadata:s:s:{s}")).arg(format!("title={label}"));
+        }
+        let disposition = match (track.forced, track.sdh) {
+            (true, true) => "forced+hearing_impaired",
+            (true, false) => "forced",
+            (false, true) => "hearing_impaired",
+            (false, false) => "0",
+        };
+        cmd.arg(format!("-disposition:s:{s}")).arg(disposition);
+    }
+    let out = cmd.arg(&tmp)
+        .output()
+        .with_context(|| format!("spawning {ffmpeg}"))?;
+    if !out.status.success() {
+        let _ = fs::remove_file(&tmp);
+        return Err(anyhow!("ffmpeg failed to add subtitles to {}: {}",
+                           path.display(), String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    fs::rename(&tmp, path)?;
+    info!("Added {} subtitle tracks to {}", embeddable.len(), path.display());
+    Ok(())
+}
+
+// Convert the subtitle file saved by the DashDownloader next to the output file with --write-subs.
+pub fn convert_downloaded_subtitles(out: &Path, format: TargetFormat) -> Result<()> {
+    for ext in ["ttml", "vtt", "srt"] {
+        let path = out.with_extension(ext);
+        if ext != format.extension() && path.exists() {
+            convert_subtitle_file(&path, format)?;
+        }
+    }
+    Ok(())
+}
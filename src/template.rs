@@ -0,0 +1,290 @@
+//! Output filename templates (--output-template)
+//
+// When no output path is specified for a manifest, the DashDownloader derives the output filename
+// from the last component of the manifest URL, which is often just "manifest.mp4". With
+// --output-template the output path is built from a template such as "{title}/{date}-{height}p.{ext}",
+// whose placeholders are replaced by values taken from the manifest and from the Representations
+// selected for download. The same template fields can be used in the paths given to --keep-video and
+// --keep-audio, and the subtitle sidecar files are named after the expanded output path.
+//
+// Values are sanitised so that they can't introduce path separators or characters that are invalid in
+// filenames on common platforms. Directory separators in the template itself are kept, and missing
+// directories are created before the download starts. Literal braces are written as "{{" and "}}".
+
+
+use std::path::Path;
+use url::Url;
+use dash_mpd::MPD;
+use crate::selection::{StreamPreferences, select_representation};
+use crate::subtitles::subtitle_format;
+
+
+const FIELDS: [&str; 12] = [
+    "title", "period_id", "width", "height", "resolution", "vcodec", "acodec", "lang",
+    "date", "url_stem", "id", "ext",
+];
+
+// The value used for a placeholder whose value isn't known.
+const MISSING: &str = "NA";
+
+// Longest expanded value, in bytes, to stay clear of filesystem limits on the length of a filename.
+const MAX_VALUE_LEN: usize = 120;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Field(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputTemplate(Vec<Part>);
+
+// Parse the value of the --output-template commandline option.
+pub fn parse_output_template(s: &str) -> Result<OutputTemplate, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            },
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unterminated placeholder {{{name} in output template")),
+                    }
+                }
+                let Some(field) = FIELDS.iter().find(|f| **f == name) else {
+                    return Err(format!("unknown placeholder {{{name}}} in output template (expecting one of {})",
+                                       FIELDS.join(", ")));
+                };
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Field(field));
+            },
+            '}' => return Err(String::from("unmatched }} in output template (write }}}} for a literal brace)")),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    if parts.is_empty() {
+        return Err(String::from("empty output template"));
+    }
+    Ok(OutputTemplate(parts))
+}
+
+// Make a value safe for use as (part of) a filename: path separators and characters that are
+// reserved on Windows are replaced by "_", control characters and runs of whitespace by a single
+// space, and leading dots are removed so that the value can't produce a hidden file or a ".."
+// component.
+pub fn sanitize(value: &str) -> String {
+    let mut clean = String::new();
+    for c in value.chars() {
+        let c = match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => ' ',
+            c => c,
+        };
+        if c.is_whitespace() {
+            if !clean.is_empty() && !clean.ends_with(' ') {
+                clean.push(' ');
+            }
+        } else {
+            clean.push(c);
+        }
+    }
+    let mut clean = clean.trim_start_matches('.')
+        .trim_end_matches(['.', ' '])
+        .to_string();
+    if clean.len() > MAX_VALUE_LEN {
+        let mut end = MAX_VALUE_LEN;
+        while !clean.is_char_boundary(end) {
+            end -= 1;
+        }
+        clean.truncate(end);
+        clean = clean.trim_end_matches(['.', ' ']).to_string();
+    }
+    if clean.is_empty() {
+        String::from(MISSING)
+    } else {
+        clean
+    }
+}
+
+// The values of the template placeholders for one manifest.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateFields {
+    pub title: Option<String>,
+    pub period_id: Option<String>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+    pub lang: Option<String>,
+    pub date: String,
+    pub url_stem: String,
+    // The Representation ids of the video and audio streams, joined with "+".
+    pub id: Option<String>,
+    // The file extensions implied by the MIME types of the selected video and audio streams.
+    pub video_ext: Option<String>,
+    pub audio_ext: Option<String>,
+    // The file extension of the selected subtitle track.
+    pub subtitle_ext: Option<String>,
+}
+
+impl TemplateFields {
+    // The extension of the output file, which determines its container: the first container named
+    // in --muxer-preference (preferred_container), or otherwise the container of the selected
+    // streams. Audio and video streams in WebM are saved to a WebM file, and to a Matroska file if
+    // only one of them is in WebM. Subtitles alone are saved in their own format.
+    pub fn output_extension(&self, preferred_container: Option<&str>) -> String {
+        if self.video_ext.is_none() && self.audio_ext.is_none() {
+            return self.subtitle_ext.clone().unwrap_or_else(|| String::from("mp4"));
+        }
+        if let Some(container) = preferred_container {
+            return container.to_string();
+        }
+        match (self.video_ext.as_deref(), self.audio_ext.as_deref()) {
+            (Some(ext), None) | (None, Some(ext)) => ext.to_string(),
+            (Some(v), Some(a)) if v == a => v.to_string(),
+            (Some("webm"), _) | (_, Some("webm")) => String::from("mkv"),
+            _ => String::from("mp4"),
+        }
+    }
+}
+
+// The last component of the path of the manifest URL, without its extension.
+pub fn url_stem(url: &str) -> String {
+    let Ok(url) = Url::parse(url) else {
+        return String::from(MISSING);
+    };
+    let stem = url.path_segments()
+        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+        .map(|last| Path::new(last).file_stem().unwrap_or_default().to_string_lossy().to_string())
+        .unwrap_or_default();
+    if stem.is_empty() {
+        url.host_str().unwrap_or(MISSING).to_string()
+    } else {
+        stem
+    }
+}
+
+// The codec family, such as "avc1" or "mp4a", from an RFC 6381 codecs string.
+fn codec_family(codecs: &str) -> String {
+    codecs.split(',')
+        .next()
+        .and_then(|c| c.trim().split('.').next())
+        .unwrap_or(codecs)
+        .to_string()
+}
+
+// The extension for a MIME type such as "video/mp4" or "audio/webm".
+fn mime_extension(mime: &str) -> Option<String> {
+    let (_, subtype) = mime.split_once('/')?;
+    Some(match subtype {
+        "mp2t" => String::from("ts"),
+        s => s.to_string(),
+    })
+}
+
+pub fn template_fields(mpd: &MPD, url: &str, prefs: &StreamPreferences, kinds: &[&str]) -> TemplateFields {
+    let today = time::OffsetDateTime::now_local()
+        .unwrap_or_else(|_| time::OffsetDateTime::now_utc())
+        .date();
+    let mut fields = TemplateFields {
+        title: mpd.ProgramInformation.as_ref()
+            .and_then(|pi| pi.Title.as_ref())
+            .and_then(|t| t.content.clone())
+            .filter(|t| !t.trim().is_empty()),
+        date: format!("{}-{:02}-{:02}", today.year(), u8::from(today.month()), today.day()),
+        url_stem: url_stem(url),
+        ..Default::default()
+    };
+    let mut ids = Vec::new();
+    // The first Period determines the values of the fields, as it's the start of the output file.
+    if let Some(period) = mpd.periods.first() {
+        fields.period_id = period.id.clone();
+        for kind in kinds {
+            let Some(choice) = select_representation(period, kind, prefs) else {
+                continue;
+            };
+            let adaptation = &period.adaptations[choice.adaptation];
+            let rep = &adaptation.representations[choice.representation];
+            let codecs = rep.codecs.as_ref().or(adaptation.codecs.as_ref()).map(|c| codec_family(c));
+            let ext = rep.mimeType.as_ref().or(adaptation.mimeType.as_ref())
+                .and_then(|m| mime_extension(m));
+            if let Some(id) = &rep.id {
+                ids.push(id.clone());
+            }
+            match *kind {
+                "video" => {
+                    fields.width = rep.width;
+                    fields.height = rep.height;
+                    fields.vcodec = codecs;
+                    fields.video_ext = ext;
+                },
+                "audio" => {
+                    fields.acodec = codecs;
+                    fields.lang = adaptation.lang.clone();
+                    fields.audio_ext = ext;
+                },
+                "text" => {
+                    fields.subtitle_ext = subtitle_format(adaptation, rep)
+                        .map(|f| f.extension().to_string());
+                },
+                _ => {},
+            }
+        }
+    }
+    if !ids.is_empty() {
+        fields.id = Some(ids.join("+"));
+    }
+    fields
+}
+
+impl OutputTemplate {
+    // Expand the template, with ext as the value of {ext}.
+    pub fn expand(&self, fields: &TemplateFields, ext: &str) -> String {
+        let mut out = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Field(name) => {
+                    let value = match *name {
+                        // Without a title in the manifest, fall back to the name derived from the URL.
+                        "title" => fields.title.clone().unwrap_or_else(|| fields.url_stem.clone()),
+                        "period_id" => fields.period_id.clone().unwrap_or_default(),
+                        "width" => fields.width.map(|w| w.to_string()).unwrap_or_default(),
+                        "height" => fields.height.map(|h| h.to_string()).unwrap_or_default(),
+                        "resolution" => match (fields.width, fields.height) {
+                            (Some(w), Some(h)) => format!("{w}x{h}"),
+                            _ => String::new(),
+                        },
+                        "vcodec" => fields.vcodec.clone().unwrap_or_default(),
+                        "acodec" => fields.acodec.clone().unwrap_or_default(),
+                        "lang" => fields.lang.clone().unwrap_or_default(),
+                        "date" => fields.date.clone(),
+                        "url_stem" => fields.url_stem.clone(),
+                        "id" => fields.id.clone().unwrap_or_default(),
+                        "ext" => ext.to_string(),
+                        _ => String::new(),
+                    };
+                    out.push_str(&sanitize(&value));
+                },
+            }
+        }
+        out
+    }
+}
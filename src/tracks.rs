@@ -0,0 +1,189 @@
+//! Downloading several audio tracks into one output file (--audio-tracks)
+//
+// The DashDownloader downloads a single audio stream. To produce a multi-track file, we rewrite the
+// manifest so that the main download contains the first requested audio track, and download each
+// of the other tracks separately from a manifest that contains only that track. The additional
+// audio streams are then added to the output file by ffmpeg, without reencoding, with a language
+// tag for each track, a title taken from the AdaptationSet Label, and the first track marked as the
+// default track.
+//
+// Tracks are identified in the first Period of the manifest, and matched in the following Periods
+// by AdaptationSet@id and language, or by language only.
+
+
+use std::path::{Path, PathBuf};
+use fs_err as fs;
+use tracing::{info, warn};
+use anyhow::{anyhow, Result, Context};
+use dash_mpd::{MPD, AdaptationSet};
+use crate::selection::{content_kind, matches_language};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LanguageSelection {
+    All,
+    Languages(Vec<String>),
+}
+
+// Parse the value of the --audio-tracks and --sub-langs commandline options: "all", or a
+// comma-separated list of languages.
+pub fn parse_language_selection(s: &str) -> Result<LanguageSelection, String> {
+    if s.trim().eq_ignore_ascii_case("all") {
+        return Ok(LanguageSelection::All);
+    }
+    let langs: Vec<String> = s.split(',')
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect();
+    if langs.is_empty() {
+        return Err(String::from("expecting \"all\" or a comma-separated list of languages"));
+    }
+    if let Some(bad) = langs.iter().find(|l| !l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')) {
+        return Err(format!("invalid language {bad}"));
+    }
+    Ok(LanguageSelection::Languages(langs))
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioTrack {
+    pub adaptation_id: Option<String>,
+    pub lang: Option<String>,
+    pub label: Option<String>,
+}
+
+impl AudioTrack {
+    fn describe(&self) -> String {
+        let lang = self.lang.as_deref().unwrap_or("undetermined language");
+        match &self.label {
+            Some(label) => format!("{lang} ({label})"),
+            None => lang.to_string(),
+        }
+    }
+
+    fn matches(&self, adaptation: &AdaptationSet) -> bool {
+        adaptation.id == self.adaptation_id && adaptation.lang == self.lang
+    }
+}
+
+fn is_audio(adaptation: &AdaptationSet) -> bool {
+    content_kind(adaptation) == Some("audio")
+}
+
+// The audio tracks of the manifest that match the selection, in the order of the selection (or of
+// the manifest for "all").
+pub fn audio_tracks(mpd: &MPD, selection: &LanguageSelection) -> Result<Vec<AudioTrack>> {
+    let Some(period) = mpd.periods.first() else {
+        return Err(anyhow!("manifest contains no Periods"));
+    };
+    let audio: Vec<&AdaptationSet> = period.adaptations.iter()
+        .filter(|a| is_audio(a))
+        .collect();
+    let chosen: Vec<&AdaptationSet> = match selection {
+        LanguageSelection::All => audio,
+        LanguageSelection::Languages(langs) => {
+            let mut chosen = Vec::new();
+            for lang in langs {
+                match audio.iter().find(|a| matches_language(a, lang)) {
+                    Some(a) => chosen.push(*a),
+                    None => warn!("No audio track in language {lang}"),
+                }
+            }
+            chosen
+        },
+    };
+    if chosen.is_empty() {
+        return Err(anyhow!("no audio tracks match --audio-tracks"));
+    }
+    let tracks: Vec<AudioTrack> = chosen.into_iter()
+        .map(|a| AudioTrack {
+            adaptation_id: a.id.clone(),
+            lang: a.lang.clone(),
+            label: a.Label.first().map(|l| l.content.clone()),
+        })
+        .collect();
+    let names: Vec<String> = tracks.iter().map(AudioTrack::describe).collect();
+    info!("Downloading {} audio tracks: {}", tracks.len(), names.join(", "));
+    Ok(tracks)
+}
+
+// A copy of the manifest in which the only audio AdaptationSet of each Period is that of the track.
+// With audio_only, the other AdaptationSets are also removed.
+pub fn with_audio_track(mpd: &MPD, track: &AudioTrack, audio_only: bool) -> Result<MPD> {
+    let mut out = mpd.clone();
+    for (idx, period) in out.periods.iter_mut().enumerate() {
+        let position = period.adaptations.iter().position(|a| is_audio(a) && track.matches(a))
+            .or_else(|| period.adaptations.iter().position(|a| is_audio(a) && a.lang == track.lang))
+            .ok_or_else(|| anyhow!("audio track {} is missing from Period {}", track.describe(), idx + 1))?;
+        let adaptations = std::mem::take(&mut period.adaptations);
+        period.adaptations = adaptations.into_iter().enumerate()
+            .filter(|(aidx, a)| *aidx == position || (!audio_only && !is_audio(a)))
+            .map(|(_, a)| a)
+            .collect();
+    }
+    Ok(out)
+}
+
+// ISO 639-1 language codes and the corresponding ISO 639-2 (bibliographic) codes, which are used
+// for the language tags of the MP4 and Matroska containers.
+const ISO639_2: [(&str, &str); 44] = [
+    ("ar", "ara"), ("bg", "bul"), ("ca", "cat"), ("cs", "cze"), ("cy", "wel"), ("da", "dan"),
+    ("de", "ger"), ("el", "gre"), ("en", "eng"), ("es", "spa"), ("et", "est"), ("eu", "baq"),
+    ("fa", "per"), ("fi", "fin"), ("fr", "fre"), ("ga", "gle"), ("gl", "glg"), ("he", "heb"),
+    ("hi", "hin"), ("hr", "hrv"), ("hu", "hun"), ("id", "ind"), ("is", "ice"), ("it", "ita"),
+    ("ja", "jpn"), ("ko", "kor"), ("lt", "lit"), ("lv", "lav"), ("ms", "may"), ("nb", "nob"),
+    ("nl", "dut"), ("nn", "nno"), ("no", "nor"), ("pl", "pol"), ("pt", "por"), ("ro", "rum"),
+    ("ru", "rus"), ("sk", "slo"), ("sl", "slv"), ("sr", "srp"), ("sv", "swe"), ("th", "tha"),
+    ("tr", "tur"), ("zh", "chi"),
+];
+
+// The ISO 639-2 code for an RFC 5646 language tag such as "fr" or "en-AU", or "und".
+pub fn iso639_2(lang: Option<&str>) -> String {
+    let Some(primary) = lang.and_then(|l| l.split(['-', '_']).next()) else {
+        return String::from("und");
+    };
+    let primary = primary.to_lowercase();
+    if primary.len() == 3 {
+        return primary;
+    }
+    ISO639_2.iter()
+        .find(|(two, _)| *two == primary)
+        .map_or_else(|| String::from("und"), |(_, three)| three.to_string())
+}
+
+// Add the audio streams of the files in extra to the media file at path, and tag the audio streams
+// with the language and title of the tracks (the first track being that of the main download).
+pub fn mux_audio_tracks(ffmpeg: &str, path: &Path, extra: &[PathBuf], tracks: &[AudioTrack]) -> Result<()> {
+    let mut tmp = path.to_path_buf();
+    tmp.set_extension(format!("tracks.{}", path.extension().unwrap_or_default().to_string_lossy()));
+    let mut cmd = std::process::Command::new(ffmpeg);
+    cmd.args(["-hide_banner", "-nostats", "-loglevel", "error", "-y"])
+        .arg("-i").arg(path);
+    for file in extra {
+        cmd.arg("-i").arg(file);
+    }
+    cmd.args(["-map", "0"]);
+    for n in 1..=extra.len() {
+        cmd.args(["-map", &format!("{n}:a")]);
+    }
+    cmd.args(["-c", "copy"]);
+    for (i, track) in tracks.iter().enumerate() {
+        cmd.arg(format!("-metadata:s:a:{i}"))
+            .arg(format!("language={}", iso639_2(track.lang.as_deref())));
+        if let Some(label) = &track.label {
+            cmd.arg(format!("-metadata:s:a:{i}")).arg(format!("title={label}"));
+        }
+        cmd.arg(format!("-disposition:a:{i}"))
+            .arg(if i == 0 { "default" } else { "0" });
+    }
+    let out = cmd.arg(&tmp)
+        .output()
+        .with_context(|| format!("spawning {ffmpeg}"))?;
+    if !out.status.success() {
+        let _ = fs::remove_file(&tmp);
+        return Err(anyhow!("ffmpeg failed to add audio tracks to {}: {}",
+                           path.display(), String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    fs::rename(&tmp, path)?;
+    info!("Added {} audio tracks to {}", extra.len(), path.display());
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! Tests for the --abort-on-error strict error policy
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test abort_on_error -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with three media segments, the second of
+//   which returns a 404 error.
+//
+//   - Check that a download with a resume journal (--continue) aborts, and that the error report
+//   identifies the failed segment, the HTTP status of the failed request and the segments that had
+//   been downloaded.
+//
+//   - Check that a download without a journal also aborts at the failed segment.
+
+
+pub mod common;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::TempDir;
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use dash_mpd::{MPD, Period, AdaptationSet, Representation, SegmentTemplate};
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4;
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_abort_on_error() -> Result<()> {
+    async fn send_mpd() -> impl IntoResponse {
+        let segment_template = SegmentTemplate {
+            initialization: Some("/media/init.mp4".to_string()),
+            media: Some("/media/seg-$Number$.mp4".to_string()),
+            startNumber: Some(1),
+            timescale: Some(1),
+            duration: Some(2.0),
+            ..Default::default()
+        };
+        let rep = Representation {
+            id: Some("v1".to_string()),
+            mimeType: Some("video/mp4".to_string()),
+            codecs: Some("avc1.640028".to_string()),
+            width: Some(1920),
+            height: Some(800),
+            bandwidth: Some(1980081),
+            SegmentTemplate: Some(segment_template),
+            ..Default::default()
+        };
+        let adapt = AdaptationSet {
+            id: Some("a1".to_string()),
+            contentType: Some("video".to_string()),
+            representations: vec!(rep),
+            ..Default::default()
+        };
+        let period = Period {
+            id: Some("p1".to_string()),
+            duration: Some(Duration::new(6, 0)),
+            adaptations: vec!(adapt),
+            ..Default::default()
+        };
+        let mpd = MPD {
+            mpdtype: Some("static".to_string()),
+            periods: vec!(period),
+            ..Default::default()
+        };
+        let xml = quick_xml::se::to_string(&mpd).unwrap();
+        ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+    }
+
+    async fn send_media(Path(seg): Path<String>) -> Response {
+        if seg.eq("seg-2.mp4") {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap();
+        }
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "video/mp4")
+            .body(Body::from(generate_minimal_mp4()))
+            .unwrap()
+    }
+
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{seg}", get(send_media));
+    let listener = TcpListener::bind("127.0.0.1:6672").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap();
+    let out = tmpd.path().join("aborted.mp4");
+    dash_mpd_cli()
+        .args(["--abort-on-error", "--continue",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6672/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("Aborted at first error"))
+        .stderr(predicate::str::contains("Period #1 (id p1)"))
+        .stderr(predicate::str::contains("Representation id v1"))
+        .stderr(predicate::str::contains("media segment 2 of 3"))
+        .stderr(predicate::str::contains("http://localhost:6672/media/seg-2.mp4"))
+        .stderr(predicate::str::contains("failed: HTTP status 404 Not Found"))
+        .stderr(predicate::str::contains("segments of this Representation"))
+        .failure();
+    dash_mpd_cli()
+        .args(["--abort-on-error", "--fragment-retries", "1",
+               "-o", &tmpd.path().join("unjournaled.mp4").to_string_lossy(),
+               "http://localhost:6672/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("Aborted at first error"))
+        .failure();
+    Ok(())
+}
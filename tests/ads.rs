@@ -0,0 +1,149 @@
+//! Tests for the removal of ad Periods (--skip-ads)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test ads -- --show-output
+//
+// What happens in these tests:
+//
+//   - Start an axum HTTP server that serves a manifest with six Periods: two Periods of programme
+//   content, and four ads that are identified respectively by a SCTE-35 ad break signalled in the
+//   first Period, by their Period@id, by being served from an ad insertion service, and by a change
+//   in video codec and resolution. We also serve two AWS MediaTailor manifests from our fixtures,
+//   one of which has its programme content served from the same MediaTailor endpoint as the ads.
+//
+//   - Download with --skip-ads, and check that only the media segments of the programme Periods are
+//   requested, and that the classification of each Period is logged.
+
+
+pub mod common;
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4;
+
+
+static SEGMENTS_REQUESTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn period_xml(id: &str, duration: u32, base: &str, codecs: &str, height: u32, extra: &str) -> String {
+    format!(r#"
+  <Period id="{id}" duration="PT{duration}S">{extra}
+    <BaseURL>{base}</BaseURL>
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="{duration}" startNumber="1"
+                       initialization="init.mp4" media="seg-$Number$.mp4"/>
+      <Representation id="v1" codecs="{codecs}" height="{height}" bandwidth="1980081"/>
+    </AdaptationSet>
+  </Period>"#)
+}
+
+async fn send_mpd() -> impl IntoResponse {
+    let scte35 = r#"
+    <EventStream schemeIdUri="urn:scte:scte35:2014:xml+bin" timescale="1">
+      <Event presentationTime="10" duration="4" id="1"/>
+    </EventStream>"#;
+    let periods = [
+        period_xml("programme-1", 10, "media/programme-1/", "avc1.640028", 1080, scte35),
+        period_xml("break-1", 4, "media/break-1/", "avc1.640028", 1080, ""),
+        period_xml("mid-roll-2-ad-1", 2, "media/mid-roll-2-ad-1/", "avc1.640028", 1080, ""),
+        period_xml("x1", 2, "https://dai.google.com/linear/pods/x1/", "avc1.640028", 1080, ""),
+        period_xml("x2", 2, "media/x2/", "hvc1.1.6.L93.B0", 720, ""),
+        period_xml("programme-2", 10, "media/programme-2/", "avc1.640028", 1080, ""),
+    ].concat();
+    let xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT30S">{periods}
+</MPD>"#);
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_mediatailor() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/dash+xml")],
+     include_str!("fixtures/avod-mediatailor.mpd"))
+}
+
+async fn send_mediatailor_content() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/dash+xml")],
+     include_str!("fixtures/mediatailor-content.mpd"))
+}
+
+async fn send_segment(Path((period, seg)): Path<(String, String)>) -> Response {
+    SEGMENTS_REQUESTED.lock().unwrap().push(format!("{period}/{seg}"));
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4()))
+        .unwrap()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_skip_ads() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/mediatailor.mpd", get(send_mediatailor))
+        .route("/mediatailor-content.mpd", get(send_mediatailor_content))
+        .route("/media/{period}/{seg}", get(send_segment));
+    let listener = TcpListener::bind("127.0.0.1:6681").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+
+    dash_mpd_cli()
+        .args(["--skip-ads", "--no-period-concatenation", "--no-progress",
+               "-o", &tmpd.child("programme.mp4").to_string_lossy(),
+               "http://localhost:6681/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Period 1 (id programme-1) is programme content"))
+        .stdout(predicate::str::contains("Period 2 (id break-1) is an ad (within the SCTE-35 ad break"))
+        .stdout(predicate::str::contains("Period 3 (id mid-roll-2-ad-1) is an ad (Period id"))
+        .stdout(predicate::str::contains("Period 4 (id x1) is an ad (media served by ad insertion service dai.google.com)"))
+        .stdout(predicate::str::contains("Period 5 (id x2) is an ad (video encoding hvc1 720p differs"))
+        .stdout(predicate::str::contains("Selected 2 of 6 Periods"))
+        .success();
+    let mut requested = SEGMENTS_REQUESTED.lock().unwrap().clone();
+    requested.sort();
+    assert_eq!(requested, vec!["programme-1/init.mp4", "programme-1/seg-1.mp4",
+                               "programme-2/init.mp4", "programme-2/seg-1.mp4"]);
+
+    // The MediaTailor manifest contains 4 Periods of programme content and 12 ads.
+    dash_mpd_cli()
+        .args(["--simulate", "--skip-ads",
+               "-o", &tmpd.child("mediatailor.mp4").to_string_lossy(),
+               "http://localhost:6681/mediatailor.mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Period 2 (id 1_PT6S_0) is an ad (media served by ad insertion service"))
+        .stdout(predicate::str::contains("Selected 4 of 16 Periods"))
+        .success();
+
+    // Only the ad segments of a MediaTailor endpoint identify an ad, not the programme content that
+    // it also serves.
+    dash_mpd_cli()
+        .args(["--simulate", "--skip-ads",
+               "-o", &tmpd.child("mediatailor-content.mp4").to_string_lossy(),
+               "http://localhost:6681/mediatailor-content.mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Period 1 (id 1_PT0S) is programme content"))
+        .stdout(predicate::str::contains("Period 2 (id 1_PT12S_0) is an ad (media served by ad insertion service"))
+        .stdout(predicate::str::contains("Period 3 (id 1_PT18S_1) is an ad (media served by ad insertion service segments.mediatailor"))
+        .stdout(predicate::str::contains("Period 4 (id 1_PT24S) is programme content"))
+        .stdout(predicate::str::contains("Selected 2 of 4 Periods"))
+        .success();
+    Ok(())
+}
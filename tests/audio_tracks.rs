@@ -0,0 +1,191 @@
+//! Tests for downloading several audio tracks into one output file (--audio-tracks)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test audio_tracks -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with a video AdaptationSet and three audio
+//   AdaptationSets in English, French and German, each with a Label. Each Representation has a
+//   single media segment of one second, generated by ffmpeg.
+//
+//   - Download with --audio-tracks fr,en and check with ffprobe that the output file contains the
+//   video stream and two audio streams, tagged with the ISO 639-2 language codes and the Labels,
+//   with the French track marked as the default. Check that the German audio is not requested.
+//
+//   - Download with --audio-tracks all and check that the output file contains the three audio
+//   tracks, in the order of the manifest.
+
+
+pub mod common;
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4_ffmpeg;
+
+
+static GERMAN_REQUESTED: AtomicUsize = AtomicUsize::new(0);
+
+// A one second fragmented MP4 file containing an AAC sine wave.
+fn generate_audio_mp4(frequency: u32) -> Vec<u8> {
+    let tmp = env::temp_dir().join(format!("audio-track-{frequency}.mp4"));
+    let ffmpeg = Command::new("ffmpeg")
+        .args(["-nostdin",
+               "-y",
+               "-f", "lavfi",
+               "-i", &format!("sine=frequency={frequency}:sample_rate=48000"),
+               "-t", "1",
+               "-c:a", "aac",
+               "-movflags", "frag_keyframe+empty_moov",
+               tmp.to_str().unwrap()])
+        .output()
+        .expect("spawning ffmpeg");
+    assert!(ffmpeg.status.success());
+    fs::read(tmp).unwrap()
+}
+
+fn audio_xml(id: u32, lang: &str, label: &str) -> String {
+    format!(r#"
+    <AdaptationSet id="{id}" contentType="audio" mimeType="audio/mp4" lang="{lang}">
+      <Label>{label}</Label>
+      <SegmentTemplate timescale="1" duration="1" startNumber="1" media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="a-{lang}" codecs="mp4a.40.2" audioSamplingRate="48000" bandwidth="64000"/>
+    </AdaptationSet>"#)
+}
+
+async fn send_mpd() -> impl IntoResponse {
+    let audio = [
+        audio_xml(2, "en", "English"),
+        audio_xml(3, "fr", "Français"),
+        audio_xml(4, "de", "Deutsch (Audiodeskription)"),
+    ].concat();
+    let xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT1S">
+  <Period id="p0" duration="PT1S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="1" startNumber="1" media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="v1" codecs="avc1.640028" width="10" height="10" bandwidth="100000"/>
+    </AdaptationSet>{audio}
+  </Period>
+</MPD>"#);
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_segment(Path((rep, _seg)): Path<(String, String)>) -> Response {
+    let (content_type, body) = match rep.as_str() {
+        "v1" => ("video/mp4", generate_minimal_mp4_ffmpeg("title=audio-tracks")),
+        "a-en" => ("audio/mp4", generate_audio_mp4(440)),
+        "a-fr" => ("audio/mp4", generate_audio_mp4(660)),
+        "a-de" => {
+            GERMAN_REQUESTED.fetch_add(1, Ordering::SeqCst);
+            ("audio/mp4", generate_audio_mp4(880))
+        },
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+// The language tag, title and default disposition of each audio stream in the media file.
+fn audio_streams(path: &std::path::Path) -> Vec<(String, String, bool)> {
+    let ffprobe = Command::new("ffprobe")
+        .args(["-loglevel", "error",
+               "-select_streams", "a",
+               "-show_streams",
+               "-of", "json",
+               path.to_str().unwrap()])
+        .output()
+        .expect("spawning ffprobe");
+    assert!(ffprobe.status.success());
+    let parsed = jzon::parse(&String::from_utf8_lossy(&ffprobe.stdout)).unwrap();
+    parsed["streams"].members()
+        .map(|s| (s["tags"]["language"].as_str().unwrap_or("").to_string(),
+                  s["tags"]["title"].as_str().unwrap_or("").to_string(),
+                  s["disposition"]["default"] == 1))
+        .collect()
+}
+
+fn video_stream_count(path: &std::path::Path) -> usize {
+    let ffprobe = Command::new("ffprobe")
+        .args(["-loglevel", "error",
+               "-select_streams", "v",
+               "-show_entries", "stream=index",
+               "-of", "csv=p=0",
+               path.to_str().unwrap()])
+        .output()
+        .expect("spawning ffprobe");
+    assert!(ffprobe.status.success());
+    String::from_utf8_lossy(&ffprobe.stdout).lines().count()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_audio_tracks() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{rep}/{seg}", get(send_segment));
+    let listener = TcpListener::bind("127.0.0.1:6686").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+
+    let out = tmpd.child("two-tracks.mkv");
+    dash_mpd_cli()
+        .args(["--audio-tracks", "fr,en", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6686/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Added 1 audio tracks"))
+        .success();
+    assert_eq!(video_stream_count(out.path()), 1);
+    assert_eq!(audio_streams(out.path()), vec![
+        (String::from("fre"), String::from("Français"), true),
+        (String::from("eng"), String::from("English"), false),
+    ]);
+    assert_eq!(GERMAN_REQUESTED.load(Ordering::SeqCst), 0);
+    // The temporary files for the additional tracks have been removed.
+    let leftovers = fs::read_dir(tmpd.path())?.count();
+    assert_eq!(leftovers, 1);
+
+    let out = tmpd.child("all-tracks.mp4");
+    dash_mpd_cli()
+        .args(["--audio-tracks", "all", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6686/mpd"])
+        .assert()
+        .success();
+    let languages: Vec<String> = audio_streams(out.path()).into_iter()
+        .map(|(lang, _, _)| lang)
+        .collect();
+    assert_eq!(languages, vec!["eng", "fre", "ger"]);
+
+    dash_mpd_cli()
+        .args(["--audio-tracks", "fr", "--video-only", "http://localhost:6686/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("cannot be used with"))
+        .failure();
+    Ok(())
+}
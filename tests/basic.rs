@@ -14,7 +14,7 @@ use std::env;
 use ffprobe::ffprobe;
 use file_format::FileFormat;
 use predicates::prelude::*;
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use assert_fs::{prelude::*, TempDir};
 use test_log::test;
 use common::check_file_size_approx;
@@ -27,7 +27,7 @@ fn test_dl_mp4 () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("cf.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--write-subs",
@@ -52,7 +52,7 @@ fn test_dl_mp4a () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("sintel-audio.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-o", &out.to_string_lossy(), mpd])
         .assert()
         .success();
@@ -80,7 +80,7 @@ fn test_dl_audio_flac () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("bbcradio-flac.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "-o", &out.to_string_lossy(), mpd])
         .assert()
@@ -108,7 +108,7 @@ fn test_dl_cmaf () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("theo-cosmos.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "-o", &out.to_string_lossy(), mpd])
         .assert()
@@ -141,7 +141,7 @@ fn test_dl_timecode () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("dune-adtime.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "-o", &out.to_string_lossy(), mpd])
         .assert()
@@ -168,7 +168,7 @@ fn test_dl_timecode () {
 // The manifest contains minBufferTime="4S", which is an invalid format for an xs:Duration.
 #[test]
 fn test_parse_failure_duration () {
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["https://dash.akamaized.net/akamai/test/manifest3.mpd"])
         .assert()
         .stderr(predicate::str::contains("Download failed"))
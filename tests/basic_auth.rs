@@ -20,7 +20,7 @@ use std::env;
 use std::time::Duration;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use tokio::net::TcpListener;
 use axum::{routing::get, Router};
 use axum::extract::State;
@@ -145,7 +145,7 @@ async fn test_basic_auth() -> Result<()> {
     // were requested. Since we are in verbose mode, we make two requests for the init fragment, the
     // second request being to attempt to extract and print the PSSH.
     let outpath = env::temp_dir().join("basic_auth.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v", "-v", "-v",
                "--auth-username", "myuser", "--auth-password", "mypassword",
                "-o", outpath.to_str().unwrap(),
@@ -163,7 +163,7 @@ async fn test_basic_auth() -> Result<()> {
     // This time we make the request in quiet mode and we should see only a single additional
     // request to the init segment.
     let outpath = env::temp_dir().join("basic_auth2.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--quiet",
                "--auth-username", "myuser", "--auth-password", "mypassword",
                "-o", outpath.to_str().unwrap(),
@@ -0,0 +1,190 @@
+//! Tests for batch download mode (--batch-file) and parallel downloads (--jobs)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test batch -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest and our media segments.
+//
+//   - Write a batch file listing the manifest twice (with different output paths) and a third
+//   manifest URL that returns a 404 error.
+//
+//   - Check that the two valid items are downloaded, that the failing item doesn't abort the batch,
+//   and that the summary reports the status of each item. A batch file that lists a single
+//   manifest also gets a summary.
+//
+//   - Download from several manifests in parallel with --jobs, with and without a shared
+//   --limit-rate bandwidth.
+
+
+pub mod common;
+use fs_err as fs;
+use std::env;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use dash_mpd::{MPD, Period, AdaptationSet, Representation, SegmentTemplate};
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4;
+
+
+async fn send_mpd() -> impl IntoResponse {
+    let segment_template = SegmentTemplate {
+        initialization: Some("/media/f1.mp4".to_string()),
+        ..Default::default()
+    };
+    let rep = Representation {
+        id: Some("1".to_string()),
+        mimeType: Some("video/mp4".to_string()),
+        codecs: Some("avc1.640028".to_string()),
+        width: Some(1920),
+        height: Some(800),
+        bandwidth: Some(1980081),
+        SegmentTemplate: Some(segment_template),
+        ..Default::default()
+    };
+    let adapt = AdaptationSet {
+        id: Some("1".to_string()),
+        contentType: Some("video".to_string()),
+        representations: vec!(rep),
+        ..Default::default()
+    };
+    let period = Period {
+        id: Some("1".to_string()),
+        duration: Some(Duration::new(5, 0)),
+        adaptations: vec!(adapt),
+        ..Default::default()
+    };
+    let mpd = MPD {
+        mpdtype: Some("static".to_string()),
+        periods: vec!(period),
+        ..Default::default()
+    };
+    let xml = quick_xml::se::to_string(&mpd).unwrap();
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_mp4() -> Response {
+    let data = generate_minimal_mp4();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(data))
+        .unwrap()
+}
+
+async fn serve_manifest(port: u16) {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{seg}", get(send_mp4));
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}")).await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_batch_file() -> Result<()> {
+    serve_manifest(6670).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+    let out1 = tmpd.child("batch1.mp4");
+    let out2 = tmpd.child("batch2.mp4");
+    let batch = tmpd.child("batch.txt");
+    batch.write_str(&format!("# Test batch file\n\
+                              http://localhost:6670/mpd {}\n\
+                              \n\
+                              http://localhost:6670/missing.mpd\n\
+                              http://localhost:6670/mpd {}\n",
+                             out1.to_string_lossy(), out2.to_string_lossy()))?;
+    dash_mpd_cli()
+        .args(["--no-progress",
+               "--batch-file", &batch.to_string_lossy()])
+        .assert()
+        .stdout(predicate::str::contains("2 succeeded, 1 failed"))
+        .stderr(predicate::str::contains("missing.mpd"))
+        .failure();
+    assert!(fs::metadata(out1.path()).is_ok());
+    assert!(fs::metadata(out2.path()).is_ok());
+
+    let out3 = tmpd.child("batch3.mp4");
+    let single = tmpd.child("single.txt");
+    single.write_str(&format!("http://localhost:6670/mpd {}\n", out3.to_string_lossy()))?;
+    dash_mpd_cli()
+        .args(["--no-progress",
+               "--batch-file", &single.to_string_lossy()])
+        .assert()
+        .stdout(predicate::str::contains("1 succeeded, 0 failed"))
+        .success();
+    assert!(fs::metadata(out3.path()).is_ok());
+    Ok(())
+}
+
+
+// Several manifests given as positional arguments, downloaded in parallel.
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_parallel_jobs() -> Result<()> {
+    serve_manifest(6671).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+    let batch = tmpd.child("batch.txt");
+    let out2 = tmpd.child("parallel2.mp4");
+    let out3 = tmpd.child("parallel3.mp4");
+    batch.write_str(&format!("http://localhost:6671/mpd {}\n\
+                              http://localhost:6671/mpd {}\n",
+                             out2.to_string_lossy(), out3.to_string_lossy()))?;
+    dash_mpd_cli()
+        .current_dir(tmpd.path())
+        .args(["--jobs", "3",
+               "--progress", "json",
+               "--batch-file", &batch.to_string_lossy(),
+               "http://localhost:6671/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("\"job\": 1"))
+        .stderr(predicate::str::contains("\"job\": 3"))
+        .success();
+    assert!(fs::metadata(out2.path()).is_ok());
+    assert!(fs::metadata(out3.path()).is_ok());
+    Ok(())
+}
+
+
+// Parallel downloads sharing the --limit-rate bandwidth through the local throttling proxy.
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_parallel_shared_rate_limit() -> Result<()> {
+    serve_manifest(6696).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+    let out1 = tmpd.child("limited1.mp4");
+    let out2 = tmpd.child("limited2.mp4");
+    let batch = tmpd.child("batch.txt");
+    batch.write_str(&format!("http://localhost:6696/mpd {}\n\
+                              http://localhost:6696/mpd {}\n",
+                             out1.to_string_lossy(), out2.to_string_lossy()))?;
+    let mut cli = dash_mpd_cli();
+    for var in ["HTTP_PROXY", "HTTPS_PROXY", "ALL_PROXY", "http_proxy", "https_proxy", "all_proxy"] {
+        cli.env_remove(var);
+    }
+    cli.args(["--jobs", "2", "--limit-rate", "1M", "--no-progress",
+              "--batch-file", &batch.to_string_lossy()])
+        .assert()
+        .stderr(predicate::str::contains("each download is limited to").not())
+        .success();
+    assert!(fs::metadata(out1.path()).is_ok());
+    assert!(fs::metadata(out2.path()).is_ok());
+    Ok(())
+}
@@ -19,7 +19,7 @@ use std::env;
 use std::time::Duration;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use tokio::net::TcpListener;
 use axum::{routing::get, Router};
 use axum::extract::State;
@@ -147,7 +147,7 @@ async fn test_bearer_auth() -> Result<()> {
     // were requested. We expect 2 segment requests because our verbosity level of 2 means that the
     // init segment will be retrieved twice, one of those times to print the PSSH if it is present.
     let outpath = env::temp_dir().join("bearer_auth.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v", "-v", "-v",
                "--auth-bearer", "eyFoobles",
                "-o", outpath.to_str().unwrap(),
@@ -165,7 +165,7 @@ async fn test_bearer_auth() -> Result<()> {
     // This time we make the request in quiet mode and we should see only a single additional
     // request to the init segment.
     let outpath = env::temp_dir().join("bearer_auth2.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--quiet",
                "--auth-bearer", "eyFoobles",
                "-o", outpath.to_str().unwrap(),
@@ -0,0 +1,218 @@
+//! Tests for the builtin decryptor (--decryption-application builtin)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test builtin_decryption -- --show-output
+//
+// What happens in this test:
+//
+//   - Generate a fragmented MP4 video stream and a fragmented MP4 audio stream with ffmpeg, and
+//   encrypt each of them with mp4encrypt from Bento4 using both the cenc and the cbcs schemes, with
+//   different keys for video and audio.
+//
+//   - Start an axum HTTP server that serves a manifest whose video is encrypted with one scheme and
+//   audio with the other.
+//
+//   - Download with --decryption-application builtin and the keys given with --key, and check that
+//   the decoded video and audio of the output file are identical to those of the unencrypted
+//   streams. The cbcs download uses the builtin muxer with the external decryption applications
+//   pointing to nonexistent files.
+//
+//   - Check that a missing key makes the download fail with the decryption exit code.
+
+
+pub mod common;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+
+
+const VIDEO_KID: &str = "0a0b0c0d0e0f10111213141516171819";
+const VIDEO_KEY: &str = "3b6e1e1f6a2c4e4c8d3b1e2a4f5a6b7c";
+const AUDIO_KID: &str = "a1a2a3a4a5a6a7a8a9aaabacadaeafb0";
+const AUDIO_KEY: &str = "9f8e7d6c5b4a39281706f5e4d3c2b1a0";
+
+// The unencrypted stream, and its encryption with the cenc and cbcs schemes.
+struct Streams {
+    clear: PathBuf,
+    cenc: Vec<u8>,
+    cbcs: Vec<u8>,
+}
+
+fn encrypt(input: &std::path::Path, method: &str, kid: &str, key: &str) -> Vec<u8> {
+    let out = input.with_extension(format!("{method}.mp4"));
+    let mp4encrypt = Command::new("mp4encrypt")
+        .args(["--method", method,
+               "--key", &format!("1:{key}:random"),
+               "--property", &format!("1:KID:{kid}"),
+               input.to_str().unwrap(),
+               out.to_str().unwrap()])
+        .output()
+        .expect("spawning mp4encrypt");
+    assert!(mp4encrypt.status.success(), "{}", String::from_utf8_lossy(&mp4encrypt.stderr));
+    fs::read(out).unwrap()
+}
+
+fn generate(args: &[&str], name: &str, kid: &str, key: &str) -> Streams {
+    let clear = env::temp_dir().join(name);
+    let ffmpeg = Command::new("ffmpeg")
+        .args(["-nostdin", "-y"])
+        .args(args)
+        .args(["-movflags", "frag_keyframe+empty_moov+default_base_moof",
+               "-f", "mp4",
+               clear.to_str().unwrap()])
+        .output()
+        .expect("spawning ffmpeg");
+    assert!(ffmpeg.status.success(), "{}", String::from_utf8_lossy(&ffmpeg.stderr));
+    let cenc = encrypt(&clear, "MPEG-CENC", kid, key);
+    let cbcs = encrypt(&clear, "MPEG-CBCS", kid, key);
+    Streams { clear, cenc, cbcs }
+}
+
+fn stream(kind: &str) -> &'static Streams {
+    static VIDEO: OnceLock<Streams> = OnceLock::new();
+    static AUDIO: OnceLock<Streams> = OnceLock::new();
+    if kind == "video" {
+        VIDEO.get_or_init(|| generate(
+            &["-f", "lavfi", "-i", "testsrc=size=64x64:rate=10",
+              "-t", "2", "-c:v", "libx264", "-g", "10", "-pix_fmt", "yuv420p"],
+            "builtin-decryption-video.mp4", VIDEO_KID, VIDEO_KEY))
+    } else {
+        AUDIO.get_or_init(|| generate(
+            &["-f", "lavfi", "-i", "sine=frequency=440:sample_rate=48000",
+              "-t", "2", "-c:a", "aac", "-frag_duration", "1000000"],
+            "builtin-decryption-audio.mp4", AUDIO_KID, AUDIO_KEY))
+    }
+}
+
+// The manifest for /{scheme}/manifest.mpd has video encrypted with scheme, and audio with the other
+// scheme.
+async fn send_mpd(Path(scheme): Path<String>) -> impl IntoResponse {
+    let xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:cenc="urn:mpeg:cenc:2013"
+     type="static" mediaPresentationDuration="PT2S">
+  <Period id="p0" duration="PT2S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="{scheme}"
+                         cenc:default_KID="{VIDEO_KID}"/>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1" media="video.mp4"/>
+      <Representation id="v1" codecs="avc1.64000a" width="64" height="64" bandwidth="100000"/>
+    </AdaptationSet>
+    <AdaptationSet id="2" contentType="audio" mimeType="audio/mp4" lang="en">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011"
+                         cenc:default_KID="{AUDIO_KID}"/>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1" media="audio.mp4"/>
+      <Representation id="a1" codecs="mp4a.40.2" audioSamplingRate="48000" bandwidth="64000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#);
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_media(Path((scheme, name)): Path<(String, String)>) -> Response {
+    let video = name == "video.mp4";
+    let streams = stream(if video { "video" } else { "audio" });
+    let body = match (scheme.as_str(), video) {
+        ("cenc", true) | ("cbcs", false) => streams.cenc.clone(),
+        ("cbcs", true) | ("cenc", false) => streams.cbcs.clone(),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+// The MD5 hash of the decoded frames of the first video or audio stream of a media file.
+fn decoded_md5(path: &std::path::Path, kind: &str) -> String {
+    let ffmpeg = Command::new("ffmpeg")
+        .args(["-nostdin", "-loglevel", "error",
+               "-i", path.to_str().unwrap(),
+               "-map", &format!("0:{kind}:0"),
+               "-f", "md5", "-"])
+        .output()
+        .expect("spawning ffmpeg");
+    assert!(ffmpeg.status.success(), "{}", String::from_utf8_lossy(&ffmpeg.stderr));
+    String::from_utf8_lossy(&ffmpeg.stdout).trim().to_string()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_builtin_decryption() -> Result<()> {
+    let app = Router::new()
+        .route("/{scheme}/manifest.mpd", get(send_mpd))
+        .route("/{scheme}/{name}", get(send_media));
+    let listener = TcpListener::bind("127.0.0.1:6692").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+    let video_key = format!("{VIDEO_KID}:{VIDEO_KEY}");
+    let audio_key = format!("{AUDIO_KID}:{AUDIO_KEY}");
+    let clear_video = decoded_md5(&stream("video").clear, "v");
+    let clear_audio = decoded_md5(&stream("audio").clear, "a");
+
+    let out = tmpd.child("cenc.mp4");
+    dash_mpd_cli()
+        .args(["--decryption-application", "builtin",
+               "--key", &video_key,
+               "--key", &audio_key,
+               "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6692/cenc/manifest.mpd"])
+        .assert()
+        .stdout(predicate::str::contains("with the builtin decryptor"))
+        .success();
+    assert_eq!(decoded_md5(out.path(), "v"), clear_video);
+    assert_eq!(decoded_md5(out.path(), "a"), clear_audio);
+
+    let out = tmpd.child("cbcs.mp4");
+    dash_mpd_cli()
+        .args(["--decryption-application", "builtin",
+               "--muxer-preference", "mp4:builtin",
+               "--mp4decrypt-location", "/nonexistent/mp4decrypt",
+               "--shaka-packager-location", "/nonexistent/packager",
+               "--key", &video_key,
+               "--key", &audio_key,
+               "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6692/cbcs/manifest.mpd"])
+        .assert()
+        .success();
+    assert_eq!(decoded_md5(out.path(), "v"), clear_video);
+    assert_eq!(decoded_md5(out.path(), "a"), clear_audio);
+    assert_eq!(fs::read_dir(tmpd.path())?.count(), 2);
+
+    let out = tmpd.child("missing-key.mp4");
+    dash_mpd_cli()
+        .args(["--decryption-application", "builtin",
+               "--key", &video_key,
+               "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6692/cenc/manifest.mpd"])
+        .assert()
+        .stderr(predicate::str::contains(format!("no key for KID {AUDIO_KID}")))
+        .code(16);
+    assert!(!out.path().exists());
+    Ok(())
+}
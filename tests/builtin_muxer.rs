@@ -0,0 +1,216 @@
+//! Tests for the builtin fragmented MP4 muxer (--muxer-preference mp4:builtin)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test builtin_muxer -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with a video, an audio and a wvtt subtitle
+//   AdaptationSet. Each Representation has an initialization segment and two media segments of one
+//   second, cut from fragmented MP4 streams generated by ffmpeg.
+//
+//   - Download with --muxer-preference mp4:builtin and --write-subs, with the paths of the external
+//   muxing applications pointing to nonexistent files, and check with ffprobe that the output file
+//   contains the video, audio and subtitle streams with the expected duration, and that no
+//   temporary files are left behind.
+//
+//   - Check that the builtin muxer is rejected for other containers.
+
+
+pub mod common;
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::check_media_duration;
+
+
+// The initialization segment and the media segments of a fragmented MP4 stream.
+struct Segments {
+    init: Vec<u8>,
+    media: Vec<Vec<u8>>,
+}
+
+fn box_type(data: &[u8], pos: usize) -> (&[u8], usize) {
+    let size = u32::from_be_bytes(data[pos..pos+4].try_into().unwrap()) as usize;
+    (&data[pos+4..pos+8], size)
+}
+
+// Split a fragmented MP4 file into its initialization segment (ftyp and moov) and one media segment
+// per moof box with its mdat.
+fn split_fragments(data: &[u8]) -> Segments {
+    let mut init = Vec::new();
+    let mut media: Vec<Vec<u8>> = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (kind, size) = box_type(data, pos);
+        let content = &data[pos..pos+size];
+        match kind {
+            b"ftyp" | b"moov" => init.extend_from_slice(content),
+            b"moof" => media.push(content.to_vec()),
+            b"mdat" => media.last_mut().unwrap().extend_from_slice(content),
+            _ => (),
+        }
+        pos += size;
+    }
+    Segments { init, media }
+}
+
+fn ffmpeg_fragmented(args: &[&str], name: &str) -> Segments {
+    let tmp = env::temp_dir().join(name);
+    let ffmpeg = Command::new("ffmpeg")
+        .args(["-nostdin", "-y"])
+        .args(args)
+        .args(["-movflags", "frag_keyframe+empty_moov+default_base_moof",
+               "-f", "mp4",
+               tmp.to_str().unwrap()])
+        .output()
+        .expect("spawning ffmpeg");
+    assert!(ffmpeg.status.success(), "{}", String::from_utf8_lossy(&ffmpeg.stderr));
+    split_fragments(&fs::read(tmp).unwrap())
+}
+
+fn stream(name: &str) -> &'static Segments {
+    static VIDEO: OnceLock<Segments> = OnceLock::new();
+    static AUDIO: OnceLock<Segments> = OnceLock::new();
+    static SUBS: OnceLock<Segments> = OnceLock::new();
+    match name {
+        "video" => VIDEO.get_or_init(|| ffmpeg_fragmented(
+            &["-f", "lavfi", "-i", "testsrc=size=64x64:rate=10",
+              "-t", "2", "-c:v", "libx264", "-g", "10", "-pix_fmt", "yuv420p"],
+            "builtin-video.mp4")),
+        "audio" => AUDIO.get_or_init(|| ffmpeg_fragmented(
+            &["-f", "lavfi", "-i", "sine=frequency=440:sample_rate=48000",
+              "-t", "2", "-c:a", "aac", "-frag_duration", "1000000"],
+            "builtin-audio.mp4")),
+        _ => SUBS.get_or_init(|| {
+            let vtt = env::temp_dir().join("builtin-subs.vtt");
+            fs::write(&vtt, "WEBVTT\n\n00:00.200 --> 00:00.800\nFirst\n\n00:01.200 --> 00:01.800\nSecond\n").unwrap();
+            ffmpeg_fragmented(&["-i", vtt.to_str().unwrap(), "-c:s", "webvtt"], "builtin-subs.mp4")
+        }),
+    }
+}
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT2S">
+  <Period id="p0" duration="PT2S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="1" startNumber="1"
+                       initialization="media/video/init.mp4" media="media/video/$Number$.m4s"/>
+      <Representation id="v1" codecs="avc1.64000a" width="64" height="64" bandwidth="100000"/>
+    </AdaptationSet>
+    <AdaptationSet id="2" contentType="audio" mimeType="audio/mp4" lang="en">
+      <SegmentTemplate timescale="1" duration="1" startNumber="1"
+                       initialization="media/audio/init.mp4" media="media/audio/$Number$.m4s"/>
+      <Representation id="a1" codecs="mp4a.40.2" audioSamplingRate="48000" bandwidth="64000"/>
+    </AdaptationSet>
+    <AdaptationSet id="3" contentType="text" mimeType="application/mp4" lang="en">
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/subs/init.mp4" media="media/subs/$Number$.m4s"/>
+      <Representation id="s1" codecs="wvtt" bandwidth="256"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_segment(Path((kind, name)): Path<(String, String)>) -> Response {
+    let segments = stream(&kind);
+    let body = if name == "init.mp4" {
+        segments.init.clone()
+    } else {
+        let number: usize = name.trim_end_matches(".m4s").parse().unwrap_or(0);
+        // The subtitle track has a single media segment containing all its fragments.
+        if kind == "subs" && number == 1 {
+            segments.media.concat()
+        } else {
+            match segments.media.get(number.wrapping_sub(1)) {
+                Some(s) => s.clone(),
+                None => return StatusCode::NOT_FOUND.into_response(),
+            }
+        }
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+// The codec type of each stream in the media file.
+fn stream_types(path: &std::path::Path) -> Vec<String> {
+    let ffprobe = Command::new("ffprobe")
+        .args(["-loglevel", "error",
+               "-show_entries", "stream=codec_type",
+               "-of", "csv=p=0",
+               path.to_str().unwrap()])
+        .output()
+        .expect("spawning ffprobe");
+    assert!(ffprobe.status.success());
+    String::from_utf8_lossy(&ffprobe.stdout).lines().map(str::to_string).collect()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_builtin_muxer() -> Result<()> {
+    // The media fragments must be the ones expected by the manifest.
+    assert_eq!(stream("video").media.len(), 2);
+    assert_eq!(stream("audio").media.len(), 2);
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{kind}/{name}", get(send_segment));
+    let listener = TcpListener::bind("127.0.0.1:6691").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+
+    let out = tmpd.child("builtin.mp4");
+    dash_mpd_cli()
+        .args(["--muxer-preference", "mp4:builtin",
+               "--ffmpeg-location", "/nonexistent/ffmpeg",
+               "--vlc-location", "/nonexistent/vlc",
+               "--mkvmerge-location", "/nonexistent/mkvmerge",
+               "--mp4box-location", "/nonexistent/MP4Box",
+               "--write-subs", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6691/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Muxed 3 tracks into"))
+        .success();
+    assert_eq!(stream_types(out.path()), vec!["video", "audio", "subtitle"]);
+    check_media_duration(&out, 2.0);
+    let leftovers = fs::read_dir(tmpd.path())?.count();
+    assert_eq!(leftovers, 1);
+
+    dash_mpd_cli()
+        .args(["--muxer-preference", "mkv:builtin", "http://localhost:6691/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("only produces MP4 files"))
+        .failure();
+    dash_mpd_cli()
+        .args(["--muxer-preference", "mp4:ffmpeg,builtin", "http://localhost:6691/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("must be the first"))
+        .failure();
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Tests for adding chapters to the output file (--embed-chapters)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test chapters -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with three Periods of one second, each
+//   containing a single media segment. Two of the Periods have a descriptive Period@id, and one has
+//   a UUID, for which the chapter title is taken from the ProgramInformation element.
+//
+//   - Download with --embed-chapters, and check with ffprobe that the concatenated output file
+//   contains a chapter for each Period, with the expected titles.
+
+
+pub mod common;
+use std::env;
+use std::process::Command;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4_ffmpeg;
+
+
+fn period_xml(id: &str, start: u32) -> String {
+    format!(r#"
+  <Period id="{id}" start="PT{start}S" duration="PT1S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="1" startNumber="1" media="media/seg-$Number$.mp4"/>
+      <Representation id="v1" codecs="avc1.640028" width="10" height="10" bandwidth="100000"/>
+    </AdaptationSet>
+  </Period>"#)
+}
+
+async fn send_mpd() -> impl IntoResponse {
+    let periods = [
+        period_xml("opening-titles", 0),
+        period_xml("9a7f4c1e-2b3d-4e5f-8a9b-0c1d2e3f4a5b", 1),
+        period_xml("end-credits", 2),
+    ].concat();
+    let xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT3S">
+  <ProgramInformation>
+    <Title>Main feature</Title>
+  </ProgramInformation>{periods}
+</MPD>"#);
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_segment() -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4_ffmpeg("title=chapters")))
+        .unwrap()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_embed_chapters() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{seg}", get(send_segment));
+    let listener = TcpListener::bind("127.0.0.1:6682").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+    let out = tmpd.child("chapters.mp4");
+
+    dash_mpd_cli()
+        .args(["--embed-chapters", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6682/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Added 3 chapters"))
+        .success();
+    let ffprobe = Command::new("ffprobe")
+        .args(["-loglevel", "error",
+               "-show_chapters",
+               "-of", "json",
+               out.to_str().unwrap()])
+        .output()
+        .expect("spawning ffprobe");
+    assert!(ffprobe.status.success());
+    let parsed = jzon::parse(&String::from_utf8_lossy(&ffprobe.stdout)).unwrap();
+    let titles: Vec<&str> = parsed["chapters"].members()
+        .map(|c| c["tags"]["title"].as_str().unwrap())
+        .collect();
+    assert_eq!(titles, vec!["opening-titles", "Main feature (2)", "end-credits"]);
+    let starts: Vec<&str> = parsed["chapters"].members()
+        .map(|c| c["start_time"].as_str().unwrap())
+        .collect();
+    assert_eq!(starts, vec!["0.000000", "1.000000", "2.000000"]);
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! Tests for ClearKey license acquisition from the clearkey:Laurl of the manifest
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test clearkey -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest whose video and audio streams are protected
+//   with ClearKey, with a clearkey:Laurl pointing to a license server stub on the same server. The
+//   second video Representation has its own KID. The license server requires bearer
+//   authentication, checks the JSON license request, and returns a JSON Web Key Set with the key for
+//   the first video KID only.
+//
+//   - Run in simulation mode with --auth-bearer, and check that the license request lists the KIDs
+//   of every Representation, that the key obtained is reported, and that a warning about the
+//   missing audio key is printed.
+//
+//   - Check that the license server is only asked for the KIDs that have no key given with --key,
+//   and that a failed license request makes the download fail.
+
+
+pub mod common;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use axum::{routing::{get, post}, Router, Json};
+use axum::response::IntoResponse;
+use axum::http::{header, StatusCode};
+use axum_auth::AuthBearer;
+use serde_json::{json, Value};
+use anyhow::Result;
+use test_log::test;
+
+
+const VIDEO_KID: &str = "0a0b0c0d0e0f10111213141516171819";
+const AUDIO_KID: &str = "a1a2a3a4a5a6a7a8a9aaabacadaeafb0";
+const AUDIO_KEY: &str = "9f8e7d6c5b4a39281706f5e4d3c2b1a0";
+// The video KID and key 3b6e1e1f6a2c4e4c8d3b1e2a4f5a6b7c, in base64url.
+const VIDEO_KID_B64: &str = "CgsMDQ4PEBESExQVFhcYGQ";
+const VIDEO_KEY_B64: &str = "O24eH2osTkyNOx4qT1prfA";
+const AUDIO_KID_B64: &str = "oaKjpKWmp6ipqqusra6vsA";
+// The KID b1b2b3b4b5b6b7b8b9babbbcbdbebfc0 of the second video Representation, in base64url.
+const VIDEO2_KID_B64: &str = "sbKztLW2t7i5uru8vb6_wA";
+
+// The KIDs listed in the last license request.
+static REQUESTED_KIDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:cenc="urn:mpeg:cenc:2013"
+     xmlns:clearkey="http://dashif.org/guidelines/clearKey"
+     type="static" mediaPresentationDuration="PT4S">
+  <Period id="p0" duration="PT4S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cenc"
+                         cenc:default_KID="0a0b0c0d-0e0f-1011-1213-141516171819"/>
+      <ContentProtection schemeIdUri="urn:uuid:e2719d58-a985-b3c9-781a-b030af78d30e" value="ClearKey1.0">
+        <clearkey:Laurl Lic_type="EME-1.0">license</clearkey:Laurl>
+      </ContentProtection>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1" media="video-$Number$.mp4"/>
+      <Representation id="v1" codecs="avc1.64000a" width="64" height="64" bandwidth="100000"/>
+      <Representation id="v2" codecs="avc1.64001f" width="128" height="128" bandwidth="200000">
+        <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cenc"
+                           cenc:default_KID="b1b2b3b4-b5b6-b7b8-b9ba-bbbcbdbebfc0"/>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet id="2" contentType="audio" mimeType="audio/mp4" lang="en">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cenc"
+                         cenc:default_KID="a1a2a3a4-a5a6-a7a8-a9aa-abacadaeafb0"/>
+      <ContentProtection schemeIdUri="urn:uuid:e2719d58-a985-b3c9-781a-b030af78d30e" value="ClearKey1.0">
+        <clearkey:Laurl Lic_type="EME-1.0">http://localhost:6694/license</clearkey:Laurl>
+      </ContentProtection>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1" media="audio-$Number$.mp4"/>
+      <Representation id="a1" codecs="mp4a.40.2" audioSamplingRate="48000" bandwidth="64000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+// A ClearKey license server that only has the key for the video KID.
+async fn send_license(AuthBearer(token): AuthBearer, Json(request): Json<Value>) -> impl IntoResponse {
+    if token != "ClearKeyToken" {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    assert_eq!(request["type"], "temporary");
+    let kids: Vec<String> = request["kids"].as_array()
+        .expect("kids in license request")
+        .iter()
+        .map(|kid| kid.as_str().unwrap().to_string())
+        .collect();
+    *REQUESTED_KIDS.lock().unwrap() = kids.clone();
+    let keys: Vec<Value> = kids.iter()
+        .filter(|kid| *kid == VIDEO_KID_B64)
+        .map(|kid| json!({ "kty": "oct", "kid": kid, "k": VIDEO_KEY_B64 }))
+        .collect();
+    Json(json!({ "keys": keys, "type": "temporary" })).into_response()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_clearkey() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/license", post(send_license))
+        .route("/{segment}", get(|| async { StatusCode::NOT_FOUND }));
+    let listener = TcpListener::bind("127.0.0.1:6694").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    dash_mpd_cli()
+        .args(["--auth-bearer", "ClearKeyToken",
+               "--simulate", "--no-version-check",
+               "http://localhost:6694/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Obtained 1 decryption keys from ClearKey license server http://localhost:6694/license"))
+        .stderr(predicate::str::contains(format!("No decryption key for KID {AUDIO_KID} of the audio stream")))
+        .stderr(predicate::str::contains(VIDEO_KID).not())
+        .success();
+    assert_eq!(*REQUESTED_KIDS.lock().unwrap(), vec![VIDEO_KID_B64, VIDEO2_KID_B64, AUDIO_KID_B64]);
+
+    dash_mpd_cli()
+        .args(["--auth-bearer", "ClearKeyToken",
+               "--key", &format!("{AUDIO_KID}:{AUDIO_KEY}"),
+               "--simulate", "--no-version-check",
+               "http://localhost:6694/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("No decryption key").not())
+        .success();
+    assert_eq!(*REQUESTED_KIDS.lock().unwrap(), vec![VIDEO_KID_B64, VIDEO2_KID_B64]);
+
+    dash_mpd_cli()
+        .args(["--auth-bearer", "WrongToken",
+               "--simulate", "--no-version-check",
+               "http://localhost:6694/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("requesting ClearKey license from http://localhost:6694/license"))
+        .failure();
+    Ok(())
+}
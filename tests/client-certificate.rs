@@ -34,13 +34,14 @@
 //
 
 
+pub mod common;
 use fs_err as fs;
 use std::net::SocketAddr;
 use std::time::Duration;
 use std::io::BufReader;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use axum::{routing::get, Router};
 use axum::extract::State;
 use axum::response::{Response, IntoResponse};
@@ -197,7 +198,7 @@ async fn test_add_client_identity() -> Result<(), anyhow::Error> {
 
     // Without the --client-identity-certificate, should see an error from dash-mpd-cli due to the
     // server refusing the connection (with rustls, is "channel closed").
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--add-root-certificate", "tests/fixtures/root-CA.crt",
                "https://localhost:6666/mpd"])
         .assert()
@@ -208,7 +209,7 @@ async fn test_add_client_identity() -> Result<(), anyhow::Error> {
     // arrays, and we don't want to implement the code to parse out the different keys and
     // certificates from a single file).
     #[cfg(feature = "rustls")]
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v", "-v", "-v",
                "--add-root-certificate", "tests/fixtures/root-CA.crt",
                "--client-identity-certificate", "tests/fixtures/client-id.pem",
@@ -0,0 +1,215 @@
+//! Tests for downloading a section of the content (--start-time and --end-time)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test clip -- --show-output
+//
+// What happens in these tests:
+//
+//   - Start an axum HTTP server that serves a manifest using SegmentTemplate addressing, with ten
+//   media segments of 2 seconds, and a manifest using SegmentBase addressing, whose single media
+//   file has a Segment Index (sidx) box listing five subsegments of 4 seconds.
+//
+//   - Download a section of each stream, and check that only the media segments (or the byte ranges
+//   of the subsegments) that overlap the requested section were requested.
+//
+//   - Check that a warning is printed for a SegmentBase manifest without an index range, which can
+//   only be downloaded in full.
+
+
+pub mod common;
+use fs_err as fs;
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4;
+
+
+static SEGMENTS_REQUESTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static RANGES_REQUESTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Byte ranges in the single media file of the SegmentBase manifest.
+const INIT_RANGE: &str = "0-799";
+const INDEX_START: u64 = 800;
+const SUBSEGMENT_SIZE: u32 = 1000;
+
+fn template_mpd() -> String {
+    String::from(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT20S">
+  <Period id="1" duration="PT20S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/init.mp4" media="media/seg-$Number$.mp4"/>
+      <Representation id="v1" codecs="avc1.640028" width="1920" height="800" bandwidth="1980081"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#)
+}
+
+// A version 0 sidx box with a timescale of 1000 and five subsegments of 4 seconds.
+fn sidx_box() -> Vec<u8> {
+    let mut sidx = Vec::new();
+    sidx.extend_from_slice(&[0, 0, 0, 0]);           // version and flags
+    sidx.extend_from_slice(&1u32.to_be_bytes());     // reference_ID
+    sidx.extend_from_slice(&1000u32.to_be_bytes());  // timescale
+    sidx.extend_from_slice(&0u32.to_be_bytes());     // earliest_presentation_time
+    sidx.extend_from_slice(&0u32.to_be_bytes());     // first_offset
+    sidx.extend_from_slice(&[0, 0, 0, 5]);           // reserved and reference_count
+    for _ in 0..5 {
+        sidx.extend_from_slice(&SUBSEGMENT_SIZE.to_be_bytes());
+        sidx.extend_from_slice(&4000u32.to_be_bytes());
+        sidx.extend_from_slice(&0x9000_0000u32.to_be_bytes());
+    }
+    let mut data = (sidx.len() as u32 + 8).to_be_bytes().to_vec();
+    data.extend_from_slice(b"sidx");
+    data.extend_from_slice(&sidx);
+    data
+}
+
+fn index_range() -> String {
+    format!("{INDEX_START}-{}", INDEX_START + sidx_box().len() as u64 - 1)
+}
+
+fn indexed_mpd() -> String {
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT20S">
+  <Period id="1" duration="PT20S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <Representation id="v1" codecs="avc1.640028" width="1920" height="800" bandwidth="1980081">
+        <BaseURL>media/single.mp4</BaseURL>
+        <SegmentBase indexRange="{}">
+          <Initialization range="{INIT_RANGE}"/>
+        </SegmentBase>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#, index_range())
+}
+
+// The SegmentBase manifest, without a Segment Index.
+fn unindexed_mpd() -> String {
+    indexed_mpd().replace(&format!(r#" indexRange="{}""#, index_range()), "")
+}
+
+async fn send_template_mpd() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/dash+xml")], template_mpd())
+}
+
+async fn send_indexed_mpd() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/dash+xml")], indexed_mpd())
+}
+
+async fn send_unindexed_mpd() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/dash+xml")], unindexed_mpd())
+}
+
+async fn send_segment(Path(seg): Path<String>) -> Response {
+    SEGMENTS_REQUESTED.lock().unwrap().push(seg);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4()))
+        .unwrap()
+}
+
+async fn send_single(headers: HeaderMap) -> Response {
+    let range = headers.get(header::RANGE)
+        .and_then(|r| r.to_str().ok())
+        .and_then(|r| r.strip_prefix("bytes="))
+        .unwrap_or("")
+        .to_string();
+    let body = if range == index_range() {
+        sidx_box()
+    } else {
+        RANGES_REQUESTED.lock().unwrap().push(range.clone());
+        generate_minimal_mp4()
+    };
+    Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .header(header::CONTENT_RANGE, format!("bytes {range}/*"))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_clip() -> Result<()> {
+    let app = Router::new()
+        .route("/template.mpd", get(send_template_mpd))
+        .route("/indexed.mpd", get(send_indexed_mpd))
+        .route("/unindexed.mpd", get(send_unindexed_mpd))
+        .route("/media/single.mp4", get(send_single))
+        .route("/media/{seg}", get(send_segment));
+    let listener = TcpListener::bind("127.0.0.1:6678").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+
+    // Segments 3, 4 and 5 cover [4, 10) seconds.
+    let out = tmpd.child("template-clip.mp4");
+    dash_mpd_cli()
+        .args(["--start-time", "5", "--end-time", "00:00:09", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6678/template.mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Downloading 6.0s of content starting at 4.0s"))
+        .success();
+    assert!(fs::metadata(out.path()).is_ok());
+    let mut requested = SEGMENTS_REQUESTED.lock().unwrap().clone();
+    requested.sort();
+    assert_eq!(requested, vec!["init.mp4", "seg-3.mp4", "seg-4.mp4", "seg-5.mp4"]);
+
+    // Subsegments 3 and 4 cover [8, 16) seconds.
+    let out = tmpd.child("indexed-clip.mp4");
+    dash_mpd_cli()
+        .args(["--start-time", "9", "--end-time", "13", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6678/indexed.mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Downloading 8.0s of content starting at 8.0s"))
+        .success();
+    assert!(fs::metadata(out.path()).is_ok());
+    let first_media = INDEX_START + sidx_box().len() as u64;
+    let subsegment = |n: u64| {
+        let first = first_media + (n - 1) * u64::from(SUBSEGMENT_SIZE);
+        format!("{first}-{}", first + u64::from(SUBSEGMENT_SIZE) - 1)
+    };
+    let requested = RANGES_REQUESTED.lock().unwrap().clone();
+    assert_eq!(requested, vec![String::from(INIT_RANGE), subsegment(3), subsegment(4)]);
+
+    // Without an index range, the Representation can't be clipped.
+    dash_mpd_cli()
+        .args(["--start-time", "9", "--end-time", "13", "--simulate",
+               "-o", &tmpd.child("unindexed.mp4").to_string_lossy(),
+               "http://localhost:6678/unindexed.mpd"])
+        .assert()
+        .stderr(predicate::str::contains("Representation v1 has no segment index"))
+        .success();
+
+    // An end time that precedes the start time is rejected.
+    dash_mpd_cli()
+        .args(["--start-time", "10", "--end-time", "5",
+               "-o", &tmpd.child("invalid.mp4").to_string_lossy(),
+               "http://localhost:6678/template.mpd"])
+        .assert()
+        .failure();
+    Ok(())
+}
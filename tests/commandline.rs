@@ -4,13 +4,15 @@
 //
 //    cargo test --test commandline -- --show-output
 
+pub mod common;
 use predicates::prelude::*;
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
 use test_log::test;
 
 #[test]
 fn test_command_spurious () {
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--spurious-option",
               "https://example.org/mpd"])
         .assert()
@@ -21,7 +23,7 @@ fn test_command_spurious () {
 
 #[test]
 fn test_command_mpd_missing () {
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--verbose"])
         .assert()
         .stderr(predicate::str::contains("following required arguments were not provided"))
@@ -31,7 +33,7 @@ fn test_command_mpd_missing () {
 
 #[test]
 fn test_command_have_help () {
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--help"])
         .assert()
         .stdout(predicate::str::contains("--help"))
@@ -43,7 +45,7 @@ fn test_command_have_help () {
 
 #[test]
 fn test_command_missing_file () {
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--add-root-certificate", "/missing/file",
                "https://example.org/ignored.mpd"])
         .assert()
@@ -54,7 +56,7 @@ fn test_command_missing_file () {
 
 #[test]
 fn test_command_missing_value () {
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--max-error-count",
                "https://example.org/ignored.mpd"])
         .assert()
@@ -65,7 +67,7 @@ fn test_command_missing_value () {
 
 #[test]
 fn test_command_funky_source () {
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--source-address", "33.44.55.66.77",
                "https://example.org/ignored.mpd"])
         .assert()
@@ -75,7 +77,7 @@ fn test_command_funky_source () {
 
 #[test]
 fn test_command_bad_rate () {
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--limit-rate", "42Z",
                "https://example.org/ignored.mpd"])
         .assert()
@@ -84,3 +86,69 @@ fn test_command_bad_rate () {
 }
 
 
+
+#[test]
+fn test_command_config_profile () {
+    let tmpd = TempDir::new().unwrap();
+    tmpd.child("dash-mpd-cli/config.toml")
+        .write_str(r#"[default]
+user-agent = "MyFakeUserAgent/42.0"
+header = ["X-Twizzles: extra"]
+
+[profile.archive]
+quality = "best"
+write-subs = true
+"#).unwrap();
+    dash_mpd_cli()
+        .env("XDG_CONFIG_HOME", tmpd.path())
+        .args(["--profile", "archive",
+               "--quality", "worst",
+               "--print-config"])
+        .assert()
+        .stdout(predicate::str::contains(r#"user-agent = "MyFakeUserAgent/42.0"  # from configuration file"#))
+        .stdout(predicate::str::contains(r#"header = ["X-Twizzles: extra"]  # from configuration file"#))
+        .stdout(predicate::str::contains("write-subs = true  # from configuration file"))
+        .stdout(predicate::str::contains("quality = \"worst\"\n"))
+        .success();
+    dash_mpd_cli()
+        .env("XDG_CONFIG_HOME", tmpd.path())
+        .args(["--profile", "missing",
+               "https://example.org/ignored.mpd"])
+        .assert()
+        .stderr(predicate::str::contains("profile missing not found"))
+        .failure();
+}
+
+#[test]
+fn test_command_exit_codes () {
+    dash_mpd_cli()
+        .args(["--base-url", "not-a-url",
+               "https://example.org/ignored.mpd"])
+        .assert()
+        .stderr(predicate::str::contains("Invalid URL for --base-url"))
+        .code(9);
+    // Nothing is listening on this port, so the request for the manifest fails with a connection error.
+    dash_mpd_cli()
+        .args(["--no-version-check",
+               "http://localhost:6679/ignored.mpd"])
+        .assert()
+        .stderr(predicate::str::contains("Download failed"))
+        .code(10);
+    // Options that make us fetch and analyze the manifest before the download keep the class of
+    // the error.
+    dash_mpd_cli()
+        .args(["--no-version-check", "--periods", "1",
+               "http://localhost:6679/ignored.mpd"])
+        .assert()
+        .stderr(predicate::str::contains("Download failed"))
+        .code(10);
+    let tmpd = TempDir::new().unwrap();
+    let broken = tmpd.child("broken.mpd");
+    broken.write_str("<MPD><Period></MPD>").unwrap();
+    let url = url::Url::from_file_path(broken.path()).unwrap();
+    dash_mpd_cli()
+        .args(["--no-version-check", "--skip-ads", url.as_str()])
+        .assert()
+        .stderr(predicate::str::contains("Download failed"))
+        .code(12);
+}
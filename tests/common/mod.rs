@@ -16,6 +16,14 @@ lazy_static! {
     static ref TRACING_INIT: Once = Once::new();
 }
 
+// Run dash-mpd-cli with XDG_CONFIG_HOME pointing to a directory that has no configuration file, so
+// that the user's own configuration file can't change the behaviour being tested.
+pub fn dash_mpd_cli() -> assert_cmd::Command {
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!();
+    cmd.env("XDG_CONFIG_HOME", concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"));
+    cmd
+}
+
 pub fn setup_logging() {
     use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
@@ -5,13 +5,14 @@
 //    cargo test --test conformity -- --show-output
 
 
+pub mod common;
 use fs_err as fs;
 use std::env;
 use std::path::PathBuf;
 use std::time::Duration;
 use tokio::net::TcpListener;
 use predicates::prelude::*;
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use axum::{routing::get, Router};
 use axum::http::header;
 use test_log::test;
@@ -22,7 +23,7 @@ fn test_conformity_empty_period() {
     // This manifest contains an empty Period. Periods should have at least one AdaptationSet.
     let mpd = "http://download.tsi.telecom-paristech.fr/gpac/DASH_CONFORMANCE/TelecomParisTech/advanced/invalid_empty_period.mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -36,7 +37,7 @@ fn test_conformity_group_string() {
     // This manifest contains <AdaptationSet group="notAnInteger">
     let mpd = "http://download.tsi.telecom-paristech.fr/gpac/DASH_CONFORMANCE/TelecomParisTech/advanced/invalid_group_string.mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -49,7 +50,7 @@ fn test_conformity_group_string() {
 fn test_conformity_maxheight () {
     let mpd = "http://download.tsi.telecom-paristech.fr/gpac/DASH_CONFORMANCE/TelecomParisTech/advanced/invalid_maxHeight.mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -63,7 +64,7 @@ fn test_conformity_maxheight () {
 fn test_conformity_invalid_maxwidth() {
     let mpd = "http://download.tsi.telecom-paristech.fr/gpac/DASH_CONFORMANCE/TelecomParisTech/advanced/invalid_maxWidth.mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -79,7 +80,7 @@ fn test_conformity_invalid_maxwidth() {
 fn test_conformity_invalid_maxheight() {
     let mpd = "https://vod.infiniteplatform.tv/dash/vod-clear/ElephantsDream/default.mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -93,7 +94,7 @@ fn test_conformity_invalid_maxheight() {
 fn test_conformity_invalid_segment_duration() {
     let mpd = "http://download.tsi.telecom-paristech.fr/gpac/DASH_CONFORMANCE/TelecomParisTech/advanced/invalid_segmenttimeline_maxsegduration.mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -107,7 +108,7 @@ fn test_conformity_invalid_segment_duration() {
 fn test_conformity_time_initialization() {
     let mpd = "http://download.tsi.telecom-paristech.fr/gpac/DASH_CONFORMANCE/TelecomParisTech/advanced/invalid_segmenttemplate_init_time.mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -120,7 +121,7 @@ fn test_conformity_time_initialization() {
 fn test_conformity_number_initialization() {
     let mpd = "http://download.tsi.telecom-paristech.fr/gpac/DASH_CONFORMANCE/TelecomParisTech/advanced/invalid_segmenttemplate_init_number.mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -133,7 +134,7 @@ fn test_conformity_number_initialization() {
 fn test_conformity_segmenttimeline_duration() {
     let mpd = "http://download.tsi.telecom-paristech.fr/gpac/DASH_CONFORMANCE/TelecomParisTech/advanced/invalid_segmenttimeline_duration.mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -146,7 +147,7 @@ fn test_conformity_segmenttimeline_duration() {
 fn test_conformity_segmenttimeline_number_time() {
     let mpd = "http://download.tsi.telecom-paristech.fr/gpac/DASH_CONFORMANCE/TelecomParisTech/advanced/invalid_segmenttimeline_number_time.mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -181,7 +182,7 @@ async fn test_conformity_invalid_maxsegmentduration() {
 
     let mpd = "http://localhost:6666/mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -218,7 +219,7 @@ async fn test_conformity_invalid_sourceurl() {
 
     let mpd = "http://localhost:6661/mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -251,7 +252,7 @@ async fn test_conformity_invalid_segmenturl() {
 
     let mpd = "http://localhost:6662/mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
@@ -276,7 +277,7 @@ async fn test_conformity_invalid_moreinformation() {
 
     let mpd = "http://localhost:6663/mpd";
     let outpath = env::temp_dir().join("empty.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--simulate",
                "-o", &outpath.to_string_lossy(), mpd])
         .assert()
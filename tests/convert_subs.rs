@@ -0,0 +1,164 @@
+//! Tests for subtitle format conversion (--convert-subs)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test convert_subs -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with two Periods of one second. Each Period
+//   contains a video AdaptationSet, an English sidecar WebVTT subtitle file and a French sidecar TTML
+//   subtitle file, whose cue times are relative to the start of the Period. The TTML uses tick-based
+//   times, an italic style and a region at the top of the screen.
+//
+//   - Download with --sub-langs en,fr --convert-subs srt, and check that the SRT files contain the
+//   cues of both Periods, with the cues of the second Period shifted by the duration of the first,
+//   and the italics and top position carried over.
+//
+//   - Download with --convert-subs vtt and --convert-subs ass, and check the conversion of the TTML
+//   subtitles to these formats.
+
+
+pub mod common;
+use std::env;
+use std::fs;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4_ffmpeg;
+
+
+fn period_xml(n: u32) -> String {
+    format!(r#"
+  <Period id="p{n}" duration="PT1S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="1" startNumber="1" media="media/video.mp4"/>
+      <Representation id="v1" codecs="avc1.640028" width="10" height="10" bandwidth="100000"/>
+    </AdaptationSet>
+    <AdaptationSet id="2" contentType="text" mimeType="text/vtt" lang="en">
+      <Representation id="sub-en" bandwidth="256">
+        <BaseURL>subs/en-{n}.vtt</BaseURL>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet id="3" contentType="text" mimeType="application/ttml+xml" lang="fr">
+      <Representation id="sub-fr" bandwidth="256">
+        <BaseURL>subs/fr-{n}.ttml</BaseURL>
+      </Representation>
+    </AdaptationSet>
+  </Period>"#)
+}
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT2S">{}{}
+</MPD>"#, period_xml(1), period_xml(2));
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_video() -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4_ffmpeg("title=convert")))
+        .unwrap()
+}
+
+const TTML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tt xmlns="http://www.w3.org/ns/ttml" xmlns:tts="http://www.w3.org/ns/ttml#styling"
+    xmlns:ttp="http://www.w3.org/ns/ttml#parameter" ttp:tickRate="10000000" xml:lang="fr">
+  <head>
+    <styling>
+      <style xml:id="italic" tts:fontStyle="italic"/>
+    </styling>
+    <layout>
+      <region xml:id="top" tts:origin="10% 5%" tts:extent="80% 20%"/>
+      <region xml:id="bottom" tts:origin="10% 70%" tts:extent="80% 20%" tts:displayAlign="after"/>
+    </layout>
+  </head>
+  <body region="bottom">
+    <div>
+      <p begin="2000000t" end="8000000t">TEXT</p>
+    </div>
+  </body>
+</tt>"#;
+
+async fn send_subs(Path(name): Path<String>) -> Response {
+    let (content_type, body) = match name.as_str() {
+        "en-1.vtt" => ("text/vtt", String::from("WEBVTT\n\n00:00.200 --> 00:00.800\nFirst <i>cue</i>\n")),
+        "en-2.vtt" => ("text/vtt", String::from("WEBVTT\n\n00:00.500 --> 00:00.900\nSecond &amp; last\n")),
+        "fr-1.ttml" => ("application/ttml+xml",
+                        TTML.replace("TEXT", r#"<span style="italic">Première</span> réplique"#)),
+        "fr-2.ttml" => ("application/ttml+xml",
+                        TTML.replace("<p begin", r#"<p region="top" begin"#)
+                        .replace("TEXT", "En haut<br/>deux lignes")),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_convert_subs() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/video.mp4", get(send_video))
+        .route("/subs/{name}", get(send_subs));
+    let listener = TcpListener::bind("127.0.0.1:6688").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+
+    let out = tmpd.child("converted.mp4");
+    dash_mpd_cli()
+        .args(["--sub-langs", "en,fr", "--convert-subs", "srt", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6688/mpd"])
+        .assert()
+        .success();
+    let en = fs::read_to_string(tmpd.path().join("converted.en.srt"))?;
+    assert_eq!(en, "1\n00:00:00,200 --> 00:00:00,800\nFirst <i>cue</i>\n\n\
+                    2\n00:00:01,500 --> 00:00:01,900\nSecond & last\n\n");
+    let fr = fs::read_to_string(tmpd.path().join("converted.fr.srt"))?;
+    assert_eq!(fr, "1\n00:00:00,200 --> 00:00:00,800\n<i>Première</i> réplique\n\n\
+                    2\n00:00:01,200 --> 00:00:01,800\n{\\an8}En haut\ndeux lignes\n\n");
+    assert!(!tmpd.path().join("converted.fr.ttml").exists());
+
+    let out = tmpd.child("webvtt.mp4");
+    dash_mpd_cli()
+        .args(["--sub-langs", "fr", "--convert-subs", "vtt", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6688/mpd"])
+        .assert()
+        .success();
+    let fr = fs::read_to_string(tmpd.path().join("webvtt.fr.vtt"))?;
+    assert!(fr.starts_with("WEBVTT\n\n"));
+    assert!(fr.contains("00:00:00.200 --> 00:00:00.800 line:90%,end\n<i>Première</i> réplique\n"));
+    assert!(fr.contains("00:00:01.200 --> 00:00:01.800 line:5%\nEn haut\ndeux lignes\n"));
+
+    let out = tmpd.child("ass.mkv");
+    dash_mpd_cli()
+        .args(["--sub-langs", "fr", "--convert-subs", "ass", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6688/mpd"])
+        .assert()
+        .success();
+    let fr = fs::read_to_string(tmpd.path().join("ass.fr.ass"))?;
+    assert!(fr.starts_with("[Script Info]"));
+    assert!(fr.contains("Dialogue: 0,0:00:00.20,0:00:00.80,Default,,0,0,29,,{\\i1}Première{\\i0} réplique\n"));
+    assert!(fr.contains("Dialogue: 0,0:00:01.20,0:00:01.80,Default,,0,0,14,,{\\an8}En haut\\Ndeux lignes\n"));
+    Ok(())
+}
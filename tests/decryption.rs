@@ -14,7 +14,7 @@ use std::env;
 use std::process::Command;
 use ffprobe::ffprobe;
 use file_format::FileFormat;
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use assert_fs::{prelude::*, TempDir};
 use test_log::test;
 use common::check_file_size_approx;
@@ -29,7 +29,7 @@ fn test_decryption_widevine_cenc () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("spring.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--ignore-content-type",
@@ -53,7 +53,7 @@ fn test_decryption_widevine_cbcs () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("tears-steel.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--ignore-content-type",
@@ -78,7 +78,7 @@ fn test_decryption_playready_cenc () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("llama.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--ignore-content-type",
@@ -101,7 +101,7 @@ fn test_decryption_marlin_cenc () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("llama-cenc.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--ignore-content-type",
@@ -124,7 +124,7 @@ fn test_decryption_marlin_cbcs () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("llama-cbcs.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--ignore-content-type",
@@ -148,7 +148,7 @@ fn test_decryption_cmaf_h265_multikey () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("axinom-h264-multikey.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--key", "53dc3eaa5164410a8f4ee15113b43040:620045a34e839061ee2e9b7798fdf89b",
@@ -173,7 +173,7 @@ fn test_decryption_cenc_kaltura () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("kaltura.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--mp4decrypt-location", "mp4decrypt",
                "--key", "a07c5d499dcead0fb416fed5913967be:caee457911302478487e6680bf0b3d1b",
                "-o", &out.to_string_lossy(), mpd])
@@ -205,7 +205,7 @@ fn test_decryption_webm() {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("angel.webm");
-    let cli = cargo_bin_cmd!()
+    let cli = dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--decryption-application", "shaka",
@@ -267,7 +267,7 @@ fn test_decryption_small_shaka () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("cf-shaka.mp4");
-    let cli = cargo_bin_cmd!()
+    let cli = dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--decryption-application", "shaka",
@@ -0,0 +1,130 @@
+//! Tests for the JSON description of the manifest and the selected streams (--dump-json)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test dump -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with three video Representations and two
+//   audio AdaptationSets in different languages, one of which is encrypted. Each Representation has
+//   five media segments of 2 seconds.
+//
+//   - Run with --dump-json and different stream preferences, and check that stdout contains only the
+//   JSON document, describing the Representations that were chosen and why, their segment URLs and
+//   their estimated size. Check that no media segments are requested.
+
+
+pub mod common;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use common::dash_mpd_cli;
+use axum::{routing::get, Router};
+use axum::response::IntoResponse;
+use axum::http::{header, StatusCode};
+use anyhow::Result;
+use test_log::test;
+
+
+static SEGMENTS_REQUESTED: AtomicUsize = AtomicUsize::new(0);
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:cenc="urn:mpeg:cenc:2013"
+     type="static" mediaPresentationDuration="PT10S" minBufferTime="PT2S">
+  <ProgramInformation>
+    <Title>Test card</Title>
+  </ProgramInformation>
+  <Period id="p0" duration="PT10S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="v-low" codecs="avc1.4d401e" width="640" height="360" bandwidth="800000"/>
+      <Representation id="v-mid" codecs="avc1.4d401f" width="1280" height="720" bandwidth="2400000"/>
+      <Representation id="v-high" codecs="avc1.640028" width="1920" height="1080" bandwidth="4800000"/>
+    </AdaptationSet>
+    <AdaptationSet id="2" contentType="audio" mimeType="audio/mp4" lang="en">
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="a-en" codecs="mp4a.40.2" bandwidth="128000"/>
+    </AdaptationSet>
+    <AdaptationSet id="3" contentType="audio" mimeType="audio/mp4" lang="fr">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cenc"
+                         cenc:default_KID="43215678-1234-1234-1234-123412341234"/>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="a-fr" codecs="mp4a.40.2" bandwidth="96000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_segment() -> impl IntoResponse {
+    SEGMENTS_REQUESTED.fetch_add(1, Ordering::SeqCst);
+    StatusCode::NOT_FOUND
+}
+
+fn dump(args: &[&str]) -> jzon::JsonValue {
+    let assert = dash_mpd_cli()
+        .args(["--dump-json", "--no-version-check"])
+        .args(args)
+        .arg("http://localhost:6684/mpd")
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+    jzon::parse(&stdout).expect("stdout should contain a single JSON document")
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_dump_json() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{rep}/{seg}", get(send_segment));
+    let listener = TcpListener::bind("127.0.0.1:6684").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    let json = dump(&["--quality", "best", "--prefer-audio-language", "fr"]);
+    assert_eq!(json["type"], "static");
+    assert_eq!(json["title"], "Test card");
+    assert_eq!(json["media_presentation_duration"], 10.0);
+    let period = &json["periods"][0];
+    assert_eq!(period["id"], "p0");
+    assert_eq!(period["representations"], 5);
+    let video = &period["selected"][0];
+    assert_eq!(video["kind"], "video");
+    assert_eq!(video["representation_id"], "v-high");
+    assert!(video["reasons"][0].as_str().unwrap().contains("highest bandwidth"));
+    assert_eq!(video["segments"]["count"], 5);
+    assert_eq!(video["segments"]["initialization"], "http://localhost:6684/media/v-high/init.mp4");
+    assert_eq!(video["segments"]["urls"][4], "http://localhost:6684/media/v-high/seg-5.mp4");
+    assert_eq!(video["estimated_size"], 6_000_000);
+    let audio = &period["selected"][1];
+    assert_eq!(audio["representation_id"], "a-fr");
+    assert!(audio["reasons"][0].as_str().unwrap().contains("language matches fr"));
+    assert_eq!(audio["content_protection"][0]["value"], "cenc");
+    assert_eq!(audio["content_protection"][0]["default_kid"], "43215678-1234-1234-1234-123412341234");
+    assert_eq!(json["estimated_size"], 6_000_000 + 120_000);
+
+    let json = dump(&["--prefer-video-width", "1300", "--video-only"]);
+    let selected = &json["periods"][0]["selected"];
+    assert_eq!(selected.len(), 1);
+    assert_eq!(selected[0]["representation_id"], "v-mid");
+    assert!(selected[0]["reasons"][0].as_str().unwrap().contains("closest to --prefer-video-width"));
+
+    let json = dump(&[]);
+    assert_eq!(json["periods"][0]["selected"][0]["representation_id"], "v-low");
+    assert_eq!(SEGMENTS_REQUESTED.load(Ordering::SeqCst), 0);
+    Ok(())
+}
@@ -21,7 +21,7 @@ use serde_json::json;
 use ffprobe::ffprobe;
 use file_format::FileFormat;
 use tracing::{trace, info};
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use common::check_file_size_approx;
 
 
@@ -144,7 +144,7 @@ async fn test_dl_resilience() -> Result<()> {
     let mpd = "http://dash.akamaized.net/dash264/TestCasesMCA/dts/1/Paint_dtsc_testA.mpd";
     let tmpd = tempfile::tempdir().unwrap();
     let out = tmpd.path().join("error-resilience.mkv");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v", "-v", "-v",
                "--ignore-content-type",
                "--quality", "best",
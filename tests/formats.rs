@@ -0,0 +1,116 @@
+//! Tests for listing the Representations in a manifest (--list-formats)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test formats -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with two video Representations and an
+//   encrypted audio Representation, which has a language, a role, a label and an audio channel
+//   configuration.
+//
+//   - Run with --list-formats and check the table printed, then with --list-formats=json and check
+//   the JSON object printed. Check that no media segments are requested.
+
+
+pub mod common;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use axum::{routing::get, Router};
+use axum::response::IntoResponse;
+use axum::http::{header, StatusCode};
+use anyhow::Result;
+use test_log::test;
+
+
+static SEGMENTS_REQUESTED: AtomicUsize = AtomicUsize::new(0);
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:cenc="urn:mpeg:cenc:2013"
+     type="static" mediaPresentationDuration="PT10S">
+  <Period id="main" duration="PT10S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4" frameRate="30000/1001">
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="video-hd" codecs="avc1.640028" width="1920" height="1080" bandwidth="4500000"/>
+      <Representation id="video-sd" codecs="avc1.4d401e" width="640" height="360" bandwidth="800000"/>
+    </AdaptationSet>
+    <AdaptationSet id="2" mimeType="audio/mp4" lang="fr">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cenc"
+                         cenc:default_KID="43215678-1234-1234-1234-123412341234"/>
+      <ContentProtection schemeIdUri="urn:uuid:EDEF8BA9-79D6-4ACE-A3C8-27DCD51D21ED"/>
+      <Role schemeIdUri="urn:mpeg:dash:role:2011" value="dub"/>
+      <Label>Français</Label>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="audio-fr" codecs="mp4a.40.2" bandwidth="128000">
+        <AudioChannelConfiguration schemeIdUri="urn:mpeg:dash:23003:3:audio_channel_configuration:2011"
+                                   value="2"/>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_segment() -> impl IntoResponse {
+    SEGMENTS_REQUESTED.fetch_add(1, Ordering::SeqCst);
+    StatusCode::NOT_FOUND
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_list_formats() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{rep}/{seg}", get(send_segment));
+    let listener = TcpListener::bind("127.0.0.1:6683").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    dash_mpd_cli()
+        .args(["-F", "--no-version-check", "http://localhost:6683/mpd"])
+        .assert()
+        .stdout(predicate::str::is_match(r"PERIOD +ADAPT +ID +TYPE +CODECS +BANDWIDTH +RESOLUTION +FPS +LANG +ROLE +LABEL +CHANNELS +DRM").unwrap())
+        .stdout(predicate::str::is_match(r"1 +1 +video-hd +video +avc1\.640028 +4\.5M +1920x1080 +29\.970 +- +- +- +- +-").unwrap())
+        .stdout(predicate::str::is_match(r"1 +1 +video-sd +video +avc1\.4d401e +800k +640x360 +29\.970").unwrap())
+        .stdout(predicate::str::is_match(r"1 +2 +audio-fr +audio +mp4a\.40\.2 +128k +- +- +fr +dub +Français +2 +cenc,Widevine").unwrap())
+        .success();
+
+    let assert = dash_mpd_cli()
+        .args(["--list-formats=json", "--no-version-check", "http://localhost:6683/mpd"])
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+    let line = stdout.lines()
+        .find(|l| l.starts_with('{'))
+        .expect("JSON output");
+    let json = jzon::parse(line).unwrap();
+    assert_eq!(json["url"], "http://localhost:6683/mpd");
+    assert_eq!(json["periods"][0]["id"], "main");
+    assert_eq!(json["periods"][0]["formats"].len(), 3);
+    let audio = &json["periods"][0]["formats"][2];
+    assert_eq!(audio["id"], "audio-fr");
+    assert_eq!(audio["content_type"], "audio");
+    assert_eq!(audio["lang"], "fr");
+    assert_eq!(audio["roles"][0], "dub");
+    assert_eq!(audio["audio_channels"], "2");
+    assert_eq!(audio["drm"][1], "Widevine");
+    let video = &json["periods"][0]["formats"][0];
+    assert_eq!(video["width"], 1920);
+    assert_eq!(video["bandwidth"], 4500000);
+    assert_eq!(SEGMENTS_REQUESTED.load(Ordering::SeqCst), 0);
+    Ok(())
+}
@@ -19,7 +19,7 @@ use fs_err as fs;
 use std::env;
 use std::time::Duration;
 use tokio::net::TcpListener;
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use axum::{routing::get, Router};
 use axum::response::{Response, IntoResponse};
 use axum::http::header::HeaderMap;
@@ -100,7 +100,7 @@ async fn test_headers() -> Result<()> {
     tokio::spawn(backend);
     tokio::time::sleep(Duration::from_millis(1000)).await;
     let outpath = env::temp_dir().join("referer.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v", "-v", "-v",
                "--referer", "https://twiddles.org/",
                "--user-agent", "MyFakeUserAgent/42.0",
@@ -0,0 +1,137 @@
+//! Tests for reading decryption keys from a file (--key-file) and selecting them by KID
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test key_file -- --show-output
+//
+// What happens in this test:
+//
+//   - Check that malformed keys given with --key or in a key file, in the KID:KEY line format or the
+//   JSON format, are rejected with a message that identifies the offending line.
+//
+//   - Start an axum HTTP server that serves a manifest whose video and audio streams are encrypted
+//   with different KIDs, declared with cenc:default_KID.
+//
+//   - Run in simulation mode with a key file that only has a key for the video KID (and for an
+//   unrelated KID), and check that a warning about the missing audio key is printed, but not when
+//   only the video stream is selected.
+
+
+pub mod common;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::response::IntoResponse;
+use axum::http::{header, StatusCode};
+use anyhow::Result;
+use test_log::test;
+
+
+const VIDEO_KID: &str = "0a0b0c0d0e0f10111213141516171819";
+const AUDIO_KID: &str = "a1a2a3a4a5a6a7a8a9aaabacadaeafb0";
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:cenc="urn:mpeg:cenc:2013"
+     type="static" mediaPresentationDuration="PT4S">
+  <Period id="p0" duration="PT4S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cenc"
+                         cenc:default_KID="0A0B0C0D-0E0F-1011-1213-141516171819"/>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1" media="video-$Number$.mp4"/>
+      <Representation id="v1" codecs="avc1.64000a" width="64" height="64" bandwidth="100000"/>
+    </AdaptationSet>
+    <AdaptationSet id="2" contentType="audio" mimeType="audio/mp4" lang="en">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cenc"
+                         cenc:default_KID="a1a2a3a4-a5a6-a7a8-a9aa-abacadaeafb0"/>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1" media="audio-$Number$.mp4"/>
+      <Representation id="a1" codecs="mp4a.40.2" audioSamplingRate="48000" bandwidth="64000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_key_file() -> Result<()> {
+    let tmpd = TempDir::new().unwrap();
+
+    dash_mpd_cli()
+        .args(["--key", &format!("{VIDEO_KID}:0123"),
+               "--simulate", "--no-version-check",
+               "http://localhost:6693/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("invalid KEY for KID"))
+        .failure();
+
+    let bad_lines = tmpd.child("bad.keys");
+    bad_lines.write_str(&format!("# Keys for testing\n{VIDEO_KID}:3b6e1e1f6a2c4e4c8d3b1e2a4f5a6b7c\n\
+                                  {AUDIO_KID}-9f8e7d6c5b4a39281706f5e4d3c2b1a0\n"))?;
+    dash_mpd_cli()
+        .args(["--key-file", &bad_lines.to_string_lossy(),
+               "--simulate", "--no-version-check",
+               "http://localhost:6693/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("line 3: expecting KID:KEY"))
+        .failure();
+
+    let conflicting = tmpd.child("conflicting.keys");
+    conflicting.write_str(&format!("{VIDEO_KID}:3b6e1e1f6a2c4e4c8d3b1e2a4f5a6b7c\n\n\
+                                    {VIDEO_KID}:9f8e7d6c5b4a39281706f5e4d3c2b1a0\n"))?;
+    dash_mpd_cli()
+        .args(["--key-file", &conflicting.to_string_lossy(),
+               "--simulate", "--no-version-check",
+               "http://localhost:6693/mpd"])
+        .assert()
+        .stderr(predicate::str::contains(format!("line 3: conflicting key for KID {VIDEO_KID} (see line 1)")))
+        .failure();
+
+    let bad_json = tmpd.child("bad.json");
+    bad_json.write_str(&format!("{{\n  \"{VIDEO_KID}\": \"3b6e1e1f6a2c4e4c8d3b1e2a4f5a6b7c\",\n  \
+                                 \"{AUDIO_KID}\": \"9f8e7d6c\"\n}}\n"))?;
+    dash_mpd_cli()
+        .args(["--key-file", &bad_json.to_string_lossy(),
+               "--simulate", "--no-version-check",
+               "http://localhost:6693/mpd"])
+        .assert()
+        .stderr(predicate::str::contains(format!("line 3: invalid KEY for KID {AUDIO_KID}")))
+        .failure();
+
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/{segment}", get(|| async { StatusCode::NOT_FOUND }));
+    let listener = TcpListener::bind("127.0.0.1:6693").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    let keystore = tmpd.child("keystore.json");
+    keystore.write_str(&format!("{{\n  \"{VIDEO_KID}\": \"3b6e1e1f6a2c4e4c8d3b1e2a4f5a6b7c\",\n  \
+                                 \"ffeeddccbbaa99887766554433221100\": \"00112233445566778899aabbccddeeff\"\n}}\n"))?;
+    dash_mpd_cli()
+        .args(["--key-file", &keystore.to_string_lossy(),
+               "--simulate", "--no-version-check",
+               "http://localhost:6693/mpd"])
+        .assert()
+        .stderr(predicate::str::contains(format!("No decryption key for KID {AUDIO_KID} of the audio stream")))
+        .stderr(predicate::str::contains(VIDEO_KID).not())
+        .success();
+
+    dash_mpd_cli()
+        .args(["--key-file", &keystore.to_string_lossy(),
+               "--video-only",
+               "--simulate", "--no-version-check",
+               "http://localhost:6693/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("No decryption key").not())
+        .success();
+    Ok(())
+}
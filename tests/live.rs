@@ -0,0 +1,237 @@
+//! Tests for recording from a live stream (dynamic MPD manifest)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test live -- --show-output
+//
+// What happens in these tests:
+//
+//   - Start an axum HTTP server that simulates a live stream: a dynamic manifest whose
+//   availabilityStartTime is 20 seconds in the past, with a 30 second timeshift buffer, one-second
+//   media segments that only become available once they are complete (earlier requests receive a
+//   404 error), and a UTCTiming source.
+//
+//   - Record from the live stream, and check that no media segment was requested before it became
+//   available, and that the recording starts at the expected position: the live edge by default,
+//   the start of the timeshift buffer with --live-from-start, or the requested point with
+//   --live-start-offset.
+//
+//   - Serve a pseudo-live stream, a dynamic manifest whose only Period ended before the live edge,
+//   and check that it is downloaded with --enable-live-streams like a static manifest.
+
+
+pub mod common;
+use fs_err as fs;
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::{Path, State};
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4;
+
+
+struct LiveStream {
+    port: u16,
+    availability_start: OffsetDateTime,
+    early_requests: AtomicUsize,
+    segments_served: AtomicUsize,
+    oldest_segment: AtomicUsize,
+}
+
+async fn send_mpd(State(live): State<Arc<LiveStream>>) -> impl IntoResponse {
+    let ast = live.availability_start.format(&Rfc3339).unwrap();
+    let port = live.port;
+    let xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic" availabilityStartTime="{ast}"
+     minimumUpdatePeriod="PT2S" suggestedPresentationDelay="PT2S" timeShiftBufferDepth="PT30S">
+  <Period id="live" start="PT0S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="1" startNumber="1"
+                       initialization="/media/init.mp4" media="/media/seg-$Number$.mp4"/>
+      <Representation id="v1" codecs="avc1.640028" width="1920" height="800" bandwidth="1980081"/>
+    </AdaptationSet>
+  </Period>
+  <UTCTiming schemeIdUri="urn:mpeg:dash:utc:http-iso:2014" value="http://localhost:{port}/time"/>
+</MPD>"#);
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_time() -> impl IntoResponse {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap()
+}
+
+async fn send_media(State(live): State<Arc<LiveStream>>, Path(seg): Path<String>) -> Response {
+    if let Some(number) = seg.strip_prefix("seg-").and_then(|s| s.strip_suffix(".mp4")) {
+        let number: usize = number.parse().unwrap();
+        // Segment number N covers [N-1, N) seconds after availabilityStartTime.
+        let available = live.availability_start + time::Duration::seconds(number as i64);
+        if OffsetDateTime::now_utc() < available {
+            live.early_requests.fetch_add(1, Ordering::SeqCst);
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap();
+        }
+        live.segments_served.fetch_add(1, Ordering::SeqCst);
+        live.oldest_segment.fetch_min(number, Ordering::SeqCst);
+    }
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4()))
+        .unwrap()
+}
+
+async fn serve_live_stream(port: u16) -> Arc<LiveStream> {
+    let live = Arc::new(LiveStream {
+        port,
+        availability_start: OffsetDateTime::now_utc() - time::Duration::seconds(20),
+        early_requests: AtomicUsize::new(0),
+        segments_served: AtomicUsize::new(0),
+        oldest_segment: AtomicUsize::new(usize::MAX),
+    });
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/time", get(send_time))
+        .route("/media/{seg}", get(send_media))
+        .with_state(Arc::clone(&live));
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}")).await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    live
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_live_recording() -> Result<()> {
+    let live = serve_live_stream(6675).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+    let out = tmpd.child("live.mp4");
+    dash_mpd_cli()
+        .args(["--record-duration", "3", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6675/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Recording 3.0s from live stream"))
+        .success();
+    assert!(fs::metadata(out.path()).is_ok());
+    assert_eq!(live.early_requests.load(Ordering::SeqCst), 0);
+    let served = live.segments_served.load(Ordering::SeqCst);
+    assert!((3..=5).contains(&served), "recorded {served} segments");
+    // The recording starts at the live edge (about 21 seconds after availabilityStartTime, minus
+    // the suggested presentation delay), not at the start of the timeshift buffer.
+    assert!(live.oldest_segment.load(Ordering::SeqCst) >= 15);
+    Ok(())
+}
+
+// Start at the beginning of the timeshift buffer, which here is the start of the stream, and catch
+// up to the live edge.
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_live_from_start() -> Result<()> {
+    let live = serve_live_stream(6676).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+    let out = tmpd.child("live-dvr.mp4");
+    dash_mpd_cli()
+        .args(["--live-from-start", "--record-duration", "25", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6676/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("behind the live edge"))
+        .success();
+    assert!(fs::metadata(out.path()).is_ok());
+    assert_eq!(live.early_requests.load(Ordering::SeqCst), 0);
+    assert_eq!(live.oldest_segment.load(Ordering::SeqCst), 1);
+    let served = live.segments_served.load(Ordering::SeqCst);
+    assert!((25..=27).contains(&served), "recorded {served} segments");
+    Ok(())
+}
+
+// Start 10 seconds before the live edge.
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_live_start_offset() -> Result<()> {
+    let live = serve_live_stream(6677).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+    let out = tmpd.child("live-offset.mp4");
+    dash_mpd_cli()
+        .args(["--live-start-offset", "-10", "--record-duration", "3", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6677/mpd"])
+        .assert()
+        .success();
+    assert_eq!(live.early_requests.load(Ordering::SeqCst), 0);
+    let oldest = live.oldest_segment.load(Ordering::SeqCst);
+    assert!((7..=11).contains(&oldest), "recording started at segment {oldest}");
+    Ok(())
+}
+
+// A pseudo-live stream whose media segments are all available has nothing left to record, and is
+// downloaded by the DashDownloader.
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_pseudo_live() -> Result<()> {
+    async fn send_mpd() -> impl IntoResponse {
+        let ast = (OffsetDateTime::now_utc() - time::Duration::seconds(60)).format(&Rfc3339).unwrap();
+        let xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic" availabilityStartTime="{ast}">
+  <Period id="ended" start="PT0S" duration="PT4S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="/media/init.mp4" media="/media/seg-$Number$.mp4"/>
+      <Representation id="v1" codecs="avc1.640028" width="1920" height="800" bandwidth="1980081"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#);
+        ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+    }
+
+    async fn send_media() -> Response {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "video/mp4")
+            .body(Body::from(generate_minimal_mp4()))
+            .unwrap()
+    }
+
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{seg}", get(send_media));
+    let listener = TcpListener::bind("127.0.0.1:6695").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+    let out = tmpd.child("pseudo-live.mp4");
+    dash_mpd_cli()
+        .args(["--enable-live-streams", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6695/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("downloading it like a static manifest"))
+        .success();
+    assert!(fs::metadata(out.path()).is_ok());
+    Ok(())
+}
@@ -0,0 +1,150 @@
+//! Tests for embedding provenance metadata (--embed-metadata, --write-info-json)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test metadata -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with a ProgramInformation element (title,
+//   source and copyright) and a single video Representation, whose media segment is generated by
+//   ffmpeg.
+//
+//   - Download to MP4 and to Matroska with --embed-metadata, and check with ffprobe that the
+//   container tags contain the title and copyright from the manifest, the manifest URL, the
+//   predicted Representation and the download date.
+//
+//   - Download with --write-info-json and --continue, and check the contents of the NAME.info.json
+//   sidecar file, where the Representation is recorded in the resume journal rather than predicted.
+
+
+pub mod common;
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4_ffmpeg;
+
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT1S">
+  <ProgramInformation moreInformationURL="https://example.com/about">
+    <Title>The Metadata Test</Title>
+    <Source>Example Broadcasting</Source>
+    <Copyright>© 2026 Example Broadcasting</Copyright>
+  </ProgramInformation>
+  <Period id="p0" duration="PT1S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="1" startNumber="1" media="media/video.mp4"/>
+      <Representation id="video-main" codecs="avc1.640028" width="10" height="10" bandwidth="100000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_video() -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4_ffmpeg("title=original")))
+        .unwrap()
+}
+
+// The format-level tags of the media file, with lowercase names (Matroska tag names are uppercase).
+fn container_tags(path: &std::path::Path) -> Vec<(String, String)> {
+    let ffprobe = Command::new("ffprobe")
+        .args(["-loglevel", "error",
+               "-show_entries", "format_tags",
+               "-of", "json",
+               path.to_str().unwrap()])
+        .output()
+        .expect("spawning ffprobe");
+    assert!(ffprobe.status.success());
+    let parsed = jzon::parse(&String::from_utf8_lossy(&ffprobe.stdout)).unwrap();
+    parsed["format"]["tags"].entries()
+        .map(|(k, v)| (k.to_lowercase(), v.as_str().unwrap_or("").to_string()))
+        .collect()
+}
+
+fn tag<'a>(tags: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    tags.iter()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.as_str())
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_metadata() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/video.mp4", get(send_video));
+    let listener = TcpListener::bind("127.0.0.1:6689").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+
+    for name in ["tagged.mp4", "tagged.mkv"] {
+        let out = tmpd.child(name);
+        dash_mpd_cli()
+            .args(["--embed-metadata", "--no-progress",
+                   "-o", &out.to_string_lossy(),
+                   "http://localhost:6689/mpd"])
+            .assert()
+            .stdout(predicate::str::contains("Added metadata to"))
+            .success();
+        let tags = container_tags(out.path());
+        assert_eq!(tag(&tags, "title"), Some("The Metadata Test"));
+        assert_eq!(tag(&tags, "copyright"), Some("© 2026 Example Broadcasting"));
+        assert_eq!(tag(&tags, "source_url"), Some("http://localhost:6689/mpd"));
+        assert_eq!(tag(&tags, "predicted_representations"), Some("video:video-main"));
+        assert!(tag(&tags, "download_date").is_some_and(|d| d.starts_with("20")));
+        assert!(tag(&tags, "comment").is_some_and(|c| c.starts_with("Downloaded from http://localhost:6689/mpd")));
+        assert!(!tmpd.path().join(name).with_extension("info.json").exists());
+    }
+
+    let out = tmpd.child("described.mp4");
+    dash_mpd_cli()
+        .args(["--write-info-json", "--continue", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6689/mpd"])
+        .assert()
+        .success();
+    let json = fs::read_to_string(tmpd.path().join("described.info.json"))?;
+    let info = jzon::parse(&json).unwrap();
+    assert_eq!(info["title"], "The Metadata Test");
+    assert_eq!(info["source"], "Example Broadcasting");
+    assert_eq!(info["copyright"], "© 2026 Example Broadcasting");
+    assert_eq!(info["more_information_url"], "https://example.com/about");
+    assert_eq!(info["manifest_url"], "http://localhost:6689/mpd");
+    assert_eq!(info["representations"].len(), 1);
+    let rep = &info["representations"][0];
+    assert_eq!(rep["period"], 1);
+    assert_eq!(rep["kind"], "video");
+    assert_eq!(rep["id"], "video-main");
+    assert_eq!(rep["codecs"], "avc1.640028");
+    assert_eq!(rep["bandwidth"], 100000);
+    assert_eq!(rep["predicted"], false);
+    assert!(info["download_date"].as_str().is_some_and(|d| d.ends_with('Z')));
+    // The output file was not retagged without --embed-metadata.
+    let tags = container_tags(out.path());
+    assert_ne!(tag(&tags, "title"), Some("The Metadata Test"));
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! Tests for setting the modification time of the output file (--mtime)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test mtime -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with a Last-Modified header, and a manifest
+//   without this header but with an MPD@publishTime attribute.
+//
+//   - Check that the modification time of the output file is set from the Last-Modified header, or
+//   from the publishTime when the header is absent, and is left alone with --no-mtime. Check that the
+//   manifest isn't fetched again after the download to determine the modification time.
+
+
+pub mod common;
+use fs_err as fs;
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4;
+
+
+const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+const LAST_MODIFIED_SECS: u64 = 1_445_412_480;
+const PUBLISH_TIME_SECS: u64 = 1_614_834_367;
+
+static MANIFEST_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+fn manifest_xml(publish_time: Option<&str>) -> String {
+    let publish = publish_time
+        .map(|pt| format!(r#" publishTime="{pt}""#))
+        .unwrap_or_default();
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static"{publish} mediaPresentationDuration="PT5S">
+  <Period id="1" duration="PT5S">
+    <AdaptationSet id="1" contentType="video">
+      <Representation id="1" mimeType="video/mp4" codecs="avc1.640028" width="1920" height="800" bandwidth="1980081">
+        <BaseURL>/media/f1.mp4</BaseURL>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#)
+}
+
+async fn send_mpd_last_modified() -> impl IntoResponse {
+    MANIFEST_REQUESTS.fetch_add(1, Ordering::SeqCst);
+    ([(header::CONTENT_TYPE, "application/dash+xml"),
+      (header::LAST_MODIFIED, LAST_MODIFIED)],
+     manifest_xml(Some("2021-03-04T05:06:07Z")))
+}
+
+async fn send_mpd_published() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/dash+xml")],
+     manifest_xml(Some("2021-03-04T05:06:07Z")))
+}
+
+async fn send_mp4() -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4()))
+        .unwrap()
+}
+
+fn mtime_secs(p: &std::path::Path) -> u64 {
+    fs::metadata(p).unwrap()
+        .modified().unwrap()
+        .duration_since(UNIX_EPOCH).unwrap()
+        .as_secs()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_mtime() -> Result<()> {
+    let app = Router::new()
+        .route("/last-modified.mpd", get(send_mpd_last_modified))
+        .route("/published.mpd", get(send_mpd_published))
+        .route("/media/{seg}", get(send_mp4));
+    let listener = TcpListener::bind("127.0.0.1:6673").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+
+    let out = tmpd.child("last-modified.mp4");
+    dash_mpd_cli()
+        .args(["--mtime", "-o", &out.to_string_lossy(),
+               "http://localhost:6673/last-modified.mpd"])
+        .assert()
+        .success();
+    assert_eq!(mtime_secs(out.path()), LAST_MODIFIED_SECS);
+    // Once when preparing the download, once by the DashDownloader.
+    assert_eq!(MANIFEST_REQUESTS.load(Ordering::SeqCst), 2);
+
+    let out = tmpd.child("published.mp4");
+    dash_mpd_cli()
+        .args(["--mtime", "-o", &out.to_string_lossy(),
+               "http://localhost:6673/published.mpd"])
+        .assert()
+        .success();
+    assert_eq!(mtime_secs(out.path()), PUBLISH_TIME_SECS);
+
+    let out = tmpd.child("now.mp4");
+    dash_mpd_cli()
+        .args(["--no-mtime", "-o", &out.to_string_lossy(),
+               "http://localhost:6673/last-modified.mpd"])
+        .assert()
+        .success();
+    assert!(mtime_secs(out.path()) > PUBLISH_TIME_SECS);
+    Ok(())
+}
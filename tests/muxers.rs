@@ -12,7 +12,7 @@ use std::process::Command;
 use std::path::Path;
 use ffprobe::ffprobe;
 use file_format::FileFormat;
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use assert_fs::{prelude::*, TempDir};
 use test_log::test;
 use common::check_file_size_approx;
@@ -53,7 +53,7 @@ fn test_muxers_mp4 () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("reddit-ffmpeg.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--muxer-preference", "mp4:ffmpeg",
                "--muxer-preference", "avi:vlc",
@@ -71,7 +71,7 @@ fn test_muxers_mp4 () {
     }
 
     let out = tmpd.child("reddit-vlc.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--muxer-preference", "mp4:vlc",
                "-o", &out.to_string_lossy(), mpd])
         .assert()
@@ -84,7 +84,7 @@ fn test_muxers_mp4 () {
     }
 
     let out = tmpd.child("reddit-mp4box.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--muxer-preference", "mp4:mp4box",
                "-o", &out.to_string_lossy(), mpd])
         .assert()
@@ -109,7 +109,7 @@ fn test_muxers_mkv () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("reddit-ffmpeg.mkv");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--muxer-preference", "mkv:ffmpeg",
                "-o", &out.to_string_lossy(), mpd])
         .assert()
@@ -122,7 +122,7 @@ fn test_muxers_mkv () {
     }
 
     let out = tmpd.child("reddit-mkvmerge.mkv");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--muxer-preference", "mkv:mkvmerge",
                "-o", &out.to_string_lossy(), mpd])
         .assert()
@@ -145,7 +145,7 @@ fn test_muxers_avi () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("angel-ffmpeg.avi");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--muxer-preference", "avi:ffmpeg",
                "-o", &out.to_string_lossy(), mpd])
         .assert()
@@ -158,7 +158,7 @@ fn test_muxers_avi () {
     }
 
     let out = tmpd.child("caminandes-vlc.avi");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["--muxer-preference", "avi:vlc",
                "-o", &out.to_string_lossy(), mpd])
         .assert()
@@ -0,0 +1,164 @@
+//! Tests for output filename templates (--output-template)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test output_template -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with a title containing characters that are
+//   not safe in filenames, and a Period with a video and an English audio Representation, whose media
+//   segments are generated by ffmpeg.
+//
+//   - Download with --output-template using the title, Period id, height and codec placeholders, and
+//   check that the output file is created under a new directory named after the sanitised title.
+//   Check that the same placeholders are expanded in the --keep-video and --keep-audio paths.
+//
+//   - Check that --simulate reports the expanded output path without creating it, that {ext} expands
+//   to the container named by --muxer-preference, and that an unknown placeholder is rejected.
+
+
+pub mod common;
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::TempDir;
+use axum::{routing::get, Router};
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4_ffmpeg;
+
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT1S">
+  <ProgramInformation>
+    <Title>My Show: Part 1/2</Title>
+  </ProgramInformation>
+  <Period id="ep1" duration="PT1S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="1" startNumber="1" media="media/video.mp4"/>
+      <Representation id="v1" codecs="avc1.640028" width="10" height="10" bandwidth="100000"/>
+    </AdaptationSet>
+    <AdaptationSet id="2" contentType="audio" mimeType="audio/mp4" lang="en">
+      <SegmentTemplate timescale="1" duration="1" startNumber="1" media="media/audio.mp4"/>
+      <Representation id="a1" codecs="mp4a.40.2" audioSamplingRate="48000" bandwidth="64000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_video() -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4_ffmpeg("title=template")))
+        .unwrap()
+}
+
+// A one second fragmented MP4 file containing an AAC sine wave.
+async fn send_audio() -> Response {
+    let tmp = env::temp_dir().join("output-template-audio.mp4");
+    let ffmpeg = Command::new("ffmpeg")
+        .args(["-nostdin",
+               "-y",
+               "-f", "lavfi",
+               "-i", "sine=frequency=440:sample_rate=48000",
+               "-t", "1",
+               "-c:a", "aac",
+               "-movflags", "frag_keyframe+empty_moov",
+               tmp.to_str().unwrap()])
+        .output()
+        .expect("spawning ffmpeg");
+    assert!(ffmpeg.status.success());
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "audio/mp4")
+        .body(Body::from(fs::read(tmp).unwrap()))
+        .unwrap()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_output_template() -> Result<()> {
+    let app = Router::new()
+        .route("/shows/episode.mpd", get(send_mpd))
+        .route("/shows/media/video.mp4", get(send_video))
+        .route("/shows/media/audio.mp4", get(send_audio));
+    let listener = TcpListener::bind("127.0.0.1:6690").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+
+    dash_mpd_cli()
+        .current_dir(tmpd.path())
+        .args(["--output-template", "{title}/{period_id}-{height}p-{vcodec}.{ext}",
+               "--keep-video", "kept/{url_stem}-{resolution}.video.{ext}",
+               "--keep-audio", "kept/{url_stem}-{lang}-{acodec}.audio.{ext}",
+               "--no-progress",
+               "http://localhost:6690/shows/episode.mpd"])
+        .assert()
+        .success();
+    let dir = tmpd.path().join("My Show_ Part 1_2");
+    assert!(dir.join("ep1-10p-avc1.mp4").exists());
+    assert_eq!(fs::read_dir(&dir)?.count(), 1);
+    assert!(tmpd.path().join("kept/episode-10x10.video.mp4").exists());
+    assert!(tmpd.path().join("kept/episode-en-mp4a.audio.mp4").exists());
+
+    dash_mpd_cli()
+        .current_dir(tmpd.path())
+        .args(["--output-template", "{url_stem}/{date}.{ext}",
+               "--no-progress",
+               "http://localhost:6690/shows/episode.mpd"])
+        .assert()
+        .success();
+    let names: Vec<String> = fs::read_dir(tmpd.path().join("episode"))?
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(names.len(), 1);
+    let date = names[0].strip_suffix(".mp4").unwrap();
+    assert_eq!(date.len(), 10);
+    assert!(date.chars().enumerate().all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() }));
+
+    dash_mpd_cli()
+        .current_dir(tmpd.path())
+        .args(["--output-template", "simulated/{title}.mkv",
+               "--simulate",
+               "http://localhost:6690/shows/episode.mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Output would be saved to simulated/My Show_ Part 1_2.mkv"))
+        .success();
+    assert!(!tmpd.path().join("simulated").exists());
+
+    dash_mpd_cli()
+        .current_dir(tmpd.path())
+        .args(["--output-template", "simulated/{id}.{ext}",
+               "--muxer-preference", "mkv:mkvmerge,ffmpeg",
+               "--simulate",
+               "http://localhost:6690/shows/episode.mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Output would be saved to simulated/v1+a1.mkv"))
+        .success();
+
+    dash_mpd_cli()
+        .args(["--output-template", "{series}.{ext}",
+               "http://localhost:6690/shows/episode.mpd"])
+        .assert()
+        .stderr(predicate::str::contains("unknown placeholder {series}"))
+        .failure();
+    Ok(())
+}
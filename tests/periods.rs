@@ -0,0 +1,126 @@
+//! Tests for selecting the Periods to download (--periods)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test periods -- --show-output
+//
+// What happens in these tests:
+//
+//   - Start an axum HTTP server that serves a manifest with three Periods, each containing a single
+//   media segment, and a multi-period manifest with mid-roll ads from our fixtures.
+//
+//   - Check that --simulate lists the Periods of the manifest with their start time and duration,
+//   and which of them are skipped by the --periods selection.
+//
+//   - Download a selection of Periods, and check that only the media segments of the selected
+//   Periods are requested.
+
+
+pub mod common;
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4;
+
+
+static SEGMENTS_REQUESTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn period_xml(id: &str) -> String {
+    format!(r#"
+  <Period id="{id}" duration="PT2S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="{id}/init.mp4" media="{id}/seg-$Number$.mp4"/>
+      <Representation id="v1" codecs="avc1.640028" width="1920" height="800" bandwidth="1980081"/>
+    </AdaptationSet>
+  </Period>"#)
+}
+
+async fn send_mpd() -> impl IntoResponse {
+    let periods: String = ["programme-1", "ad-1", "programme-2"].iter()
+        .map(|id| period_xml(id))
+        .collect();
+    let xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT6S">
+  <BaseURL>media/</BaseURL>{periods}
+</MPD>"#);
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_telenet() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/dash+xml")],
+     include_str!("fixtures/telenet-mid-ad-rolls.mpd"))
+}
+
+async fn send_segment(Path((period, seg)): Path<(String, String)>) -> Response {
+    SEGMENTS_REQUESTED.lock().unwrap().push(format!("{period}/{seg}"));
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4()))
+        .unwrap()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_periods() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/telenet.mpd", get(send_telenet))
+        .route("/media/{period}/{seg}", get(send_segment));
+    let listener = TcpListener::bind("127.0.0.1:6680").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+
+    dash_mpd_cli()
+        .args(["--simulate", "--periods", "@mid-roll-*",
+               "-o", &tmpd.child("ads.mp4").to_string_lossy(),
+               "http://localhost:6680/telenet.mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Manifest contains 5 Period(s)"))
+        .stdout(predicate::str::contains(
+            "Period 1 (id 96d40c7b-4de1-4f93-b622-77719e867588): start 00:00:00.000, duration 00:14:14.160, skipped"))
+        .stdout(predicate::str::contains(
+            "Period 2 (id mid-roll-1-ad-1): start 00:14:14.160, duration 00:00:31.360\n"))
+        .success();
+    assert!(SEGMENTS_REQUESTED.lock().unwrap().is_empty());
+
+    dash_mpd_cli()
+        .args(["--periods", "1,time=3-", "--no-period-concatenation", "--no-progress",
+               "-o", &tmpd.child("programme.mp4").to_string_lossy(),
+               "http://localhost:6680/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Selected 2 of 3 Periods"))
+        .success();
+    let mut requested = SEGMENTS_REQUESTED.lock().unwrap().clone();
+    requested.sort();
+    assert_eq!(requested, vec!["programme-1/init.mp4", "programme-1/seg-1.mp4",
+                               "programme-2/init.mp4", "programme-2/seg-1.mp4"]);
+
+    dash_mpd_cli()
+        .args(["--periods", "@nothing-*",
+               "-o", &tmpd.child("nothing.mp4").to_string_lossy(),
+               "http://localhost:6680/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("no Periods match"))
+        .failure();
+    Ok(())
+}
@@ -12,7 +12,7 @@ use std::env;
 use ffprobe::ffprobe;
 use file_format::FileFormat;
 use predicates::prelude::*;
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use assert_fs::{prelude::*, TempDir};
 use test_log::test;
 use common::check_file_size_approx;
@@ -25,7 +25,7 @@ fn test_progressbar_indicatif () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("cf.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--progress=bar",
@@ -49,7 +49,7 @@ fn test_progressbar_json () {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("cf.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--progress=json",
@@ -0,0 +1,145 @@
+//! Tests for resumable downloads (--continue)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test resume -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with three media segments. The third segment
+//   returns a 503 error until we allow it to be served.
+//
+//   - Run a download with --continue and --abort-on-error, which fails on the third segment and
+//   leaves a resume journal next to the output file.
+//
+//   - Allow the third segment to be served, and rerun the download with --continue. Check that it
+//   completes without requesting the first two segments again, and that the resume journal has been
+//   deleted.
+
+
+pub mod common;
+use fs_err as fs;
+use std::env;
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tokio::net::TcpListener;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use dash_mpd::{MPD, Period, AdaptationSet, Representation, SegmentTemplate};
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4;
+
+
+static SEGMENT_REQUESTS: [AtomicUsize; 4] =
+    [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+static SEGMENT3_AVAILABLE: AtomicBool = AtomicBool::new(false);
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_resume_download() -> Result<()> {
+    async fn send_mpd() -> impl IntoResponse {
+        let segment_template = SegmentTemplate {
+            initialization: Some("/media/seg-0.mp4".to_string()),
+            media: Some("/media/seg-$Number$.mp4".to_string()),
+            startNumber: Some(1),
+            timescale: Some(1),
+            duration: Some(2.0),
+            ..Default::default()
+        };
+        let rep = Representation {
+            id: Some("v1".to_string()),
+            mimeType: Some("video/mp4".to_string()),
+            codecs: Some("avc1.640028".to_string()),
+            width: Some(1920),
+            height: Some(800),
+            bandwidth: Some(1980081),
+            SegmentTemplate: Some(segment_template),
+            ..Default::default()
+        };
+        let adapt = AdaptationSet {
+            id: Some("a1".to_string()),
+            contentType: Some("video".to_string()),
+            representations: vec!(rep),
+            ..Default::default()
+        };
+        let period = Period {
+            id: Some("p1".to_string()),
+            duration: Some(Duration::new(6, 0)),
+            adaptations: vec!(adapt),
+            ..Default::default()
+        };
+        let mpd = MPD {
+            mpdtype: Some("static".to_string()),
+            periods: vec!(period),
+            ..Default::default()
+        };
+        let xml = quick_xml::se::to_string(&mpd).unwrap();
+        ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+    }
+
+    async fn send_media(Path(seg): Path<String>) -> Response {
+        let number: usize = seg.trim_start_matches("seg-")
+            .trim_end_matches(".mp4")
+            .parse()
+            .unwrap();
+        SEGMENT_REQUESTS[number].fetch_add(1, Ordering::SeqCst);
+        if number == 3 && !SEGMENT3_AVAILABLE.load(Ordering::SeqCst) {
+            return Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(Body::empty())
+                .unwrap();
+        }
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "video/mp4")
+            .body(Body::from(generate_minimal_mp4()))
+            .unwrap()
+    }
+
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{seg}", get(send_media));
+    let listener = TcpListener::bind("127.0.0.1:6674").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+    let out = tmpd.child("resumed.mp4");
+    let journal_dir = tmpd.child("resumed.mp4.resume");
+
+    dash_mpd_cli()
+        .args(["--continue", "--abort-on-error", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6674/mpd"])
+        .assert()
+        .failure();
+    assert!(fs::metadata(journal_dir.child("journal.json").path()).is_ok());
+    assert_eq!(SEGMENT_REQUESTS[1].load(Ordering::SeqCst), 1);
+    assert_eq!(SEGMENT_REQUESTS[2].load(Ordering::SeqCst), 1);
+
+    SEGMENT3_AVAILABLE.store(true, Ordering::SeqCst);
+    dash_mpd_cli()
+        .args(["--continue", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6674/mpd"])
+        .assert()
+        .success();
+    assert!(fs::metadata(out.path()).is_ok());
+    // The segments downloaded by the first run were served from the resume directory.
+    assert_eq!(SEGMENT_REQUESTS[0].load(Ordering::SeqCst), 1);
+    assert_eq!(SEGMENT_REQUESTS[1].load(Ordering::SeqCst), 1);
+    assert_eq!(SEGMENT_REQUESTS[2].load(Ordering::SeqCst), 1);
+    assert!(fs::metadata(journal_dir.path()).is_err());
+    Ok(())
+}
@@ -150,6 +150,7 @@ async fn test_add_root_cert() -> Result<(), anyhow::Error> {
     // certificate: UnknownIssuer".
     let failed = Command::new("cargo")
         .args(["run", "--no-default-features", "--", "https://localhost:6666/mpd"])
+        .env("XDG_CONFIG_HOME", concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"))
         .output()
         .expect("failure spawning cargo run / dash-mpd-cli");
     assert!(!failed.status.success());
@@ -166,6 +167,7 @@ async fn test_add_root_cert() -> Result<(), anyhow::Error> {
                "--add-root-certificate",
                "tests/fixtures/root-CA.crt",
                "https://localhost:6666/mpd"])
+        .env("XDG_CONFIG_HOME", concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"))
         .output()
         .expect("failed spawning cargo run / dash-mpd-cli");
     assert!(cli.status.success());
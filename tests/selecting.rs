@@ -10,7 +10,7 @@ use std::env;
 use ffprobe::ffprobe;
 use file_format::FileFormat;
 use predicates::prelude::*;
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use assert_fs::{prelude::*, TempDir};
 use common::{check_file_size_approx, check_media_duration, setup_logging};
 
@@ -22,7 +22,7 @@ fn test_select_introspect() {
     setup_logging();
     let mpd_url = "https://ftp.itec.aau.at/datasets/mmsys22/Skateboarding/4sec/multi-codecs-manifest.mpd";
 
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v", "--simulate", mpd_url])
         .assert()
         .stdout(predicate::str::contains("Only simulating"))
@@ -47,7 +47,7 @@ fn test_select_av1_small() {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("mmsys22-multiple-video-adaptations-av1.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--prefer-video-codecs", "av01",
@@ -78,7 +78,7 @@ fn test_select_hev1_small() {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("mmsys22-multiple-video-adaptations-hev1.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--prefer-video-codecs", "inexistent,hev1,vp09",
@@ -109,7 +109,7 @@ fn test_select_vvc_big() {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("mmsys22-multiple-video-adaptations-vvc.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "best",
                "--prefer-video-codecs", "vvc1,h264",
@@ -140,7 +140,7 @@ fn test_select_id() {
     let tmpd = TempDir::new().unwrap()
         .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
     let out = tmpd.child("mmsys22-multiple-video-adaptations-id.mp4");
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--want-video-id", "34",
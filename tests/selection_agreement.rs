@@ -0,0 +1,186 @@
+//! Tests that our choice of Representations agrees with the choice of the DashDownloader
+//
+// The DashDownloader chooses the Representations that it downloads itself, and src/selection.rs
+// reimplements its preference rules to report the choice (--dump-json) and to record live streams.
+// These tests pin the two choices together for each of the stream preference options.
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test selection_agreement -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with five video Representations, some of
+//   which have the same bandwidth, and four audio AdaptationSets with different languages and
+//   roles. Media segment requests are recorded and fail with a 404 error.
+//
+//   - For each combination of stream preferences, run a download with --abort-on-error and record
+//   the Representation whose segments the DashDownloader requested, then run with --dump-json and
+//   the same preferences, and check that it reports the same Representation.
+
+
+pub mod common;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use common::dash_mpd_cli;
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::IntoResponse;
+use axum::http::{header, StatusCode};
+use assert_fs::TempDir;
+use anyhow::Result;
+use test_log::test;
+
+
+static REQUESTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011"
+     type="static" mediaPresentationDuration="PT4S" minBufferTime="PT2S">
+  <Period id="p0" duration="PT4S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="sd-avc" codecs="avc1.4d401e" width="640" height="360" bandwidth="800000"/>
+      <Representation id="hd-avc" codecs="avc1.4d401f" width="1280" height="720" bandwidth="2400000"/>
+      <Representation id="hd-hevc" codecs="hev1.1.6.L93.B0" width="1280" height="720" bandwidth="2400000"/>
+      <Representation id="fhd-hevc" codecs="hev1.1.6.L120.B0" width="1920" height="1080" bandwidth="4800000"/>
+      <Representation id="fhd-avc" codecs="avc1.640028" width="1920" height="1080" bandwidth="4800000"/>
+    </AdaptationSet>
+    <AdaptationSet id="2" contentType="audio" mimeType="audio/mp4" lang="en">
+      <Role schemeIdUri="urn:mpeg:dash:role:2011" value="main"/>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="a-en-main" codecs="mp4a.40.2" bandwidth="128000"/>
+    </AdaptationSet>
+    <AdaptationSet id="3" contentType="audio" mimeType="audio/mp4" lang="fr">
+      <Role schemeIdUri="urn:mpeg:dash:role:2011" value="main"/>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="a-fr-main" codecs="mp4a.40.2" bandwidth="128000"/>
+    </AdaptationSet>
+    <AdaptationSet id="4" contentType="audio" mimeType="audio/mp4" lang="fr">
+      <Role schemeIdUri="urn:mpeg:dash:role:2011" value="alternate"/>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="a-fr-alt-1" codecs="mp4a.40.2" bandwidth="96000"/>
+      <Representation id="a-fr-alt-2" codecs="mp4a.40.2" bandwidth="96000"/>
+    </AdaptationSet>
+    <AdaptationSet id="5" contentType="audio" mimeType="audio/mp4" lang="de">
+      <Role schemeIdUri="urn:mpeg:dash:role:2011" value="commentary"/>
+      <SegmentTemplate timescale="1" duration="2" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="a-de-commentary" codecs="mp4a.40.2" bandwidth="64000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_segment(Path((rep, _seg)): Path<(String, String)>) -> impl IntoResponse {
+    REQUESTED.lock().unwrap().push(rep);
+    StatusCode::NOT_FOUND
+}
+
+// The Representation whose segments the DashDownloader requests first.
+fn downloaded(tmpd: &TempDir, args: &[&str]) -> String {
+    REQUESTED.lock().unwrap().clear();
+    let out = tmpd.path().join("agreement.mp4");
+    dash_mpd_cli()
+        .args(["--abort-on-error", "--fragment-retries", "0", "--no-version-check",
+               "-o", &out.to_string_lossy()])
+        .args(args)
+        .arg("http://localhost:6697/mpd")
+        .assert()
+        .failure();
+    REQUESTED.lock().unwrap().first()
+        .cloned()
+        .expect("the download should request a media segment")
+}
+
+// The Representation reported by --dump-json.
+fn reported(args: &[&str]) -> String {
+    let assert = dash_mpd_cli()
+        .args(["--dump-json", "--no-version-check"])
+        .args(args)
+        .arg("http://localhost:6697/mpd")
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+    let json = jzon::parse(&stdout).expect("stdout should contain a single JSON document");
+    json["periods"][0]["selected"][0]["representation_id"].as_str()
+        .expect("a Representation should be selected")
+        .to_string()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_selection_agreement() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{rep}/{seg}", get(send_segment));
+    let listener = TcpListener::bind("127.0.0.1:6697").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap();
+
+    let video_preferences: &[&[&str]] = &[
+        &[],
+        &["--quality", "worst"],
+        &["--quality", "intermediate"],
+        &["--quality", "best"],
+        &["--prefer-video-width", "1300"],
+        &["--prefer-video-height", "700"],
+        &["--prefer-video-width", "1300", "--quality", "best"],
+        &["--prefer-video-codecs", "hev1"],
+        &["--prefer-video-codecs", "vp09,avc1", "--quality", "best"],
+        &["--prefer-video-codecs", "hev1", "--quality", "intermediate"],
+        &["--want-video-id", "hd-avc"],
+        &["--want-video-id", "hd-avc", "--quality", "best"],
+        &["--want-video-id", "sd-avc", "--quality", "intermediate"],
+        &["--want-video-id", "hd", "--quality", "best"],
+        &["--want-video-id", "hd", "--quality", "worst"],
+        &["--want-video-id", "hevc", "--prefer-video-codecs", "avc1"],
+        &["--want-video-id", "hd-avc", "--prefer-video-codecs", "hev1"],
+        &["--want-video-id", "inexistent", "--quality", "best"],
+    ];
+    for prefs in video_preferences {
+        let args = [&["--video-only"], *prefs].concat();
+        assert_eq!(reported(&args), downloaded(&tmpd, &args),
+                   "video choice differs with {prefs:?}");
+    }
+
+    let audio_preferences: &[&[&str]] = &[
+        &[],
+        &["--quality", "best"],
+        &["--prefer-audio-language", "fr"],
+        &["--prefer-audio-language", "fr", "--quality", "best"],
+        &["--prefer-audio-language", "de"],
+        &["--prefer-audio-language", "es"],
+        &["--role-preference", "alternate,main"],
+        &["--role-preference", "commentary,alternate"],
+        &["--prefer-audio-language", "fr", "--role-preference", "alternate,main"],
+        &["--prefer-audio-language", "fr", "--role-preference", "alternate,main", "--quality", "best"],
+        &["--prefer-audio-language", "fr", "--role-preference", "commentary,main"],
+        &["--prefer-audio-language", "en", "--role-preference", "alternate"],
+        &["--prefer-audio-language", "es", "--role-preference", "alternate,main"],
+    ];
+    for prefs in audio_preferences {
+        let args = [&["--audio-only"], *prefs].concat();
+        assert_eq!(reported(&args), downloaded(&tmpd, &args),
+                   "audio choice differs with {prefs:?}");
+    }
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! Tests for the format selector expression language (--format)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test selector -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with three video Representations (two of them
+//   720p, using different codecs) and three audio Representations in two languages with stereo and
+//   5.1 channels.
+//
+//   - Check with --dump-json that a format selector with fallbacks chooses the expected video and
+//   audio Representations, and that the fallback alternative is used when the first one can't be
+//   satisfied.
+//
+//   - Download using a format selector with sort keys, and check that only the media segments of the
+//   chosen Representation are requested.
+//
+//   - Check that a malformed format selector is rejected with an error message that points to the
+//   problem.
+
+
+pub mod common;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4;
+
+
+static SEGMENTS_REQUESTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT4S">
+  <Period id="p0" duration="PT4S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="4" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="avc-1080" codecs="avc1.640028" width="1920" height="1080" bandwidth="4800000"/>
+      <Representation id="avc-720" codecs="avc1.4d401f" width="1280" height="720" bandwidth="2400000"/>
+      <Representation id="hevc-720" codecs="hev1.1.6.L93.B0" width="1280" height="720" bandwidth="1600000"/>
+    </AdaptationSet>
+    <AdaptationSet id="2" contentType="audio" mimeType="audio/mp4" lang="en">
+      <SegmentTemplate timescale="1" duration="4" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="en-stereo" codecs="mp4a.40.2" bandwidth="128000">
+        <AudioChannelConfiguration schemeIdUri="urn:mpeg:dash:23003:3:audio_channel_configuration:2011" value="2"/>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet id="3" contentType="audio" mimeType="audio/mp4" lang="fr">
+      <SegmentTemplate timescale="1" duration="4" startNumber="1"
+                       initialization="media/$RepresentationID$/init.mp4"
+                       media="media/$RepresentationID$/seg-$Number$.mp4"/>
+      <Representation id="fr-stereo" codecs="mp4a.40.2" bandwidth="128000">
+        <AudioChannelConfiguration schemeIdUri="urn:mpeg:dash:23003:3:audio_channel_configuration:2011" value="2"/>
+      </Representation>
+      <Representation id="fr-surround" codecs="ec-3" bandwidth="384000">
+        <AudioChannelConfiguration schemeIdUri="urn:mpeg:dash:23003:3:audio_channel_configuration:2011" value="6"/>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_segment(Path((rep, seg)): Path<(String, String)>) -> Response {
+    SEGMENTS_REQUESTED.lock().unwrap().push(format!("{rep}/{seg}"));
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4()))
+        .unwrap()
+}
+
+// The ids of the Representations selected in the first Period, according to --dump-json.
+fn selected(format: &str) -> Vec<String> {
+    let assert = dash_mpd_cli()
+        .args(["--dump-json", "--no-version-check", "--format", format,
+               "http://localhost:6685/mpd"])
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+    let json = jzon::parse(&stdout).unwrap();
+    json["periods"][0]["selected"].members()
+        .map(|s| s["representation_id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_format_selector() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/{rep}/{seg}", get(send_segment));
+    let listener = TcpListener::bind("127.0.0.1:6685").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap();
+
+    assert_eq!(selected("v[height<=720][codec^=hev1]/v[height<=720]+a[lang=fr][channels>=6]/a"),
+               vec!["hevc-720"]);
+    assert_eq!(selected("v[height<=720][codec^=vp09]+a/v[height<=720][codec*=4d]+a[lang=fr][channels>=6]"),
+               vec!["avc-720", "fr-surround"]);
+    assert_eq!(selected("v(height,+bandwidth)+a[lang^=EN]"),
+               vec!["avc-1080", "en-stereo"]);
+    assert_eq!(selected("a[channels>=?2](+bandwidth)"),
+               vec!["en-stereo"]);
+
+    dash_mpd_cli()
+        .args(["--format", "v[height=720](+bandwidth)", "--no-progress",
+               "-o", &tmpd.child("hevc.mp4").to_string_lossy(),
+               "http://localhost:6685/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Period 1: --format alternative 1 selects video hevc-720"))
+        .success();
+    let mut requested = SEGMENTS_REQUESTED.lock().unwrap().clone();
+    requested.sort();
+    assert_eq!(requested, vec!["hevc-720/init.mp4", "hevc-720/seg-1.mp4"]);
+
+    dash_mpd_cli()
+        .args(["--format", "v[heigth<=720]", "http://localhost:6685/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("unknown attribute \"heigth\""))
+        .failure();
+    dash_mpd_cli()
+        .args(["--format", "v[lang<fr]", "http://localhost:6685/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("operator < can't be used with a text attribute at position 7"))
+        .failure();
+    dash_mpd_cli()
+        .args(["--format", "v[height>2000]", "http://localhost:6685/mpd"])
+        .assert()
+        .stderr(predicate::str::contains("no Representations in Period 1 match --format"))
+        .failure();
+    Ok(())
+}
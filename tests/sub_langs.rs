@@ -0,0 +1,207 @@
+//! Tests for downloading subtitles in several languages (--sub-langs, --embed-subs)
+//
+// To run tests while enabling printing to stdout/stderr
+//
+//    cargo test --test sub_langs -- --show-output
+//
+// What happens in this test:
+//
+//   - Start an axum HTTP server that serves a manifest with a video AdaptationSet and four subtitle
+//   AdaptationSets: a sidecar WebVTT file in English, a sidecar WebVTT file in English for the hard
+//   of hearing (signalled by an Accessibility descriptor), a sidecar WebVTT file in German with the
+//   forced-subtitle Role, and TTML in fragmented MP4 (stpp) in French, split into two segments.
+//
+//   - Download with --sub-langs en,de --embed-subs to an MKV file, and check that the sidecar files
+//   are saved with the language in their name, and that the output file contains three subtitle
+//   streams with the expected language tags, titles and forced disposition. Check that the French
+//   subtitles are not requested.
+//
+//   - Download with --sub-langs fr, and check that the TTML documents of the two fragmented MP4
+//   segments are merged into one TTML file, and that the conflicting definitions of the style used
+//   by each segment are both kept, the second one renamed.
+
+
+pub mod common;
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use predicates::prelude::*;
+use common::dash_mpd_cli;
+use assert_fs::{prelude::*, TempDir};
+use axum::{routing::get, Router};
+use axum::extract::Path;
+use axum::response::{Response, IntoResponse};
+use axum::http::{header, StatusCode};
+use axum::body::Body;
+use anyhow::Result;
+use test_log::test;
+use common::generate_minimal_mp4_ffmpeg;
+
+
+static FRENCH_REQUESTED: AtomicUsize = AtomicUsize::new(0);
+
+async fn send_mpd() -> impl IntoResponse {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT2S">
+  <Period id="p0" duration="PT2S">
+    <AdaptationSet id="1" contentType="video" mimeType="video/mp4">
+      <SegmentTemplate timescale="1" duration="2" startNumber="1" media="media/video.mp4"/>
+      <Representation id="v1" codecs="avc1.640028" width="10" height="10" bandwidth="100000"/>
+    </AdaptationSet>
+    <AdaptationSet id="2" contentType="text" mimeType="text/vtt" lang="en">
+      <Label>English</Label>
+      <Representation id="sub-en" bandwidth="256">
+        <BaseURL>subs/en.vtt</BaseURL>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet id="3" contentType="text" mimeType="text/vtt" lang="en">
+      <Label>English (SDH)</Label>
+      <Accessibility schemeIdUri="urn:mpeg:dash:role:2011" value="caption"/>
+      <Representation id="sub-en-sdh" bandwidth="256">
+        <BaseURL>subs/en-sdh.vtt</BaseURL>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet id="4" contentType="text" mimeType="text/vtt" lang="de">
+      <Role schemeIdUri="urn:mpeg:dash:role:2011" value="forced-subtitle"/>
+      <Representation id="sub-de" bandwidth="256">
+        <BaseURL>subs/de.vtt</BaseURL>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet id="5" contentType="text" mimeType="application/mp4" codecs="stpp" lang="fr">
+      <SegmentTemplate timescale="1" duration="1" startNumber="1"
+                       initialization="subs/fr/init.mp4" media="subs/fr/seg-$Number$.mp4"/>
+      <Representation id="sub-fr" bandwidth="256"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+    ([(header::CONTENT_TYPE, "application/dash+xml")], xml)
+}
+
+async fn send_video() -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .body(Body::from(generate_minimal_mp4_ffmpeg("title=subtitles")))
+        .unwrap()
+}
+
+fn webvtt(text: &str) -> String {
+    format!("WEBVTT\n\n00:00:00.000 --> 00:00:01.000\n{text}\n")
+}
+
+async fn send_vtt(Path(name): Path<String>) -> Response {
+    let body = match name.as_str() {
+        "en.vtt" => webvtt("Hello"),
+        "en-sdh.vtt" => webvtt("[door creaks] Hello"),
+        "de.vtt" => webvtt("Hallo"),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    ([(header::CONTENT_TYPE, "text/vtt")], body).into_response()
+}
+
+// An MP4 box with the given type and payload.
+fn mp4_box(boxtype: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut b = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    b.extend_from_slice(boxtype);
+    b.extend_from_slice(payload);
+    b
+}
+
+// A TTML document whose single cue uses the style s1, defined in its head with the font style.
+fn ttml(begin: u32, font_style: &str, text: &str) -> String {
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<tt xmlns="http://www.w3.org/ns/ttml" xmlns:tts="http://www.w3.org/ns/ttml#styling" xml:lang="fr"><head><styling><style xml:id="s1" tts:fontStyle="{font_style}"/></styling></head><body><div><p begin="{begin}s" end="{}s" style="s1">{text}</p></div></body></tt>"#,
+            begin + 1)
+}
+
+// Fragments of an stpp subtitle stream. Only the boxes that matter for extracting the TTML documents
+// are realistic.
+async fn send_stpp(Path(name): Path<String>) -> Response {
+    FRENCH_REQUESTED.fetch_add(1, Ordering::SeqCst);
+    let body = match name.as_str() {
+        "init.mp4" => [mp4_box(b"ftyp", b"iso6\0\0\0\0iso6dash"), mp4_box(b"moov", &[])].concat(),
+        "seg-1.mp4" => [mp4_box(b"moof", &[]), mp4_box(b"mdat", ttml(0, "italic", "Bonjour").as_bytes())].concat(),
+        "seg-2.mp4" => [mp4_box(b"moof", &[]), mp4_box(b"mdat", ttml(1, "normal", "Au revoir").as_bytes())].concat(),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/mp4")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+
+#[test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_sub_langs() -> Result<()> {
+    let app = Router::new()
+        .route("/mpd", get(send_mpd))
+        .route("/media/video.mp4", get(send_video))
+        .route("/subs/{name}", get(send_vtt))
+        .route("/subs/fr/{name}", get(send_stpp));
+    let listener = TcpListener::bind("127.0.0.1:6687").await.unwrap();
+    let backend = async {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap()
+    };
+    tokio::spawn(backend);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let tmpd = TempDir::new().unwrap()
+        .into_persistent_if(env::var("TEST_PERSIST_FILES").is_ok());
+
+    let out = tmpd.child("subtitled.mkv");
+    dash_mpd_cli()
+        .args(["--sub-langs", "en,de", "--embed-subs", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6687/mpd"])
+        .assert()
+        .stdout(predicate::str::contains("Added 3 subtitle tracks"))
+        .success();
+    let en = fs::read_to_string(tmpd.path().join("subtitled.en.vtt"))?;
+    assert!(en.contains("Hello"));
+    let sdh = fs::read_to_string(tmpd.path().join("subtitled.en.sdh.vtt"))?;
+    assert!(sdh.contains("[door creaks]"));
+    let de = fs::read_to_string(tmpd.path().join("subtitled.de.forced.vtt"))?;
+    assert!(de.contains("Hallo"));
+    assert_eq!(FRENCH_REQUESTED.load(Ordering::SeqCst), 0);
+
+    let ffprobe = Command::new("ffprobe")
+        .args(["-loglevel", "error",
+               "-select_streams", "s",
+               "-show_streams",
+               "-of", "json",
+               out.to_str().unwrap()])
+        .output()
+        .expect("spawning ffprobe");
+    assert!(ffprobe.status.success());
+    let parsed = jzon::parse(&String::from_utf8_lossy(&ffprobe.stdout)).unwrap();
+    let streams: Vec<(&str, &str, bool)> = parsed["streams"].members()
+        .map(|s| (s["tags"]["language"].as_str().unwrap_or(""),
+                  s["tags"]["title"].as_str().unwrap_or(""),
+                  s["disposition"]["forced"] == 1))
+        .collect();
+    assert_eq!(streams, vec![
+        ("eng", "English", false),
+        ("eng", "English (SDH)", false),
+        ("ger", "", true),
+    ]);
+
+    let out = tmpd.child("french.mp4");
+    dash_mpd_cli()
+        .args(["--sub-langs", "fr", "--no-progress",
+               "-o", &out.to_string_lossy(),
+               "http://localhost:6687/mpd"])
+        .assert()
+        .success();
+    let fr = fs::read_to_string(tmpd.path().join("french.fr.ttml"))?;
+    assert!(fr.starts_with("<?xml"));
+    assert!(fr.contains("Bonjour</p></div><div><p begin=\"1s\" end=\"2s\" style=\"s1-2\">Au revoir"));
+    assert!(fr.contains(r#"<style xml:id="s1" tts:fontStyle="italic"/><style xml:id="s1-2" tts:fontStyle="normal"/></styling>"#));
+    assert_eq!(fr.matches("<head>").count(), 1);
+    assert_eq!(fr.matches("</tt>").count(), 1);
+    Ok(())
+}
@@ -12,7 +12,7 @@ pub mod common;
 use fs_err as fs;
 use std::env;
 use std::path::Path;
-use assert_cmd::cargo::cargo_bin_cmd;
+use common::dash_mpd_cli;
 use assert_fs::{prelude::*, TempDir};
 use ffprobe::ffprobe;
 use file_format::FileFormat;
@@ -32,7 +32,7 @@ fn test_subtitles_wvtt () {
     let mut subpath = out.to_path_buf();
     subpath.set_extension("srt");
     let subpath = Path::new(&subpath);
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--write-subs",
@@ -46,7 +46,7 @@ fn test_subtitles_wvtt () {
     // Dutch) is downloaded.
     assert!(srt.contains("land van de poortwachters"));
 
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--write-subs",
@@ -72,7 +72,7 @@ fn test_subtitles_ttml () {
     let mut subpath = out.to_path_buf();
     subpath.set_extension("ttml");
     let subpath = Path::new(&subpath);
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--write-subs",
@@ -86,7 +86,7 @@ fn test_subtitles_ttml () {
     // English) is downloaded.
     assert!(ttml.contains("You're a jerk"));
 
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--write-subs",
@@ -112,7 +112,7 @@ fn test_subtitles_vtt () {
     let mut subpath = out.to_path_buf();
     subpath.set_extension("vtt");
     let subpath = Path::new(&subpath);
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--write-subs",
@@ -140,7 +140,7 @@ async fn test_subtitles_vtt_fragments () {
     let mut subpath_vtt = out.to_path_buf();
     subpath_vtt.set_extension("vtt");
     let subpath_vtt = Path::new(&subpath_vtt);
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--write-subs",
@@ -169,7 +169,7 @@ fn test_subtitles_convert_wvtt () {
     let mut subpath_wvtt = out.to_path_buf();
     subpath_wvtt.set_extension("wvtt");
     let subpath_wvtt = Path::new(&subpath_wvtt);
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--write-subs",
@@ -209,7 +209,7 @@ async fn test_subtitles_stpp() {
     let mut subpath = out.to_path_buf();
     subpath.set_extension("ttml");
     let subpath = Path::new(&subpath);
-    cargo_bin_cmd!()
+    dash_mpd_cli()
         .args(["-v",
                "--quality", "worst",
                "--write-subs",